@@ -0,0 +1,158 @@
+//! `StandardStorage`のベンチマーク
+//!
+//! ## Summary
+//! 10万件以上のデータに対して、挿入・削除・再挿入・`compact`の
+//! 所要時間を計測する。
+//!
+//! `cargo bench --bench storage`で実行する。
+
+use std::{
+  fmt::Display,
+  time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use sousarc_content_types::{
+  storage::StandardStorage, traits::prelude::*,
+};
+
+/// 計測するデータ件数
+const COUNTS: [u64; 3] = [10_000, 100_000, 200_000];
+
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+struct BenchId(u64);
+impl Display for BenchId {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+impl SousARCId for BenchId {
+  type Bound = BenchData;
+}
+
+#[derive(
+  Debug,
+  Clone,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+struct BenchKey(String);
+impl Display for BenchKey {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+impl SousARCKey for BenchKey {
+  type Bound = BenchData;
+}
+
+#[derive(Debug)]
+struct BenchData {
+  id_key: IdKeySet<Self>,
+}
+impl SousARCData for BenchData {
+  type Id = BenchId;
+  type Key = BenchKey;
+
+  fn id_key_set(&self) -> &IdKeySet<Self> {
+    &self.id_key
+  }
+
+  fn id_key_set_mut(
+    &mut self,
+    _: IdKeyToken,
+  ) -> &mut IdKeySet<Self> {
+    &mut self.id_key
+  }
+}
+impl BenchData {
+  fn new(n: u64) -> Self {
+    let key = BenchKey(format!("element-{n:08}"));
+    Self { id_key: IdKeySet::new(BenchId(n), key) }
+  }
+}
+
+/// 削除順を散らす為の置換(`n`と互いに素な歩幅で巡回する)
+fn scattered(n: u64) -> impl Iterator<Item = u64> {
+  let step =
+    (n / 2..n).find(|s| gcd(*s, n) == 1).unwrap_or(1);
+  (0..n).map(move |i| i * step % n)
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+  if b == 0 { a } else { gcd(b, a % b) }
+}
+
+fn measure(
+  label: &str,
+  n: u64,
+  f: impl FnOnce(),
+) -> Duration {
+  let start = Instant::now();
+  f();
+  let elapsed = start.elapsed();
+  println!(
+    "{label:<24} n={n:>7} total={:>10.3?} per_op={:>8.1?}",
+    elapsed,
+    elapsed / n as u32
+  );
+  elapsed
+}
+
+fn main() {
+  for n in COUNTS {
+    let mut storage = StandardStorage::new();
+    measure("insert", n, || {
+      for i in 0..n {
+        let _ = storage.insert(BenchData::new(i));
+      }
+    });
+    measure("get", n, || {
+      for i in scattered(n) {
+        assert!(storage.get(BenchId(i)).is_some());
+      }
+    });
+    measure("remove (half)", n / 2, || {
+      for i in scattered(n).take(n as usize / 2) {
+        assert!(storage.remove(BenchId(i)).is_some());
+      }
+    });
+    measure("insert (reuse slots)", n / 2, || {
+      for i in n..n + n / 2 {
+        let _ = storage.insert(BenchData::new(i));
+      }
+    });
+    measure("remove (half again)", n / 2, || {
+      for i in scattered(n).skip(n as usize / 2) {
+        assert!(storage.remove(BenchId(i)).is_some());
+      }
+    });
+    measure("compact", 1, || {
+      storage.compact();
+    });
+    assert_eq!(storage.len(), n as usize / 2);
+    println!();
+  }
+}
@@ -0,0 +1,62 @@
+use std::fmt::Display;
+
+/// 操作の適用のエラー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CrdtError {
+  /// 操作のエポックが現在のエポックと異なる
+  ///
+  /// チェックポイントの前後の操作は混ぜられないので、
+  /// 最新のスナップショットを取得し直す必要がある。
+  EpochMismatch { current: u64, op: u64 },
+
+  /// 識別子の`counter`またはエポックが上限を超える
+  ///
+  /// 不正な操作・状態か、Lamport時刻を使い切った複製によるもの。
+  CounterOverflow,
+
+  /// 保留している操作が上限(`MAX_PENDING`)に達した
+  ///
+  /// 依存する操作が届いていないので、
+  /// 最新のスナップショットを取得し直す必要がある。
+  PendingOverflow,
+
+  /// 操作の符号化・復号に失敗した
+  Codec(String),
+}
+
+impl Display for CrdtError {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    match self {
+      Self::EpochMismatch { current, op } => write!(
+        f,
+        "operation epoch {op} does not match current epoch {current}"
+      ),
+      Self::CounterOverflow => {
+        write!(f, "operation counter overflows")
+      }
+      Self::PendingOverflow => {
+        write!(f, "too many operations are pending")
+      }
+      Self::Codec(reason) => {
+        write!(f, "failed to encode or decode: {reason}")
+      }
+    }
+  }
+}
+
+impl std::error::Error for CrdtError {}
+
+impl From<rmp_serde::encode::Error> for CrdtError {
+  fn from(e: rmp_serde::encode::Error) -> Self {
+    Self::Codec(e.to_string())
+  }
+}
+
+impl From<rmp_serde::decode::Error> for CrdtError {
+  fn from(e: rmp_serde::decode::Error) -> Self {
+    Self::Codec(e.to_string())
+  }
+}
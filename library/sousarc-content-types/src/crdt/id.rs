@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// 複製(編集者の接続)の識別子
+///
+/// ## Summary
+/// 同時に編集する複製の間で一意である必要がある。
+/// `ReplicaId::ORIGIN`は、初期の文字列の為に予約されている。
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+pub struct ReplicaId(pub u64);
+
+impl ReplicaId {
+  /// 初期の文字列の複製
+  pub const ORIGIN: Self = Self(0);
+}
+
+impl Display for ReplicaId {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+/// 1文字の挿入の識別子(Lamport時刻と複製)
+///
+/// ## Summary
+/// `counter`・`replica`の順に比較し、大きい方が新しい。
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+pub struct OpId {
+  pub counter: u64,
+  pub replica: ReplicaId,
+}
+
+impl Display for OpId {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    write!(f, "{}@{}", self.counter, self.replica)
+  }
+}
+
+/// 同じ複製の連続した識別子の範囲
+///
+/// ## Member
+/// - `replica`: `ReplicaId`型
+/// - `start`: `u64`型
+///   - 最初の`counter`
+/// - `len`: `u64`型
+///   - 範囲の長さ
+#[derive(
+  Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct IdSpan {
+  pub replica: ReplicaId,
+  pub start: u64,
+  pub len: u64,
+}
+
+impl IdSpan {
+  /// 範囲に識別子が含まれるか判定する
+  ///
+  /// ## Summary
+  /// `start + len`が`u64`を超える範囲でも溢れずに判定する。
+  pub fn contains(&self, id: OpId) -> bool {
+    id.replica == self.replica
+      && id
+        .counter
+        .checked_sub(self.start)
+        .is_some_and(|offset| offset < self.len)
+  }
+
+  /// 範囲の最後の次の`counter`
+  ///
+  /// ## Return
+  /// `u64`を超える場合は`None`
+  pub fn end(&self) -> Option<u64> {
+    self.start.checked_add(self.len)
+  }
+}
@@ -0,0 +1,14 @@
+//! 共同編集の為の複製データ型(CRDT)
+//!
+//! ## Summary
+//! - `TextCrdt`: 文字列の順序付き複製データ型(RGA)
+//! - `TextOp`: 複製間で送り合う操作
+//! - `ReplicaId`・`OpId`: 複製と操作の識別子
+//! - `CrdtError`: 操作の適用に失敗した理由
+
+pub mod id;
+pub use id::*;
+pub mod text;
+pub use text::*;
+pub mod error;
+pub use error::*;
@@ -0,0 +1,770 @@
+//! 文字列の複製データ型
+//!
+//! ## Summary
+//! RGA(Replicated Growable Array)による文字列。
+//! 各文字は挿入した操作の識別子を持ち、削除した文字は墓標として残す。
+//! 同じ操作の集合を適用した複製は、適用の順序に依らず同じ文字列になる。
+//!
+//! 墓標はチェックポイントで取り除く。
+//! チェックポイントの度にエポックが進み、それより前の操作は適用できなくなる。
+//!
+//! 位置と長さは、文字(Unicodeスカラー値)単位で数える。
+//!
+//! 識別子から文字の位置を引く索引を持つので、リモートの操作の適用で
+//! 文字列全体を走査するのは、索引が古くなった範囲を引き直す時のみになる。
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{CrdtError, IdSpan, OpId, ReplicaId};
+
+/// 複製間で送り合う操作
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum TextOp {
+  /// `origin`の直後に文字列を挿入する
+  ///
+  /// `text`の`i`文字目の識別子は、`id`の`counter`に`i`を足したもの。
+  /// `origin`が`None`の場合は先頭に挿入する。
+  Insert {
+    epoch: u64,
+    id: OpId,
+    origin: Option<OpId>,
+    text: String,
+  },
+
+  /// 識別子の範囲の文字を削除する
+  Delete { epoch: u64, spans: Vec<IdSpan> },
+}
+
+impl TextOp {
+  /// 操作を作成した時点のエポック
+  pub fn epoch(&self) -> u64 {
+    match self {
+      Self::Insert { epoch, .. }
+      | Self::Delete { epoch, .. } => *epoch,
+    }
+  }
+
+  /// MessagePack(配列形式)に符号化する
+  pub fn to_msgpack(&self) -> Result<Vec<u8>, CrdtError> {
+    Ok(rmp_serde::to_vec(self)?)
+  }
+
+  /// MessagePackから復号する
+  pub fn from_msgpack(
+    bytes: &[u8],
+  ) -> Result<Self, CrdtError> {
+    Ok(rmp_serde::from_slice(bytes)?)
+  }
+
+  /// 識別子が`u64`の範囲に収まるか検査する
+  ///
+  /// ## Error
+  /// - `CrdtError::CounterOverflow`: 挿入する文字・削除する範囲の
+  ///   `counter`が`u64`を超える
+  fn check(&self) -> Result<(), CrdtError> {
+    match self {
+      Self::Insert { id, text, .. } => {
+        last_counter(id.counter, text).map(|_| ())
+      }
+      Self::Delete { spans, .. } => {
+        for span in spans {
+          span.end().ok_or(CrdtError::CounterOverflow)?;
+        }
+        Ok(())
+      }
+    }
+  }
+}
+
+/// `counter`から始まる`text`の最後の文字の`counter`
+///
+/// ## Error
+/// - `CrdtError::CounterOverflow`: `u64`を超える
+fn last_counter(
+  counter: u64,
+  text: &str,
+) -> Result<u64, CrdtError> {
+  let len = text.chars().count() as u64;
+  counter
+    .checked_add(len.saturating_sub(1))
+    .ok_or(CrdtError::CounterOverflow)
+}
+
+/// 1つの複製が保留できる操作の数の上限
+///
+/// 依存する操作が届かない操作を際限なく溜めないよう、
+/// これを超える場合は`apply`が`CrdtError::PendingOverflow`を返す。
+pub const MAX_PENDING: usize = 1024;
+
+/// 1文字
+#[derive(Debug, Clone)]
+struct Item {
+  id: OpId,
+  ch: char,
+  deleted: bool,
+}
+
+/// 識別子が連続し、削除の有無が同じ文字の並び
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct TextRun {
+  pub id: OpId,
+  pub text: String,
+  pub deleted: bool,
+}
+
+/// 複製の状態(新しい複製の初期化に使う)
+///
+/// ## Member
+/// - `epoch`: `u64`型
+/// - `clock`: `u64`型
+///   - 観測した最大のLamport時刻
+/// - `runs`: `Vec<TextRun>`型
+///   - 文書の順序に並べた文字(墓標を含む)
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct TextSnapshot {
+  pub epoch: u64,
+  pub clock: u64,
+  pub runs: Vec<TextRun>,
+}
+
+/// 文字列の複製データ型
+///
+/// ## Summary
+/// - ローカルの編集: `insert`・`delete`(他の複製へ送る`TextOp`を返す)
+/// - リモートの操作: `apply`
+/// - 墓標の除去: `checkpoint`
+///
+/// 挿入位置の文字や削除する文字が未到着の操作は保留し、
+/// 依存する操作が届いた時点で適用する。
+/// 保留できる操作は`MAX_PENDING`個までで、溢れた場合は
+/// 最新のスナップショットから複製を作り直す。
+///
+/// ## Member
+/// - `items`: `Vec<Item>`型
+///   - 文書の順序に並べた文字(墓標を含む)
+/// - `index`: `HashMap<OpId, usize>`型
+///   - 識別子→`items`の添字
+///   - `indexed`より前の添字のみ正しい(それ以降は引き直す)
+/// - `indexed`: `usize`型
+///   - 挿入で文字がずれていない範囲の長さ
+/// - `pending`: `Vec<TextOp>`型
+///   - 依存する文字が未到着の操作
+#[derive(Debug, Clone)]
+pub struct TextCrdt {
+  replica: ReplicaId,
+  epoch: u64,
+  clock: u64,
+  items: Vec<Item>,
+  index: HashMap<OpId, usize>,
+  indexed: usize,
+  pending: Vec<TextOp>,
+}
+
+impl TextCrdt {
+  /// 初期の文字列から複製を作成する
+  ///
+  /// ## Summary
+  /// 初期の文字は`ReplicaId::ORIGIN`の識別子を持つので、
+  /// 同じ文字列から作成した複製同士は同じ状態になる。
+  pub fn new(replica: ReplicaId, text: &str) -> Self {
+    Self::at_epoch(replica, 0, text)
+  }
+
+  fn at_epoch(
+    replica: ReplicaId,
+    epoch: u64,
+    text: &str,
+  ) -> Self {
+    let items: Vec<_> = text
+      .chars()
+      .enumerate()
+      .map(|(i, ch)| Item {
+        id: OpId {
+          counter: i as u64 + 1,
+          replica: ReplicaId::ORIGIN,
+        },
+        ch,
+        deleted: false,
+      })
+      .collect();
+    Self::with_items(
+      replica,
+      epoch,
+      items.len() as u64,
+      items,
+    )
+  }
+
+  fn with_items(
+    replica: ReplicaId,
+    epoch: u64,
+    clock: u64,
+    items: Vec<Item>,
+  ) -> Self {
+    Self {
+      replica,
+      epoch,
+      clock,
+      items,
+      index: HashMap::new(),
+      indexed: 0,
+      pending: Vec::new(),
+    }
+  }
+
+  /// 状態から複製を作成する
+  ///
+  /// ## Error
+  /// - `CrdtError::CounterOverflow`: 文字の`counter`が`u64`を超える
+  pub fn from_snapshot(
+    replica: ReplicaId,
+    snapshot: TextSnapshot,
+  ) -> Result<Self, CrdtError> {
+    let mut items = Vec::new();
+    for run in snapshot.runs {
+      last_counter(run.id.counter, &run.text)?;
+      items.extend(run.text.chars().zip(0..).map(
+        |(ch, i)| Item {
+          id: OpId {
+            counter: run.id.counter + i,
+            ..run.id
+          },
+          ch,
+          deleted: run.deleted,
+        },
+      ));
+    }
+    Ok(Self::with_items(
+      replica,
+      snapshot.epoch,
+      snapshot.clock,
+      items,
+    ))
+  }
+
+  /// 状態を書き出す
+  pub fn snapshot(&self) -> TextSnapshot {
+    let mut runs: Vec<TextRun> = Vec::new();
+    for item in &self.items {
+      if let Some(run) = runs.last_mut()
+        && run.deleted == item.deleted
+        && run.id.replica == item.id.replica
+        && run
+          .id
+          .counter
+          .checked_add(run.text.chars().count() as u64)
+          == Some(item.id.counter)
+      {
+        run.text.push(item.ch);
+        continue;
+      }
+      runs.push(TextRun {
+        id: item.id,
+        text: item.ch.to_string(),
+        deleted: item.deleted,
+      });
+    }
+    TextSnapshot {
+      epoch: self.epoch,
+      clock: self.clock,
+      runs,
+    }
+  }
+
+  /// 複製の識別子
+  pub fn replica(&self) -> ReplicaId {
+    self.replica
+  }
+
+  /// 現在のエポック
+  pub fn epoch(&self) -> u64 {
+    self.epoch
+  }
+
+  /// 保留している操作の数
+  pub fn pending_len(&self) -> usize {
+    self.pending.len()
+  }
+
+  /// 現在の文字列
+  pub fn text(&self) -> String {
+    self
+      .items
+      .iter()
+      .filter(|item| !item.deleted)
+      .map(|item| item.ch)
+      .collect()
+  }
+
+  /// 現在の文字数
+  pub fn len(&self) -> usize {
+    self.items.iter().filter(|item| !item.deleted).count()
+  }
+
+  /// 現在の文字列が空か判定する
+  pub fn is_empty(&self) -> bool {
+    self.items.iter().all(|item| item.deleted)
+  }
+
+  /// 表示されている`index`文字目の識別子
+  fn visible_id(&self, index: usize) -> Option<OpId> {
+    self
+      .items
+      .iter()
+      .filter(|item| !item.deleted)
+      .nth(index)
+      .map(|item| item.id)
+  }
+
+  /// 識別子の文字の`items`での添字
+  ///
+  /// ## Summary
+  /// 索引が古い場合は、ずれた範囲のみを引き直す。
+  fn position(&mut self, id: OpId) -> Option<usize> {
+    if let Some(&at) = self.index.get(&id)
+      && at < self.indexed
+    {
+      return Some(at);
+    }
+    for (at, item) in
+      self.items.iter().enumerate().skip(self.indexed)
+    {
+      self.index.insert(item.id, at);
+    }
+    self.indexed = self.items.len();
+    self.index.get(&id).copied()
+  }
+
+  /// 挿入を統合する
+  ///
+  /// ## Summary
+  /// `origin`の直後から、識別子が新しい文字を飛ばした位置に挿入する。
+  /// 飛ばした文字は並行して同じ位置に挿入された文字とその子孫なので、
+  /// どの複製でも同じ順序になる。
+  /// `last`は`text`の最後の文字の`counter`(検査済みであること)。
+  fn integrate(
+    &mut self,
+    id: OpId,
+    origin: Option<OpId>,
+    text: &str,
+    last: u64,
+  ) {
+    let mut at = match origin.and_then(|o| self.position(o))
+    {
+      Some(index) => index + 1,
+      None => 0,
+    };
+    while self
+      .items
+      .get(at)
+      .is_some_and(|item| item.id > id)
+    {
+      at += 1;
+    }
+    let items = text.chars().zip(0..).map(|(ch, i)| Item {
+      id: OpId { counter: id.counter + i, ..id },
+      ch,
+      deleted: false,
+    });
+    self.items.splice(at..at, items);
+    self.indexed = self.indexed.min(at);
+    self.clock = self.clock.max(last);
+  }
+
+  /// `index`文字目の前に文字列を挿入する
+  ///
+  /// ## Summary
+  /// `index`が文字数を超える場合は末尾に挿入する。
+  ///
+  /// ## Return
+  /// 他の複製へ送る操作(`text`が空の場合は`None`)
+  ///
+  /// ## Error
+  /// - `CrdtError::CounterOverflow`: Lamport時刻が`u64`を超える
+  pub fn insert(
+    &mut self,
+    index: usize,
+    text: &str,
+  ) -> Result<Option<TextOp>, CrdtError> {
+    if text.is_empty() {
+      return Ok(None);
+    }
+    let counter = self
+      .clock
+      .checked_add(1)
+      .ok_or(CrdtError::CounterOverflow)?;
+    let last = last_counter(counter, text)?;
+    let index = index.min(self.len());
+    let origin = index
+      .checked_sub(1)
+      .and_then(|prev| self.visible_id(prev));
+    let id = OpId { counter, replica: self.replica };
+    self.integrate(id, origin, text, last);
+    Ok(Some(TextOp::Insert {
+      epoch: self.epoch,
+      id,
+      origin,
+      text: text.to_string(),
+    }))
+  }
+
+  /// `index`文字目から`len`文字を削除する
+  ///
+  /// ## Return
+  /// 他の複製へ送る操作(削除する文字が無い場合は`None`)
+  pub fn delete(
+    &mut self,
+    index: usize,
+    len: usize,
+  ) -> Option<TextOp> {
+    let mut spans: Vec<IdSpan> = Vec::new();
+    for item in self
+      .items
+      .iter_mut()
+      .filter(|item| !item.deleted)
+      .skip(index)
+      .take(len)
+    {
+      item.deleted = true;
+      if let Some(span) = spans.last_mut()
+        && span.replica == item.id.replica
+        && span.end() == Some(item.id.counter)
+      {
+        span.len += 1;
+        continue;
+      }
+      spans.push(IdSpan {
+        replica: item.id.replica,
+        start: item.id.counter,
+        len: 1,
+      });
+    }
+    (!spans.is_empty()).then_some(TextOp::Delete {
+      epoch: self.epoch,
+      spans,
+    })
+  }
+
+  /// 依存する文字が揃っていれば操作を適用する
+  ///
+  /// ## Summary
+  /// `op`は`TextOp::check`で検査済みであること。
+  ///
+  /// ## Return
+  /// 適用した(または適用済みだった)か
+  fn try_apply(&mut self, op: &TextOp) -> bool {
+    match op {
+      TextOp::Insert { id, origin, text, .. } => {
+        if self.position(*id).is_some() {
+          return true;
+        }
+        if origin
+          .is_some_and(|o| self.position(o).is_none())
+        {
+          return false;
+        }
+        if let Ok(last) = last_counter(id.counter, text)
+          && !text.is_empty()
+        {
+          self.integrate(*id, *origin, text, last);
+        }
+        true
+      }
+      TextOp::Delete { spans, .. } => {
+        // 文字数より長い範囲は揃わないので、1文字ずつ引く前に除く
+        let expected =
+          spans.iter().try_fold(0u64, |sum, span| {
+            sum.checked_add(span.len)
+          });
+        if expected
+          .is_none_or(|n| n > self.items.len() as u64)
+        {
+          return false;
+        }
+        let mut found = Vec::new();
+        for span in spans {
+          for counter in span.start..span.start + span.len {
+            let id =
+              OpId { counter, replica: span.replica };
+            let Some(at) = self.position(id) else {
+              return false;
+            };
+            found.push(at);
+          }
+        }
+        for at in found {
+          self.items[at].deleted = true;
+        }
+        true
+      }
+    }
+  }
+
+  /// 他の複製の操作を適用する
+  ///
+  /// ## Summary
+  /// 同じ操作を複数回適用しても結果は変わらない。
+  /// 依存する文字が未到着の場合は保留し、後の`apply`で適用する。
+  ///
+  /// ## Error
+  /// - `CrdtError::EpochMismatch`: 操作のエポックが現在のエポックと異なる
+  /// - `CrdtError::CounterOverflow`: 操作の識別子が`u64`を超える
+  /// - `CrdtError::PendingOverflow`: 保留している操作が`MAX_PENDING`個に達した
+  ///   (操作は保留せずに破棄する)
+  pub fn apply(
+    &mut self,
+    op: TextOp,
+  ) -> Result<(), CrdtError> {
+    if op.epoch() != self.epoch {
+      return Err(CrdtError::EpochMismatch {
+        current: self.epoch,
+        op: op.epoch(),
+      });
+    }
+    op.check()?;
+    if !self.try_apply(&op) {
+      if self.pending.len() >= MAX_PENDING {
+        return Err(CrdtError::PendingOverflow);
+      }
+      self.pending.push(op);
+      return Ok(());
+    }
+    // 保留している操作を、適用できなくなるまで繰り返し適用する
+    loop {
+      let pending = std::mem::take(&mut self.pending);
+      let before = pending.len();
+      for op in pending {
+        if !self.try_apply(&op) {
+          self.pending.push(op);
+        }
+      }
+      if self.pending.len() == before {
+        return Ok(());
+      }
+    }
+  }
+
+  /// 墓標を取り除き、エポックを進める
+  ///
+  /// ## Summary
+  /// 現在の文字列を初期の文字列とする複製に置き換える。
+  /// 保留している操作は破棄する。
+  /// 同じ操作の集合を適用した複製がチェックポイントを行うと、同じ状態になる。
+  ///
+  /// ## Return
+  /// 新しいエポックの状態(他の複製の初期化に使う)
+  ///
+  /// ## Error
+  /// - `CrdtError::CounterOverflow`: エポックが`u64`を超える
+  ///   (状態は変更しない)
+  pub fn checkpoint(
+    &mut self,
+  ) -> Result<TextSnapshot, CrdtError> {
+    let epoch = self
+      .epoch
+      .checked_add(1)
+      .ok_or(CrdtError::CounterOverflow)?;
+    *self =
+      Self::at_epoch(self.replica, epoch, &self.text());
+    Ok(self.snapshot())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    MAX_PENDING, TextCrdt, TextOp, TextRun, TextSnapshot,
+  };
+  use crate::crdt::{CrdtError, IdSpan, OpId, ReplicaId};
+
+  /// `items`の全ての並べ替えを列挙する
+  fn permutations(items: &[usize]) -> Vec<Vec<usize>> {
+    if items.is_empty() {
+      return vec![Vec::new()];
+    }
+    let mut all = Vec::new();
+    for (i, first) in items.iter().enumerate() {
+      let mut rest = items.to_vec();
+      rest.remove(i);
+      for mut tail in permutations(&rest) {
+        tail.insert(0, *first);
+        all.push(tail);
+      }
+    }
+    all
+  }
+
+  #[test]
+  fn converges_in_any_delivery_order() {
+    let (mut a, mut b, mut c) = (
+      TextCrdt::new(ReplicaId(1), "hello"),
+      TextCrdt::new(ReplicaId(2), "hello"),
+      TextCrdt::new(ReplicaId(3), "hello"),
+    );
+    // A・Bは同じ位置に並行して挿入し、Aは続けて自分の挿入の一部を消す
+    let ops = vec![
+      a.insert(5, " world").unwrap().unwrap(),
+      b.insert(5, "!!").unwrap().unwrap(),
+      a.delete(5, 3).unwrap(),
+      c.delete(0, 1).unwrap(),
+      c.insert(0, "J").unwrap().unwrap(),
+      b.insert(2, "y").unwrap().unwrap(),
+    ];
+
+    let mut expected = None;
+    for order in
+      permutations(&(0..ops.len()).collect::<Vec<_>>())
+    {
+      let mut replica =
+        TextCrdt::new(ReplicaId(4), "hello");
+      for i in order {
+        replica.apply(ops[i].clone()).unwrap();
+      }
+      assert_eq!(replica.pending_len(), 0);
+      let text = replica.text();
+      assert_eq!(
+        *expected.get_or_insert(text.clone()),
+        text
+      );
+    }
+    assert_eq!(expected.as_deref(), Some("Jeyllo!!rld"));
+
+    // 同じ操作を繰り返し適用しても変わらない
+    for replica in [&mut a, &mut b, &mut c] {
+      for op in &ops {
+        replica.apply(op.clone()).unwrap();
+        replica.apply(op.clone()).unwrap();
+      }
+      assert_eq!(replica.text(), "Jeyllo!!rld");
+    }
+  }
+
+  #[test]
+  fn rejects_overflowing_counters() {
+    let mut text = TextCrdt::new(ReplicaId(1), "ab");
+    let insert = |counter, text: &str| TextOp::Insert {
+      epoch: 0,
+      id: OpId { counter, replica: ReplicaId(2) },
+      origin: None,
+      text: text.to_string(),
+    };
+    assert_eq!(
+      text.apply(insert(u64::MAX, "xy")),
+      Err(CrdtError::CounterOverflow)
+    );
+    let span = IdSpan {
+      replica: ReplicaId(2),
+      start: u64::MAX,
+      len: 2,
+    };
+    assert!(!span.contains(OpId {
+      counter: 0,
+      replica: ReplicaId(2)
+    }));
+    assert_eq!(
+      text.apply(TextOp::Delete {
+        epoch: 0,
+        spans: vec![span]
+      }),
+      Err(CrdtError::CounterOverflow)
+    );
+    assert_eq!(text.text(), "ab");
+
+    // 最後の`counter`に達した複製は、それ以上挿入できない
+    text.apply(insert(u64::MAX, "x")).unwrap();
+    assert_eq!(text.text(), "xab");
+    assert_eq!(
+      text.insert(0, "z"),
+      Err(CrdtError::CounterOverflow)
+    );
+    let snapshot = text.snapshot();
+    assert!(
+      TextCrdt::from_snapshot(ReplicaId(3), snapshot)
+        .is_ok()
+    );
+
+    let overflowing = TextSnapshot {
+      epoch: 0,
+      clock: 0,
+      runs: vec![TextRun {
+        id: OpId {
+          counter: u64::MAX,
+          replica: ReplicaId(2),
+        },
+        text: "xy".to_string(),
+        deleted: false,
+      }],
+    };
+    assert!(matches!(
+      TextCrdt::from_snapshot(ReplicaId(3), overflowing),
+      Err(CrdtError::CounterOverflow)
+    ));
+  }
+
+  #[test]
+  fn caps_operations_waiting_for_their_origin() {
+    let mut text = TextCrdt::new(ReplicaId(1), "ab");
+    // 届かない文字の直後への挿入は、全て保留される
+    let orphan = |counter| TextOp::Insert {
+      epoch: 0,
+      id: OpId { counter, replica: ReplicaId(2) },
+      origin: Some(OpId {
+        counter: 1,
+        replica: ReplicaId(3),
+      }),
+      text: "x".to_string(),
+    };
+    for counter in 1..=MAX_PENDING as u64 {
+      text.apply(orphan(counter)).unwrap();
+    }
+    assert_eq!(text.pending_len(), MAX_PENDING);
+    assert_eq!(
+      text.apply(orphan(MAX_PENDING as u64 + 1)),
+      Err(CrdtError::PendingOverflow)
+    );
+    assert_eq!(text.pending_len(), MAX_PENDING);
+    assert_eq!(text.text(), "ab");
+
+    // スナップショットから作り直した複製は、保留を持たない
+    let resynced = TextCrdt::from_snapshot(
+      ReplicaId(1),
+      text.snapshot(),
+    )
+    .unwrap();
+    assert_eq!(resynced.pending_len(), 0);
+    assert_eq!(resynced.text(), "ab");
+  }
+
+  #[test]
+  fn checkpoint_advances_the_epoch() {
+    let mut text = TextCrdt::new(ReplicaId(1), "abc");
+    let op = text.delete(1, 1).unwrap();
+    let snapshot = text.checkpoint().unwrap();
+    assert_eq!(snapshot.epoch, 1);
+    assert_eq!(text.text(), "ac");
+    assert_eq!(
+      text.apply(op),
+      Err(CrdtError::EpochMismatch { current: 1, op: 0 })
+    );
+
+    let mut last = TextCrdt::from_snapshot(
+      ReplicaId(2),
+      TextSnapshot { epoch: u64::MAX, ..snapshot },
+    )
+    .unwrap();
+    assert_eq!(
+      last.checkpoint(),
+      Err(CrdtError::CounterOverflow)
+    );
+    assert_eq!(last.epoch(), u64::MAX);
+    assert_eq!(last.text(), "ac");
+  }
+}
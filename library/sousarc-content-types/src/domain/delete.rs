@@ -0,0 +1,756 @@
+//! 階層を考慮したデータの削除
+//!
+//! ## Summary
+//! - `DeletePolicy`: 子を持つデータを削除する際の方針
+//! - `DeleteReport`: 削除・付け替えたデータの一覧
+//! - `Transaction::delete_user`・`delete_work`・`delete_element`: データを削除する
+//!
+//! 子はキー(`WorkKey::user_id`・`ElementKey::parent`)から探すので、
+//! 親の`children`に載っていない子も対象になる。
+//! 削除したデータは親の`children`からも取り除く。
+//! 削除した要素を参照するレコードは書き換えず、`DeleteReport`で報告する。
+
+use std::collections::HashSet;
+
+use crate::traits::prelude::*;
+
+use super::{
+  element::{
+    ElementId, ElementKey, ElementKind, ElementParent,
+  },
+  error::HierarchyError,
+  schema::StaleReference,
+  transaction::Transaction,
+  user::UserId,
+  work::{WorkId, WorkKey},
+};
+
+/// 子を持つデータを削除する際の方針
+///
+/// ## Summary
+/// `P`は子の付け替え先の型。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeletePolicy<P> {
+  /// 子孫もまとめて削除する
+  Cascade,
+
+  /// 子がある場合は削除しない
+  Restrict,
+
+  /// 直下の子を`P`の直下へ付け替えてから削除する
+  Reparent(P),
+}
+
+/// 削除の結果
+///
+/// ## Member
+/// - `users`・`works`・`elements`: 削除したデータのID
+/// - `reparented_works`・`reparented_elements`: 付け替えたデータのID
+/// - `stale_references`: 削除した要素を参照する、残った要素のレコードの属性
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeleteReport {
+  pub users: Vec<UserId>,
+  pub works: Vec<WorkId>,
+  pub elements: Vec<ElementId>,
+  pub reparented_works: Vec<WorkId>,
+  pub reparented_elements: Vec<ElementId>,
+  pub stale_references: Vec<StaleReference>,
+}
+
+impl DeleteReport {
+  /// 削除したデータの総数
+  pub fn removed(&self) -> usize {
+    self.users.len()
+      + self.works.len()
+      + self.elements.len()
+  }
+}
+
+impl Transaction<'_> {
+  /// 削除した要素を参照するレコードの属性を列挙する
+  fn stale_references(
+    &self,
+    removed: &[ElementId],
+  ) -> Vec<StaleReference> {
+    if removed.is_empty() {
+      return Vec::new();
+    }
+    let removed: HashSet<_> = removed.iter().collect();
+    let mut stale = Vec::new();
+    for element in self.elements.storage().iter() {
+      let ElementKind::Custom(record) = &element.body.kind
+      else {
+        continue;
+      };
+      for (field, target) in record.references() {
+        if removed.contains(&target) {
+          stale.push(StaleReference {
+            element: element.id(),
+            field,
+            target,
+          });
+        }
+      }
+    }
+    stale
+  }
+
+  /// ユーザの作品のIDを列挙する
+  fn works_of(&self, user_id: UserId) -> Vec<WorkId> {
+    self
+      .works
+      .storage()
+      .prefix(&user_id)
+      .map(|w| w.id())
+      .collect()
+  }
+
+  /// 要素の親の直下の要素のIDを列挙する
+  fn elements_under(
+    &self,
+    parent: ElementParent,
+  ) -> Vec<ElementId> {
+    self
+      .elements
+      .storage()
+      .prefix(&parent)
+      .map(|e| e.id())
+      .collect()
+  }
+
+  /// 親の直下の要素と、その子孫のIDを列挙する
+  pub(super) fn subtree_under(
+    &self,
+    parent: ElementParent,
+  ) -> Vec<ElementId> {
+    let mut visited = HashSet::new();
+    let mut stack = self.elements_under(parent);
+    let mut subtree = Vec::new();
+    while let Some(id) = stack.pop() {
+      if visited.insert(id) {
+        subtree.push(id);
+        stack.extend(
+          self.elements_under(ElementParent::Nest(id)),
+        );
+      }
+    }
+    subtree
+  }
+
+  /// 要素を親の`children`から取り除く
+  pub(super) fn unlink_element(
+    &mut self,
+    id: ElementId,
+    parent: ElementParent,
+  ) -> Result<(), HierarchyError> {
+    match parent {
+      ElementParent::Root(work_id) => {
+        if self.works.get(work_id).is_some() {
+          self.works.modify(work_id, |b| {
+            b.children.retain(|c| *c != id)
+          })?;
+        }
+      }
+      ElementParent::Nest(parent_id) => {
+        if self.elements.get(parent_id).is_some() {
+          self.elements.modify(parent_id, |b| {
+            if let Some(children) = &mut b.children {
+              children.retain(|c| *c != id);
+            }
+          })?;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// 要素を親の`children`の末尾に登録する
+  pub(super) fn link_element(
+    &mut self,
+    id: ElementId,
+    parent: ElementParent,
+  ) -> Result<(), HierarchyError> {
+    match parent {
+      ElementParent::Root(work_id) => {
+        self
+          .works
+          .modify(work_id, |b| b.children.push(id))?;
+      }
+      ElementParent::Nest(parent_id) => {
+        self.elements.modify(parent_id, |b| {
+          b.children.get_or_insert_default().push(id)
+        })?;
+      }
+    }
+    Ok(())
+  }
+
+  /// 要素の付け替え先を検査する
+  ///
+  /// ## Error
+  /// - `HierarchyError::WorkNotFound`・`ElementNotFound`: 付け替え先が存在しない
+  /// - `HierarchyError::NotContainer`: 付け替え先の要素が子を持てない
+  /// - `HierarchyError::CyclicParent`: 付け替え先が`id`自身またはその子孫である
+  pub(super) fn check_element_parent(
+    &self,
+    id: Option<ElementId>,
+    parent: ElementParent,
+  ) -> Result<(), HierarchyError> {
+    let mut current = match parent {
+      ElementParent::Root(work_id) => {
+        return match self.works.get(work_id) {
+          Some(_) => Ok(()),
+          None => {
+            Err(HierarchyError::WorkNotFound(work_id))
+          }
+        };
+      }
+      ElementParent::Nest(parent_id) => {
+        let element = self.elements.get(parent_id).ok_or(
+          HierarchyError::ElementNotFound(parent_id),
+        )?;
+        if element.body.children.is_none() {
+          return Err(HierarchyError::NotContainer(
+            parent_id,
+          ));
+        }
+        parent_id
+      }
+    };
+    // 付け替え先から祖先を辿り、`id`に行き着く場合は循環する
+    let Some(id) = id else { return Ok(()) };
+    let mut visited = HashSet::new();
+    loop {
+      if current == id || !visited.insert(current) {
+        return Err(HierarchyError::CyclicParent(id));
+      }
+      match self
+        .elements
+        .get(current)
+        .map(|e| e.key().parent)
+      {
+        Some(ElementParent::Nest(next)) => current = next,
+        _ => return Ok(()),
+      }
+    }
+  }
+
+  /// 要素のキーの親を差し替え、新しい親の`children`に登録する
+  ///
+  /// ## Summary
+  /// 要素とその子孫へのパスによるリンクは、新しいパスに書き換える。
+  pub(super) fn reparent_element(
+    &mut self,
+    id: ElementId,
+    parent: ElementParent,
+  ) -> Result<(), HierarchyError> {
+    let key = self
+      .elements
+      .get(id)
+      .ok_or(HierarchyError::ElementNotFound(id))?
+      .key()
+      .clone();
+    let old_path = self.element_path(id);
+    self
+      .elements
+      .rekey(id, ElementKey { parent, name: key.name })?;
+    if let Some(old) = old_path
+      && let Some(new) = self.element_path(id)
+    {
+      self.relink(&old, &new)?;
+    }
+    self.link_element(id, parent)
+  }
+
+  /// 要素を削除する
+  ///
+  /// ## Summary
+  /// `DeletePolicy::Reparent`の場合、直下の子を指定した親の`children`の末尾へ移す。
+  /// 失敗した場合は、この操作による変更を全て取り消す。
+  /// 削除した要素を参照するレコードは`stale_references`で報告する。
+  ///
+  /// ## Error
+  /// - `HierarchyError::ElementNotFound`: 要素が存在しない
+  /// - `HierarchyError::ElementHasChildren`: `Restrict`で、子が存在する
+  /// - `HierarchyError::CyclicParent`: 付け替え先が削除する要素自身またはその子孫である
+  /// - `HierarchyError::Schema`: 別の作品へ付け替える子・子孫のレコードが、付け替え先のスキーマに従わない
+  /// - `HierarchyError::Element`: 付け替え先に同名の要素が存在する 等
+  pub fn delete_element(
+    &mut self,
+    id: ElementId,
+    policy: DeletePolicy<ElementParent>,
+  ) -> Result<DeleteReport, HierarchyError> {
+    self.atomic(|tx| {
+      let parent = tx
+        .elements
+        .get(id)
+        .ok_or(HierarchyError::ElementNotFound(id))?
+        .key()
+        .parent;
+      let mut report = DeleteReport::default();
+      let children =
+        tx.elements_under(ElementParent::Nest(id));
+      match policy {
+        DeletePolicy::Restrict if !children.is_empty() => {
+          return Err(HierarchyError::ElementHasChildren(
+            id,
+          ));
+        }
+        DeletePolicy::Restrict => {}
+        DeletePolicy::Cascade => {
+          for child in
+            tx.subtree_under(ElementParent::Nest(id))
+          {
+            tx.elements.remove(child)?;
+            report.elements.push(child);
+          }
+        }
+        DeletePolicy::Reparent(new_parent) => {
+          tx.check_element_parent(Some(id), new_parent)?;
+          for child in children {
+            tx.check_moved_records(child, new_parent)?;
+            tx.reparent_element(child, new_parent)?;
+            report.reparented_elements.push(child);
+          }
+        }
+      }
+      tx.elements.remove(id)?;
+      tx.unlink_element(id, parent)?;
+      report.elements.push(id);
+      report.stale_references =
+        tx.stale_references(&report.elements);
+      Ok(report)
+    })
+  }
+
+  /// 作品を削除する
+  ///
+  /// ## Summary
+  /// `DeletePolicy::Reparent`の場合、直下の要素を指定した作品へ移す。
+  /// 失敗した場合は、この操作による変更を全て取り消す。
+  /// 削除した要素を参照するレコードは`stale_references`で報告する。
+  ///
+  /// ## Error
+  /// - `HierarchyError::WorkNotFound`: 作品または付け替え先が存在しない
+  /// - `HierarchyError::WorkHasElements`: `Restrict`で、要素が存在する
+  /// - `HierarchyError::WorkReparentToSelf`: 付け替え先が削除する作品自身である
+  /// - `HierarchyError::Schema`: 付け替える要素・子孫のレコードが、付け替え先のスキーマに従わない
+  /// - `HierarchyError::Element`: 付け替え先に同名の要素が存在する 等
+  pub fn delete_work(
+    &mut self,
+    id: WorkId,
+    policy: DeletePolicy<WorkId>,
+  ) -> Result<DeleteReport, HierarchyError> {
+    self.atomic(|tx| {
+      let user_id = tx
+        .works
+        .get(id)
+        .ok_or(HierarchyError::WorkNotFound(id))?
+        .key()
+        .user_id();
+      let mut report = DeleteReport::default();
+      let children =
+        tx.elements_under(ElementParent::Root(id));
+      match policy {
+        DeletePolicy::Restrict if !children.is_empty() => {
+          return Err(HierarchyError::WorkHasElements(id));
+        }
+        DeletePolicy::Restrict => {}
+        DeletePolicy::Cascade => {
+          for child in
+            tx.subtree_under(ElementParent::Root(id))
+          {
+            tx.elements.remove(child)?;
+            report.elements.push(child);
+          }
+        }
+        DeletePolicy::Reparent(target) => {
+          if target == id {
+            return Err(
+              HierarchyError::WorkReparentToSelf(id),
+            );
+          }
+          let new_parent = ElementParent::Root(target);
+          tx.check_element_parent(None, new_parent)?;
+          for child in children {
+            tx.check_moved_records(child, new_parent)?;
+            tx.reparent_element(child, new_parent)?;
+            report.reparented_elements.push(child);
+          }
+        }
+      }
+      tx.works.remove(id)?;
+      if tx.users.get(user_id).is_some() {
+        tx.users.modify(user_id, |b| {
+          b.children.retain(|c| *c != id)
+        })?;
+      }
+      report.works.push(id);
+      report.stale_references =
+        tx.stale_references(&report.elements);
+      Ok(report)
+    })
+  }
+
+  /// ユーザを削除する
+  ///
+  /// ## Summary
+  /// `DeletePolicy::Cascade`の場合、作品とその要素もまとめて削除する。
+  /// `DeletePolicy::Reparent`の場合、作品を指定したユーザへ移す。
+  /// 失敗した場合は、この操作による変更を全て取り消す。
+  /// 削除した要素を参照するレコードは`stale_references`で報告する。
+  ///
+  /// ## Error
+  /// - `HierarchyError::UserNotFound`: ユーザまたは付け替え先が存在しない
+  /// - `HierarchyError::UserHasWorks`: `Restrict`で、作品が存在する
+  /// - `HierarchyError::UserReparentToSelf`: 付け替え先が削除するユーザ自身である
+  /// - `HierarchyError::Work`: 付け替え先に同名の作品が存在する 等
+  pub fn delete_user(
+    &mut self,
+    id: UserId,
+    policy: DeletePolicy<UserId>,
+  ) -> Result<DeleteReport, HierarchyError> {
+    self.atomic(|tx| {
+      if tx.users.get(id).is_none() {
+        return Err(HierarchyError::UserNotFound(id));
+      }
+      let mut report = DeleteReport::default();
+      let works = tx.works_of(id);
+      match policy {
+        DeletePolicy::Restrict if !works.is_empty() => {
+          return Err(HierarchyError::UserHasWorks(id));
+        }
+        DeletePolicy::Restrict => {}
+        DeletePolicy::Cascade => {
+          for work in works {
+            let removed =
+              tx.delete_work(work, DeletePolicy::Cascade)?;
+            report.works.extend(removed.works);
+            report.elements.extend(removed.elements);
+          }
+        }
+        DeletePolicy::Reparent(target) => {
+          if target == id {
+            return Err(
+              HierarchyError::UserReparentToSelf(id),
+            );
+          }
+          if tx.users.get(target).is_none() {
+            return Err(HierarchyError::UserNotFound(
+              target,
+            ));
+          }
+          for work in works {
+            let Some(key) =
+              tx.works.get(work).map(|w| w.key())
+            else {
+              continue;
+            };
+            let key =
+              WorkKey::from((target, key.work_name()));
+            let old_path = tx.work_path(work);
+            tx.works.rekey(work, key)?;
+            if let Some(old) = old_path
+              && let Some(new) = tx.work_path(work)
+            {
+              tx.relink(&old, &new)?;
+            }
+            tx.users
+              .modify(target, |b| b.children.push(work))?;
+            report.reparented_works.push(work);
+          }
+        }
+      }
+      tx.users.remove(id)?;
+      report.users.push(id);
+      report.stale_references =
+        tx.stale_references(&report.elements);
+      Ok(report)
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::DeletePolicy;
+  use crate::{
+    domain::{
+      element::{ElementId, ElementParent},
+      error::HierarchyError,
+      user::{UserDataBody, UserId},
+      work::WorkId,
+    },
+    fixture::{
+      Store, container_body, element_body, work_body,
+    },
+    traits::prelude::*,
+  };
+
+  /// `alice`の作品`novel`(`a`→`a1`→`a11`・`b`)と空の作品`other`、
+  /// 作品を持たない`bob`
+  struct Tree {
+    alice: UserId,
+    bob: UserId,
+    novel: WorkId,
+    other: WorkId,
+    a: ElementId,
+    a1: ElementId,
+    a11: ElementId,
+    b: ElementId,
+  }
+
+  fn tree(store: &mut Store) -> Tree {
+    store
+      .run(|tx| {
+        let alice =
+          tx.spawn_user("alice", UserDataBody::default())?;
+        let bob =
+          tx.spawn_user("bob", UserDataBody::default())?;
+        let novel = tx.spawn_work(
+          alice,
+          "novel",
+          work_body("novel"),
+        )?;
+        let other = tx.spawn_work(
+          alice,
+          "other",
+          work_body("other"),
+        )?;
+        let a = tx.spawn_element(
+          ElementParent::Root(novel),
+          "a",
+          container_body(),
+        )?;
+        let a1 = tx.spawn_element(
+          ElementParent::Nest(a),
+          "a1",
+          container_body(),
+        )?;
+        let a11 = tx.spawn_element(
+          ElementParent::Nest(a1),
+          "a11",
+          element_body("text"),
+        )?;
+        let b = tx.spawn_element(
+          ElementParent::Root(novel),
+          "b",
+          element_body("text"),
+        )?;
+        Ok(Tree { alice, bob, novel, other, a, a1, a11, b })
+      })
+      .unwrap()
+  }
+
+  #[test]
+  fn delete_element_follows_the_policy() {
+    let mut store = Store::default();
+    let t = tree(&mut store);
+
+    let refused = |store: &mut Store, policy| {
+      store
+        .run(|tx| tx.delete_element(t.a, policy))
+        .unwrap_err()
+    };
+    assert!(matches!(
+      refused(&mut store, DeletePolicy::Restrict),
+      HierarchyError::ElementHasChildren(id) if id == t.a
+    ));
+    for target in [t.a, t.a1] {
+      assert!(matches!(
+        refused(
+          &mut store,
+          DeletePolicy::Reparent(ElementParent::Nest(target))
+        ),
+        HierarchyError::CyclicParent(id) if id == t.a
+      ));
+    }
+    assert!(matches!(
+      refused(
+        &mut store,
+        DeletePolicy::Reparent(ElementParent::Nest(t.b))
+      ),
+      HierarchyError::NotContainer(id) if id == t.b
+    ));
+    assert_eq!(store.elements.len(), 4);
+
+    // 直下の子だけを付け替え先の末尾へ移す
+    let report = store
+      .run(|tx| {
+        tx.delete_element(
+          t.a,
+          DeletePolicy::Reparent(ElementParent::Root(
+            t.novel,
+          )),
+        )
+      })
+      .unwrap();
+    assert_eq!(report.elements, [t.a]);
+    assert_eq!(report.reparented_elements, [t.a1]);
+    let novel = store.works.get(t.novel).unwrap();
+    assert_eq!(novel.body.children, [t.b, t.a1]);
+    assert_eq!(
+      store.elements.key(t.a1).unwrap().parent,
+      ElementParent::Root(t.novel)
+    );
+
+    let report = store
+      .run(|tx| {
+        tx.delete_element(t.a1, DeletePolicy::Cascade)
+      })
+      .unwrap();
+    assert_eq!(report.removed(), 2);
+    assert!(store.elements.get(t.a11).is_none());
+    let novel = store.works.get(t.novel).unwrap();
+    assert_eq!(novel.body.children, [t.b]);
+
+    // 子を持たない要素は`Restrict`でも削除できる
+    store
+      .run(|tx| {
+        tx.delete_element(t.b, DeletePolicy::Restrict)
+      })
+      .unwrap();
+    assert!(store.elements.is_empty());
+    assert!(
+      store
+        .works
+        .get(t.novel)
+        .unwrap()
+        .body
+        .children
+        .is_empty()
+    );
+  }
+
+  #[test]
+  fn delete_work_follows_the_policy() {
+    let mut store = Store::default();
+    let t = tree(&mut store);
+
+    let result = store.run(|tx| {
+      tx.delete_work(t.novel, DeletePolicy::Restrict)
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::WorkHasElements(id)) if id == t.novel
+    ));
+    let result = store.run(|tx| {
+      tx.delete_work(
+        t.novel,
+        DeletePolicy::Reparent(t.novel),
+      )
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::WorkReparentToSelf(id)) if id == t.novel
+    ));
+    let missing = WorkId::new();
+    let result = store.run(|tx| {
+      tx.delete_work(
+        t.novel,
+        DeletePolicy::Reparent(missing),
+      )
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::WorkNotFound(id)) if id == missing
+    ));
+    assert_eq!(store.works.len(), 2);
+
+    let report = store
+      .run(|tx| {
+        tx.delete_work(
+          t.novel,
+          DeletePolicy::Reparent(t.other),
+        )
+      })
+      .unwrap();
+    assert_eq!(report.works, [t.novel]);
+    assert_eq!(report.reparented_elements, [t.a, t.b]);
+    let other = store.works.get(t.other).unwrap();
+    assert_eq!(other.body.children, [t.a, t.b]);
+    assert_eq!(
+      store.elements.key(t.b).unwrap().parent,
+      ElementParent::Root(t.other)
+    );
+    let alice = store.users.get(t.alice).unwrap();
+    assert_eq!(
+      alice.children().collect::<Vec<_>>(),
+      [t.other]
+    );
+
+    let report = store
+      .run(|tx| {
+        tx.delete_work(t.other, DeletePolicy::Cascade)
+      })
+      .unwrap();
+    assert_eq!(report.removed(), 5);
+    assert!(store.elements.is_empty());
+    assert!(store.works.is_empty());
+  }
+
+  #[test]
+  fn delete_user_follows_the_policy() {
+    let mut store = Store::default();
+    let t = tree(&mut store);
+
+    let result = store.run(|tx| {
+      tx.delete_user(t.alice, DeletePolicy::Restrict)
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::UserHasWorks(id)) if id == t.alice
+    ));
+    let result = store.run(|tx| {
+      tx.delete_user(
+        t.alice,
+        DeletePolicy::Reparent(t.alice),
+      )
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::UserReparentToSelf(id)) if id == t.alice
+    ));
+    let missing = UserId::new();
+    let result = store.run(|tx| {
+      tx.delete_user(
+        t.alice,
+        DeletePolicy::Reparent(missing),
+      )
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::UserNotFound(id)) if id == missing
+    ));
+    assert_eq!(store.users.len(), 2);
+
+    let report = store
+      .run(|tx| {
+        tx.delete_user(
+          t.alice,
+          DeletePolicy::Reparent(t.bob),
+        )
+      })
+      .unwrap();
+    assert_eq!(report.users, [t.alice]);
+    assert_eq!(report.reparented_works, [t.novel, t.other]);
+    let bob = store.users.get(t.bob).unwrap();
+    assert_eq!(
+      bob.children().collect::<Vec<_>>(),
+      [t.novel, t.other]
+    );
+    assert_eq!(
+      store.works.key(t.novel).unwrap().user_id(),
+      t.bob
+    );
+
+    let report = store
+      .run(|tx| {
+        tx.delete_user(t.bob, DeletePolicy::Cascade)
+      })
+      .unwrap();
+    assert_eq!(report.removed(), 7);
+    assert!(store.users.is_empty());
+    assert!(store.works.is_empty());
+    assert!(store.elements.is_empty());
+  }
+}
@@ -23,6 +23,28 @@ impl Display for ElementId {
   }
 }
 
+impl std::str::FromStr for ElementId {
+  type Err = uuid::Error;
+
+  /// UUIDの文字列からIDを読み取る
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Uuid::parse_str(s).map(Self)
+  }
+}
+
 impl SousARCId for ElementId {
   type Bound = ElementData;
 }
+
+impl ElementId {
+  /// UUIDv7で新しいIDを生成する
+  pub fn new() -> Self {
+    Self(Uuid::now_v7())
+  }
+}
+
+impl Default for ElementId {
+  fn default() -> Self {
+    Self::new()
+  }
+}
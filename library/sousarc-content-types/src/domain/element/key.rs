@@ -45,6 +45,16 @@ pub struct ElementKey {
   pub name: String,
 }
 
+impl ElementKey {
+  /// 要素名だけを差し替えたキーを作成する
+  ///
+  /// ## Summary
+  /// `SousARCStorageMut::rekey`と組み合わせて要素名の変更に使う。
+  pub fn renamed(&self, name: impl ToString) -> Self {
+    Self { parent: self.parent, name: name.to_string() }
+  }
+}
+
 impl Display for ElementKey {
   fn fmt(
     &self,
@@ -75,3 +85,39 @@ impl<T: ToString> From<(ElementId, T)> for ElementKey {
 impl SousARCKey for ElementKey {
   type Bound = ElementData;
 }
+
+/// 同じ親を持つ要素は`name`の昇順で連続して並ぶ
+impl SousARCKeyPrefix<ElementParent> for ElementKey {
+  fn prefix_lower(prefix: &ElementParent) -> Self {
+    Self { parent: *prefix, name: String::new() }
+  }
+
+  fn has_prefix(&self, prefix: &ElementParent) -> bool {
+    &self.parent == prefix
+  }
+}
+
+impl SousARCKeyHasParent<WorkData, ElementData, WorkId>
+  for ElementKey
+{
+  /// 親が`ElementParent::Root`の場合のみ`WorkId`を返す
+  fn parent_id(&self) -> Option<WorkId> {
+    match self.parent {
+      ElementParent::Root(id) => Some(id),
+      ElementParent::Nest(_) => None,
+    }
+  }
+}
+
+impl
+  SousARCKeyHasParent<ElementData, ElementData, ElementId>
+  for ElementKey
+{
+  /// 親が`ElementParent::Nest`の場合のみ`ElementId`を返す
+  fn parent_id(&self) -> Option<ElementId> {
+    match self.parent {
+      ElementParent::Root(_) => None,
+      ElementParent::Nest(id) => Some(id),
+    }
+  }
+}
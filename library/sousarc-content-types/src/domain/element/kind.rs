@@ -0,0 +1,554 @@
+//! 要素の種類と属性
+//!
+//! ## Summary
+//! - `ElementKind`・`ElementKindTag`: 要素の種類と、種類ごとの属性
+//! - `KindField`・`FieldValue`: 索引する属性(種類・属性名・値)
+//! - `ElementKindIndexes`: 種類・属性で要素を検索するセカンダリインデックス
+//!
+//! 属性の値は`FieldValue`に揃えて索引するので、
+//! 同じ種類・属性名の値は範囲で検索できる。
+
+use std::collections::BTreeMap;
+
+use crate::storage::{
+  IndexHandle, IndexKind, StandardStorage, StorageError,
+};
+
+use super::*;
+use crate::domain::schema::{
+  CustomDate, CustomRecord, CustomValue,
+};
+
+/// 要素の種類(`ElementKind`の判別子)
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+pub enum ElementKindTag {
+  Generic,
+  Character,
+  Place,
+  Organization,
+  Event,
+  Item,
+  Custom,
+}
+
+/// 人物の属性
+///
+/// ## Member
+/// - `aliases`: 別名
+/// - `born`・`died`: 生没(作中の暦で書く為、文字列)
+/// - `home`: 出身・拠点の場所の要素
+/// - `affiliations`: 所属する組織の要素
+#[derive(
+  Debug,
+  Clone,
+  Default,
+  PartialEq,
+  Eq,
+  Serialize,
+  Deserialize,
+)]
+#[serde(default)]
+pub struct CharacterAttrs {
+  pub aliases: Vec<String>,
+  pub born: Option<String>,
+  pub died: Option<String>,
+  pub home: Option<ElementId>,
+  pub affiliations: Vec<ElementId>,
+}
+
+/// 場所(国・地域・都市等)の属性
+///
+/// ## Member
+/// - `aliases`: 別名
+/// - `located_in`: 上位の場所の要素
+/// - `population`: 人口
+#[derive(
+  Debug,
+  Clone,
+  Default,
+  PartialEq,
+  Eq,
+  Serialize,
+  Deserialize,
+)]
+#[serde(default)]
+pub struct PlaceAttrs {
+  pub aliases: Vec<String>,
+  pub located_in: Option<ElementId>,
+  pub population: Option<u64>,
+}
+
+/// 組織(国家・団体・文化圏等)の属性
+///
+/// ## Member
+/// - `aliases`: 別名
+/// - `founded`: 成立(作中の暦で書く為、文字列)
+/// - `headquarters`: 本拠地の場所の要素
+/// - `members`: 構成員の要素
+#[derive(
+  Debug,
+  Clone,
+  Default,
+  PartialEq,
+  Eq,
+  Serialize,
+  Deserialize,
+)]
+#[serde(default)]
+pub struct OrganizationAttrs {
+  pub aliases: Vec<String>,
+  pub founded: Option<String>,
+  pub headquarters: Option<ElementId>,
+  pub members: Vec<ElementId>,
+}
+
+/// 出来事(歴史)の属性
+///
+/// ## Member
+/// - `start`・`end`: 期間(作中の暦で書く為、文字列)
+/// - `location`: 起きた場所の要素
+/// - `participants`: 関わった人物・組織の要素
+#[derive(
+  Debug,
+  Clone,
+  Default,
+  PartialEq,
+  Eq,
+  Serialize,
+  Deserialize,
+)]
+#[serde(default)]
+pub struct EventAttrs {
+  pub start: Option<String>,
+  pub end: Option<String>,
+  pub location: Option<ElementId>,
+  pub participants: Vec<ElementId>,
+}
+
+/// 物品の属性
+///
+/// ## Member
+/// - `aliases`: 別名
+/// - `owner`: 所有者の要素
+/// - `location`: 所在の場所の要素
+#[derive(
+  Debug,
+  Clone,
+  Default,
+  PartialEq,
+  Eq,
+  Serialize,
+  Deserialize,
+)]
+#[serde(default)]
+pub struct ItemAttrs {
+  pub aliases: Vec<String>,
+  pub owner: Option<ElementId>,
+  pub location: Option<ElementId>,
+}
+
+/// 要素の種類と、種類ごとの属性
+///
+/// ## Summary
+/// 種類を持たない要素は`Generic`として扱う。
+/// `Generic`は任意の属性を名前→値で持つ。
+/// `Custom`は作品が定義したスキーマに従う属性を持つ(`domain::schema`)。
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum ElementKind {
+  Generic(BTreeMap<String, String>),
+  Character(CharacterAttrs),
+  Place(PlaceAttrs),
+  Organization(OrganizationAttrs),
+  Event(EventAttrs),
+  Item(ItemAttrs),
+  Custom(CustomRecord),
+}
+
+impl Default for ElementKind {
+  fn default() -> Self {
+    Self::Generic(BTreeMap::new())
+  }
+}
+
+/// 索引する属性の値
+///
+/// ## Summary
+/// 数値は全て`Number`(`i64`)で表す(`CustomValue::Number`と同じ)。
+#[derive(
+  Debug,
+  Clone,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+pub enum FieldValue {
+  Text(String),
+  Number(i64),
+  Element(ElementId),
+  Bool(bool),
+  Date(CustomDate),
+}
+
+/// 索引する属性(種類・属性名・値)
+///
+/// ## Summary
+/// `ElementKindIndexes::field`の値。
+/// 同じ種類・属性名の値は連続するので、範囲で検索できる。
+#[derive(
+  Debug,
+  Clone,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+pub struct KindField {
+  pub kind: ElementKindTag,
+  pub name: String,
+  pub value: FieldValue,
+}
+
+impl KindField {
+  pub fn new(
+    kind: ElementKindTag,
+    name: impl ToString,
+    value: FieldValue,
+  ) -> Self {
+    Self { kind, name: name.to_string(), value }
+  }
+}
+
+impl ElementKind {
+  /// 種類の判別子
+  pub fn tag(&self) -> ElementKindTag {
+    match self {
+      Self::Generic(_) => ElementKindTag::Generic,
+      Self::Character(_) => ElementKindTag::Character,
+      Self::Place(_) => ElementKindTag::Place,
+      Self::Organization(_) => ElementKindTag::Organization,
+      Self::Event(_) => ElementKindTag::Event,
+      Self::Item(_) => ElementKindTag::Item,
+      Self::Custom(_) => ElementKindTag::Custom,
+    }
+  }
+
+  /// 索引する属性を列挙する
+  ///
+  /// ## Summary
+  /// 値を持たない属性(`None`・空の`Vec`)は含めない。
+  /// `Vec`の属性は、要素ごとに1つの`KindField`にする。
+  /// `Custom`の属性名は`スキーマ名.属性名`にする。
+  pub fn fields(&self) -> Vec<KindField> {
+    use FieldValue::{Element, Number, Text};
+
+    fn texts<'a>(
+      values: impl IntoIterator<Item = &'a String>,
+    ) -> Vec<FieldValue> {
+      values.into_iter().cloned().map(Text).collect()
+    }
+    fn elements<'a>(
+      values: impl IntoIterator<Item = &'a ElementId>,
+    ) -> Vec<FieldValue> {
+      values.into_iter().copied().map(Element).collect()
+    }
+    fn custom(value: &CustomValue) -> Vec<FieldValue> {
+      match value {
+        CustomValue::Text(v) | CustomValue::Enum(v) => {
+          vec![Text(v.clone())]
+        }
+        CustomValue::Number(v) => vec![Number(*v)],
+        CustomValue::Bool(v) => vec![FieldValue::Bool(*v)],
+        CustomValue::Date(v) => vec![FieldValue::Date(*v)],
+        CustomValue::Reference(v) => vec![Element(*v)],
+        CustomValue::List(values) => {
+          values.iter().flat_map(custom).collect()
+        }
+      }
+    }
+
+    let kind = self.tag();
+    let mut fields = Vec::new();
+    let mut push = |name: &str, values: Vec<FieldValue>| {
+      fields.extend(
+        values
+          .into_iter()
+          .map(|v| KindField::new(kind, name, v)),
+      )
+    };
+    match self {
+      Self::Generic(attributes) => {
+        for (name, value) in attributes {
+          push(name, vec![Text(value.clone())]);
+        }
+      }
+      Self::Character(attrs) => {
+        push("aliases", texts(&attrs.aliases));
+        push("born", texts(&attrs.born));
+        push("died", texts(&attrs.died));
+        push("home", elements(&attrs.home));
+        push("affiliations", elements(&attrs.affiliations));
+      }
+      Self::Place(attrs) => {
+        push("aliases", texts(&attrs.aliases));
+        push("located_in", elements(&attrs.located_in));
+        push(
+          "population",
+          // `i64`を超える人口は上限の値として索引する
+          attrs
+            .population
+            .map(|p| i64::try_from(p).unwrap_or(i64::MAX))
+            .into_iter()
+            .map(Number)
+            .collect(),
+        );
+      }
+      Self::Organization(attrs) => {
+        push("aliases", texts(&attrs.aliases));
+        push("founded", texts(&attrs.founded));
+        push("headquarters", elements(&attrs.headquarters));
+        push("members", elements(&attrs.members));
+      }
+      Self::Event(attrs) => {
+        push("start", texts(&attrs.start));
+        push("end", texts(&attrs.end));
+        push("location", elements(&attrs.location));
+        push("participants", elements(&attrs.participants));
+      }
+      Self::Item(attrs) => {
+        push("aliases", texts(&attrs.aliases));
+        push("owner", elements(&attrs.owner));
+        push("location", elements(&attrs.location));
+      }
+      Self::Custom(record) => {
+        for (name, value) in &record.fields {
+          let name = format!("{}.{name}", record.schema);
+          push(&name, custom(value));
+        }
+      }
+    }
+    fields
+  }
+}
+
+/// 種類・属性のセカンダリインデックス
+///
+/// ## Member
+/// - `kind`: `IndexHandle<ElementKindTag>`型
+///   - 種類→要素
+/// - `field`: `IndexHandle<KindField>`型
+///   - 種類・属性名・値→要素
+#[derive(Debug, Clone, Copy)]
+pub struct ElementKindIndexes {
+  pub kind: IndexHandle<ElementKindTag>,
+  pub field: IndexHandle<KindField>,
+}
+
+impl ElementKindIndexes {
+  /// 要素のストレージにインデックスを登録する
+  ///
+  /// ## Error
+  /// - `StorageError::IndexConflict`: 登録に失敗した
+  pub fn register(
+    storage: &mut StandardStorage<ElementData>,
+  ) -> Result<Self, StorageError<ElementData>> {
+    let kind = storage.add_index(
+      "element kind",
+      IndexKind::NonUnique,
+      |e: &ElementData| [e.body.kind.tag()],
+    )?;
+    let field = storage.add_index(
+      "element kind field",
+      IndexKind::NonUnique,
+      |e: &ElementData| e.body.kind.fields(),
+    )?;
+    Ok(Self { kind, field })
+  }
+
+  /// 種類が一致する要素を列挙する
+  pub fn find_kind<'a>(
+    &self,
+    storage: &'a StandardStorage<ElementData>,
+    kind: &'a ElementKindTag,
+  ) -> impl Iterator<Item = &'a ElementData> {
+    storage.find(self.kind, kind)
+  }
+
+  /// 属性が一致する要素を列挙する
+  pub fn find_field<'a>(
+    &self,
+    storage: &'a StandardStorage<ElementData>,
+    field: &'a KindField,
+  ) -> impl Iterator<Item = &'a ElementData> {
+    storage.find(self.field, field)
+  }
+
+  /// 種類・属性名が一致し、値が範囲に含まれる要素を、値の昇順で列挙する
+  ///
+  /// ## Summary
+  /// 範囲に含まれる値を複数持つ要素は、値ごとに列挙する。
+  /// 始端が終端より大きい範囲や、`(v, v)`の様に両端を除いた空の範囲は、
+  /// 何も列挙しない。
+  pub fn find_field_range<'a>(
+    &self,
+    storage: &'a StandardStorage<ElementData>,
+    kind: ElementKindTag,
+    name: &str,
+    range: impl std::ops::RangeBounds<FieldValue>,
+  ) -> impl Iterator<Item = &'a ElementData> {
+    use std::ops::Bound;
+
+    // 範囲の端が無い場合も、同じ種類・属性名の値のみに限る
+    // (`Text("")`は最小の値で、`name`の直後の属性名は`name`に`\0`を足したもの)
+    let field = |name: &str, v: &FieldValue| {
+      KindField::new(kind, name, v.clone())
+    };
+    let least = FieldValue::Text(String::new());
+    let start = match range.start_bound() {
+      Bound::Included(v) => Bound::Included(field(name, v)),
+      Bound::Excluded(v) => Bound::Excluded(field(name, v)),
+      Bound::Unbounded => {
+        Bound::Included(field(name, &least))
+      }
+    };
+    let end = match range.end_bound() {
+      Bound::Included(v) => Bound::Included(field(name, v)),
+      Bound::Excluded(v) => Bound::Excluded(field(name, v)),
+      Bound::Unbounded => {
+        Bound::Excluded(field(&format!("{name}\0"), &least))
+      }
+    };
+    // `BTreeMap::range`は空の範囲の一部でパニックするので、先に除く
+    let empty = match (&start, &end) {
+      (Bound::Excluded(s), Bound::Excluded(e)) => s >= e,
+      (
+        Bound::Included(s) | Bound::Excluded(s),
+        Bound::Included(e) | Bound::Excluded(e),
+      ) => s > e,
+      _ => false,
+    };
+    storage
+      .index(self.field)
+      .filter(|_| !empty)
+      .into_iter()
+      .flat_map(move |i| {
+        i.range((start.clone(), end.clone()))
+      })
+      .filter_map(|id| storage.get(id))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::ops::Bound;
+
+  use super::*;
+  use crate::domain::work::WorkId;
+
+  #[test]
+  fn custom_numbers_share_the_number_value() {
+    let kind = ElementKind::Custom(CustomRecord {
+      schema: "stats".to_string(),
+      fields: [(
+        "level".to_string(),
+        CustomValue::Number(-5),
+      )]
+      .into_iter()
+      .collect(),
+    });
+    assert_eq!(
+      kind.fields(),
+      [KindField::new(
+        ElementKindTag::Custom,
+        "stats.level",
+        FieldValue::Number(-5),
+      )]
+    );
+  }
+
+  #[test]
+  fn find_field_range_handles_empty_ranges() {
+    let mut storage = StandardStorage::new();
+    let indexes =
+      ElementKindIndexes::register(&mut storage).unwrap();
+    let work = WorkId::new();
+    for (name, population) in
+      [("a", 10), ("b", 20), ("c", 30)]
+    {
+      let body = ElementDataBody {
+        children: None,
+        display_name: name.to_string(),
+        content: String::new(),
+        kind: ElementKind::Place(PlaceAttrs {
+          population: Some(population),
+          ..Default::default()
+        }),
+      };
+      storage
+        .try_insert(ElementData::new(
+          ElementId::new(),
+          ElementKey {
+            parent: ElementParent::Root(work),
+            name: name.to_string(),
+          },
+          body,
+        ))
+        .unwrap();
+    }
+    let names = |range: (Bound<i64>, Bound<i64>)| {
+      let range = (
+        range.0.map(FieldValue::Number),
+        range.1.map(FieldValue::Number),
+      );
+      indexes
+        .find_field_range(
+          &storage,
+          ElementKindTag::Place,
+          "population",
+          range,
+        )
+        .map(|e| e.body.display_name.clone())
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+      names((Bound::Included(15), Bound::Unbounded)),
+      ["b", "c"]
+    );
+    assert_eq!(
+      names((Bound::Excluded(10), Bound::Included(20))),
+      ["b"]
+    );
+    assert!(
+      names((Bound::Included(30), Bound::Included(10)))
+        .is_empty()
+    );
+    assert!(
+      names((Bound::Excluded(20), Bound::Excluded(20)))
+        .is_empty()
+    );
+    assert!(
+      names((Bound::Included(20), Bound::Excluded(20)))
+        .is_empty()
+    );
+  }
+}
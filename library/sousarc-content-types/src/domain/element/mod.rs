@@ -4,34 +4,111 @@ use uuid::Uuid;
 
 use crate::traits::prelude::*;
 
-use super::work::WorkId;
+use super::{
+  history::History,
+  work::{WorkData, WorkId},
+};
 
 pub mod id;
 pub use id::*;
 pub mod key;
 pub use key::*;
+pub mod kind;
+pub use kind::*;
 
-#[derive(Debug)]
+/// 要素
+///
+/// ## Member
+/// - `body`: `ElementDataBody`型
+/// - `history`: `History`型
+///   - 表示名・本文の版(本体とは別に持つので、本体の変更・取り消しで複製されない)
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ElementData {
   id_key: IdKeySet<Self>,
 
   pub body: ElementDataBody,
+
+  #[serde(default)]
+  pub(in crate::domain) history: History,
 }
 
 impl SousARCData for ElementData {
   type Id = ElementId;
   type Key = ElementKey;
 
+  /// 版の記録と検証は`Transaction`の確定時に行う
+  const TRANSACTIONAL: bool = true;
+
   fn id_key_set(&self) -> &IdKeySet<Self> {
     &self.id_key
   }
+
+  fn id_key_set_mut(
+    &mut self,
+    _: IdKeyToken,
+  ) -> &mut IdKeySet<Self> {
+    &mut self.id_key
+  }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl SousARCDataHasBody for ElementData {
+  type Body = ElementDataBody;
+
+  fn body(&self) -> &ElementDataBody {
+    &self.body
+  }
+
+  fn body_mut(&mut self) -> &mut ElementDataBody {
+    &mut self.body
+  }
+
+  fn from_parts(
+    id_key: IdKeySet<Self>,
+    body: Self::Body,
+  ) -> Self {
+    Self { id_key, body, history: History::default() }
+  }
+
+  fn into_parts(self) -> (IdKeySet<Self>, Self::Body) {
+    (self.id_key, self.body)
+  }
+}
+
+impl SousARCDataHasChild<ElementData, ElementId, ElementKey>
+  for ElementData
+{
+  fn children(&self) -> impl Iterator<Item = ElementId> {
+    self.body.children.iter().flatten().copied()
+  }
+}
+
+impl ElementData {
+  pub fn new(
+    id: ElementId,
+    key: ElementKey,
+    body: ElementDataBody,
+  ) -> Self {
+    Self {
+      id_key: IdKeySet::new(id, key),
+      body,
+      history: History::default(),
+    }
+  }
+
+  /// 表示名・本文の変更履歴
+  pub fn history(&self) -> &History {
+    &self.history
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ElementDataBody {
   pub children: Option<Vec<ElementId>>,
 
   pub display_name: String,
 
   pub content: String,
+
+  #[serde(default)]
+  pub kind: ElementKind,
 }
@@ -0,0 +1,181 @@
+//! 階層(ユーザ→作品→要素)の操作のエラー型
+//!
+//! ## Summary
+//! - `HierarchyError`: 作成・削除・移動・並び替え・編集・スキーマ操作が失敗した理由
+
+use std::fmt::Display;
+
+use crate::storage::StorageError;
+
+use super::{
+  element::{ElementData, ElementId},
+  schema::SchemaError,
+  user::{UserData, UserId},
+  work::{WorkData, WorkId},
+};
+
+/// 階層の操作のエラー
+///
+/// ## Summary
+/// `Transaction`上の作成・削除・移動・並び替え・編集が失敗した理由を表す。
+/// いずれの場合も、その操作による変更は取り消される。
+#[derive(Debug)]
+pub enum HierarchyError {
+  /// ユーザが存在しない
+  UserNotFound(UserId),
+
+  /// 作品が存在しない
+  WorkNotFound(WorkId),
+
+  /// 要素が存在しない
+  ElementNotFound(ElementId),
+
+  /// 要素が子を持てない(`children`が`None`)
+  NotContainer(ElementId),
+
+  /// ユーザ名・作品名・要素名が空である
+  EmptyName,
+
+  /// ユーザが作品を持つ為、削除できない
+  UserHasWorks(UserId),
+
+  /// 作品が要素を持つ為、削除できない
+  WorkHasElements(WorkId),
+
+  /// 要素が子を持つ為、削除できない
+  ElementHasChildren(ElementId),
+
+  /// 削除するユーザ自身を、作品の付け替え先に指定した
+  UserReparentToSelf(UserId),
+
+  /// 削除する作品自身を、要素の付け替え先に指定した
+  WorkReparentToSelf(WorkId),
+
+  /// 移動先が、対象自身またはその子孫である
+  CyclicParent(ElementId),
+
+  /// 並び替えの基準の要素が、同じ親の`children`に載っていない
+  NotSibling(ElementId),
+
+  /// 要素の版が存在しない
+  RevisionNotFound(ElementId, usize),
+
+  /// 要素のレコードがスキーマに従わない、またはスキーマを変更できない
+  Schema(SchemaError),
+
+  /// ユーザのストレージ操作に失敗した
+  User(StorageError<UserData>),
+
+  /// 作品のストレージ操作に失敗した
+  Work(StorageError<WorkData>),
+
+  /// 要素のストレージ操作に失敗した
+  Element(StorageError<ElementData>),
+
+  /// トランザクションをジャーナルへ記録できなかった
+  Journal(std::io::Error),
+}
+
+impl Display for HierarchyError {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    match self {
+      Self::UserNotFound(id) => {
+        write!(f, "user not found: {id}")
+      }
+      Self::WorkNotFound(id) => {
+        write!(f, "work not found: {id}")
+      }
+      Self::ElementNotFound(id) => {
+        write!(f, "element not found: {id}")
+      }
+      Self::NotContainer(id) => {
+        write!(f, "element cannot have children: {id}")
+      }
+      Self::EmptyName => {
+        write!(f, "name must not be empty")
+      }
+      Self::UserHasWorks(id) => {
+        write!(f, "user still has works: {id}")
+      }
+      Self::WorkHasElements(id) => {
+        write!(f, "work still has elements: {id}")
+      }
+      Self::ElementHasChildren(id) => {
+        write!(f, "element still has children: {id}")
+      }
+      Self::UserReparentToSelf(id) => {
+        write!(
+          f,
+          "user cannot take over its own works: {id}"
+        )
+      }
+      Self::WorkReparentToSelf(id) => {
+        write!(
+          f,
+          "work cannot take over its own elements: {id}"
+        )
+      }
+      Self::CyclicParent(id) => {
+        write!(
+          f,
+          "element cannot be moved under itself: {id}"
+        )
+      }
+      Self::NotSibling(id) => {
+        write!(f, "element is not a sibling: {id}")
+      }
+      Self::RevisionNotFound(id, number) => {
+        write!(f, "revision {number} not found: {id}")
+      }
+      Self::Schema(e) => write!(f, "{e}"),
+      Self::User(e) => write!(f, "{e}"),
+      Self::Work(e) => write!(f, "{e}"),
+      Self::Element(e) => write!(f, "{e}"),
+      Self::Journal(e) => {
+        write!(f, "journal write failed: {e}")
+      }
+    }
+  }
+}
+
+impl std::error::Error for HierarchyError {
+  fn source(
+    &self,
+  ) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Self::User(e) => Some(e),
+      Self::Work(e) => Some(e),
+      Self::Element(e) => Some(e),
+      Self::Schema(e) => Some(e),
+      Self::Journal(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<StorageError<UserData>> for HierarchyError {
+  fn from(e: StorageError<UserData>) -> Self {
+    Self::User(e)
+  }
+}
+
+impl From<StorageError<WorkData>> for HierarchyError {
+  fn from(e: StorageError<WorkData>) -> Self {
+    Self::Work(e)
+  }
+}
+
+impl From<StorageError<ElementData>> for HierarchyError {
+  fn from(e: StorageError<ElementData>) -> Self {
+    Self::Element(e)
+  }
+}
+
+impl From<SchemaError> for HierarchyError {
+  fn from(e: SchemaError) -> Self {
+    Self::Schema(e)
+  }
+}
@@ -0,0 +1,880 @@
+//! 参照整合性の検査と修復
+//!
+//! ## Summary
+//! - `check`: 3つのストレージを走査し、親子関係の不整合を列挙する
+//! - `Transaction::repair`: 検査した上で不整合を修復する
+//! - `FsckIssue`・`FsckReport`: 不整合の種類と検査結果
+//! - `OrphanPolicy`: 親が存在しないデータの修復方法
+//!
+//! 親子関係は「子のキーが指す親」(`WorkKey::user_id`・`ElementKey::parent`)と
+//! 「親の`children`」の2か所で表されるので、キーを正として突き合わせる。
+//! レコードの参照(`CustomValue::Reference`)は、参照先が存在するかのみを検査する。
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+
+use crate::traits::prelude::*;
+
+use super::{
+  delete::DeletePolicy,
+  element::{
+    ElementData, ElementId, ElementKey, ElementKind,
+    ElementParent,
+  },
+  error::HierarchyError,
+  transaction::Transaction,
+  user::{UserData, UserId},
+  work::{WorkData, WorkId, WorkKey},
+};
+
+/// 検出した不整合
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckIssue {
+  /// 作品の所有者のユーザが存在しない
+  OrphanWork { work: WorkId, user: UserId },
+
+  /// 要素の親が存在しない
+  OrphanElement {
+    element: ElementId,
+    parent: ElementParent,
+  },
+
+  /// 要素の親を辿ると循環している(循環に含まれる要素)
+  ElementCycle { elements: Vec<ElementId> },
+
+  /// ユーザの`children`に、存在しない(または別のユーザの)作品がある
+  DanglingWork { user: UserId, work: WorkId },
+
+  /// 親の`children`に、存在しない(または別の親の)要素がある
+  DanglingElement {
+    parent: ElementParent,
+    element: ElementId,
+  },
+
+  /// 作品が所有者の`children`に載っていない
+  UnlistedWork { user: UserId, work: WorkId },
+
+  /// 要素が親の`children`に載っていない
+  UnlistedElement {
+    parent: ElementParent,
+    element: ElementId,
+  },
+
+  /// ユーザの`children`に同じ作品が複数ある
+  DuplicateWork { user: UserId, work: WorkId },
+
+  /// 親の`children`に同じ要素が複数ある
+  DuplicateElement {
+    parent: ElementParent,
+    element: ElementId,
+  },
+
+  /// レコードの属性が存在しない要素を参照している
+  StaleReference {
+    element: ElementId,
+    field: String,
+    target: ElementId,
+  },
+}
+
+impl Display for FsckIssue {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    match self {
+      Self::OrphanWork { work, user } => {
+        write!(
+          f,
+          "work {work} belongs to missing user {user}"
+        )
+      }
+      Self::OrphanElement { element, parent } => {
+        write!(
+          f,
+          "element {element} has missing parent {parent}"
+        )
+      }
+      Self::ElementCycle { elements } => {
+        write!(f, "element parent cycle:")?;
+        for id in elements {
+          write!(f, " {id}")?;
+        }
+        Ok(())
+      }
+      Self::DanglingWork { user, work } => {
+        write!(
+          f,
+          "user {user} lists foreign or missing work {work}"
+        )
+      }
+      Self::DanglingElement { parent, element } => write!(
+        f,
+        "{parent} lists foreign or missing element {element}"
+      ),
+      Self::UnlistedWork { user, work } => {
+        write!(
+          f,
+          "work {work} is not listed by user {user}"
+        )
+      }
+      Self::UnlistedElement { parent, element } => {
+        write!(
+          f,
+          "element {element} is not listed by {parent}"
+        )
+      }
+      Self::DuplicateWork { user, work } => {
+        write!(f, "user {user} lists work {work} twice")
+      }
+      Self::DuplicateElement { parent, element } => {
+        write!(f, "{parent} lists element {element} twice")
+      }
+      Self::StaleReference { element, field, target } => {
+        write!(
+          f,
+          "element {element} field `{field}` refers to missing element {target}"
+        )
+      }
+    }
+  }
+}
+
+/// 検査結果
+///
+/// ## Member
+/// - `issues`: `Vec<FsckIssue>`型
+///   - 検出した不整合(親子関係を壊すものから順)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsckReport {
+  pub issues: Vec<FsckIssue>,
+}
+
+impl FsckReport {
+  /// 不整合が無いか判定する
+  pub fn is_clean(&self) -> bool {
+    self.issues.is_empty()
+  }
+}
+
+/// 親が存在しない作品・要素と、循環している要素の修復方法
+///
+/// ## Summary
+/// データを失わないよう、削除は`Delete`を指定した場合に限る。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrphanPolicy {
+  /// 変更せず、報告のみ行う
+  Keep,
+
+  /// 作品を`user`へ、要素を`work`の直下へ付け替える(lost+found)
+  ///
+  /// 付け替え先に同名のデータがある場合は、名前の末尾に`~ID`を付ける。
+  /// 循環している要素は、循環に含まれる最初の要素を付け替えて循環を断つ。
+  /// レコードは付け替え先のスキーマで検証しない。
+  Reattach { user: UserId, work: WorkId },
+
+  /// 子孫ごと削除する
+  Delete,
+}
+
+/// `children`を走査し、重複・不正な子を検出する
+///
+/// ## Return
+/// 親→`children`に載っている子の集合
+fn scan_children<P, C>(
+  parent: P,
+  children: impl IntoIterator<Item = C>,
+  belongs: impl Fn(C) -> bool,
+  duplicate: impl Fn(P, C) -> FsckIssue,
+  dangling: impl Fn(P, C) -> FsckIssue,
+  issues: &mut Vec<FsckIssue>,
+) -> HashSet<C>
+where
+  P: Copy,
+  C: Copy + Eq + std::hash::Hash,
+{
+  let mut listed = HashSet::new();
+  for child in children {
+    if !listed.insert(child) {
+      issues.push(duplicate(parent, child));
+    } else if !belongs(child) {
+      issues.push(dangling(parent, child));
+    }
+  }
+  listed
+}
+
+/// 3つのストレージの参照整合性を検査する
+pub fn check(
+  users: &impl SousARCStorage<UserData>,
+  works: &impl SousARCStorage<WorkData>,
+  elements: &impl SousARCStorage<ElementData>,
+) -> FsckReport {
+  let mut structural = Vec::new();
+  let mut lists = Vec::new();
+
+  let element_parent =
+    |id: ElementId| elements.key(id).map(|k| k.parent);
+  let parent_exists = |parent: ElementParent| match parent {
+    ElementParent::Root(id) => works.get(id).is_some(),
+    ElementParent::Nest(id) => elements.get(id).is_some(),
+  };
+
+  // 親の`children`を走査する
+  let mut user_lists = HashMap::new();
+  for user in users.iter() {
+    let body = user.body.read();
+    let listed = scan_children(
+      user.id(),
+      body.children(),
+      |w| {
+        works
+          .key(w)
+          .is_some_and(|k| k.user_id() == user.id())
+      },
+      |user, work| FsckIssue::DuplicateWork { user, work },
+      |user, work| FsckIssue::DanglingWork { user, work },
+      &mut lists,
+    );
+    user_lists.insert(user.id(), listed);
+  }
+  let mut element_lists = HashMap::new();
+  let parents = works
+    .iter()
+    .map(|w| {
+      let children: Vec<_> = w.children().collect();
+      (ElementParent::Root(w.id()), children)
+    })
+    .chain(elements.iter().map(|e| {
+      let children: Vec<_> = e.children().collect();
+      (ElementParent::Nest(e.id()), children)
+    }));
+  for (parent, children) in parents {
+    let listed = scan_children(
+      parent,
+      children,
+      |e| element_parent(e) == Some(parent),
+      |parent, element| FsckIssue::DuplicateElement {
+        parent,
+        element,
+      },
+      |parent, element| FsckIssue::DanglingElement {
+        parent,
+        element,
+      },
+      &mut lists,
+    );
+    element_lists.insert(parent, listed);
+  }
+
+  // 子のキーが指す親を検査する
+  for work in works.iter() {
+    let (user, id) = (work.key().user_id(), work.id());
+    match user_lists.get(&user) {
+      None => structural
+        .push(FsckIssue::OrphanWork { work: id, user }),
+      Some(listed) if !listed.contains(&id) => {
+        lists
+          .push(FsckIssue::UnlistedWork { user, work: id });
+      }
+      Some(_) => {}
+    }
+  }
+  for element in elements.iter() {
+    let (parent, id) = (element.key().parent, element.id());
+    if !parent_exists(parent) {
+      structural.push(FsckIssue::OrphanElement {
+        element: id,
+        parent,
+      });
+    } else if element_lists
+      .get(&parent)
+      .is_none_or(|listed| !listed.contains(&id))
+    {
+      lists.push(FsckIssue::UnlistedElement {
+        parent,
+        element: id,
+      });
+    }
+  }
+
+  // 親を辿り、作品に行き着かない循環を検出する
+  let mut settled: HashSet<ElementId> = HashSet::new();
+  for element in elements.iter() {
+    let mut path = Vec::new();
+    let mut on_path = HashSet::new();
+    let mut current = element.id();
+    loop {
+      if settled.contains(&current) {
+        break;
+      }
+      if !on_path.insert(current) {
+        let start =
+          path.iter().position(|id| *id == current);
+        let cycle = path.split_off(start.unwrap_or(0));
+        structural.push(FsckIssue::ElementCycle {
+          elements: cycle,
+        });
+        break;
+      }
+      path.push(current);
+      match element_parent(current) {
+        Some(ElementParent::Nest(next)) => current = next,
+        _ => break,
+      }
+    }
+    settled.extend(on_path);
+  }
+
+  // レコードの参照先を検査する
+  let mut references = Vec::new();
+  for element in elements.iter() {
+    let ElementKind::Custom(record) = &element.body.kind
+    else {
+      continue;
+    };
+    for (field, target) in record.references() {
+      if elements.get(target).is_none() {
+        references.push(FsckIssue::StaleReference {
+          element: element.id(),
+          field,
+          target,
+        });
+      }
+    }
+  }
+
+  structural.extend(lists);
+  structural.extend(references);
+  FsckReport { issues: structural }
+}
+
+impl Transaction<'_> {
+  /// 参照整合性を検査し、不整合を修復する
+  ///
+  /// ## Summary
+  /// - 親が存在しない作品・要素と、循環している要素は`orphans`に従って修復する
+  /// - `children`の不正な子・重複は取り除き、載っていない子は末尾に加える
+  /// - 存在しない要素への参照は、レコードの内容なので修復せずに報告のみ行う
+  ///
+  /// 失敗した場合は、修復による変更を全て取り消す。
+  ///
+  /// ## Return
+  /// 修復前の検査結果
+  ///
+  /// ## Error
+  /// - `HierarchyError::UserNotFound`・`WorkNotFound`: `Reattach`の付け替え先が存在しない
+  /// - `HierarchyError`: ストレージ操作に失敗した
+  pub fn repair(
+    &mut self,
+    orphans: OrphanPolicy,
+  ) -> Result<FsckReport, HierarchyError> {
+    if let OrphanPolicy::Reattach { user, work } = orphans {
+      if self.users.get(user).is_none() {
+        return Err(HierarchyError::UserNotFound(user));
+      }
+      if self.works.get(work).is_none() {
+        return Err(HierarchyError::WorkNotFound(work));
+      }
+    }
+    self.atomic(|tx| {
+      let report = check(
+        tx.users.storage(),
+        tx.works.storage(),
+        tx.elements.storage(),
+      );
+      for issue in &report.issues {
+        tx.repair_issue(issue, orphans)?;
+      }
+      Ok(report)
+    })
+  }
+
+  /// 不整合を1つ修復する
+  fn repair_issue(
+    &mut self,
+    issue: &FsckIssue,
+    orphans: OrphanPolicy,
+  ) -> Result<(), HierarchyError> {
+    match (issue, orphans) {
+      (
+        FsckIssue::OrphanWork { .. }
+        | FsckIssue::OrphanElement { .. }
+        | FsckIssue::ElementCycle { .. },
+        OrphanPolicy::Keep,
+      ) => {}
+      (
+        FsckIssue::OrphanWork { work, .. },
+        OrphanPolicy::Reattach { user, .. },
+      ) => self.reattach_work(*work, user)?,
+      (
+        FsckIssue::OrphanElement { element, .. },
+        OrphanPolicy::Reattach { work, .. },
+      ) => self.reattach_element(*element, work)?,
+      (
+        FsckIssue::ElementCycle { elements },
+        OrphanPolicy::Reattach { work, .. },
+      ) => {
+        if let Some(first) = elements.first() {
+          self.reattach_element(*first, work)?;
+        }
+      }
+      (FsckIssue::OrphanWork { work, .. }, _) => {
+        if self.works.get(*work).is_some() {
+          self.delete_work(*work, DeletePolicy::Cascade)?;
+        }
+      }
+      (FsckIssue::OrphanElement { element, .. }, _) => {
+        if self.elements.get(*element).is_some() {
+          self.delete_element(
+            *element,
+            DeletePolicy::Cascade,
+          )?;
+        }
+      }
+      (FsckIssue::ElementCycle { elements }, _) => {
+        // 循環する要素は互いの子孫になるので、まとめて削除する
+        let mut doomed = elements.clone();
+        for id in elements {
+          doomed.extend(
+            self.subtree_under(ElementParent::Nest(*id)),
+          );
+        }
+        let mut removed = HashSet::new();
+        for id in doomed {
+          if removed.insert(id)
+            && self.elements.get(id).is_some()
+          {
+            self.elements.remove(id)?;
+          }
+        }
+      }
+      (FsckIssue::DanglingWork { user, work }, _) => {
+        if self.users.get(*user).is_some() {
+          self.users.modify(*user, |b| {
+            b.children.retain(|c| c != work)
+          })?;
+        }
+      }
+      (
+        FsckIssue::DanglingElement { parent, element },
+        _,
+      ) => {
+        self.modify_element_list(*parent, |children| {
+          children.retain(|c| c != element)
+        })?;
+      }
+      // 付け替えた子は、元の親に加えない
+      (FsckIssue::UnlistedWork { user, work }, _) => {
+        if self.works.get(*work).map(|w| w.key().user_id())
+          == Some(*user)
+        {
+          self
+            .users
+            .modify(*user, |b| b.children.push(*work))?;
+        }
+      }
+      (
+        FsckIssue::UnlistedElement { parent, element },
+        _,
+      ) => {
+        if self
+          .elements
+          .get(*element)
+          .map(|e| e.key().parent)
+          == Some(*parent)
+        {
+          self
+            .modify_element_list(*parent, |children| {
+              children.push(*element)
+            })?;
+        }
+      }
+      (FsckIssue::DuplicateWork { user, .. }, _) => {
+        if self.users.get(*user).is_some() {
+          self
+            .users
+            .modify(*user, |b| dedup(&mut b.children))?;
+        }
+      }
+      (FsckIssue::DuplicateElement { parent, .. }, _) => {
+        self.modify_element_list(*parent, dedup)?;
+      }
+      (FsckIssue::StaleReference { .. }, _) => {}
+    }
+    Ok(())
+  }
+
+  /// 作品を`user`へ付け替える
+  fn reattach_work(
+    &mut self,
+    id: WorkId,
+    user: UserId,
+  ) -> Result<(), HierarchyError> {
+    let Some(name) = self
+      .works
+      .get(id)
+      .map(|w| w.key().work_name().to_string())
+    else {
+      return Ok(());
+    };
+    let mut key = WorkKey::from((user, &name));
+    if self.works.storage().id(&key).is_some() {
+      key = WorkKey::from((user, format!("{name}~{id}")));
+    }
+    self.works.rekey(id, key)?;
+    self.users.modify(user, |b| b.children.push(id))?;
+    Ok(())
+  }
+
+  /// 要素を`work`の直下へ付け替え、元の親の`children`から取り除く
+  fn reattach_element(
+    &mut self,
+    id: ElementId,
+    work: WorkId,
+  ) -> Result<(), HierarchyError> {
+    let Some(old) =
+      self.elements.get(id).map(|e| e.key().clone())
+    else {
+      return Ok(());
+    };
+    let parent = ElementParent::Root(work);
+    let mut key = ElementKey { parent, ..old.clone() };
+    if self.elements.storage().id(&key).is_some() {
+      key = key.renamed(format!("{}~{id}", old.name));
+    }
+    self.elements.rekey(id, key)?;
+    self.link_element(id, parent)?;
+    self.unlink_element(id, old.parent)
+  }
+
+  /// 親(作品・要素)の`children`を変更する
+  ///
+  /// ## Summary
+  /// 親が存在しない場合は何もしない。
+  /// 要素の`children`が`None`の場合は空のリストとして扱う。
+  fn modify_element_list(
+    &mut self,
+    parent: ElementParent,
+    f: impl FnOnce(&mut Vec<ElementId>),
+  ) -> Result<(), HierarchyError> {
+    match parent {
+      ElementParent::Root(id) => {
+        if self.works.get(id).is_some() {
+          self.works.modify(id, |b| f(&mut b.children))?;
+        }
+      }
+      ElementParent::Nest(id) => {
+        if self.elements.get(id).is_some() {
+          self.elements.modify(id, |b| {
+            f(b.children.get_or_insert_default())
+          })?;
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+/// 最初の出現を残して重複を取り除く
+fn dedup<T: Copy + Eq + std::hash::Hash>(
+  children: &mut Vec<T>,
+) {
+  let mut seen = HashSet::new();
+  children.retain(|c| seen.insert(*c));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{FsckIssue, OrphanPolicy, check};
+  use crate::{
+    domain::{
+      delete::DeletePolicy,
+      element::{
+        ElementDataBody, ElementId, ElementKey,
+        ElementKind, ElementParent,
+      },
+      error::HierarchyError,
+      schema::{
+        CustomRecord, CustomValue, FieldDef, FieldType,
+        RecordSchema, StaleReference,
+      },
+      transaction::Transaction,
+      user::{UserData, UserDataBody, UserId},
+      work::{WorkData, WorkId},
+    },
+    fixture::{
+      Store, container_body, element_body, work_body,
+    },
+    storage::StandardStorage,
+    traits::prelude::*,
+  };
+
+  #[test]
+  fn reports_references_to_deleted_elements() {
+    let mut users = StandardStorage::<UserData>::new();
+    let mut works = StandardStorage::<WorkData>::new();
+    let mut elements = StandardStorage::new();
+    let (target, referrer, report) = Transaction::run(
+      &mut users,
+      &mut works,
+      &mut elements,
+      |tx| {
+        let user =
+          tx.spawn_user("alice", UserDataBody::default())?;
+        let work =
+          tx.spawn_work(user, "novel", work_body("novel"))?;
+        tx.define_schema(
+          work,
+          "link",
+          RecordSchema {
+            version: 0,
+            fields: [(
+              "to".to_string(),
+              FieldDef::optional(FieldType::List(
+                Box::new(FieldType::Reference),
+              )),
+            )]
+            .into_iter()
+            .collect(),
+          },
+        )?;
+        let target = tx.spawn_element(
+          ElementParent::Root(work),
+          "target",
+          element_body(""),
+        )?;
+        let referrer = tx.spawn_element(
+          ElementParent::Root(work),
+          "referrer",
+          ElementDataBody {
+            kind: ElementKind::Custom(CustomRecord {
+              schema: "link".to_string(),
+              fields: [(
+                "to".to_string(),
+                CustomValue::List(vec![
+                  CustomValue::Reference(target),
+                ]),
+              )]
+              .into_iter()
+              .collect(),
+            }),
+            ..element_body("")
+          },
+        )?;
+        let report = tx
+          .delete_element(target, DeletePolicy::Restrict)?;
+        Ok::<_, HierarchyError>((target, referrer, report))
+      },
+    )
+    .unwrap();
+
+    let stale = StaleReference {
+      element: referrer,
+      field: "to[0]".to_string(),
+      target,
+    };
+    assert_eq!(report.stale_references, [stale]);
+    let issue = FsckIssue::StaleReference {
+      element: referrer,
+      field: "to[0]".to_string(),
+      target,
+    };
+    let report = check(&users, &works, &elements);
+    assert_eq!(report.issues, std::slice::from_ref(&issue));
+
+    // 参照は修復しないので、修復後も報告される
+    Transaction::run(
+      &mut users,
+      &mut works,
+      &mut elements,
+      |tx| tx.repair(OrphanPolicy::Keep),
+    )
+    .unwrap();
+    let report = check(&users, &works, &elements);
+    assert_eq!(report.issues, [issue]);
+  }
+
+  /// 修復前の不整合を含むストレージ
+  ///
+  /// - `bob`を削除して、作品`found`を孤立させる
+  /// - `c`を削除して、子`x`を孤立させる
+  /// - `p`の親を子の`q`に差し替えて循環させる
+  struct Broken {
+    store: Store,
+    lost: (UserId, WorkId),
+    orphan_work: WorkId,
+    orphan: ElementId,
+    cycle: [ElementId; 2],
+  }
+
+  fn broken() -> Broken {
+    let mut store = Store::default();
+    let (lost, bob, orphan_work, c, orphan, cycle) = store
+      .run(|tx| {
+        let lost = tx.spawn_user(
+          "lost+found",
+          UserDataBody::default(),
+        )?;
+        let found =
+          tx.spawn_work(lost, "found", work_body("found"))?;
+        tx.spawn_element(
+          ElementParent::Root(found),
+          "x",
+          element_body(""),
+        )?;
+        let bob =
+          tx.spawn_user("bob", UserDataBody::default())?;
+        let orphan_work =
+          tx.spawn_work(bob, "found", work_body("bob"))?;
+        let alice =
+          tx.spawn_user("alice", UserDataBody::default())?;
+        let novel = tx.spawn_work(
+          alice,
+          "novel",
+          work_body("novel"),
+        )?;
+        let root = ElementParent::Root(novel);
+        let c =
+          tx.spawn_element(root, "c", container_body())?;
+        let orphan = tx.spawn_element(
+          ElementParent::Nest(c),
+          "x",
+          element_body("orphan"),
+        )?;
+        let p =
+          tx.spawn_element(root, "p", container_body())?;
+        let q = tx.spawn_element(
+          ElementParent::Nest(p),
+          "q",
+          container_body(),
+        )?;
+        Ok((
+          (lost, found),
+          bob,
+          orphan_work,
+          c,
+          orphan,
+          [p, q],
+        ))
+      })
+      .unwrap();
+    store.users.remove(bob).unwrap();
+    store.elements.remove(c).unwrap();
+    store
+      .elements
+      .rekey(cycle[0], ElementKey::from((cycle[1], "p")))
+      .unwrap();
+    Broken { store, lost, orphan_work, orphan, cycle }
+  }
+
+  #[test]
+  fn repair_keeps_orphans_unless_told_otherwise() {
+    let Broken {
+      mut store,
+      orphan_work,
+      orphan,
+      cycle,
+      ..
+    } = broken();
+    let before = store.elements.len();
+    let report = store
+      .run(|tx| tx.repair(OrphanPolicy::Keep))
+      .unwrap();
+    assert!(!report.is_clean());
+
+    let Store { users, works, elements } = &store;
+    let remaining = check(users, works, elements).issues;
+    assert!(remaining.iter().all(|issue| matches!(
+      issue,
+      FsckIssue::OrphanWork { .. }
+        | FsckIssue::OrphanElement { .. }
+        | FsckIssue::ElementCycle { .. }
+    )));
+    assert_eq!(remaining.len(), 3);
+    assert!(works.get(orphan_work).is_some());
+    assert!(elements.get(orphan).is_some());
+    assert!(
+      cycle.iter().all(|id| elements.get(*id).is_some())
+    );
+    assert_eq!(elements.len(), before);
+  }
+
+  #[test]
+  fn repair_reattaches_orphans_to_lost_and_found() {
+    let Broken {
+      mut store,
+      lost: (user, work),
+      orphan_work,
+      orphan,
+      cycle,
+    } = broken();
+    store
+      .run(|tx| {
+        tx.repair(OrphanPolicy::Reattach { user, work })
+      })
+      .unwrap();
+
+    let Store { users, works, elements } = &store;
+    assert!(check(users, works, elements).is_clean());
+    // 同名のデータがある場合は`~ID`を付ける
+    let key = works.key(orphan_work).unwrap();
+    assert_eq!(key.user_id(), user);
+    assert_eq!(
+      key.work_name(),
+      format!("found~{orphan_work}")
+    );
+    let key = elements.key(orphan).unwrap();
+    assert_eq!(key.parent, ElementParent::Root(work));
+    assert_eq!(key.name, format!("x~{orphan}"));
+    assert_eq!(
+      elements.get(orphan).unwrap().body.content,
+      "orphan"
+    );
+    // 循環は1か所で断たれ、残りはその下に残る
+    let attached = cycle
+      .iter()
+      .filter(|id| {
+        elements.key(**id).unwrap().parent
+          == ElementParent::Root(work)
+      })
+      .count();
+    assert_eq!(attached, 1);
+  }
+
+  #[test]
+  fn repair_deletes_orphans_only_on_request() {
+    let Broken {
+      mut store,
+      orphan_work,
+      orphan,
+      cycle,
+      ..
+    } = broken();
+    store
+      .run(|tx| tx.repair(OrphanPolicy::Delete))
+      .unwrap();
+
+    let Store { users, works, elements } = &store;
+    assert!(check(users, works, elements).is_clean());
+    assert!(works.get(orphan_work).is_none());
+    assert!(elements.get(orphan).is_none());
+    assert!(
+      cycle.iter().all(|id| elements.get(*id).is_none())
+    );
+  }
+
+  #[test]
+  fn reattach_target_must_exist() {
+    let Broken { mut store, lost: (user, _), .. } =
+      broken();
+    let work = WorkId::new();
+    let result = store.run(|tx| {
+      tx.repair(OrphanPolicy::Reattach { user, work })
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::WorkNotFound(id)) if id == work
+    ));
+  }
+}
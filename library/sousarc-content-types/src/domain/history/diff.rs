@@ -0,0 +1,398 @@
+//! 行単位の差分と差分圧縮
+//!
+//! ## Summary
+//! - `diff_lines`: 2つの文字列の行単位の差分を求める
+//! - `Delta`: ある文字列から別の文字列を復元する為の差分
+
+use serde::{Deserialize, Serialize};
+
+/// 差分の1行
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum DiffLine {
+  /// 両方にある行
+  Same(String),
+
+  /// 旧い方にのみある行
+  Removed(String),
+
+  /// 新しい方にのみある行
+  Added(String),
+}
+
+/// 編集の1手(行の添字)
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Edit {
+  /// 旧い方の`.0`行目と新しい方の`.1`行目が同じ
+  Same(usize, usize),
+  Removed(usize),
+  Added(usize),
+}
+
+/// 改行を含めて行に分割する
+pub(super) fn lines(text: &str) -> Vec<&str> {
+  text.split_inclusive('\n').collect()
+}
+
+/// 最短の編集を求める(Myersの差分アルゴリズム)
+///
+/// ## Summary
+/// 中央のスネークで分割して再帰的に探索する線形空間版なので、
+/// 作業領域は`O(N + M)`に収まる。
+/// 連続する変更は、削除を追加より前に並べる。
+pub(super) fn edit_script(
+  a: &[&str],
+  b: &[&str],
+) -> Vec<Edit> {
+  let mut found = Vec::with_capacity(a.len().max(b.len()));
+  compare(a, b, 0, 0, &mut found);
+
+  let mut script = Vec::with_capacity(found.len());
+  let mut added = Vec::new();
+  for edit in found {
+    match edit {
+      Edit::Added(_) => added.push(edit),
+      Edit::Removed(_) => script.push(edit),
+      Edit::Same(..) => {
+        script.append(&mut added);
+        script.push(edit);
+      }
+    }
+  }
+  script.append(&mut added);
+  script
+}
+
+/// `a`・`b`(元の列の`x0`・`y0`行目から)の編集を`script`に加える
+fn compare(
+  a: &[&str],
+  b: &[&str],
+  x0: usize,
+  y0: usize,
+  script: &mut Vec<Edit>,
+) {
+  let prefix =
+    a.iter().zip(b).take_while(|(x, y)| x == y).count();
+  let suffix = a[prefix..]
+    .iter()
+    .rev()
+    .zip(b[prefix..].iter().rev())
+    .take_while(|(x, y)| x == y)
+    .count();
+  script.extend(
+    (0..prefix).map(|i| Edit::Same(x0 + i, y0 + i)),
+  );
+  let (ma, mb) = (
+    &a[prefix..a.len() - suffix],
+    &b[prefix..b.len() - suffix],
+  );
+  let (mx, my) = (x0 + prefix, y0 + prefix);
+
+  if ma.is_empty() {
+    script
+      .extend((0..mb.len()).map(|j| Edit::Added(my + j)));
+  } else if mb.is_empty() {
+    script
+      .extend((0..ma.len()).map(|i| Edit::Removed(mx + i)));
+  } else {
+    let (x, y, u, v) = middle_snake(ma, mb);
+    compare(&ma[..x], &mb[..y], mx, my, script);
+    script.extend(
+      (0..u - x)
+        .map(|i| Edit::Same(mx + x + i, my + y + i)),
+    );
+    compare(&ma[u..], &mb[v..], mx + u, my + v, script);
+  }
+
+  let (ex, ey) =
+    (x0 + a.len() - suffix, y0 + b.len() - suffix);
+  script.extend(
+    (0..suffix).map(|i| Edit::Same(ex + i, ey + i)),
+  );
+}
+
+/// 最短の編集の経路の中央にあるスネークを求める
+///
+/// ## Summary
+/// 始点からの探索と終点からの探索を交互に進め、
+/// 両者が重なった対角線のスネークを返す。
+/// `a`・`b`はどちらも空でない前提。
+///
+/// ## Return
+/// スネークの始点`(x, y)`と終点`(u, v)`の組`(x, y, u, v)`
+fn middle_snake(
+  a: &[&str],
+  b: &[&str],
+) -> (usize, usize, usize, usize) {
+  let (n, m) = (a.len() as isize, b.len() as isize);
+  let delta = n - m;
+  let odd = delta % 2 != 0;
+  let max = (n + m + 1) / 2;
+  let offset = max + 1;
+  let at = |k: isize| (k + offset) as usize;
+  // 対角線`k`上で到達した最も遠い`x`(後ろからの探索は末尾からの距離)
+  let mut forward = vec![0isize; 2 * offset as usize + 1];
+  let mut backward = forward.clone();
+  let step = |v: &[isize], d: isize, k: isize| {
+    if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+      v[at(k + 1)]
+    } else {
+      v[at(k - 1)] + 1
+    }
+  };
+
+  for d in 0..=max {
+    for k in (-d..=d).step_by(2) {
+      let mut x = step(&forward, d, k);
+      let mut y = x - k;
+      let (sx, sy) = (x, y);
+      while x < n && y < m && a[x as usize] == b[y as usize]
+      {
+        x += 1;
+        y += 1;
+      }
+      forward[at(k)] = x;
+      let r = delta - k;
+      if odd
+        && (-(d - 1)..=d - 1).contains(&r)
+        && x + backward[at(r)] >= n
+      {
+        return (
+          sx as usize,
+          sy as usize,
+          x as usize,
+          y as usize,
+        );
+      }
+    }
+    for k in (-d..=d).step_by(2) {
+      let mut x = step(&backward, d, k);
+      let mut y = x - k;
+      let (sx, sy) = (x, y);
+      while x < n
+        && y < m
+        && a[(n - 1 - x) as usize]
+          == b[(m - 1 - y) as usize]
+      {
+        x += 1;
+        y += 1;
+      }
+      backward[at(k)] = x;
+      let r = delta - k;
+      if !odd
+        && (-d..=d).contains(&r)
+        && x + forward[at(r)] >= n
+      {
+        return (
+          (n - x) as usize,
+          (m - y) as usize,
+          (n - sx) as usize,
+          (m - sy) as usize,
+        );
+      }
+    }
+  }
+  unreachable!("the forward and backward paths always meet")
+}
+
+/// 2つの文字列の行単位の差分を求める
+///
+/// ## Summary
+/// 各行は末尾の改行を含む。
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+  let (a, b) = (lines(old), lines(new));
+  edit_script(&a, &b)
+    .into_iter()
+    .map(|e| match e {
+      Edit::Same(i, _) => DiffLine::Same(a[i].to_string()),
+      Edit::Removed(i) => {
+        DiffLine::Removed(a[i].to_string())
+      }
+      Edit::Added(j) => DiffLine::Added(b[j].to_string()),
+    })
+    .collect()
+}
+
+/// 差分の1手
+///
+/// ## Summary
+/// 履歴の大きさを抑える為、タグを付けずに直列化する。
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(untagged)]
+enum DeltaOp {
+  /// 元の文字列の`start`行目から`len`行を写す(`(start, len)`)
+  Copy(usize, usize),
+
+  /// 文字列を挿入する
+  Insert(String),
+}
+
+/// 元の文字列から目的の文字列を復元する為の差分
+///
+/// ## Summary
+/// 変更の無い行は行番号の範囲のみを持つので、
+/// 似た文字列同士の差分は小さくなる。
+#[derive(
+  Debug,
+  Clone,
+  Default,
+  PartialEq,
+  Eq,
+  Serialize,
+  Deserialize,
+)]
+pub struct Delta(Vec<DeltaOp>);
+
+impl Delta {
+  /// `base`から`target`を復元する差分を求める
+  pub fn new(base: &str, target: &str) -> Self {
+    let (a, b) = (lines(base), lines(target));
+    let mut ops: Vec<DeltaOp> = Vec::new();
+    for edit in edit_script(&a, &b) {
+      match (edit, ops.last_mut()) {
+        (Edit::Removed(_), _) => {}
+        (
+          Edit::Same(i, _),
+          Some(DeltaOp::Copy(start, len)),
+        ) if *start + *len == i => *len += 1,
+        (Edit::Same(i, _), _) => {
+          ops.push(DeltaOp::Copy(i, 1))
+        }
+        (Edit::Added(j), Some(DeltaOp::Insert(text))) => {
+          text.push_str(b[j])
+        }
+        (Edit::Added(j), _) => {
+          ops.push(DeltaOp::Insert(b[j].to_string()))
+        }
+      }
+    }
+    Self(ops)
+  }
+
+  /// `base`に差分を適用する
+  ///
+  /// ## Return
+  /// 差分が`base`の範囲外の行を指す場合は`None`
+  /// (範囲の終端が桁溢れする場合を含む)
+  pub fn apply(&self, base: &str) -> Option<String> {
+    let a = lines(base);
+    let mut target = String::new();
+    for op in &self.0 {
+      match op {
+        DeltaOp::Copy(start, len) => {
+          let end = start.checked_add(*len)?;
+          for line in a.get(*start..end)? {
+            target.push_str(line);
+          }
+        }
+        DeltaOp::Insert(text) => target.push_str(text),
+      }
+    }
+    Some(target)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    Delta, DiffLine, Edit, diff_lines, edit_script,
+  };
+
+  /// 最長共通部分列の長さ(検算用)
+  fn lcs(a: &[&str], b: &[&str]) -> usize {
+    let mut row = vec![0; b.len() + 1];
+    for x in a {
+      let mut diagonal = 0;
+      for (j, y) in b.iter().enumerate() {
+        let above = row[j + 1];
+        row[j + 1] = if x == y {
+          diagonal + 1
+        } else {
+          above.max(row[j])
+        };
+        diagonal = above;
+      }
+    }
+    row[b.len()]
+  }
+
+  /// 疑似乱数で短い行の列を作る
+  fn lines_from(
+    seed: &mut u64,
+    len: usize,
+  ) -> Vec<&'static str> {
+    const WORDS: [&str; 4] = ["a\n", "b\n", "c\n", "d\n"];
+    (0..len)
+      .map(|_| {
+        *seed = seed
+          .wrapping_mul(6364136223846793005)
+          .wrapping_add(1442695040888963407);
+        WORDS[(*seed >> 62) as usize]
+      })
+      .collect()
+  }
+
+  #[test]
+  fn edit_script_is_minimal_and_consistent() {
+    let mut seed = 7;
+    for round in 0..300 {
+      let a = lines_from(&mut seed, round % 13);
+      let b = lines_from(&mut seed, (round * 7) % 11);
+      let script = edit_script(&a, &b);
+      let (mut i, mut j, mut same) = (0, 0, 0);
+      for edit in script {
+        match edit {
+          Edit::Same(x, y) => {
+            assert_eq!((x, y), (i, j));
+            assert_eq!(a[x], b[y]);
+            (i, j, same) = (i + 1, j + 1, same + 1);
+          }
+          Edit::Removed(x) => {
+            assert_eq!(x, i);
+            i += 1;
+          }
+          Edit::Added(y) => {
+            assert_eq!(y, j);
+            j += 1;
+          }
+        }
+      }
+      assert_eq!((i, j), (a.len(), b.len()));
+      assert_eq!(same, lcs(&a, &b), "{a:?} -> {b:?}");
+    }
+  }
+
+  #[test]
+  fn diff_lines_marks_changes() {
+    let diff = diff_lines("a\nb\nc\n", "a\nx\nc\nd\n");
+    assert_eq!(
+      diff,
+      [
+        DiffLine::Same("a\n".to_string()),
+        DiffLine::Removed("b\n".to_string()),
+        DiffLine::Added("x\n".to_string()),
+        DiffLine::Same("c\n".to_string()),
+        DiffLine::Added("d\n".to_string()),
+      ]
+    );
+  }
+
+  #[test]
+  fn delta_restores_target() {
+    let base = "one\ntwo\nthree\nfour";
+    let target = "zero\none\nthree\nfour\nfive";
+    let delta = Delta::new(base, target);
+    assert_eq!(delta.apply(base).as_deref(), Some(target));
+    assert_eq!(delta.apply(""), None);
+  }
+
+  #[test]
+  fn delta_with_overflowing_range_is_rejected() {
+    let delta =
+      Delta(vec![super::DeltaOp::Copy(1, usize::MAX)]);
+    assert_eq!(delta.apply("one\ntwo"), None);
+  }
+}
@@ -0,0 +1,393 @@
+//! 履歴を残す要素の編集
+//!
+//! ## Summary
+//! - `ElementEdit`: 表示名・本文の変更内容
+//! - `Transaction::edit_element`: 要素を編集し、版を記録する
+//! - `Transaction::restore_element`: 古い版の内容に戻す(新しい版として記録する)
+//!
+//! `edit_element`以外で表示名・本文を変更した要素も、
+//! `Transaction::commit`で作品の所有者の版として記録する。
+
+use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::traits::prelude::*;
+
+use super::{
+  super::{
+    element::{ElementId, ElementParent},
+    error::HierarchyError,
+    transaction::Transaction,
+    user::UserId,
+    work::WorkId,
+  },
+  RevisionText,
+};
+
+/// 表示名・本文の変更内容
+///
+/// ## Member
+/// - `display_name`・`content`: `Option<String>`型
+///   - `None`の場合は変更しない
+#[derive(
+  Debug,
+  Clone,
+  Default,
+  PartialEq,
+  Eq,
+  Serialize,
+  Deserialize,
+)]
+pub struct ElementEdit {
+  pub display_name: Option<String>,
+  pub content: Option<String>,
+}
+
+/// 現在の日時(UNIX時間のミリ秒)
+pub(in crate::domain) fn now_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |d| d.as_millis() as u64)
+}
+
+impl Transaction<'_> {
+  /// 要素の親を辿り、属する作品を求める
+  ///
+  /// ## Return
+  /// 祖先が存在しない、または循環している場合は`None`
+  pub(in crate::domain) fn work_of(
+    &self,
+    parent: ElementParent,
+  ) -> Option<WorkId> {
+    let mut parent = parent;
+    let mut visited = HashSet::new();
+    loop {
+      match parent {
+        ElementParent::Root(work_id) => {
+          return self
+            .works
+            .get(work_id)
+            .is_some()
+            .then_some(work_id);
+        }
+        ElementParent::Nest(id) => {
+          if !visited.insert(id) {
+            return None;
+          }
+          parent = self.elements.get(id)?.key().parent;
+        }
+      }
+    }
+  }
+
+  /// 要素の親を辿り、作品の所有者を求める
+  ///
+  /// ## Return
+  /// 祖先が存在しない、または循環している場合は`None`
+  pub(in crate::domain) fn owner_of(
+    &self,
+    parent: ElementParent,
+  ) -> Option<UserId> {
+    let work_id = self.work_of(parent)?;
+    self.works.get(work_id).map(|w| w.key().user_id())
+  }
+
+  /// 要素の表示名・本文を編集し、版を記録する
+  ///
+  /// ## Summary
+  /// 履歴の無い要素は、編集前の内容を作品の所有者の版として先に記録する。
+  /// 内容が変わらない場合は何もしない。
+  ///
+  /// ## Return
+  /// 記録した版の番号(変更が無い場合は`None`)
+  ///
+  /// ## Error
+  /// - `HierarchyError::UserNotFound`: 編集者が存在しない
+  /// - `HierarchyError::ElementNotFound`: 要素が存在しない
+  pub fn edit_element(
+    &mut self,
+    id: ElementId,
+    author: UserId,
+    edit: ElementEdit,
+  ) -> Result<Option<usize>, HierarchyError> {
+    if self.users.get(author).is_none() {
+      return Err(HierarchyError::UserNotFound(author));
+    }
+    let element = self
+      .elements
+      .get(id)
+      .ok_or(HierarchyError::ElementNotFound(id))?;
+    let old = RevisionText::from(&element.body);
+    let new = RevisionText {
+      display_name: edit
+        .display_name
+        .unwrap_or_else(|| old.display_name.clone()),
+      content: edit
+        .content
+        .unwrap_or_else(|| old.content.clone()),
+    };
+    if old == new {
+      return Ok(None);
+    }
+    self.elements.modify(id, |b| {
+      b.display_name = new.display_name;
+      b.content = new.content;
+    })?;
+    self.record_revision(id, Some(author))
+  }
+
+  /// 要素の現在の表示名・本文を版として記録する
+  ///
+  /// ## Summary
+  /// 最新の版と内容が同じ場合は何もしない。
+  /// 履歴の無い要素は、トランザクションの開始時点の内容を
+  /// 作品の所有者の版として先に記録する。
+  /// `author`が`None`の場合は作品の所有者の版とする。
+  ///
+  /// ## Return
+  /// 記録した版の番号(記録しない場合は`None`)
+  fn record_revision(
+    &mut self,
+    id: ElementId,
+    author: Option<UserId>,
+  ) -> Result<Option<usize>, HierarchyError> {
+    let Some(element) = self.elements.get(id) else {
+      return Ok(None);
+    };
+    let history = element.history();
+    let text = RevisionText::from(&element.body);
+    if history.head().is_some_and(|h| h.text == text) {
+      return Ok(None);
+    }
+    let owner = self.owner_of(element.key().parent);
+    let Some(author) = author.or(owner) else {
+      return Ok(None);
+    };
+    let baseline = history
+      .is_empty()
+      .then(|| self.elements.original_body(id))
+      .flatten()
+      .map(RevisionText::from)
+      .filter(|old| *old != text);
+
+    let (now, len) = (now_millis(), history.len());
+    let number = self.elements.modify_data(
+      id,
+      |e| {
+        if let Some(old) = baseline {
+          e.history.record(
+            owner.unwrap_or(author),
+            now,
+            old,
+          );
+        }
+        e.history.record(author, now, text)
+      },
+      move |e| e.history.truncate(len),
+    )?;
+    Ok(number)
+  }
+
+  /// 表示名・本文を変更した要素の版を記録する
+  ///
+  /// ## Summary
+  /// `Transaction::commit`から呼び出し、`edit_element`以外で
+  /// 変更した要素も作品の所有者の版として記録する。
+  pub(in crate::domain) fn record_revisions(
+    &mut self,
+  ) -> Result<(), HierarchyError> {
+    let touched: Vec<_> = self.elements.touched().collect();
+    for id in touched {
+      self.record_revision(id, None)?;
+    }
+    Ok(())
+  }
+
+  /// 要素の表示名・本文を古い版の内容に戻す
+  ///
+  /// ## Summary
+  /// 戻した内容は新しい版として記録するので、それ以降の版も残る。
+  ///
+  /// ## Return
+  /// 記録した版の番号(最新の版と内容が同じ場合は`None`)
+  ///
+  /// ## Error
+  /// - `HierarchyError::UserNotFound`: 編集者が存在しない
+  /// - `HierarchyError::ElementNotFound`: 要素が存在しない
+  /// - `HierarchyError::RevisionNotFound`: 版が存在しない
+  pub fn restore_element(
+    &mut self,
+    id: ElementId,
+    author: UserId,
+    number: usize,
+  ) -> Result<Option<usize>, HierarchyError> {
+    let revision = self
+      .elements
+      .get(id)
+      .ok_or(HierarchyError::ElementNotFound(id))?
+      .history()
+      .get(number)
+      .ok_or(HierarchyError::RevisionNotFound(
+        id, number,
+      ))?;
+    self.edit_element(
+      id,
+      author,
+      ElementEdit {
+        display_name: Some(revision.text.display_name),
+        content: Some(revision.text.content),
+      },
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::ElementEdit;
+  use crate::{
+    domain::{
+      element::{
+        ElementDataBody, ElementId, ElementParent,
+      },
+      error::HierarchyError,
+      history::DiffLine,
+      user::{UserDataBody, UserId},
+    },
+    fixture::{Store, element_body, work_body},
+    storage::StorageError,
+    traits::prelude::*,
+  };
+
+  /// ユーザ・作品・要素(本文`v1`)を1つずつ作成する
+  fn setup() -> (Store, UserId, ElementId) {
+    let mut store = Store::default();
+    let (user, element) = store
+      .run(|tx| {
+        let user =
+          tx.spawn_user("alice", UserDataBody::default())?;
+        let work =
+          tx.spawn_work(user, "novel", work_body("novel"))?;
+        let element = tx.spawn_element(
+          ElementParent::Root(work),
+          "chapter",
+          ElementDataBody {
+            display_name: "Chapter".to_string(),
+            ..element_body("v1\n")
+          },
+        )?;
+        Ok((user, element))
+      })
+      .unwrap();
+    (store, user, element)
+  }
+
+  #[test]
+  fn records_transaction_edits_and_restores() {
+    let (mut store, user, id) = setup();
+    let history = |store: &Store| {
+      store.elements.get(id).unwrap().history().clone()
+    };
+    assert_eq!(history(&store).len(), 1);
+
+    // `edit_element`を通さない変更も版として記録する
+    store
+      .run(|tx| {
+        tx.elements.modify(id, |b| {
+          b.content = "v1\nv2\n".to_string()
+        })?;
+        Ok(())
+      })
+      .unwrap();
+    let head = history(&store).head().unwrap().clone();
+    assert_eq!(head.meta.number, 1);
+    assert_eq!(head.text.content, "v1\nv2\n");
+
+    let number = store
+      .run(|tx| {
+        tx.edit_element(
+          id,
+          user,
+          ElementEdit {
+            content: Some("v3\n".to_string()),
+            ..Default::default()
+          },
+        )
+      })
+      .unwrap();
+    assert_eq!(number, Some(2));
+
+    let diff = history(&store).diff(1, 2).unwrap();
+    assert_eq!(
+      diff.content,
+      [
+        DiffLine::Removed("v1\n".to_string()),
+        DiffLine::Removed("v2\n".to_string()),
+        DiffLine::Added("v3\n".to_string()),
+      ]
+    );
+
+    let number = store
+      .run(|tx| tx.restore_element(id, user, 0))
+      .unwrap();
+    assert_eq!(number, Some(3));
+    let element = store.elements.get(id).unwrap();
+    assert_eq!(element.body.content, "v1\n");
+    let history = element.history();
+    assert_eq!(history.len(), 4);
+    assert_eq!(
+      history.get(0).unwrap().text.content,
+      "v1\n"
+    );
+    assert_eq!(
+      history.get(2).unwrap().text.content,
+      "v3\n"
+    );
+    assert_eq!(
+      history.get(3).unwrap().text,
+      history.get(0).unwrap().text
+    );
+    assert!(matches!(
+      store.run(|tx| tx.restore_element(id, user, 4)),
+      Err(HierarchyError::RevisionNotFound(_, 4))
+    ));
+  }
+
+  #[test]
+  fn rolled_back_edits_leave_no_revision() {
+    let (mut store, user, id) = setup();
+    let failed = store.run(|tx| {
+      tx.edit_element(
+        id,
+        user,
+        ElementEdit {
+          content: Some("draft\n".to_string()),
+          ..Default::default()
+        },
+      )?;
+      Err::<(), _>(HierarchyError::ElementNotFound(id))
+    });
+    assert!(failed.is_err());
+    let element = store.elements.get(id).unwrap();
+    assert_eq!(element.body.content, "v1\n");
+    assert_eq!(element.history().len(), 1);
+    assert_eq!(
+      element.history().head().unwrap().text.content,
+      "v1\n"
+    );
+  }
+
+  #[test]
+  fn elements_are_only_written_in_transactions() {
+    let (mut store, _, id) = setup();
+    assert!(store.elements.get_mut(id).is_none());
+    assert!(matches!(
+      store.elements.modify(id, |b| b.content.clear()),
+      Err(StorageError::Transactional(e)) if e == id
+    ));
+    let element = store.elements.get(id).unwrap();
+    assert_eq!(element.body.content, "v1\n");
+    assert_eq!(element.history().len(), 1);
+  }
+}
@@ -0,0 +1,392 @@
+//! 3方向マージ
+//!
+//! ## Summary
+//! - `merge_text`: 共通の元と2つの編集から、1つの文字列を求める
+//! - `merge_revision`: 表示名・本文をそれぞれマージする
+//! - `TextMerge`・`MergeHunk`・`RevisionMerge`: マージの結果
+//!
+//! 行単位でマージし、両方が同じ箇所を変更した部分は単語単位でマージし直す。
+//! それでも衝突する部分は、元・両方の編集を持つ`MergeHunk::Conflict`として返す。
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+  RevisionText,
+  diff::{Edit, edit_script, lines},
+};
+
+/// マージ結果の一部
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum MergeHunk {
+  /// 衝突しなかった部分
+  Resolved(String),
+
+  /// 両方が異なる変更をした部分
+  Conflict { base: String, ours: String, theirs: String },
+}
+
+/// 文字列のマージ結果
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum TextMerge {
+  /// 衝突無くマージできた
+  Clean(String),
+
+  /// 衝突がある(前から順の部分)
+  Conflicted(Vec<MergeHunk>),
+}
+
+impl TextMerge {
+  /// 衝突が無いか判定する
+  pub fn is_clean(&self) -> bool {
+    matches!(self, Self::Clean(_))
+  }
+
+  /// 衝突が無い場合に、マージした文字列を取り出す
+  pub fn resolved(&self) -> Option<&str> {
+    match self {
+      Self::Clean(text) => Some(text),
+      Self::Conflicted(_) => None,
+    }
+  }
+
+  /// 衝突箇所を`<<<<<<<`・`=======`・`>>>>>>>`で囲んだ文字列にする
+  pub fn to_marked_text(&self) -> String {
+    let hunks = match self {
+      Self::Clean(text) => return text.clone(),
+      Self::Conflicted(hunks) => hunks,
+    };
+    let mut text = String::new();
+    for hunk in hunks {
+      match hunk {
+        MergeHunk::Resolved(resolved) => {
+          text.push_str(resolved)
+        }
+        MergeHunk::Conflict { ours, theirs, .. } => {
+          if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+          }
+          text.push_str("<<<<<<< ours\n");
+          push_block(&mut text, ours);
+          text.push_str("=======\n");
+          push_block(&mut text, theirs);
+          text.push_str(">>>>>>> theirs\n");
+        }
+      }
+    }
+    text
+  }
+}
+
+/// 衝突箇所の片方を、改行で終わるように書き出す
+fn push_block(text: &mut String, block: &str) {
+  text.push_str(block);
+  if !block.is_empty() && !block.ends_with('\n') {
+    text.push('\n');
+  }
+}
+
+/// 衝突しなかった部分を、直前の`Resolved`に繋げて加える
+fn push_resolved(hunks: &mut Vec<MergeHunk>, text: String) {
+  match hunks.last_mut() {
+    Some(MergeHunk::Resolved(last)) => last.push_str(&text),
+    _ => hunks.push(MergeHunk::Resolved(text)),
+  }
+}
+
+/// 表示名・本文のマージ結果
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct RevisionMerge {
+  pub display_name: TextMerge,
+  pub content: TextMerge,
+}
+
+impl RevisionMerge {
+  /// 衝突が無いか判定する
+  pub fn is_clean(&self) -> bool {
+    self.display_name.is_clean() && self.content.is_clean()
+  }
+
+  /// 衝突が無い場合に、マージした内容を取り出す
+  pub fn resolved(&self) -> Option<RevisionText> {
+    Some(RevisionText {
+      display_name: self
+        .display_name
+        .resolved()?
+        .to_string(),
+      content: self.content.resolved()?.to_string(),
+    })
+  }
+}
+
+/// 単語(空白の連続とそれ以外の連続)に分割する
+fn words(text: &str) -> Vec<&str> {
+  let mut words = Vec::new();
+  let mut start = 0;
+  let mut prev = None;
+  for (i, c) in text.char_indices() {
+    let space = c.is_whitespace();
+    if prev.is_some_and(|p| p != space) {
+      words.push(&text[start..i]);
+      start = i;
+    }
+    prev = Some(space);
+  }
+  if start < text.len() {
+    words.push(&text[start..]);
+  }
+  words
+}
+
+/// トークン列のマージ結果の一部
+enum Chunk<'a> {
+  Resolved(Vec<&'a str>),
+  Conflict(&'a [&'a str], &'a [&'a str], &'a [&'a str]),
+}
+
+/// `base`の各トークンに対応する`other`の添字
+fn matches(
+  base: &[&str],
+  other: &[&str],
+) -> Vec<Option<usize>> {
+  let mut map = vec![None; base.len()];
+  for edit in edit_script(base, other) {
+    if let Edit::Same(i, j) = edit {
+      map[i] = Some(j);
+    }
+  }
+  map
+}
+
+/// トークン列を3方向マージする(diff3)
+///
+/// ## Summary
+/// 元のトークンが両方の編集に残っている箇所で区切り、
+/// 区切りの間を片方のみが変更していればその変更を採る。
+fn merge_tokens<'a>(
+  base: &'a [&'a str],
+  ours: &'a [&'a str],
+  theirs: &'a [&'a str],
+) -> Vec<Chunk<'a>> {
+  let (to_ours, to_theirs) =
+    (matches(base, ours), matches(base, theirs));
+  let mut chunks = Vec::new();
+  let (mut i, mut o, mut t) = (0, 0, 0);
+  loop {
+    // 3つとも一致する部分はそのまま採る
+    let mut stable = Vec::new();
+    while i < base.len()
+      && to_ours[i] == Some(o)
+      && to_theirs[i] == Some(t)
+    {
+      stable.push(base[i]);
+      (i, o, t) = (i + 1, o + 1, t + 1);
+    }
+    if !stable.is_empty() {
+      chunks.push(Chunk::Resolved(stable));
+    }
+
+    // 次に3つとも一致する位置までが、いずれかが変更した部分
+    let next = (i..base.len())
+      .find_map(|k| Some((k, to_ours[k]?, to_theirs[k]?)));
+    let (k, ok, tk) = next.unwrap_or((
+      base.len(),
+      ours.len(),
+      theirs.len(),
+    ));
+    if (k, ok, tk) == (i, o, t) {
+      break;
+    }
+    let (b, x, y) =
+      (&base[i..k], &ours[o..ok], &theirs[t..tk]);
+    if x == b || x == y {
+      chunks.push(Chunk::Resolved(y.to_vec()));
+    } else if y == b {
+      chunks.push(Chunk::Resolved(x.to_vec()));
+    } else {
+      chunks.push(Chunk::Conflict(b, x, y));
+    }
+    (i, o, t) = (k, ok, tk);
+  }
+  chunks
+}
+
+/// 文字列を3方向マージする
+///
+/// ## Summary
+/// 行単位で衝突した部分は、単語単位でマージし直す。
+pub fn merge_text(
+  base: &str,
+  ours: &str,
+  theirs: &str,
+) -> TextMerge {
+  let (b, x, y) = (lines(base), lines(ours), lines(theirs));
+  let mut hunks: Vec<MergeHunk> = Vec::new();
+  for chunk in merge_tokens(&b, &x, &y) {
+    match chunk {
+      Chunk::Resolved(tokens) => {
+        push_resolved(&mut hunks, tokens.concat())
+      }
+      Chunk::Conflict(b, x, y) => {
+        let (b, x, y) =
+          (b.concat(), x.concat(), y.concat());
+        let (bw, xw, yw) =
+          (words(&b), words(&x), words(&y));
+        let chunks = merge_tokens(&bw, &xw, &yw);
+        if chunks
+          .iter()
+          .all(|c| matches!(c, Chunk::Resolved(_)))
+        {
+          for chunk in chunks {
+            if let Chunk::Resolved(tokens) = chunk {
+              push_resolved(&mut hunks, tokens.concat());
+            }
+          }
+        } else {
+          hunks.push(MergeHunk::Conflict {
+            base: b,
+            ours: x,
+            theirs: y,
+          });
+        }
+      }
+    }
+  }
+
+  if hunks
+    .iter()
+    .any(|h| matches!(h, MergeHunk::Conflict { .. }))
+  {
+    return TextMerge::Conflicted(hunks);
+  }
+  match hunks.pop() {
+    Some(MergeHunk::Resolved(text)) => {
+      TextMerge::Clean(text)
+    }
+    _ => TextMerge::Clean(String::new()),
+  }
+}
+
+/// 表示名・本文をそれぞれ3方向マージする
+pub fn merge_revision(
+  base: &RevisionText,
+  ours: &RevisionText,
+  theirs: &RevisionText,
+) -> RevisionMerge {
+  RevisionMerge {
+    display_name: merge_text(
+      &base.display_name,
+      &ours.display_name,
+      &theirs.display_name,
+    ),
+    content: merge_text(
+      &base.content,
+      &ours.content,
+      &theirs.content,
+    ),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    Chunk, MergeHunk, TextMerge, merge_text, merge_tokens,
+  };
+
+  /// マージ結果を比較しやすい文字列の列にする
+  fn render(chunks: &[Chunk<'_>]) -> Vec<String> {
+    chunks
+      .iter()
+      .map(|chunk| match chunk {
+        Chunk::Resolved(tokens) => tokens.concat(),
+        Chunk::Conflict(b, x, y) => format!(
+          "{}|{}|{}",
+          b.concat(),
+          x.concat(),
+          y.concat()
+        ),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn merge_tokens_takes_one_sided_changes() {
+    let base = ["a", "b", "c", "d"];
+    let ours = ["a", "B", "c", "d"];
+    let theirs = ["a", "b", "c", "D", "e"];
+    let chunks = merge_tokens(&base, &ours, &theirs);
+    assert_eq!(render(&chunks), ["a", "B", "c", "De"]);
+  }
+
+  #[test]
+  fn merge_tokens_accepts_identical_changes() {
+    let base = ["a", "b", "c"];
+    let both = ["a", "x", "c"];
+    let chunks = merge_tokens(&base, &both, &both);
+    assert_eq!(render(&chunks), ["a", "x", "c"]);
+  }
+
+  #[test]
+  fn merge_tokens_reports_overlapping_changes() {
+    let base = ["a", "b", "c"];
+    let ours = ["a", "x", "c"];
+    let theirs = ["a", "y", "c"];
+    let chunks = merge_tokens(&base, &ours, &theirs);
+    assert_eq!(render(&chunks), ["a", "b|x|y", "c"]);
+  }
+
+  #[test]
+  fn merge_text_retries_conflicts_by_word() {
+    let merged = merge_text(
+      "title\nthe quick brown fox\n",
+      "title\nthe slow brown fox\n",
+      "title\nthe quick brown dog\n",
+    );
+    assert_eq!(
+      merged,
+      TextMerge::Clean(
+        "title\nthe slow brown dog\n".to_string()
+      )
+    );
+  }
+
+  #[test]
+  fn merge_text_marks_conflicts() {
+    let merged = merge_text(
+      "a\nb c\nd\n",
+      "a\nx c\nd\n",
+      "a\ny c\nd\n",
+    );
+    assert_eq!(
+      merged,
+      TextMerge::Conflicted(vec![
+        MergeHunk::Resolved("a\n".to_string()),
+        MergeHunk::Conflict {
+          base: "b c\n".to_string(),
+          ours: "x c\n".to_string(),
+          theirs: "y c\n".to_string(),
+        },
+        MergeHunk::Resolved("d\n".to_string()),
+      ])
+    );
+    assert!(merged.resolved().is_none());
+    assert_eq!(
+      merged.to_marked_text(),
+      "a\n<<<<<<< ours\nx c\n=======\ny c\n>>>>>>> theirs\nd\n"
+    );
+  }
+
+  #[test]
+  fn marked_text_ends_blocks_with_newlines() {
+    let merged = merge_text("a", "b", "c");
+    assert_eq!(
+      merged.to_marked_text(),
+      "<<<<<<< ours\nb\n=======\nc\n>>>>>>> theirs\n"
+    );
+  }
+}
@@ -0,0 +1,251 @@
+//! 要素の変更履歴
+//!
+//! ## Summary
+//! - `History`: 表示名・本文の版の列
+//! - `Revision`・`RevisionMeta`・`RevisionText`: 版とその情報
+//! - `RevisionDiff`: 2つの版の差分
+//! - `History::merge`: 版を元に、2つの編集を3方向マージする
+//!
+//! 履歴は要素の本体(`ElementDataBody`)ではなく`ElementData::history`に持ち、
+//! `Transaction`の確定時に、表示名・本文を変更した要素の版を記録する。
+//! 要素は`SousARCData::TRANSACTIONAL`なので、ストレージの`get_mut`・`modify`等で
+//! トランザクションを通さずに変更することはできない。
+//!
+//! 最新の版のみを全文で持ち、それより前の版は1つ新しい版からの差分(`Delta`)で持つ。
+//! 古い版を取り出す際は、最新の版から順に差分を適用して復元する。
+
+use serde::{Deserialize, Serialize};
+
+use super::{element::ElementDataBody, user::UserId};
+
+pub mod diff;
+pub use diff::*;
+pub mod edit;
+pub use edit::*;
+pub mod merge;
+pub use merge::*;
+
+/// 版の情報
+///
+/// ## Member
+/// - `number`: `usize`型
+///   - 版番号(最初の版が0)
+/// - `timestamp`: `u64`型
+///   - 記録した日時(UNIX時間のミリ秒)
+/// - `author`: `UserId`型
+///   - 変更したユーザ
+#[derive(
+  Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct RevisionMeta {
+  pub number: usize,
+  pub timestamp: u64,
+  pub author: UserId,
+}
+
+/// 版の内容
+#[derive(
+  Debug,
+  Clone,
+  Default,
+  PartialEq,
+  Eq,
+  Serialize,
+  Deserialize,
+)]
+pub struct RevisionText {
+  pub display_name: String,
+  pub content: String,
+}
+
+impl From<&ElementDataBody> for RevisionText {
+  fn from(body: &ElementDataBody) -> Self {
+    Self {
+      display_name: body.display_name.clone(),
+      content: body.content.clone(),
+    }
+  }
+}
+
+/// 版
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct Revision {
+  pub meta: RevisionMeta,
+  pub text: RevisionText,
+}
+
+/// 2つの版の差分
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct RevisionDiff {
+  pub from: RevisionMeta,
+  pub to: RevisionMeta,
+  pub display_name: Vec<DiffLine>,
+  pub content: Vec<DiffLine>,
+}
+
+/// 最新でない版(1つ新しい版から復元する差分)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PastRevision {
+  meta: RevisionMeta,
+  display_name: Delta,
+  content: Delta,
+}
+
+/// 表示名・本文の版の列
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct History {
+  past: Vec<PastRevision>,
+  head: Option<Revision>,
+}
+
+impl History {
+  /// 版の数
+  pub fn len(&self) -> usize {
+    self.past.len() + usize::from(self.head.is_some())
+  }
+
+  /// 版が無いか判定する
+  pub fn is_empty(&self) -> bool {
+    self.head.is_none()
+  }
+
+  /// 最新の版
+  pub fn head(&self) -> Option<&Revision> {
+    self.head.as_ref()
+  }
+
+  /// 全ての版の情報を古い順に列挙する
+  pub fn list(
+    &self,
+  ) -> impl Iterator<Item = RevisionMeta> + '_ {
+    self
+      .past
+      .iter()
+      .map(|r| r.meta)
+      .chain(self.head.iter().map(|r| r.meta))
+  }
+
+  /// 版を取り出す
+  ///
+  /// ## Return
+  /// 版が存在しない、または差分が壊れている場合は`None`
+  pub fn get(&self, number: usize) -> Option<Revision> {
+    let head = self.head.as_ref()?;
+    if number >= self.len() {
+      return None;
+    }
+    let mut text = head.text.clone();
+    for past in self.past[number..].iter().rev() {
+      text = RevisionText {
+        display_name: past
+          .display_name
+          .apply(&text.display_name)?,
+        content: past.content.apply(&text.content)?,
+      };
+    }
+    let meta = match self.past.get(number) {
+      Some(past) => past.meta,
+      None => head.meta,
+    };
+    Some(Revision { meta, text })
+  }
+
+  /// 2つの版の差分を求める
+  pub fn diff(
+    &self,
+    from: usize,
+    to: usize,
+  ) -> Option<RevisionDiff> {
+    let (from, to) = (self.get(from)?, self.get(to)?);
+    Some(RevisionDiff {
+      from: from.meta,
+      to: to.meta,
+      display_name: diff_lines(
+        &from.text.display_name,
+        &to.text.display_name,
+      ),
+      content: diff_lines(
+        &from.text.content,
+        &to.text.content,
+      ),
+    })
+  }
+
+  /// 版を元に、2つの編集を3方向マージする
+  ///
+  /// ## Return
+  /// 版が存在しない場合は`None`
+  pub fn merge(
+    &self,
+    base: usize,
+    ours: &RevisionText,
+    theirs: &RevisionText,
+  ) -> Option<RevisionMerge> {
+    let base = self.get(base)?;
+    Some(merge_revision(&base.text, ours, theirs))
+  }
+
+  /// 新しい版を記録する
+  ///
+  /// ## Summary
+  /// 最新の版と内容が同じ場合は記録しない。
+  /// それまでの最新の版は、新しい版からの差分に置き換える。
+  ///
+  /// ## Return
+  /// 記録した版の番号
+  pub fn record(
+    &mut self,
+    author: UserId,
+    timestamp: u64,
+    text: RevisionText,
+  ) -> Option<usize> {
+    if self.head.as_ref().is_some_and(|h| h.text == text) {
+      return None;
+    }
+    let number = self.len();
+    if let Some(head) = self.head.take() {
+      self.past.push(PastRevision {
+        meta: head.meta,
+        display_name: Delta::new(
+          &text.display_name,
+          &head.text.display_name,
+        ),
+        content: Delta::new(
+          &text.content,
+          &head.text.content,
+        ),
+      });
+    }
+    let meta = RevisionMeta { number, timestamp, author };
+    self.head = Some(Revision { meta, text });
+    Some(number)
+  }
+
+  /// 版の数が`len`になるまで新しい版を取り除く
+  ///
+  /// ## Summary
+  /// 記録の取り消しに使う。
+  /// 差分が壊れている場合は、それより前の版も全て取り除く。
+  pub(in crate::domain) fn truncate(&mut self, len: usize) {
+    while self.len() > len {
+      let Some(head) = self.head.take() else { break };
+      let Some(past) = self.past.pop() else { break };
+      let text = past
+        .display_name
+        .apply(&head.text.display_name)
+        .zip(past.content.apply(&head.text.content));
+      let Some((display_name, content)) = text else {
+        self.past.clear();
+        break;
+      };
+      self.head = Some(Revision {
+        meta: past.meta,
+        text: RevisionText { display_name, content },
+      });
+    }
+  }
+}
@@ -0,0 +1,340 @@
+//! トランザクション単位のジャーナルとクラッシュからの復旧
+//!
+//! ## Summary
+//! - `TransactionRecord`: 1つのトランザクションでの3つのストレージの変更
+//! - `TransactionJournal`: `TransactionRecord`を記録するジャーナル
+//! - `JournaledStore`: 3つのストレージとジャーナルを束ね、
+//!   `Transaction`の確定毎に変更を記録する
+//!
+//! ディレクトリには次のファイルを置く。
+//!
+//! ```text
+//! users.snapshot / works.snapshot / elements.snapshot / transactions.journal
+//! ```
+//!
+//! スナップショットはストレージ毎に反映済みの連番を持つので、
+//! チェックポイントの途中で異常終了しても、
+//! 各ストレージには反映されていないフレームだけを再適用する。
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{
+  Journal, JournalRecord, JournalRecordRef, SnapshotError,
+  StandardStorage, load_or_new,
+};
+
+use super::{
+  element::ElementData, error::HierarchyError,
+  transaction::Transaction, user::UserData, work::WorkData,
+};
+
+/// 1つのトランザクションでの変更
+///
+/// ## Member
+/// - `users`: `Vec<JournalRecord<UserData>>`型
+/// - `works`: `Vec<JournalRecord<WorkData>>`型
+/// - `elements`: `Vec<JournalRecord<ElementData>>`型
+#[derive(Debug, Deserialize)]
+pub struct TransactionRecord {
+  pub users: Vec<JournalRecord<UserData>>,
+  pub works: Vec<JournalRecord<WorkData>>,
+  pub elements: Vec<JournalRecord<ElementData>>,
+}
+
+/// 記録用に借用した`TransactionRecord`(同じ形式に変換される)
+#[derive(Serialize)]
+#[serde(rename = "TransactionRecord")]
+pub(crate) struct TransactionRecordRef<'a> {
+  pub users: Vec<JournalRecordRef<'a, UserData>>,
+  pub works: Vec<JournalRecordRef<'a, WorkData>>,
+  pub elements: Vec<JournalRecordRef<'a, ElementData>>,
+}
+
+impl TransactionRecordRef<'_> {
+  /// 変更が無いか判定する
+  pub fn is_empty(&self) -> bool {
+    self.users.is_empty()
+      && self.works.is_empty()
+      && self.elements.is_empty()
+  }
+}
+
+/// トランザクション単位のジャーナル
+pub type TransactionJournal = Journal<TransactionRecord>;
+
+/// 3つのストレージとジャーナルの組
+///
+/// ## Summary
+/// 読み取りは`users`・`works`・`elements`で行い、
+/// 変更は`transaction`を通して行う。
+///
+/// ## Member
+/// - `users`・`works`・`elements`: `StandardStorage`型
+///   - 操作を適用するストレージ
+/// - `journal`: `TransactionJournal`型
+///   - 確定したトランザクションの記録
+/// - `dir`: `PathBuf`型
+///   - スナップショットとジャーナルを置くディレクトリ
+/// - `checkpoint_interval`: `Option<u64>`型
+///   - 自動でチェックポイントを行う記録数
+#[derive(Debug)]
+pub struct JournaledStore {
+  users: StandardStorage<UserData>,
+  works: StandardStorage<WorkData>,
+  elements: StandardStorage<ElementData>,
+  journal: TransactionJournal,
+  dir: PathBuf,
+  checkpoint_interval: Option<u64>,
+}
+
+/// スナップショットのファイル名(ユーザ・作品・要素)
+const SNAPSHOTS: [&str; 3] =
+  ["users.snapshot", "works.snapshot", "elements.snapshot"];
+
+/// ジャーナルのファイル名
+const JOURNAL: &str = "transactions.journal";
+
+impl JournaledStore {
+  /// ディレクトリのスナップショットとジャーナルから復元する
+  ///
+  /// ## Summary
+  /// ディレクトリやスナップショットが存在しない場合は空のストレージから始める。
+  ///
+  /// ## Error
+  /// - `SnapshotError`: スナップショットまたはジャーナルを読めない
+  /// - `SnapshotError::Corrupted`: 記録を再適用できない
+  pub fn open(
+    dir: impl Into<PathBuf>,
+  ) -> Result<Self, SnapshotError> {
+    let dir = dir.into();
+    std::fs::create_dir_all(&dir)?;
+    let [users, works, elements] =
+      SNAPSHOTS.map(|name| dir.join(name));
+    let (mut users, users_seq) = load_or_new(&users)?;
+    let (mut works, works_seq) = load_or_new(&works)?;
+    let (mut elements, elements_seq) =
+      load_or_new(&elements)?;
+
+    let after = users_seq.min(works_seq).min(elements_seq);
+    let mut failed = None;
+    let journal = Journal::open(
+      dir.join(JOURNAL),
+      after,
+      |seq, record: TransactionRecord| {
+        if failed.is_some() {
+          return;
+        }
+        let replayed = (|| {
+          if seq > users_seq {
+            JournalRecord::apply_all(
+              record.users,
+              &mut users,
+            )?;
+          }
+          if seq > works_seq {
+            JournalRecord::apply_all(
+              record.works,
+              &mut works,
+            )?;
+          }
+          if seq > elements_seq {
+            JournalRecord::apply_all(
+              record.elements,
+              &mut elements,
+            )?;
+          }
+          Ok::<_, HierarchyError>(())
+        })();
+        if let Err(e) = replayed {
+          failed = Some(format!(
+            "cannot replay journal frame {seq}: {e}"
+          ));
+        }
+      },
+    )?;
+    if let Some(reason) = failed {
+      return Err(SnapshotError::Corrupted(reason));
+    }
+    Ok(Self {
+      users,
+      works,
+      elements,
+      journal,
+      dir,
+      checkpoint_interval: None,
+    })
+  }
+
+  /// 記録数が`interval`に達する毎に自動でチェックポイントを行う
+  pub fn with_checkpoint_interval(
+    mut self,
+    interval: u64,
+  ) -> Self {
+    self.checkpoint_interval = Some(interval);
+    self
+  }
+
+  /// ユーザのストレージを参照する
+  pub fn users(&self) -> &StandardStorage<UserData> {
+    &self.users
+  }
+
+  /// 作品のストレージを参照する
+  pub fn works(&self) -> &StandardStorage<WorkData> {
+    &self.works
+  }
+
+  /// 要素のストレージを参照する
+  pub fn elements(&self) -> &StandardStorage<ElementData> {
+    &self.elements
+  }
+
+  /// ジャーナルを参照する
+  pub fn journal(&self) -> &TransactionJournal {
+    &self.journal
+  }
+
+  /// クロージャをトランザクション内で実行する
+  ///
+  /// ## Summary
+  /// `f`が`Ok`を返した場合は変更を1つのフレームとして記録してから確定し、
+  /// `Err`を返した場合、または記録に失敗した場合は全て取り消す。
+  ///
+  /// ## Error
+  /// - `HierarchyError::Journal`: ジャーナルへの記録に失敗した
+  /// - その他は`f`が返したエラー
+  pub fn transaction<T, E>(
+    &mut self,
+    f: impl FnOnce(&mut Transaction<'_>) -> Result<T, E>,
+  ) -> Result<T, E>
+  where
+    E: From<HierarchyError>,
+  {
+    let mut tx = Transaction::journaled(
+      &mut self.users,
+      &mut self.works,
+      &mut self.elements,
+      &mut self.journal,
+    );
+    let ret = f(&mut tx)?;
+    tx.commit()?;
+    self.maybe_checkpoint();
+    Ok(ret)
+  }
+
+  /// スナップショットを保存し、ジャーナルを空にする
+  ///
+  /// ## Error
+  /// - `SnapshotError`: スナップショットの保存、またはジャーナルの切り詰めに失敗した
+  pub fn checkpoint(
+    &mut self,
+  ) -> Result<(), SnapshotError> {
+    let seq = self.journal.last_seq();
+    let [users, works, elements] =
+      SNAPSHOTS.map(|name| self.dir.join(name));
+    self.users.save_at(&users, seq)?;
+    self.works.save_at(&works, seq)?;
+    self.elements.save_at(&elements, seq)?;
+    self.journal.truncate()?;
+    Ok(())
+  }
+
+  /// 記録数が上限に達していればチェックポイントを行う
+  fn maybe_checkpoint(&mut self) {
+    let due = self
+      .checkpoint_interval
+      .is_some_and(|n| self.journal.records() >= n);
+    if due && let Err(e) = self.checkpoint() {
+      tracing::error!("checkpoint failed: {e}");
+    }
+  }
+
+  /// スナップショットとジャーナルを置くディレクトリ
+  pub fn dir(&self) -> &Path {
+    &self.dir
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::JournaledStore;
+  use crate::{
+    domain::{
+      element::ElementParent, error::HierarchyError,
+      user::UserDataBody, work::WorkId,
+    },
+    fixture::{element_body, work_body},
+    traits::prelude::*,
+  };
+
+  #[test]
+  fn replays_transactions_across_storages() {
+    let dir = std::env::temp_dir()
+      .join(format!("sousarc-store-{}", WorkId::new()));
+    let (user, work, element) = {
+      let mut store = JournaledStore::open(&dir).unwrap();
+      let ids = store
+        .transaction(|tx| {
+          let user = tx
+            .spawn_user("alice", UserDataBody::default())?;
+          let work = tx.spawn_work(
+            user,
+            "novel",
+            work_body("work"),
+          )?;
+          let element = tx.spawn_element(
+            ElementParent::Root(work),
+            "chapter",
+            element_body("text"),
+          )?;
+          Ok::<_, HierarchyError>((user, work, element))
+        })
+        .unwrap();
+      let failed = store.transaction(|tx| {
+        tx.spawn_user("bob", UserDataBody::default())?;
+        Err::<(), _>(HierarchyError::UserNotFound(ids.0))
+      });
+      assert!(failed.is_err());
+      assert_eq!(store.journal().records(), 1);
+      ids
+    };
+
+    let mut store = JournaledStore::open(&dir).unwrap();
+    assert_eq!(store.users().len(), 1);
+    assert_eq!(
+      store
+        .users()
+        .get(user)
+        .unwrap()
+        .children()
+        .collect::<Vec<_>>(),
+      [work]
+    );
+    assert_eq!(
+      store
+        .works()
+        .get(work)
+        .unwrap()
+        .children()
+        .collect::<Vec<_>>(),
+      [element]
+    );
+    assert!(store.elements().get(element).is_some());
+
+    store.checkpoint().unwrap();
+    store
+      .transaction(|tx| {
+        tx.spawn_user("bob", UserDataBody::default())
+      })
+      .unwrap();
+    drop(store);
+
+    let store = JournaledStore::open(&dir).unwrap();
+    assert_eq!(store.users().len(), 2);
+    assert_eq!(store.journal().records(), 1);
+    assert_eq!(store.journal().last_seq(), 2);
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
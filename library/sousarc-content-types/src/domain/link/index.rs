@@ -0,0 +1,347 @@
+//! リンクのセカンダリインデックス
+//!
+//! ## Summary
+//! - `ElementLinkIndex`: リンク先→リンク元の要素の索引
+//! - `element_links`: 要素のリンク先を解決して列挙する
+//!
+//! 索引するのは本文に書かれたままのリンク先で、解決はストレージのキー検索で行う。
+//! 要素の本文が変わると、ストレージがインデックスを更新する。
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  storage::{
+    IndexHandle, IndexKind, StandardStorage, StorageError,
+  },
+  traits::prelude::*,
+};
+
+use super::{
+  super::{
+    element::{ElementData, ElementId},
+    path::PathError,
+    user::UserData,
+    work::WorkData,
+  },
+  LinkTarget, link_targets, parse_links,
+};
+
+/// 解決したリンク
+///
+/// ## Member
+/// - `target`: `LinkTarget`型
+///   - 本文に書かれたリンク先
+/// - `element`: `Option<ElementId>`型
+///   - リンク先の要素(解決できない場合は`None`)
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct ElementLink {
+  pub target: LinkTarget,
+  pub element: Option<ElementId>,
+}
+
+/// 解決できないリンク
+///
+/// ## Member
+/// - `source`: `ElementId`型
+///   - リンク元の要素
+/// - `target`: `LinkTarget`型
+/// - `error`: `PathError`型
+///   - 解決できない理由
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenLink {
+  pub source: ElementId,
+  pub target: LinkTarget,
+  pub error: PathError,
+}
+
+/// 要素のリンク先を、本文の順に重複無く解決して列挙する
+pub fn element_links(
+  element: &ElementData,
+  users: &impl SousARCStorage<UserData>,
+  works: &impl SousARCStorage<WorkData>,
+  elements: &impl SousARCStorage<ElementData>,
+) -> Vec<ElementLink> {
+  let mut seen = BTreeSet::new();
+  parse_links(&element.body.content)
+    .into_iter()
+    .filter(|l| seen.insert(l.target.clone()))
+    .map(|l| ElementLink {
+      element: l
+        .target
+        .resolve(users, works, elements)
+        .ok(),
+      target: l.target,
+    })
+    .collect()
+}
+
+/// `prefix`で始まる文字列より大きい最小の文字列
+///
+/// ## Return
+/// 全ての文字が最大の文字の場合は`None`(上限が無い)
+fn prefix_end(prefix: &str) -> Option<String> {
+  let mut chars: Vec<char> = prefix.chars().collect();
+  while let Some(last) = chars.pop() {
+    let next = (u32::from(last) + 1..=u32::from(char::MAX))
+      .find_map(char::from_u32);
+    if let Some(next) = next {
+      chars.push(next);
+      return Some(chars.into_iter().collect());
+    }
+  }
+  None
+}
+
+/// リンクのセカンダリインデックス
+///
+/// ## Member
+/// - `target`: `IndexHandle<LinkTarget>`型
+///   - リンク先→リンク元の要素
+#[derive(Debug, Clone, Copy)]
+pub struct ElementLinkIndex {
+  pub target: IndexHandle<LinkTarget>,
+}
+
+impl ElementLinkIndex {
+  /// インデックスの名称
+  const NAME: &str = "element link";
+
+  /// 要素のストレージにインデックスを登録する
+  ///
+  /// ## Error
+  /// - `StorageError::IndexConflict`: 登録に失敗した
+  pub fn register(
+    storage: &mut StandardStorage<ElementData>,
+  ) -> Result<Self, StorageError<ElementData>> {
+    let target = storage.add_index(
+      Self::NAME,
+      IndexKind::NonUnique,
+      |e: &ElementData| link_targets(&e.body.content),
+    )?;
+    Ok(Self { target })
+  }
+
+  /// ストレージに登録済みのインデックスを引く
+  pub fn find(
+    storage: &StandardStorage<ElementData>,
+  ) -> Option<Self> {
+    storage
+      .index_handle(Self::NAME)
+      .map(|target| Self { target })
+  }
+
+  /// パス`prefix`で始まるパスへリンクしている要素のIDを昇順に列挙する
+  ///
+  /// ## Summary
+  /// 文字列として前方一致するものを全て含むので、
+  /// `prefix`の子孫かどうかは呼び出し側で判定する。
+  pub fn path_sources(
+    &self,
+    storage: &StandardStorage<ElementData>,
+    prefix: &str,
+  ) -> Vec<ElementId> {
+    use std::ops::Bound;
+
+    let Some(index) = storage.index(self.target) else {
+      return Vec::new();
+    };
+    let start =
+      Bound::Included(LinkTarget::Path(prefix.to_string()));
+    let end = match prefix_end(prefix) {
+      Some(end) => Bound::Excluded(LinkTarget::Path(end)),
+      None => Bound::Unbounded,
+    };
+    let sources: BTreeSet<_> =
+      index.range((start, end)).collect();
+    sources.into_iter().collect()
+  }
+
+  /// 要素へリンクしている要素のIDを昇順に列挙する
+  ///
+  /// ## Summary
+  /// IDによるリンクと、要素の現在の完全修飾パスによるリンクの両方を含む。
+  pub fn backlinks(
+    &self,
+    id: ElementId,
+    users: &impl SousARCStorage<UserData>,
+    works: &impl SousARCStorage<WorkData>,
+    elements: &StandardStorage<ElementData>,
+  ) -> Vec<ElementId> {
+    let mut sources: BTreeSet<_> = elements
+      .find(self.target, &LinkTarget::Id(id))
+      .map(|e| e.id())
+      .collect();
+    let mut path = String::new();
+    if let Some(key) = elements.key(id)
+      && key
+        .fq_name(&mut path, users, works, elements)
+        .is_ok()
+    {
+      let target = LinkTarget::Path(path);
+      sources.extend(
+        elements.find(self.target, &target).map(|e| e.id()),
+      );
+    }
+    sources.into_iter().collect()
+  }
+
+  /// 解決できないリンクを、リンク先の昇順に列挙する
+  pub fn broken_links(
+    &self,
+    users: &impl SousARCStorage<UserData>,
+    works: &impl SousARCStorage<WorkData>,
+    elements: &StandardStorage<ElementData>,
+  ) -> Vec<BrokenLink> {
+    let Some(index) = elements.index(self.target) else {
+      return Vec::new();
+    };
+    let mut broken = Vec::new();
+    for target in index.values() {
+      let Err(error) =
+        target.resolve(users, works, elements)
+      else {
+        continue;
+      };
+      broken.extend(index.ids(target).map(|source| {
+        BrokenLink {
+          source,
+          target: target.clone(),
+          error: error.clone(),
+        }
+      }));
+    }
+    broken
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::ElementLinkIndex;
+  use crate::{
+    domain::{
+      delete::DeletePolicy,
+      element::{ElementId, ElementParent},
+      link::LinkTarget,
+      path::PathError,
+      user::UserDataBody,
+    },
+    fixture::{Store, element_body, work_body},
+  };
+
+  /// `target`と、それにIDでリンクする`by_id`・パスでリンクする`by_path`
+  fn setup(
+    store: &mut Store,
+  ) -> (ElementId, ElementId, ElementId, String) {
+    store
+      .run(|tx| {
+        let user =
+          tx.spawn_user("alice", UserDataBody::default())?;
+        let work =
+          tx.spawn_work(user, "novel", work_body("novel"))?;
+        let root = ElementParent::Root(work);
+        let target = tx.spawn_element(
+          root,
+          "target",
+          element_body(""),
+        )?;
+        let path = tx.element_path(target).unwrap();
+        let by_id = tx.spawn_element(
+          root,
+          "by_id",
+          element_body(&format!("[[{target}]]")),
+        )?;
+        let by_path = tx.spawn_element(
+          root,
+          "by_path",
+          element_body(&format!("[[{path}]] [[{path}]]")),
+        )?;
+        Ok((target, by_id, by_path, path))
+      })
+      .unwrap()
+  }
+
+  #[test]
+  fn backlinks_include_id_and_path_links() {
+    let mut store = Store::default();
+    let index =
+      ElementLinkIndex::register(&mut store.elements)
+        .unwrap();
+    let (target, by_id, by_path, _) = setup(&mut store);
+
+    let mut expected = vec![by_id, by_path];
+    expected.sort();
+    assert_eq!(
+      index.backlinks(
+        target,
+        &store.users,
+        &store.works,
+        &store.elements
+      ),
+      expected
+    );
+    assert!(
+      index
+        .backlinks(
+          by_id,
+          &store.users,
+          &store.works,
+          &store.elements
+        )
+        .is_empty()
+    );
+    assert!(
+      index
+        .broken_links(
+          &store.users,
+          &store.works,
+          &store.elements
+        )
+        .is_empty()
+    );
+  }
+
+  #[test]
+  fn broken_links_after_target_is_deleted() {
+    let mut store = Store::default();
+    let index =
+      ElementLinkIndex::register(&mut store.elements)
+        .unwrap();
+    let (target, by_id, by_path, path) = setup(&mut store);
+    store
+      .run(|tx| {
+        tx.delete_element(target, DeletePolicy::Restrict)
+      })
+      .unwrap();
+
+    let broken = index.broken_links(
+      &store.users,
+      &store.works,
+      &store.elements,
+    );
+    // パスによるリンクは、同じ本文に2つあっても1件
+    assert_eq!(broken.len(), 2);
+    let by_source = |source| {
+      broken.iter().find(|b| b.source == source).unwrap()
+    };
+    assert_eq!(
+      by_source(by_id).target,
+      LinkTarget::Id(target)
+    );
+    assert!(matches!(
+      by_source(by_id).error,
+      PathError::Unresolved(ref id) if *id == target.to_string()
+    ));
+    assert_eq!(
+      by_source(by_path).target,
+      LinkTarget::Path(path)
+    );
+    assert!(matches!(
+      by_source(by_path).error,
+      PathError::Unresolved(_)
+    ));
+  }
+}
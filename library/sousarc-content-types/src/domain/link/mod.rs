@@ -0,0 +1,19 @@
+//! 要素間のリンク
+//!
+//! ## Summary
+//! - `LinkTarget`・`parse_links`: 本文の`[[...]]`からリンク先を読み取る
+//! - `ElementLinkIndex`: リンク先→リンク元の索引(被リンク・リンク切れの検索)
+//! - `Transaction::rename_element`等: 名前の変更・移動に合わせてリンクを書き換える
+//!
+//! リンクは要素の本文(Markdown)に、リンク先の完全修飾パスかIDで書く。
+//!
+//! ```text
+//! [[user::work::elem::sub]]
+//! [[01912345-6789-7abc-8def-0123456789ab]]
+//! ```
+
+pub mod parse;
+pub use parse::*;
+pub mod index;
+pub use index::*;
+pub mod rewrite;
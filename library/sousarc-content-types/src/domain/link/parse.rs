@@ -0,0 +1,342 @@
+//! 本文からのリンクの抽出・書き換え
+//!
+//! ## Summary
+//! - `parse_links`: 本文のリンクを位置とともに列挙する
+//! - `link_targets`: 本文のリンク先を重複無く列挙する
+//! - `rewrite_links`: 本文のリンク先を書き換える
+//!
+//! 本文はMarkdownとして扱い、コードブロック(```・~~~)と
+//! インラインコード(`` ` ``)の中の`[[...]]`はリンクにしない。
+
+use std::{fmt::Display, ops::Range};
+
+use serde::{Deserialize, Serialize};
+
+use crate::traits::prelude::*;
+
+use super::super::{
+  element::{ElementData, ElementId},
+  path::{
+    PATH_SEPARATOR, PathError, escape_segment,
+    resolve_element, split_path,
+  },
+  user::UserData,
+  work::WorkData,
+};
+
+/// リンク先
+///
+/// ## Summary
+/// `[[...]]`の中身がUUIDであれば`Id`、それ以外は完全修飾パスの`Path`。
+/// `Path`はエスケープを正規化した形(`ElementKey::fq_name`と同じ形)で持つ。
+#[derive(
+  Debug,
+  Clone,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+pub enum LinkTarget {
+  Id(ElementId),
+  Path(String),
+}
+
+impl Display for LinkTarget {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    match self {
+      Self::Id(id) => write!(f, "{id}"),
+      Self::Path(path) => write!(f, "{path}"),
+    }
+  }
+}
+
+impl LinkTarget {
+  /// `[[...]]`の中身を読み取る
+  ///
+  /// ## Summary
+  /// IDとして読むのはハイフン区切りの正規形のみで、
+  /// それ以外の書式はパスとして読む。
+  ///
+  /// ## Return
+  /// 空、またはパスの書式が正しくない場合は`None`
+  pub fn parse(text: &str) -> Option<Self> {
+    let text = text.trim();
+    if text.is_empty() {
+      return None;
+    }
+    if is_canonical_id(text)
+      && let Ok(id) = text.parse()
+    {
+      return Some(Self::Id(id));
+    }
+    let segments: Vec<_> = split_path(text)
+      .ok()?
+      .iter()
+      .map(|s| escape_segment(s))
+      .collect();
+    Some(Self::Path(segments.join(PATH_SEPARATOR)))
+  }
+
+  /// リンク先の要素を引く
+  ///
+  /// ## Error
+  /// - `PathError::Unresolved`: 要素が存在しない
+  /// - `PathError::Syntax`: パスが要素を指していない
+  pub fn resolve(
+    &self,
+    users: &impl SousARCStorage<UserData>,
+    works: &impl SousARCStorage<WorkData>,
+    elements: &impl SousARCStorage<ElementData>,
+  ) -> Result<ElementId, PathError> {
+    match self {
+      Self::Id(id) => match elements.get(*id) {
+        Some(_) => Ok(*id),
+        None => Err(PathError::Unresolved(id.to_string())),
+      },
+      Self::Path(path) => {
+        resolve_element(path, users, works, elements)
+      }
+    }
+  }
+}
+
+/// ハイフン区切りの正規形のUUIDか
+///
+/// ## Summary
+/// 36文字で、8・13・18・23文字目が`-`、それ以外が16進数字のもの。
+fn is_canonical_id(text: &str) -> bool {
+  text.len() == 36
+    && text.bytes().enumerate().all(|(i, b)| match i {
+      8 | 13 | 18 | 23 => b == b'-',
+      _ => b.is_ascii_hexdigit(),
+    })
+}
+
+/// 本文中のリンク
+///
+/// ## Member
+/// - `range`: `Range<usize>`型
+///   - `[[`・`]]`の内側のバイト範囲
+/// - `target`: `LinkTarget`型
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkSpan {
+  pub range: Range<usize>,
+  pub target: LinkTarget,
+}
+
+/// インラインコードを閉じる、同じ長さの`` ` ``の並びの直後の位置
+fn code_span_end(
+  bytes: &[u8],
+  run: usize,
+) -> Option<usize> {
+  let mut i = 0;
+  while i < bytes.len() {
+    let len =
+      bytes[i..].iter().take_while(|b| **b == b'`').count();
+    if len == run {
+      return Some(i + len);
+    }
+    i += len.max(1);
+  }
+  None
+}
+
+/// 1行のリンクを列挙する
+fn scan_line(
+  line: &str,
+  offset: usize,
+  links: &mut Vec<LinkSpan>,
+) {
+  let bytes = line.as_bytes();
+  let mut i = 0;
+  while i < bytes.len() {
+    match bytes[i] {
+      b'`' => {
+        let run = bytes[i..]
+          .iter()
+          .take_while(|b| **b == b'`')
+          .count();
+        i += run;
+        if let Some(end) = code_span_end(&bytes[i..], run) {
+          i += end;
+        }
+      }
+      b'[' if bytes.get(i + 1) == Some(&b'[') => {
+        let start = i + 2;
+        let Some(len) = line[start..].find("]]") else {
+          return;
+        };
+        let text = &line[start..start + len];
+        match LinkTarget::parse(text) {
+          Some(target) if !text.contains("[[") => {
+            links.push(LinkSpan {
+              range: offset + start..offset + start + len,
+              target,
+            });
+            i = start + len + 2;
+          }
+          _ => i += 1,
+        }
+      }
+      _ => i += 1,
+    }
+  }
+}
+
+/// 本文のリンクを前から順に列挙する
+///
+/// ## Summary
+/// リンクは1行に収まる`[[...]]`のみ。
+pub fn parse_links(content: &str) -> Vec<LinkSpan> {
+  let mut links = Vec::new();
+  // 開いているコードブロックの記号と長さ
+  let mut fence: Option<(u8, usize)> = None;
+  let mut offset = 0;
+  for line in content.split_inclusive('\n') {
+    let start = offset;
+    offset += line.len();
+    let trimmed = line.trim_start_matches(' ');
+    let marker = trimmed.bytes().next();
+    if line.len() - trimmed.len() <= 3
+      && let Some(marker @ (b'`' | b'~')) = marker
+    {
+      let run = trimmed
+        .bytes()
+        .take_while(|b| *b == marker)
+        .count();
+      if run >= 3 {
+        match fence {
+          None => {
+            fence = Some((marker, run));
+            continue;
+          }
+          Some((open, len))
+            if open == marker
+              && run >= len
+              && trimmed[run..].trim().is_empty() =>
+          {
+            fence = None;
+            continue;
+          }
+          _ => {}
+        }
+      }
+    }
+    if fence.is_none() {
+      scan_line(line, start, &mut links);
+    }
+  }
+  links
+}
+
+/// 本文のリンク先を昇順・重複無く列挙する
+pub fn link_targets(content: &str) -> Vec<LinkTarget> {
+  let mut targets: Vec<_> = parse_links(content)
+    .into_iter()
+    .map(|l| l.target)
+    .collect();
+  targets.sort();
+  targets.dedup();
+  targets
+}
+
+/// 本文のリンク先を書き換える
+///
+/// ## Argument
+/// - `f`: `FnMut(&LinkTarget) -> Option<String>`
+///   - 新しい`[[...]]`の中身を返す関数(`None`の場合は書き換えない)
+///
+/// ## Return
+/// 書き換えた本文(書き換えたリンクが無い場合は`None`)
+pub fn rewrite_links(
+  content: &str,
+  mut f: impl FnMut(&LinkTarget) -> Option<String>,
+) -> Option<String> {
+  let mut rewritten = String::with_capacity(content.len());
+  let mut last = 0;
+  for link in parse_links(content) {
+    if let Some(text) = f(&link.target) {
+      rewritten.push_str(&content[last..link.range.start]);
+      rewritten.push_str(&text);
+      last = link.range.end;
+    }
+  }
+  if last == 0 {
+    return None;
+  }
+  rewritten.push_str(&content[last..]);
+  Some(rewritten)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{LinkTarget, link_targets, rewrite_links};
+
+  #[test]
+  fn only_canonical_ids_are_id_links() {
+    let id = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+    assert_eq!(
+      LinkTarget::parse(id),
+      Some(LinkTarget::Id(id.parse().unwrap()))
+    );
+    assert!(matches!(
+      LinkTarget::parse(&id.to_uppercase()),
+      Some(LinkTarget::Id(_))
+    ));
+    for other in [
+      "67e5504410b1426f9247bb680e5fe0c8",
+      "{67e55044-10b1-426f-9247-bb680e5fe0c8}",
+      "urn:uuid:67e55044-10b1-426f-9247-bb680e5fe0c8",
+    ] {
+      assert!(
+        !matches!(
+          LinkTarget::parse(other),
+          Some(LinkTarget::Id(_))
+        ),
+        "{other}"
+      );
+    }
+  }
+
+  #[test]
+  fn links_in_code_are_ignored() {
+    let content = "\
+[[a]] `[[b]]` ``x ` [[c]]`` [[d]]
+```
+[[e]]
+```
+~~~~rust
+[[f]]
+~~~
+still code [[g]]
+~~~~
+   ```
+[[h]]
+   ```
+`unclosed [[i]]
+";
+    let path = |p: &str| LinkTarget::Path(p.to_string());
+    assert_eq!(
+      link_targets(content),
+      [path("a"), path("d"), path("i")]
+    );
+
+    let rewritten =
+      rewrite_links(content, |_| Some("z".to_string()))
+        .unwrap();
+    assert_eq!(
+      rewritten,
+      content
+        .replace("[[a]]", "[[z]]")
+        .replace("[[d]]", "[[z]]")
+        .replace("[[i]]", "[[z]]")
+    );
+  }
+}
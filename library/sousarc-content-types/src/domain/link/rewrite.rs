@@ -0,0 +1,358 @@
+//! 名前の変更・移動に伴うリンクの書き換え
+//!
+//! ## Summary
+//! - `Transaction::rename_element`・`rename_work`・`rename_user`: 名前を変更する
+//!
+//! 名前の変更・要素の移動で完全修飾パスが変わると、
+//! そのパスとその子孫のパスへのリンクを新しいパスに書き換える。
+//! IDによるリンクは書き換えない。
+//!
+//! 書き換えた本文は、確定時にリンク元の作品の所有者による版として記録される。
+
+use crate::traits::prelude::*;
+
+use super::{
+  super::{
+    element::{ElementData, ElementId},
+    error::HierarchyError,
+    path::{PATH_SEPARATOR, escape_segment},
+    spawn::key_name,
+    transaction::Transaction,
+    user::{UserId, UserKey},
+    work::WorkId,
+  },
+  ElementLinkIndex, LinkTarget, rewrite_links,
+};
+
+impl Transaction<'_> {
+  /// 要素の完全修飾パス(祖先が欠けている場合は`None`)
+  pub(in crate::domain) fn element_path(
+    &self,
+    id: ElementId,
+  ) -> Option<String> {
+    let mut path = String::new();
+    self
+      .elements
+      .get(id)?
+      .key()
+      .fq_name(
+        &mut path,
+        self.users.storage(),
+        self.works.storage(),
+        self.elements.storage(),
+      )
+      .ok()?;
+    Some(path)
+  }
+
+  /// 作品の完全修飾パス(所有者が存在しない場合は`None`)
+  pub(in crate::domain) fn work_path(
+    &self,
+    id: WorkId,
+  ) -> Option<String> {
+    let mut path = String::new();
+    self
+      .works
+      .get(id)?
+      .key()
+      .fq_name(&mut path, self.users.storage())
+      .ok()?;
+    Some(path)
+  }
+
+  /// パス`old`とその子孫へのリンクを、`new`以下へのリンクに書き換える
+  ///
+  /// ## Summary
+  /// `ElementLinkIndex`が登録されていれば、`old`以下へリンクしている
+  /// 要素のみを引いて書き換える。登録されていなければ全ての要素を走査する。
+  ///
+  /// ## Return
+  /// 本文を書き換えた要素のID
+  pub(in crate::domain) fn relink(
+    &mut self,
+    old: &str,
+    new: &str,
+  ) -> Result<Vec<ElementId>, HierarchyError> {
+    if old == new {
+      return Ok(Vec::new());
+    }
+    let moved = |path: &str| {
+      let rest = path.strip_prefix(old)?;
+      (rest.is_empty() || rest.starts_with(PATH_SEPARATOR))
+        .then(|| format!("{new}{rest}"))
+    };
+    let storage = self.elements.storage();
+    let sources: Vec<&ElementData> =
+      match ElementLinkIndex::find(storage) {
+        Some(index) => index
+          .path_sources(storage, old)
+          .into_iter()
+          .filter_map(|id| storage.get(id))
+          .collect(),
+        None => storage
+          .iter()
+          .filter(|e| e.body.content.contains("[["))
+          .collect(),
+      };
+    let rewrites: Vec<_> = sources
+      .into_iter()
+      .filter_map(|e| {
+        let content =
+          rewrite_links(&e.body.content, |t| match t {
+            LinkTarget::Path(path) => moved(path),
+            LinkTarget::Id(_) => None,
+          })?;
+        Some((e.id(), content))
+      })
+      .collect();
+
+    let mut rewritten = Vec::with_capacity(rewrites.len());
+    for (id, content) in rewrites {
+      self.elements.modify(id, |b| b.content = content)?;
+      rewritten.push(id);
+    }
+    Ok(rewritten)
+  }
+
+  /// 要素名を変更し、要素とその子孫へのリンクを書き換える
+  ///
+  /// ## Summary
+  /// 失敗した場合は、この操作による変更を全て取り消す。
+  ///
+  /// ## Return
+  /// 本文を書き換えた要素のID
+  ///
+  /// ## Error
+  /// - `HierarchyError::EmptyName`: 要素名が空である
+  /// - `HierarchyError::ElementNotFound`: 要素が存在しない
+  /// - `HierarchyError::Element`: 同じ親に同名の要素が存在する 等
+  pub fn rename_element(
+    &mut self,
+    id: ElementId,
+    name: impl ToString,
+  ) -> Result<Vec<ElementId>, HierarchyError> {
+    let name = key_name(name)?;
+    self.atomic(|tx| {
+      let key = tx
+        .elements
+        .get(id)
+        .ok_or(HierarchyError::ElementNotFound(id))?
+        .key()
+        .renamed(name);
+      let old = tx.element_path(id);
+      tx.elements.rekey(id, key)?;
+      match (old, tx.element_path(id)) {
+        (Some(old), Some(new)) => tx.relink(&old, &new),
+        _ => Ok(Vec::new()),
+      }
+    })
+  }
+
+  /// 作品名を変更し、作品の要素へのリンクを書き換える
+  ///
+  /// ## Summary
+  /// 失敗した場合は、この操作による変更を全て取り消す。
+  ///
+  /// ## Return
+  /// 本文を書き換えた要素のID
+  ///
+  /// ## Error
+  /// - `HierarchyError::EmptyName`: 作品名が空である
+  /// - `HierarchyError::WorkNotFound`: 作品が存在しない
+  /// - `HierarchyError::Work`: 同じユーザに同名の作品が存在する 等
+  pub fn rename_work(
+    &mut self,
+    id: WorkId,
+    name: impl ToString,
+  ) -> Result<Vec<ElementId>, HierarchyError> {
+    let name = key_name(name)?;
+    self.atomic(|tx| {
+      let key = tx
+        .works
+        .get(id)
+        .ok_or(HierarchyError::WorkNotFound(id))?
+        .key()
+        .renamed(name);
+      let old = tx.work_path(id);
+      tx.works.rekey(id, key)?;
+      match (old, tx.work_path(id)) {
+        (Some(old), Some(new)) => tx.relink(&old, &new),
+        _ => Ok(Vec::new()),
+      }
+    })
+  }
+
+  /// ユーザ名を変更し、ユーザの作品の要素へのリンクを書き換える
+  ///
+  /// ## Summary
+  /// 失敗した場合は、この操作による変更を全て取り消す。
+  ///
+  /// ## Return
+  /// 本文を書き換えた要素のID
+  ///
+  /// ## Error
+  /// - `HierarchyError::EmptyName`: ユーザ名が空である
+  /// - `HierarchyError::UserNotFound`: ユーザが存在しない
+  /// - `HierarchyError::User`: ユーザ名が既に使われている 等
+  pub fn rename_user(
+    &mut self,
+    id: UserId,
+    name: impl ToString,
+  ) -> Result<Vec<ElementId>, HierarchyError> {
+    let name = key_name(name)?;
+    self.atomic(|tx| {
+      let old = tx
+        .users
+        .get(id)
+        .ok_or(HierarchyError::UserNotFound(id))?
+        .key()
+        .clone();
+      let new = UserKey::new(name);
+      tx.users.rekey(id, new.clone())?;
+      tx.relink(
+        &escape_segment(old.name()),
+        &escape_segment(new.name()),
+      )
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    domain::{
+      element::{ElementId, ElementParent},
+      error::HierarchyError,
+      link::ElementLinkIndex,
+      transaction::Transaction,
+      user::UserDataBody,
+    },
+    fixture::{
+      Store, container_body, element_body, work_body,
+    },
+    traits::prelude::*,
+  };
+
+  #[test]
+  fn relink_rewrites_only_indexed_sources() {
+    let mut store = Store::default();
+    let index =
+      ElementLinkIndex::register(&mut store.elements)
+        .unwrap();
+
+    let (target, linking, sibling, other) = store
+      .run(|tx| {
+        let user =
+          tx.spawn_user("alice", UserDataBody::default())?;
+        let work =
+          tx.spawn_work(user, "novel", work_body("novel"))?;
+        let spawn = |tx: &mut Transaction<'_>,
+                     name: &str,
+                     content: &str| {
+          tx.spawn_element(
+            ElementParent::Root(work),
+            name,
+            element_body(content),
+          )
+        };
+        let target = spawn(tx, "a", "")?;
+        let path = tx.element_path(target).unwrap();
+        let linking =
+          spawn(tx, "b", &format!("see [[{path}]]"))?;
+        // 前方一致するが子孫ではないパス
+        let sibling =
+          spawn(tx, "c", &format!("see [[{path}x]]"))?;
+        let other = spawn(tx, "d", "see [[elsewhere]]")?;
+        Ok((target, linking, sibling, other))
+      })
+      .unwrap();
+
+    let old = store
+      .run(|tx| {
+        tx.element_path(target)
+          .ok_or(HierarchyError::ElementNotFound(target))
+      })
+      .unwrap();
+    let candidates: Vec<ElementId> =
+      index.path_sources(&store.elements, &old);
+    assert!(candidates.contains(&linking));
+    assert!(candidates.contains(&sibling));
+    assert!(!candidates.contains(&other));
+
+    let rewritten = store
+      .run(|tx| tx.rename_element(target, "z"))
+      .unwrap();
+    assert_eq!(rewritten, vec![linking]);
+    let content = |id| {
+      store.elements.get(id).unwrap().body.content.clone()
+    };
+    assert!(content(linking).contains("z]]"));
+    assert!(content(sibling).contains("ax]]"));
+  }
+
+  #[test]
+  fn relink_follows_moves_and_user_renames() {
+    let mut store = Store::default();
+    ElementLinkIndex::register(&mut store.elements)
+      .unwrap();
+    let (user, a1, b, linking) = store
+      .run(|tx| {
+        let user =
+          tx.spawn_user("alice", UserDataBody::default())?;
+        let work =
+          tx.spawn_work(user, "novel", work_body("novel"))?;
+        let root = ElementParent::Root(work);
+        let a =
+          tx.spawn_element(root, "a", container_body())?;
+        let a1 = tx.spawn_element(
+          ElementParent::Nest(a),
+          "a1",
+          element_body(""),
+        )?;
+        let b =
+          tx.spawn_element(root, "b", container_body())?;
+        // IDによるリンクとコード内のリンクは書き換えない
+        let linking = tx.spawn_element(
+          root,
+          "linking",
+          element_body(&format!(
+            "[[alice::novel::a::a1]] [[alice::novel::a]] \
+             `[[alice::novel::a::a1]]` [[{a1}]]"
+          )),
+        )?;
+        Ok((user, a1, b, linking))
+      })
+      .unwrap();
+    let content = |store: &Store| {
+      store
+        .elements
+        .get(linking)
+        .unwrap()
+        .body
+        .content
+        .clone()
+    };
+
+    store
+      .run(|tx| tx.move_element(a1, ElementParent::Nest(b)))
+      .unwrap();
+    assert_eq!(
+      content(&store),
+      format!(
+        "[[alice::novel::b::a1]] [[alice::novel::a]] \
+         `[[alice::novel::a::a1]]` [[{a1}]]"
+      )
+    );
+
+    let rewritten =
+      store.run(|tx| tx.rename_user(user, "bob")).unwrap();
+    assert_eq!(rewritten, [linking]);
+    assert_eq!(
+      content(&store),
+      format!(
+        "[[bob::novel::b::a1]] [[bob::novel::a]] \
+         `[[alice::novel::a::a1]]` [[{a1}]]"
+      )
+    );
+  }
+}
@@ -3,3 +3,29 @@ pub mod user;
 pub mod work;
 
 pub mod element;
+
+pub mod transaction;
+
+pub mod spawn;
+
+pub mod record;
+
+pub mod path;
+
+pub mod error;
+
+pub mod delete;
+
+pub mod fsck;
+
+pub mod relocate;
+
+pub mod order;
+
+pub mod history;
+
+pub mod schema;
+
+pub mod link;
+
+pub mod journal;
@@ -0,0 +1,410 @@
+//! 兄弟要素の並び順
+//!
+//! ## Summary
+//! - `SiblingPosition`: 親の`children`の中の位置の指定
+//! - `Transaction::place_element`: 要素を兄弟の中で並び替える
+//! - `Transaction::spawn_element_at`・`move_element_at`: 位置を指定して作成・移動する
+//! - `Transaction::sort_children_by_key`: 兄弟を任意のキーで並べ替える
+//!
+//! 並び順は親(作品・要素)の`children`の順序そのもので、
+//! 変更は親の更新としてストレージのイベントに流れる。
+
+use serde::{Deserialize, Serialize};
+
+use crate::traits::prelude::*;
+
+use super::{
+  element::{
+    ElementData, ElementDataBody, ElementId, ElementParent,
+  },
+  error::HierarchyError,
+  transaction::Transaction,
+};
+
+/// 親の`children`の中の位置
+///
+/// ## Summary
+/// `Index`が`children`の長さを超える場合は末尾として扱う。
+#[derive(
+  Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum SiblingPosition {
+  /// 先頭
+  First,
+
+  /// 末尾
+  Last,
+
+  /// 対象を取り除いた後の`children`の添字
+  Index(usize),
+
+  /// 指定した兄弟の直前
+  Before(ElementId),
+
+  /// 指定した兄弟の直後
+  After(ElementId),
+}
+
+impl Transaction<'_> {
+  /// 親の`children`を複製する
+  ///
+  /// ## Error
+  /// - `HierarchyError::WorkNotFound`・`ElementNotFound`: 親が存在しない
+  /// - `HierarchyError::NotContainer`: 親の要素が子を持てない
+  fn element_list(
+    &self,
+    parent: ElementParent,
+  ) -> Result<Vec<ElementId>, HierarchyError> {
+    match parent {
+      ElementParent::Root(work_id) => self
+        .works
+        .get(work_id)
+        .map(|w| w.body.children.clone())
+        .ok_or(HierarchyError::WorkNotFound(work_id)),
+      ElementParent::Nest(parent_id) => self
+        .elements
+        .get(parent_id)
+        .ok_or(HierarchyError::ElementNotFound(parent_id))?
+        .body
+        .children
+        .clone()
+        .ok_or(HierarchyError::NotContainer(parent_id)),
+    }
+  }
+
+  /// 親の`children`を置き換える
+  fn set_element_list(
+    &mut self,
+    parent: ElementParent,
+    children: Vec<ElementId>,
+  ) -> Result<(), HierarchyError> {
+    match parent {
+      ElementParent::Root(work_id) => {
+        self
+          .works
+          .modify(work_id, |b| b.children = children)?;
+      }
+      ElementParent::Nest(parent_id) => {
+        self.elements.modify(parent_id, |b| {
+          b.children = Some(children)
+        })?;
+      }
+    }
+    Ok(())
+  }
+
+  /// 要素を親の`children`の中で指定した位置へ移す
+  ///
+  /// ## Summary
+  /// 親の`children`に載っていない要素は、指定した位置へ加える。
+  /// `Before`・`After`に要素自身を指定した場合は位置を変えない。
+  ///
+  /// ## Return
+  /// 移した後の添字
+  ///
+  /// ## Error
+  /// - `HierarchyError::ElementNotFound`: 要素または親の要素が存在しない
+  /// - `HierarchyError::WorkNotFound`: 親の作品が存在しない
+  /// - `HierarchyError::NotSibling`: 基準の要素が同じ親の`children`に載っていない
+  pub fn place_element(
+    &mut self,
+    id: ElementId,
+    position: SiblingPosition,
+  ) -> Result<usize, HierarchyError> {
+    let parent = self
+      .elements
+      .get(id)
+      .ok_or(HierarchyError::ElementNotFound(id))?
+      .key()
+      .parent;
+    let mut children = self.element_list(parent)?;
+    let current = children.iter().position(|c| *c == id);
+    if let (
+      Some(index),
+      SiblingPosition::Before(anchor)
+      | SiblingPosition::After(anchor),
+    ) = (current, position)
+      && anchor == id
+    {
+      return Ok(index);
+    }
+    children.retain(|c| *c != id);
+    let anchor_index = |anchor: ElementId| {
+      children
+        .iter()
+        .position(|c| *c == anchor)
+        .ok_or(HierarchyError::NotSibling(anchor))
+    };
+    let index = match position {
+      SiblingPosition::First => 0,
+      SiblingPosition::Last => children.len(),
+      SiblingPosition::Index(index) => {
+        index.min(children.len())
+      }
+      SiblingPosition::Before(anchor) => {
+        anchor_index(anchor)?
+      }
+      SiblingPosition::After(anchor) => {
+        anchor_index(anchor)? + 1
+      }
+    };
+    if current == Some(index) {
+      return Ok(index);
+    }
+    children.insert(index, id);
+    self.set_element_list(parent, children)?;
+    Ok(index)
+  }
+
+  /// 位置を指定して、作品または要素の直下に要素を作成する
+  ///
+  /// ## Summary
+  /// 失敗した場合は、この操作による変更を全て取り消す。
+  ///
+  /// ## Return
+  /// 作成した要素のID
+  ///
+  /// ## Error
+  /// - `spawn_element`・`place_element`のエラー
+  pub fn spawn_element_at(
+    &mut self,
+    parent: ElementParent,
+    name: impl ToString,
+    body: ElementDataBody,
+    position: SiblingPosition,
+  ) -> Result<ElementId, HierarchyError> {
+    self.atomic(|tx| {
+      let id = tx.spawn_element(parent, name, body)?;
+      tx.place_element(id, position)?;
+      Ok(id)
+    })
+  }
+
+  /// 位置を指定して、要素を子孫ごと別の親の直下へ移す
+  ///
+  /// ## Summary
+  /// 移動先が現在の親と同じ場合は、並び替えのみを行う。
+  /// 失敗した場合は、この操作による変更を全て取り消す。
+  ///
+  /// ## Return
+  /// 移した後の添字
+  ///
+  /// ## Error
+  /// - `move_element`・`place_element`のエラー
+  pub fn move_element_at(
+    &mut self,
+    id: ElementId,
+    parent: ElementParent,
+    position: SiblingPosition,
+  ) -> Result<usize, HierarchyError> {
+    self.atomic(|tx| {
+      tx.move_element(id, parent)?;
+      tx.place_element(id, position)
+    })
+  }
+
+  /// 親の直下の要素を、キーの昇順に並べ替える
+  ///
+  /// ## Summary
+  /// 安定ソートで、キーが等しい要素は元の順序を保つ。
+  /// ストレージに存在しない子は末尾へ寄せる。
+  ///
+  /// ## Error
+  /// - `HierarchyError::WorkNotFound`・`ElementNotFound`: 親が存在しない
+  /// - `HierarchyError::NotContainer`: 親の要素が子を持てない
+  pub fn sort_children_by_key<K: Ord>(
+    &mut self,
+    parent: ElementParent,
+    mut f: impl FnMut(&ElementData) -> K,
+  ) -> Result<(), HierarchyError> {
+    let mut children = self.element_list(parent)?;
+    let elements = self.elements.storage();
+    children.sort_by_cached_key(|id| {
+      let key = elements.get(*id).map(&mut f);
+      (key.is_none(), key)
+    });
+    self.set_element_list(parent, children)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::SiblingPosition::{self, *};
+  use crate::{
+    domain::{
+      element::{ElementId, ElementParent},
+      error::HierarchyError,
+      user::UserDataBody,
+      work::WorkId,
+    },
+    fixture::{
+      Store, container_body, element_body, work_body,
+    },
+    traits::prelude::*,
+  };
+
+  /// 作品の直下に`names`の順で要素を作成する
+  fn siblings(
+    store: &mut Store,
+    names: &[&str],
+  ) -> (WorkId, Vec<ElementId>) {
+    store
+      .run(|tx| {
+        let user =
+          tx.spawn_user("alice", UserDataBody::default())?;
+        let work =
+          tx.spawn_work(user, "novel", work_body("novel"))?;
+        let ids = names
+          .iter()
+          .map(|name| {
+            tx.spawn_element(
+              ElementParent::Root(work),
+              name,
+              element_body(name),
+            )
+          })
+          .collect::<Result<_, _>>()?;
+        Ok((work, ids))
+      })
+      .unwrap()
+  }
+
+  fn order(store: &Store, work: WorkId) -> Vec<ElementId> {
+    store.works.get(work).unwrap().body.children.clone()
+  }
+
+  #[test]
+  fn place_element_moves_within_siblings() {
+    let mut store = Store::default();
+    let (work, ids) =
+      siblings(&mut store, &["a", "b", "c", "d"]);
+    let [a, b, c, d] = ids[..] else { unreachable!() };
+    let mut place = |id, position: SiblingPosition| {
+      store.run(|tx| tx.place_element(id, position))
+    };
+
+    assert_eq!(place(d, First).unwrap(), 0);
+    assert_eq!(place(d, Last).unwrap(), 3);
+    assert_eq!(place(a, Index(2)).unwrap(), 2);
+    // 長さを超える添字は末尾になる
+    assert_eq!(place(b, Index(100)).unwrap(), 3);
+    assert_eq!(place(d, Before(c)).unwrap(), 0);
+    assert_eq!(place(b, After(d)).unwrap(), 1);
+    // 自身を基準にした場合は動かない
+    assert_eq!(place(a, Before(a)).unwrap(), 3);
+    assert_eq!(order(&store, work), [d, b, c, a]);
+
+    let stranger = ElementId::new();
+    let result =
+      store.run(|tx| tx.place_element(a, After(stranger)));
+    assert!(matches!(
+      result,
+      Err(HierarchyError::NotSibling(id)) if id == stranger
+    ));
+    assert_eq!(order(&store, work), [d, b, c, a]);
+  }
+
+  #[test]
+  fn place_element_lists_unlisted_children() {
+    let mut store = Store::default();
+    let (work, ids) = siblings(&mut store, &["a", "b"]);
+    store
+      .works
+      .modify(work, |b| b.children.retain(|c| *c != ids[0]))
+      .unwrap();
+
+    let index = store
+      .run(|tx| tx.place_element(ids[0], After(ids[1])))
+      .unwrap();
+    assert_eq!(index, 1);
+    assert_eq!(order(&store, work), [ids[1], ids[0]]);
+  }
+
+  #[test]
+  fn spawn_and_move_at_a_position() {
+    let mut store = Store::default();
+    let (work, ids) = siblings(&mut store, &["a", "b"]);
+    let (box_id, spawned) = store
+      .run(|tx| {
+        let box_id = tx.spawn_element_at(
+          ElementParent::Root(work),
+          "box",
+          container_body(),
+          Index(1),
+        )?;
+        let spawned = tx.spawn_element_at(
+          ElementParent::Root(work),
+          "c",
+          element_body("c"),
+          First,
+        )?;
+        Ok((box_id, spawned))
+      })
+      .unwrap();
+    assert_eq!(
+      order(&store, work),
+      [spawned, ids[0], box_id, ids[1]]
+    );
+
+    let index = store
+      .run(|tx| {
+        tx.move_element_at(
+          ids[1],
+          ElementParent::Nest(box_id),
+          First,
+        )
+      })
+      .unwrap();
+    assert_eq!(index, 0);
+    assert_eq!(
+      order(&store, work),
+      [spawned, ids[0], box_id]
+    );
+    let box_children: Vec<_> = store
+      .elements
+      .get(box_id)
+      .unwrap()
+      .children()
+      .collect();
+    assert_eq!(box_children, [ids[1]]);
+  }
+
+  #[test]
+  fn sort_children_by_key_is_stable() {
+    let mut store = Store::default();
+    let (work, ids) =
+      siblings(&mut store, &["b2", "a1", "b1", "a2", "c"]);
+    let missing = ElementId::new();
+    store
+      .works
+      .modify(work, |b| b.children.insert(0, missing))
+      .unwrap();
+
+    // 先頭の文字のみで比べるので、`a1`・`a2`と`b2`・`b1`は元の順を保つ
+    store
+      .run(|tx| {
+        tx.sort_children_by_key(
+          ElementParent::Root(work),
+          |e| e.key().name.chars().next(),
+        )
+      })
+      .unwrap();
+    let [b2, a1, b1, a2, c] = ids[..] else {
+      unreachable!()
+    };
+    assert_eq!(
+      order(&store, work),
+      [a1, a2, b2, b1, c, missing]
+    );
+
+    let result = store.run(|tx| {
+      tx.sort_children_by_key(ElementParent::Nest(c), |e| {
+        e.id()
+      })
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::NotContainer(id)) if id == c
+    ));
+  }
+}
@@ -0,0 +1,431 @@
+//! 完全修飾パス
+//!
+//! ## Summary
+//! - `WorkKey::fq_name`・`ElementKey::fq_name`: 完全修飾パスを書き出す
+//! - `resolve_path`・`resolve_work`・`resolve_element`: 完全修飾パスからIDを引く
+//! - `escape_segment`・`split_path`: パスの各部分のエスケープと分割
+//!
+//! パスはユーザ名・作品名・要素名(親から順)を`::`で繋いだもの。
+//!
+//! ```text
+//! user::work::elem::sub
+//! ```
+//!
+//! 名前に含まれる`\`と`:`は`\`でエスケープする(`a::b`は`a\:\:b`になる)。
+
+use std::fmt::{Display, Write};
+
+use crate::traits::prelude::*;
+
+use super::{
+  element::{
+    ElementData, ElementId, ElementKey, ElementParent,
+  },
+  user::{UserData, UserId, UserKey},
+  work::{WorkData, WorkId, WorkKey},
+};
+
+/// パスの区切り
+pub const PATH_SEPARATOR: &str = "::";
+
+/// 完全修飾パスの書き出し・解決のエラー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathError {
+  /// 祖先のユーザが存在しない
+  UserNotFound(UserId),
+
+  /// 祖先の作品が存在しない
+  WorkNotFound(WorkId),
+
+  /// 祖先の要素が存在しない
+  ElementNotFound(ElementId),
+
+  /// 要素の親を辿ると循環している
+  Cycle(ElementId),
+
+  /// パスの書式が正しくない
+  Syntax(String),
+
+  /// パスに対応するデータが存在しない(解決できた部分までのパス)
+  Unresolved(String),
+
+  /// 書き出し先への書き込みに失敗した
+  Fmt,
+}
+
+impl Display for PathError {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    match self {
+      Self::UserNotFound(id) => {
+        write!(f, "ancestor user not found: {id}")
+      }
+      Self::WorkNotFound(id) => {
+        write!(f, "ancestor work not found: {id}")
+      }
+      Self::ElementNotFound(id) => {
+        write!(f, "ancestor element not found: {id}")
+      }
+      Self::Cycle(id) => {
+        write!(f, "element ancestry has a cycle at {id}")
+      }
+      Self::Syntax(reason) => {
+        write!(f, "malformed path: {reason}")
+      }
+      Self::Unresolved(path) => {
+        write!(f, "path not found: {path}")
+      }
+      Self::Fmt => write!(f, "failed to write a path"),
+    }
+  }
+}
+
+impl std::error::Error for PathError {}
+
+impl From<std::fmt::Error> for PathError {
+  fn from(_: std::fmt::Error) -> Self {
+    Self::Fmt
+  }
+}
+
+/// パスの1つの部分(名前)をエスケープする
+pub fn escape_segment(name: &str) -> String {
+  let mut escaped = String::with_capacity(name.len());
+  for c in name.chars() {
+    if matches!(c, '\\' | ':') {
+      escaped.push('\\');
+    }
+    escaped.push(c);
+  }
+  escaped
+}
+
+/// パスを`::`で分割し、各部分のエスケープを解く
+///
+/// ## Error
+/// - `PathError::Syntax`: 末尾が`\`で終わる、または空の部分がある
+pub fn split_path(
+  path: &str,
+) -> Result<Vec<String>, PathError> {
+  let mut segments = Vec::new();
+  let mut current = String::new();
+  let mut chars = path.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      '\\' => match chars.next() {
+        Some(escaped) => current.push(escaped),
+        None => {
+          return Err(PathError::Syntax(
+            "trailing escape character".to_string(),
+          ));
+        }
+      },
+      ':' if chars.peek() == Some(&':') => {
+        chars.next();
+        segments.push(std::mem::take(&mut current));
+      }
+      _ => current.push(c),
+    }
+  }
+  segments.push(current);
+  if segments.iter().any(String::is_empty) {
+    return Err(PathError::Syntax(format!(
+      "empty segment in `{path}`"
+    )));
+  }
+  Ok(segments)
+}
+
+impl WorkKey {
+  /// 作品の完全修飾パス(`user::work`)を書き出す
+  ///
+  /// ## Error
+  /// - `PathError::UserNotFound`: 所有者のユーザが存在しない
+  /// - `PathError::Fmt`: 書き込みに失敗した
+  pub fn fq_name(
+    &self,
+    wrt: &mut impl Write,
+    users: &impl SousARCStorage<UserData>,
+  ) -> Result<(), PathError> {
+    let user = users
+      .key(self.user_id())
+      .ok_or(PathError::UserNotFound(self.user_id()))?;
+    write!(
+      wrt,
+      "{u}{PATH_SEPARATOR}{w}",
+      u = escape_segment(user.name()),
+      w = escape_segment(self.work_name()),
+    )?;
+    Ok(())
+  }
+}
+
+impl ElementKey {
+  /// 要素の完全修飾パス(`user::work::elem::...`)を書き出す
+  ///
+  /// ## Error
+  /// - `PathError::UserNotFound`・`WorkNotFound`・`ElementNotFound`:
+  ///   祖先のデータが存在しない
+  /// - `PathError::Cycle`: 親を辿ると循環している
+  /// - `PathError::Fmt`: 書き込みに失敗した
+  pub fn fq_name(
+    &self,
+    wrt: &mut impl Write,
+    users: &impl SousARCStorage<UserData>,
+    works: &impl SousARCStorage<WorkData>,
+    elements: &impl SousARCStorage<ElementData>,
+  ) -> Result<(), PathError> {
+    let mut names = vec![self.name.as_str()];
+    let mut parent = self.parent;
+    // 要素の数より多く辿った場合は循環している
+    let work_id = loop {
+      match parent {
+        ElementParent::Root(work_id) => break work_id,
+        ElementParent::Nest(id) => {
+          if names.len() > elements.len() {
+            return Err(PathError::Cycle(id));
+          }
+          let key = elements
+            .key(id)
+            .ok_or(PathError::ElementNotFound(id))?;
+          names.push(&key.name);
+          parent = key.parent;
+        }
+      }
+    };
+    works
+      .key(work_id)
+      .ok_or(PathError::WorkNotFound(work_id))?
+      .fq_name(wrt, users)?;
+    for name in names.iter().rev() {
+      write!(
+        wrt,
+        "{PATH_SEPARATOR}{}",
+        escape_segment(name)
+      )?;
+    }
+    Ok(())
+  }
+}
+
+/// 完全修飾パスが指すデータ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PathTarget {
+  User(UserId),
+  Work(WorkId),
+  Element(ElementId),
+}
+
+/// 完全修飾パスを解決する
+///
+/// ## Summary
+/// 部分が1つならユーザ、2つなら作品、3つ以上なら要素を指す。
+///
+/// ## Error
+/// - `PathError::Syntax`: パスの書式が正しくない
+/// - `PathError::Unresolved`: パスに対応するデータが存在しない
+pub fn resolve_path(
+  path: &str,
+  users: &impl SousARCStorage<UserData>,
+  works: &impl SousARCStorage<WorkData>,
+  elements: &impl SousARCStorage<ElementData>,
+) -> Result<PathTarget, PathError> {
+  let segments = split_path(path)?;
+  let unresolved = |depth: usize| {
+    let resolved: Vec<_> = segments[..depth]
+      .iter()
+      .map(|s| escape_segment(s))
+      .collect();
+    PathError::Unresolved(resolved.join(PATH_SEPARATOR))
+  };
+
+  let mut segments_iter = segments.iter();
+  let Some(user) = segments_iter.next() else {
+    return Err(PathError::Syntax(
+      "empty path".to_string(),
+    ));
+  };
+  let user_id = users
+    .id(&UserKey::new(user))
+    .ok_or_else(|| unresolved(1))?;
+  let Some(work) = segments_iter.next() else {
+    return Ok(PathTarget::User(user_id));
+  };
+  let work_id = works
+    .id(&WorkKey::from((user_id, work)))
+    .ok_or_else(|| unresolved(2))?;
+
+  let mut parent = ElementParent::Root(work_id);
+  let mut target = PathTarget::Work(work_id);
+  for (depth, name) in segments_iter.enumerate() {
+    let key = ElementKey { parent, name: name.clone() };
+    let id = elements
+      .id(&key)
+      .ok_or_else(|| unresolved(depth + 3))?;
+    parent = ElementParent::Nest(id);
+    target = PathTarget::Element(id);
+  }
+  Ok(target)
+}
+
+/// 作品の完全修飾パス(`user::work`)を解決する
+///
+/// ## Error
+/// - `PathError::Syntax`: パスの書式が正しくない、または作品を指していない
+/// - `PathError::Unresolved`: パスに対応するデータが存在しない
+pub fn resolve_work(
+  path: &str,
+  users: &impl SousARCStorage<UserData>,
+  works: &impl SousARCStorage<WorkData>,
+) -> Result<WorkId, PathError> {
+  let segments = split_path(path)?;
+  let [user, work] = segments.as_slice() else {
+    return Err(PathError::Syntax(format!(
+      "`{path}` is not a work path"
+    )));
+  };
+  let user_id =
+    users.id(&UserKey::new(user)).ok_or_else(|| {
+      PathError::Unresolved(escape_segment(user))
+    })?;
+  works
+    .id(&WorkKey::from((user_id, work)))
+    .ok_or_else(|| PathError::Unresolved(path.to_string()))
+}
+
+/// 要素の完全修飾パス(`user::work::elem::...`)を解決する
+///
+/// ## Error
+/// - `PathError::Syntax`: パスの書式が正しくない、または要素を指していない
+/// - `PathError::Unresolved`: パスに対応するデータが存在しない
+pub fn resolve_element(
+  path: &str,
+  users: &impl SousARCStorage<UserData>,
+  works: &impl SousARCStorage<WorkData>,
+  elements: &impl SousARCStorage<ElementData>,
+) -> Result<ElementId, PathError> {
+  match resolve_path(path, users, works, elements)? {
+    PathTarget::Element(id) => Ok(id),
+    _ => Err(PathError::Syntax(format!(
+      "`{path}` is not an element path"
+    ))),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    domain::user::UserDataBody,
+    fixture::{
+      Store, container_body, element_body, work_body,
+    },
+  };
+
+  #[test]
+  fn escape_and_split_round_trip() {
+    let names = ["a", "a:b", "a::b", "c:\\d", "\\", ":"];
+    assert_eq!(escape_segment("a::b"), "a\\:\\:b");
+    assert_eq!(escape_segment("c:\\d"), "c\\:\\\\d");
+    let path = names
+      .iter()
+      .map(|n| escape_segment(n))
+      .collect::<Vec<_>>()
+      .join(PATH_SEPARATOR);
+    assert_eq!(split_path(&path).unwrap(), names);
+
+    for malformed in ["", "a::", "::a", "a::::b", "a\\"] {
+      assert!(
+        matches!(
+          split_path(malformed),
+          Err(PathError::Syntax(_))
+        ),
+        "{malformed}"
+      );
+    }
+  }
+
+  #[test]
+  fn fq_names_resolve_back_to_ids() {
+    let mut store = Store::default();
+    let (user, work, outer, inner) = store
+      .run(|tx| {
+        let user = tx.spawn_user(
+          "a:li\\ce",
+          UserDataBody::default(),
+        )?;
+        let work = tx.spawn_work(
+          user,
+          "no::vel",
+          work_body("novel"),
+        )?;
+        let outer = tx.spawn_element(
+          ElementParent::Root(work),
+          "chap:ter",
+          container_body(),
+        )?;
+        let inner = tx.spawn_element(
+          ElementParent::Nest(outer),
+          "sec\\tion",
+          element_body("text"),
+        )?;
+        Ok((user, work, outer, inner))
+      })
+      .unwrap();
+    let Store { users, works, elements } = &store;
+
+    let mut path = String::new();
+    works
+      .key(work)
+      .unwrap()
+      .fq_name(&mut path, users)
+      .unwrap();
+    assert_eq!(path, "a\\:li\\\\ce::no\\:\\:vel");
+    assert_eq!(resolve_work(&path, users, works), Ok(work));
+
+    let mut path = String::new();
+    elements
+      .key(inner)
+      .unwrap()
+      .fq_name(&mut path, users, works, elements)
+      .unwrap();
+    assert_eq!(
+      path,
+      "a\\:li\\\\ce::no\\:\\:vel::chap\\:ter::sec\\\\tion"
+    );
+    assert_eq!(
+      resolve_element(&path, users, works, elements),
+      Ok(inner)
+    );
+
+    let resolve =
+      |path| resolve_path(path, users, works, elements);
+    assert_eq!(
+      resolve("a\\:li\\\\ce"),
+      Ok(PathTarget::User(user))
+    );
+    assert_eq!(
+      resolve("a\\:li\\\\ce::no\\:\\:vel::chap\\:ter"),
+      Ok(PathTarget::Element(outer))
+    );
+    assert_eq!(
+      resolve("a\\:li\\\\ce::no\\:\\:vel::missing::x"),
+      Err(PathError::Unresolved(
+        "a\\:li\\\\ce::no\\:\\:vel::missing".to_string()
+      ))
+    );
+    // エスケープしない`::`は区切りとして扱う
+    assert_eq!(
+      resolve("a\\:li\\\\ce::no::vel"),
+      Err(PathError::Unresolved(
+        "a\\:li\\\\ce::no".to_string()
+      ))
+    );
+    assert!(matches!(
+      resolve_work(&path, users, works),
+      Err(PathError::Syntax(_))
+    ));
+  }
+}
@@ -0,0 +1,209 @@
+//! 通信用のデータレコード
+//!
+//! ## Summary
+//! - `DataRecord`: ID・キー・本体をまとめた、シリアライズ可能なエンベロープ
+//! - `UserRecord`・`WorkRecord`・`ElementRecord`: 各データ型のレコード
+//!
+//! サーバとwasmクライアントで同じ形式を使う為、
+//! MessagePackでは`to_msgpack`・`from_msgpack`(フィールド名付きのマップ)を、
+//! JSONでは`serde_json`をそのまま使う。いずれも次の形になる。
+//!
+//! ```text
+//! { "id": ..., "key": ..., "body": { ... } }
+//! ```
+//!
+//! レコードは要素の履歴等、本体以外の状態を持たないので、
+//! データからは参照で作成し、データへは戻さない。
+//! 受け取ったレコードはストレージの`modify`等で本体として反映する。
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use super::{
+  element::ElementData, user::UserData, work::WorkData,
+};
+use crate::traits::prelude::*;
+
+/// ID・キー・本体をまとめたレコード
+///
+/// ## Member
+/// - `id`: `D::Id`型
+/// - `key`: `D::Key`型
+/// - `body`: `D::Body`型
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound(
+  serialize = "D::Body: Serialize",
+  deserialize = "D::Body: DeserializeOwned"
+))]
+pub struct DataRecord<D: SousARCDataHasBody> {
+  pub id: D::Id,
+  pub key: D::Key,
+  pub body: D::Body,
+}
+
+impl<D> Clone for DataRecord<D>
+where
+  D: SousARCDataHasBody,
+  D::Body: Clone,
+{
+  fn clone(&self) -> Self {
+    Self {
+      id: self.id,
+      key: self.key.clone(),
+      body: self.body.clone(),
+    }
+  }
+}
+
+/// ユーザのレコード
+pub type UserRecord = DataRecord<UserData>;
+
+/// 作品のレコード
+pub type WorkRecord = DataRecord<WorkData>;
+
+/// 要素のレコード
+pub type ElementRecord = DataRecord<ElementData>;
+
+impl<D: SousARCDataHasBody> DataRecord<D> {
+  pub fn new(
+    id: D::Id,
+    key: D::Key,
+    body: D::Body,
+  ) -> Self {
+    Self { id, key, body }
+  }
+
+  /// IDを取得する
+  pub fn id(&self) -> D::Id {
+    self.id
+  }
+
+  /// キーを取得する
+  pub fn key(&self) -> &D::Key {
+    &self.key
+  }
+}
+
+impl<D> DataRecord<D>
+where
+  D: SousARCDataHasBody,
+  D::Body: Serialize,
+{
+  /// MessagePack(フィールド名付き)に変換する
+  ///
+  /// ## Error
+  /// - `rmp_serde::encode::Error`: 変換に失敗した
+  pub fn to_msgpack(
+    &self,
+  ) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+    rmp_serde::to_vec_named(self)
+  }
+}
+
+impl<D> DataRecord<D>
+where
+  D: SousARCDataHasBody,
+  D::Body: DeserializeOwned,
+{
+  /// MessagePackから変換する
+  ///
+  /// ## Summary
+  /// フィールド名付きのマップと、フィールド順の配列のどちらも受け付ける。
+  ///
+  /// ## Error
+  /// - `rmp_serde::decode::Error`: 変換に失敗した
+  pub fn from_msgpack(
+    bytes: &[u8],
+  ) -> Result<Self, rmp_serde::decode::Error> {
+    rmp_serde::from_slice(bytes)
+  }
+}
+
+impl<D> From<&D> for DataRecord<D>
+where
+  D: SousARCDataHasBody,
+  D::Body: Clone,
+{
+  fn from(data: &D) -> Self {
+    Self {
+      id: data.id(),
+      key: data.key().clone(),
+      body: data.body().clone(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{ElementRecord, UserRecord};
+  use crate::{
+    domain::{element::ElementParent, user::UserDataBody},
+    fixture::{Store, element_body, work_body},
+    traits::prelude::*,
+  };
+
+  #[test]
+  fn records_round_trip_in_msgpack_and_json() {
+    let mut store = Store::default();
+    let (user, element) = store
+      .run(|tx| {
+        let user = tx.spawn_user(
+          "alice",
+          UserDataBody {
+            display_name: "Alice".to_string(),
+            ..Default::default()
+          },
+        )?;
+        let work =
+          tx.spawn_work(user, "novel", work_body("novel"))?;
+        let element = tx.spawn_element(
+          ElementParent::Root(work),
+          "chapter",
+          element_body("text"),
+        )?;
+        Ok((user, element))
+      })
+      .unwrap();
+
+    let data = store.users.get(user).unwrap();
+    let record = UserRecord::from(data);
+    let bytes = record.to_msgpack().unwrap();
+    let decoded = UserRecord::from_msgpack(&bytes).unwrap();
+    assert_eq!(decoded.id(), user);
+    assert_eq!(decoded.key().name(), "alice");
+    assert_eq!(decoded.body.display_name, "Alice");
+    let json = serde_json::to_string(&record).unwrap();
+    let decoded: UserRecord =
+      serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.id(), user);
+    assert_eq!(decoded.body.display_name, "Alice");
+
+    // フィールド名付きのマップになる
+    let value: serde_json::Value =
+      serde_json::from_str(&json).unwrap();
+    for field in ["id", "key", "body"] {
+      assert!(value.get(field).is_some(), "{field}");
+    }
+    assert_eq!(bytes[0], 0x83);
+
+    // 配列の形式も受け付ける
+    let positional = rmp_serde::to_vec(&record).unwrap();
+    let decoded =
+      UserRecord::from_msgpack(&positional).unwrap();
+    assert_eq!(decoded.key().name(), "alice");
+
+    // 参照から作成するので、要素の履歴は失われない
+    let data = store.elements.get(element).unwrap();
+    let record = ElementRecord::from(data);
+    let json = serde_json::to_string(&record).unwrap();
+    let decoded: ElementRecord =
+      serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.key(), data.key());
+    assert_eq!(decoded.body.content, "text");
+    let bytes = record.to_msgpack().unwrap();
+    let decoded =
+      ElementRecord::from_msgpack(&bytes).unwrap();
+    assert_eq!(decoded.id(), element);
+    assert_eq!(decoded.body.content, "text");
+    assert_eq!(data.history().len(), 1);
+  }
+}
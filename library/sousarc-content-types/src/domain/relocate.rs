@@ -0,0 +1,262 @@
+//! 要素の移動
+//!
+//! ## Summary
+//! - `Transaction::move_element`: 要素を子孫ごと別の親の直下へ移す
+//!
+//! 子孫のキーは移動する要素のIDを指すので、書き換えるのは移動する要素のキーと、
+//! 移動元・移動先の`children`のみ。移動先は別の作品でもよい。
+//! 別の作品へ移す場合は、子孫を含むレコードを移動先の作品のスキーマで検証する。
+
+use crate::traits::prelude::*;
+
+use super::{
+  element::{ElementId, ElementParent},
+  error::HierarchyError,
+  transaction::Transaction,
+};
+
+impl Transaction<'_> {
+  /// 要素を子孫ごと別の親の直下へ移す
+  ///
+  /// ## Summary
+  /// 移動元の`children`から取り除き、移動先の`children`の末尾に登録する。
+  /// 移動先が現在の親と同じ場合は何もしない。
+  /// 要素とその子孫へのパスによるリンクは、移動後のパスに書き換える。
+  /// 失敗した場合は、この操作による変更を全て取り消す。
+  ///
+  /// ## Error
+  /// - `HierarchyError::ElementNotFound`: 要素または移動先の要素が存在しない
+  /// - `HierarchyError::WorkNotFound`: 移動先の作品が存在しない
+  /// - `HierarchyError::NotContainer`: 移動先の要素が子を持てない
+  /// - `HierarchyError::CyclicParent`: 移動先が要素自身またはその子孫である
+  /// - `HierarchyError::Schema`: 別の作品へ移す要素・子孫のレコードが、移動先のスキーマに従わない
+  /// - `HierarchyError::Element`: 移動先に同名の要素が存在する 等
+  pub fn move_element(
+    &mut self,
+    id: ElementId,
+    parent: ElementParent,
+  ) -> Result<(), HierarchyError> {
+    self.atomic(|tx| {
+      let old_parent = tx
+        .elements
+        .get(id)
+        .ok_or(HierarchyError::ElementNotFound(id))?
+        .key()
+        .parent;
+      tx.check_element_parent(Some(id), parent)?;
+      if old_parent == parent {
+        return Ok(());
+      }
+      tx.check_moved_records(id, parent)?;
+      tx.reparent_element(id, parent)?;
+      tx.unlink_element(id, old_parent)
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    domain::{
+      element::{ElementId, ElementParent},
+      error::HierarchyError,
+      user::UserDataBody,
+      work::WorkId,
+    },
+    fixture::{
+      Store, container_body, element_body, spell_body,
+      spell_schema, work_body,
+    },
+    traits::prelude::*,
+  };
+
+  /// `novel`(`a`→[`a1`→[`a`], `s`]・`b`)と`essay`(`a`)
+  ///
+  /// `novel`だけがスキーマ`spell`を持ち、`s`はそのレコードを持つ。
+  struct Tree {
+    novel: WorkId,
+    essay: WorkId,
+    a: ElementId,
+    a1: ElementId,
+    a11: ElementId,
+    s: ElementId,
+    b: ElementId,
+    essay_a: ElementId,
+  }
+
+  fn tree(store: &mut Store) -> Tree {
+    store
+      .run(|tx| {
+        let user =
+          tx.spawn_user("alice", UserDataBody::default())?;
+        let novel =
+          tx.spawn_work(user, "novel", work_body("novel"))?;
+        let essay =
+          tx.spawn_work(user, "essay", work_body("essay"))?;
+        tx.define_schema(novel, "spell", spell_schema())?;
+        let root = ElementParent::Root(novel);
+        let a =
+          tx.spawn_element(root, "a", container_body())?;
+        let a1 = tx.spawn_element(
+          ElementParent::Nest(a),
+          "a1",
+          container_body(),
+        )?;
+        let a11 = tx.spawn_element(
+          ElementParent::Nest(a1),
+          "a",
+          element_body("text"),
+        )?;
+        let s = tx.spawn_element(
+          ElementParent::Nest(a),
+          "s",
+          spell_body(3),
+        )?;
+        let b =
+          tx.spawn_element(root, "b", container_body())?;
+        let essay_a = tx.spawn_element(
+          ElementParent::Root(essay),
+          "a",
+          element_body("text"),
+        )?;
+        Ok(Tree { novel, essay, a, a1, a11, s, b, essay_a })
+      })
+      .unwrap()
+  }
+
+  fn children(
+    store: &Store,
+    parent: ElementParent,
+  ) -> Vec<ElementId> {
+    match parent {
+      ElementParent::Root(id) => {
+        store.works.get(id).unwrap().body.children.clone()
+      }
+      ElementParent::Nest(id) => {
+        store.elements.get(id).unwrap().children().collect()
+      }
+    }
+  }
+
+  #[test]
+  fn moves_subtree_and_updates_both_parents() {
+    let mut store = Store::default();
+    let t = tree(&mut store);
+
+    store
+      .run(|tx| {
+        tx.move_element(t.a1, ElementParent::Nest(t.b))
+      })
+      .unwrap();
+    assert_eq!(
+      children(&store, ElementParent::Nest(t.a)),
+      [t.s]
+    );
+    assert_eq!(
+      children(&store, ElementParent::Nest(t.b)),
+      [t.a1]
+    );
+    assert_eq!(
+      store.elements.key(t.a1).unwrap().parent,
+      ElementParent::Nest(t.b)
+    );
+    // 子孫のキーは変わらない
+    assert_eq!(
+      store.elements.key(t.a11).unwrap().parent,
+      ElementParent::Nest(t.a1)
+    );
+
+    // レコードを持たない子孫は、スキーマの無い作品へ移せる
+    store
+      .run(|tx| {
+        tx.move_element(t.a1, ElementParent::Root(t.essay))
+      })
+      .unwrap();
+    assert!(
+      children(&store, ElementParent::Nest(t.b)).is_empty()
+    );
+    assert_eq!(
+      children(&store, ElementParent::Root(t.essay)),
+      [t.essay_a, t.a1]
+    );
+  }
+
+  #[test]
+  fn refused_moves_change_nothing() {
+    let mut store = Store::default();
+    let t = tree(&mut store);
+    let mut attempt = |id, parent| {
+      store
+        .run(|tx| tx.move_element(id, parent))
+        .unwrap_err()
+    };
+
+    for target in [t.a, t.a1] {
+      assert!(matches!(
+        attempt(t.a, ElementParent::Nest(target)),
+        HierarchyError::CyclicParent(id) if id == t.a
+      ));
+    }
+    assert!(matches!(
+      attempt(t.a1, ElementParent::Nest(t.a11)),
+      HierarchyError::NotContainer(id) if id == t.a11
+    ));
+    // 移動先に同名の要素がある
+    assert!(matches!(
+      attempt(t.a11, ElementParent::Root(t.novel)),
+      HierarchyError::Element(_)
+    ));
+    // 子孫の`s`のレコードが、移動先の作品のスキーマに無い
+    assert!(matches!(
+      attempt(t.a, ElementParent::Root(t.essay)),
+      HierarchyError::Schema(_)
+    ));
+
+    assert_eq!(
+      children(&store, ElementParent::Root(t.novel)),
+      [t.a, t.b]
+    );
+    assert_eq!(
+      children(&store, ElementParent::Root(t.essay)),
+      [t.essay_a]
+    );
+    assert_eq!(
+      children(&store, ElementParent::Nest(t.a)),
+      [t.a1, t.s]
+    );
+    assert_eq!(
+      children(&store, ElementParent::Nest(t.a1)),
+      [t.a11]
+    );
+    assert_eq!(
+      store.elements.key(t.a).unwrap().parent,
+      ElementParent::Root(t.novel)
+    );
+  }
+
+  #[test]
+  fn failure_after_a_move_rolls_it_back() {
+    let mut store = Store::default();
+    let t = tree(&mut store);
+    let result = store.run(|tx| {
+      tx.move_element(t.a1, ElementParent::Nest(t.b))?;
+      tx.move_element(t.a11, ElementParent::Root(t.novel))
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::Element(_))
+    ));
+
+    assert_eq!(
+      children(&store, ElementParent::Nest(t.a)),
+      [t.a1, t.s]
+    );
+    assert!(
+      children(&store, ElementParent::Nest(t.b)).is_empty()
+    );
+    assert_eq!(
+      store.elements.key(t.a1).unwrap().parent,
+      ElementParent::Nest(t.a)
+    );
+  }
+}
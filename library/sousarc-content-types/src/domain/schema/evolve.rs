@@ -0,0 +1,674 @@
+//! スキーマの定義・変更とレコードの検証
+//!
+//! ## Summary
+//! - `Transaction::define_schema`・`remove_schema`: 作品のスキーマを定義・削除する
+//! - `Transaction::evolve_schema`: スキーマを変更し、既存のレコードを移行する
+//! - `Transaction::set_element_kind`: 要素の種類・属性を検証して書き換える
+//!
+//! レコードは`Transaction`の確定時にも検証するので、
+//! 要素の本体を直接書き換えても、スキーマに従わないレコードは確定できない。
+//!
+//! スキーマの変更は`SchemaChange`で表し、次の規則に従う。
+//! - 属性の追加: 省略できない属性には、既存のレコードへ入れる値が必要
+//! - 属性の改名: 既存のレコードの値も改名し、定義の順序は保つ
+//! - 属性の削除: 既存のレコードの値も削除する
+
+use serde::{Deserialize, Serialize};
+
+use crate::traits::prelude::*;
+
+use super::{
+  super::{
+    element::{ElementId, ElementKind, ElementParent},
+    error::HierarchyError,
+    transaction::Transaction,
+    work::WorkId,
+  },
+  CustomValue, FieldDef, RecordSchema, SchemaError,
+};
+
+/// スキーマの変更
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum SchemaChange {
+  /// 属性を追加する
+  ///
+  /// `default`が`Some`の場合は、その属性を持たない既存のレコードに入れる。
+  AddField {
+    name: String,
+    def: FieldDef,
+    default: Option<CustomValue>,
+  },
+
+  /// 属性を改名する
+  RenameField { from: String, to: String },
+
+  /// 属性を削除する
+  RemoveField { name: String },
+}
+
+impl Transaction<'_> {
+  /// 作品のスキーマを取り出す
+  fn schema_of(
+    &self,
+    work_id: WorkId,
+    name: &str,
+  ) -> Result<RecordSchema, HierarchyError> {
+    self
+      .works
+      .get(work_id)
+      .ok_or(HierarchyError::WorkNotFound(work_id))?
+      .body
+      .schemas
+      .get(name)
+      .cloned()
+      .ok_or_else(|| {
+        SchemaError::SchemaNotFound {
+          work: work_id,
+          schema: name.to_string(),
+        }
+        .into()
+      })
+  }
+
+  /// 作品の要素のうち、スキーマに従うレコードを持つ要素のIDを列挙する
+  fn records_of(
+    &self,
+    work_id: WorkId,
+    name: &str,
+  ) -> Vec<ElementId> {
+    self
+      .subtree_under(ElementParent::Root(work_id))
+      .into_iter()
+      .filter(|id| {
+        self.elements.get(*id).is_some_and(|e| {
+          matches!(
+            &e.body.kind,
+            ElementKind::Custom(r) if r.schema == name
+          )
+        })
+      })
+      .collect()
+  }
+
+  /// 親の要素が属する作品を求める
+  ///
+  /// ## Error
+  /// - `HierarchyError::WorkNotFound`: 親の作品が存在しない
+  /// - `HierarchyError::Schema`: 親の要素から作品を辿れない
+  fn record_work(
+    &self,
+    parent: ElementParent,
+  ) -> Result<WorkId, HierarchyError> {
+    self.work_of(parent).ok_or(match parent {
+      ElementParent::Root(work_id) => {
+        HierarchyError::WorkNotFound(work_id)
+      }
+      ElementParent::Nest(id) => {
+        SchemaError::NoWork(id).into()
+      }
+    })
+  }
+
+  /// 親の直下に置く要素の種類・属性を検証する
+  ///
+  /// ## Summary
+  /// `ElementKind::Custom`の場合のみ、親の属する作品のスキーマで検証する。
+  ///
+  /// ## Error
+  /// - `HierarchyError::Schema`: レコードがスキーマに従わない
+  pub(in crate::domain) fn check_record(
+    &self,
+    parent: ElementParent,
+    kind: &ElementKind,
+  ) -> Result<(), HierarchyError> {
+    self.check_record_with(parent, kind, |id| {
+      self.elements.get(id).is_some()
+    })
+  }
+
+  /// 参照先が存在するか判定する関数`exists`を指定して検証する
+  fn check_record_with(
+    &self,
+    parent: ElementParent,
+    kind: &ElementKind,
+    exists: impl Fn(ElementId) -> bool,
+  ) -> Result<(), HierarchyError> {
+    let ElementKind::Custom(record) = kind else {
+      return Ok(());
+    };
+    let work_id = self.record_work(parent)?;
+    let schema = self.schema_of(work_id, &record.schema)?;
+    schema.validate(&record.fields, exists)?;
+    Ok(())
+  }
+
+  /// 要素を子孫ごと親の直下へ移す場合のレコードを検証する
+  ///
+  /// ## Summary
+  /// 移動先が別の作品の場合のみ、要素とその子孫のレコードを
+  /// 移動先の作品のスキーマで検証する。
+  ///
+  /// ## Error
+  /// - `HierarchyError::ElementNotFound`: 要素が存在しない
+  /// - `HierarchyError::Schema`: レコードが移動先のスキーマに従わない
+  pub(in crate::domain) fn check_moved_records(
+    &self,
+    id: ElementId,
+    parent: ElementParent,
+  ) -> Result<(), HierarchyError> {
+    let old_parent = self
+      .elements
+      .get(id)
+      .ok_or(HierarchyError::ElementNotFound(id))?
+      .key()
+      .parent;
+    if self.work_of(old_parent) == self.work_of(parent) {
+      return Ok(());
+    }
+    let subtree = std::iter::once(id)
+      .chain(self.subtree_under(ElementParent::Nest(id)));
+    for element_id in subtree {
+      if let Some(element) = self.elements.get(element_id) {
+        self.check_record(parent, &element.body.kind)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// トランザクション内で書き込んだ要素のレコードを検証する
+  ///
+  /// ## Summary
+  /// `Transaction::commit`から呼び出し、`tx.elements.modify`等で
+  /// `set_element_kind`を通さずに書き込んだレコードも検証する。
+  /// 種類・属性を変更していない要素(本文の編集のみ等)は検証しない。
+  /// 同じトランザクション内で削除した要素への参照は、
+  /// 削除による古い参照(`StaleReference`)として許す。
+  ///
+  /// ## Error
+  /// - `HierarchyError::Schema`: レコードがスキーマに従わない
+  pub(in crate::domain) fn check_touched_records(
+    &self,
+  ) -> Result<(), HierarchyError> {
+    for id in self.elements.touched() {
+      let Some(element) = self.elements.get(id) else {
+        continue;
+      };
+      let unchanged = self
+        .elements
+        .original_body(id)
+        .is_some_and(|b| b.kind == element.body.kind);
+      if !unchanged {
+        self.check_record_with(
+          element.key().parent,
+          &element.body.kind,
+          |id| {
+            self.elements.get(id).is_some()
+              || self.elements.is_removed(id)
+          },
+        )?;
+      }
+    }
+    Ok(())
+  }
+
+  /// 作品にスキーマを定義する
+  ///
+  /// ## Summary
+  /// `schema.version`は1にする。
+  ///
+  /// ## Error
+  /// - `HierarchyError::WorkNotFound`: 作品が存在しない
+  /// - `HierarchyError::Schema`: 同名のスキーマが定義されている
+  pub fn define_schema(
+    &mut self,
+    work_id: WorkId,
+    name: impl ToString,
+    mut schema: RecordSchema,
+  ) -> Result<(), HierarchyError> {
+    let name = name.to_string();
+    let work = self
+      .works
+      .get(work_id)
+      .ok_or(HierarchyError::WorkNotFound(work_id))?;
+    if work.body.schemas.contains_key(&name) {
+      return Err(SchemaError::SchemaExists(name).into());
+    }
+    schema.version = 1;
+    self.works.modify(work_id, |b| {
+      b.schemas.insert(name, schema);
+    })?;
+    Ok(())
+  }
+
+  /// 作品のスキーマを削除する
+  ///
+  /// ## Error
+  /// - `HierarchyError::WorkNotFound`: 作品が存在しない
+  /// - `HierarchyError::Schema`: スキーマが定義されていない、
+  ///   またはスキーマに従うレコードが残っている
+  pub fn remove_schema(
+    &mut self,
+    work_id: WorkId,
+    name: &str,
+  ) -> Result<RecordSchema, HierarchyError> {
+    let schema = self.schema_of(work_id, name)?;
+    let records = self.records_of(work_id, name).len();
+    if records > 0 {
+      return Err(
+        SchemaError::SchemaInUse {
+          schema: name.to_string(),
+          records,
+        }
+        .into(),
+      );
+    }
+    self.works.modify(work_id, |b| {
+      b.schemas.shift_remove(name);
+    })?;
+    Ok(schema)
+  }
+
+  /// スキーマを変更し、既存のレコードを移行する
+  ///
+  /// ## Summary
+  /// 作品の全ての要素のうち、スキーマに従うレコードを変更に合わせて書き換え、
+  /// `version`を1つ進める。
+  /// 失敗した場合は、この操作による変更を全て取り消す。
+  ///
+  /// ## Return
+  /// 変更後の`version`
+  ///
+  /// ## Error
+  /// - `HierarchyError::WorkNotFound`: 作品が存在しない
+  /// - `HierarchyError::Schema`:
+  ///   - `SchemaNotFound`: スキーマが定義されていない
+  ///   - `FieldExists`: 追加・改名先の属性が既にある
+  ///   - `UnknownField`: 改名・削除する属性が無い
+  ///   - `DefaultRequired`: 省略できない属性の追加に`default`が無い
+  ///   - `TypeMismatch`等: `default`が属性の型に従わない
+  pub fn evolve_schema(
+    &mut self,
+    work_id: WorkId,
+    name: &str,
+    change: SchemaChange,
+  ) -> Result<u32, HierarchyError> {
+    self.atomic(|tx| {
+      let mut schema = tx.schema_of(work_id, name)?;
+      let records = tx.records_of(work_id, name);
+      match change {
+        SchemaChange::AddField {
+          name: field,
+          def,
+          default,
+        } => {
+          if schema.fields.contains_key(&field) {
+            return Err(
+              SchemaError::FieldExists(field).into(),
+            );
+          }
+          if def.required && default.is_none() {
+            return Err(
+              SchemaError::DefaultRequired(field).into(),
+            );
+          }
+          schema.fields.insert(field.clone(), def);
+          if let Some(default) = default {
+            schema.validate_field(
+              &field,
+              &default,
+              &|id| tx.elements.get(id).is_some(),
+            )?;
+            for id in records {
+              tx.modify_record(id, |fields| {
+                fields
+                  .entry(field.clone())
+                  .or_insert_with(|| default.clone());
+              })?;
+            }
+          }
+        }
+        SchemaChange::RenameField { from, to } => {
+          if schema.fields.contains_key(&to) {
+            return Err(
+              SchemaError::FieldExists(to).into(),
+            );
+          }
+          let Some((index, _, def)) =
+            schema.fields.shift_remove_full(&from)
+          else {
+            return Err(
+              SchemaError::UnknownField(from).into(),
+            );
+          };
+          let (last, _) =
+            schema.fields.insert_full(to.clone(), def);
+          schema.fields.move_index(last, index);
+          for id in records {
+            tx.modify_record(id, |fields| {
+              if let Some(value) = fields.remove(&from) {
+                fields.insert(to.clone(), value);
+              }
+            })?;
+          }
+        }
+        SchemaChange::RemoveField { name: field } => {
+          if schema.fields.shift_remove(&field).is_none() {
+            return Err(
+              SchemaError::UnknownField(field).into(),
+            );
+          }
+          for id in records {
+            tx.modify_record(id, |fields| {
+              fields.remove(&field);
+            })?;
+          }
+        }
+      }
+      schema.version += 1;
+      let version = schema.version;
+      tx.works.modify(work_id, |b| {
+        b.schemas.insert(name.to_string(), schema);
+      })?;
+      Ok(version)
+    })
+  }
+
+  /// 要素のレコードの属性を書き換える
+  fn modify_record(
+    &mut self,
+    id: ElementId,
+    f: impl FnOnce(
+      &mut std::collections::BTreeMap<String, CustomValue>,
+    ),
+  ) -> Result<(), HierarchyError> {
+    self.elements.modify(id, |b| {
+      if let ElementKind::Custom(record) = &mut b.kind {
+        f(&mut record.fields);
+      }
+    })?;
+    Ok(())
+  }
+
+  /// 要素の種類・属性を書き換える
+  ///
+  /// ## Summary
+  /// `ElementKind::Custom`の場合は、要素の属する作品のスキーマで検証する。
+  ///
+  /// ## Error
+  /// - `HierarchyError::ElementNotFound`: 要素が存在しない
+  /// - `HierarchyError::Schema`: レコードがスキーマに従わない
+  pub fn set_element_kind(
+    &mut self,
+    id: ElementId,
+    kind: ElementKind,
+  ) -> Result<(), HierarchyError> {
+    let parent = self
+      .elements
+      .get(id)
+      .ok_or(HierarchyError::ElementNotFound(id))?
+      .key()
+      .parent;
+    self.check_record(parent, &kind)?;
+    self.elements.modify(id, |b| b.kind = kind)?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::SchemaChange;
+  use crate::{
+    domain::{
+      element::{ElementId, ElementKind, ElementParent},
+      error::HierarchyError,
+      schema::{
+        CustomRecord, CustomValue, FieldDef, FieldType,
+        SchemaError,
+      },
+      user::UserDataBody,
+      work::WorkId,
+    },
+    fixture::{
+      Store, element_body, spell_body, spell_schema,
+      work_body,
+    },
+    traits::prelude::*,
+  };
+
+  /// スキーマ`spell`を持つ作品と、そのレコード(`power`が3)の要素
+  fn setup() -> (Store, WorkId, ElementId) {
+    let mut store = Store::default();
+    let (work, spell) = store
+      .run(|tx| {
+        let user =
+          tx.spawn_user("alice", UserDataBody::default())?;
+        let work =
+          tx.spawn_work(user, "novel", work_body("novel"))?;
+        tx.define_schema(work, "spell", spell_schema())?;
+        let spell = tx.spawn_element(
+          ElementParent::Root(work),
+          "fire",
+          spell_body(3),
+        )?;
+        Ok((work, spell))
+      })
+      .unwrap();
+    (store, work, spell)
+  }
+
+  /// 要素のレコードの属性
+  fn fields(
+    store: &Store,
+    id: ElementId,
+  ) -> Vec<(String, CustomValue)> {
+    match &store.elements.get(id).unwrap().body.kind {
+      ElementKind::Custom(record) => record
+        .fields
+        .iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect(),
+      kind => panic!("not a record: {kind:?}"),
+    }
+  }
+
+  /// スキーマの属性名(定義した順)と`version`
+  fn schema(
+    store: &Store,
+    work: WorkId,
+  ) -> (Vec<String>, u32) {
+    let schema =
+      &store.works.get(work).unwrap().body.schemas["spell"];
+    (
+      schema.fields.keys().cloned().collect(),
+      schema.version,
+    )
+  }
+
+  #[test]
+  fn define_schema_starts_at_version_one() {
+    let (mut store, work, _) = setup();
+    assert_eq!(
+      schema(&store, work),
+      (vec!["power".into()], 1)
+    );
+    assert!(matches!(
+      store.run(|tx| {
+        tx.define_schema(work, "spell", spell_schema())
+      }),
+      Err(HierarchyError::Schema(
+        SchemaError::SchemaExists(name)
+      )) if name == "spell"
+    ));
+    let missing = WorkId::new();
+    assert!(matches!(
+      store.run(|tx| {
+        tx.define_schema(missing, "spell", spell_schema())
+      }),
+      Err(HierarchyError::WorkNotFound(id)) if id == missing
+    ));
+  }
+
+  #[test]
+  fn evolve_schema_migrates_records() {
+    let (mut store, work, spell) = setup();
+    let mut evolve = |change| {
+      store
+        .run(|tx| tx.evolve_schema(work, "spell", change))
+    };
+
+    // 省略できない属性の追加には既定値が要る
+    assert!(matches!(
+      evolve(SchemaChange::AddField {
+        name: "element".into(),
+        def: FieldDef::required(FieldType::Text),
+        default: None,
+      }),
+      Err(HierarchyError::Schema(
+        SchemaError::DefaultRequired(_)
+      ))
+    ));
+    assert!(matches!(
+      evolve(SchemaChange::AddField {
+        name: "element".into(),
+        def: FieldDef::required(FieldType::Text),
+        default: Some(CustomValue::Number(1)),
+      }),
+      Err(HierarchyError::Schema(
+        SchemaError::TypeMismatch { .. }
+      ))
+    ));
+    let version = evolve(SchemaChange::AddField {
+      name: "element".into(),
+      def: FieldDef::required(FieldType::Text),
+      default: Some(CustomValue::Text("fire".into())),
+    })
+    .unwrap();
+    assert_eq!(version, 2);
+
+    assert_eq!(
+      evolve(SchemaChange::RenameField {
+        from: "power".into(),
+        to: "cost".into(),
+      })
+      .unwrap(),
+      3
+    );
+    assert!(matches!(
+      evolve(SchemaChange::RenameField {
+        from: "power".into(),
+        to: "level".into(),
+      }),
+      Err(HierarchyError::Schema(
+        SchemaError::UnknownField(_)
+      ))
+    ));
+    assert_eq!(
+      schema(&store, work),
+      (vec!["cost".into(), "element".into()], 3)
+    );
+    assert_eq!(
+      fields(&store, spell),
+      [
+        ("cost".into(), CustomValue::Number(3)),
+        (
+          "element".into(),
+          CustomValue::Text("fire".into())
+        ),
+      ]
+    );
+
+    let version = store
+      .run(|tx| {
+        tx.evolve_schema(
+          work,
+          "spell",
+          SchemaChange::RemoveField { name: "cost".into() },
+        )
+      })
+      .unwrap();
+    assert_eq!(version, 4);
+    assert_eq!(
+      fields(&store, spell),
+      [(
+        "element".into(),
+        CustomValue::Text("fire".into())
+      )]
+    );
+  }
+
+  #[test]
+  fn records_are_checked_on_commit() {
+    let (mut store, work, spell) = setup();
+    let invalid = ElementKind::Custom(CustomRecord {
+      schema: "spell".into(),
+      fields: [("power".into(), CustomValue::Bool(true))]
+        .into_iter()
+        .collect(),
+    });
+
+    assert!(matches!(
+      store.run(
+        |tx| tx.set_element_kind(spell, invalid.clone())
+      ),
+      Err(HierarchyError::Schema(
+        SchemaError::TypeMismatch { .. }
+      ))
+    ));
+    // `set_element_kind`を通さない書き込みも、確定時に拒否する
+    let result = store.run(|tx| {
+      tx.elements
+        .modify(spell, |b| b.kind = invalid.clone())?;
+      Ok(())
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::Schema(
+        SchemaError::TypeMismatch { .. }
+      ))
+    ));
+    assert_eq!(
+      fields(&store, spell),
+      [("power".into(), CustomValue::Number(3))]
+    );
+
+    let plain = store
+      .run(|tx| {
+        tx.spawn_element(
+          ElementParent::Root(work),
+          "plain",
+          element_body(""),
+        )
+      })
+      .unwrap();
+    let result = store.run(|tx| {
+      tx.elements.modify(plain, |b| {
+        b.kind = ElementKind::Custom(CustomRecord {
+          schema: "spell".into(),
+          ..Default::default()
+        })
+      })?;
+      Ok(())
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::Schema(
+        SchemaError::MissingField(field)
+      )) if field == "power"
+    ));
+    assert_eq!(
+      store.elements.get(plain).unwrap().body.kind,
+      ElementKind::default()
+    );
+
+    // 種類を変えない書き込みは検証しない
+    store
+      .run(|tx| {
+        tx.elements
+          .modify(spell, |b| b.content = "edited".into())?;
+        Ok(())
+      })
+      .unwrap();
+  }
+}
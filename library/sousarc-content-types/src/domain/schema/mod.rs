@@ -0,0 +1,614 @@
+//! 作品ごとのスキーマ
+//!
+//! ## Summary
+//! - `RecordSchema`・`FieldDef`・`FieldType`: 作品が定義する独自のレコード型
+//! - `CustomRecord`・`CustomValue`: 要素が持つレコード(`ElementKind::Custom`)
+//! - `SchemaError`: 検証・変更が失敗した理由
+//! - `SchemaChange`: スキーマの変更(`Transaction::evolve_schema`)
+//!
+//! スキーマは`WorkDataBody::schemas`に名前で登録し、
+//! その作品の要素のレコードは書き込み時にスキーマで検証する。
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use super::{element::ElementId, work::WorkId};
+
+pub mod evolve;
+pub use evolve::*;
+
+/// 属性の型
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum FieldType {
+  /// 文字列
+  Text,
+
+  /// 整数(`CustomValue::Number`・`FieldValue::Number`と同じ`i64`)
+  Number,
+
+  /// 真偽値
+  Bool,
+
+  /// 日付
+  Date,
+
+  /// 列挙した文字列のいずれか
+  Enum(Vec<String>),
+
+  /// 他の要素への参照
+  Reference,
+
+  /// 同じ型の値の列
+  List(Box<FieldType>),
+}
+
+impl Display for FieldType {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    match self {
+      Self::Text => write!(f, "text"),
+      Self::Number => write!(f, "number"),
+      Self::Bool => write!(f, "bool"),
+      Self::Date => write!(f, "date"),
+      Self::Enum(allowed) => {
+        write!(f, "enum({})", allowed.join("|"))
+      }
+      Self::Reference => write!(f, "reference"),
+      Self::List(inner) => write!(f, "list<{inner}>"),
+    }
+  }
+}
+
+/// 属性の定義
+///
+/// ## Member
+/// - `ty`: `FieldType`型
+/// - `required`: `bool`型
+///   - 省略できないか
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct FieldDef {
+  pub ty: FieldType,
+
+  #[serde(default)]
+  pub required: bool,
+}
+
+impl FieldDef {
+  /// 省略できる属性
+  pub fn optional(ty: FieldType) -> Self {
+    Self { ty, required: false }
+  }
+
+  /// 省略できない属性
+  pub fn required(ty: FieldType) -> Self {
+    Self { ty, required: true }
+  }
+}
+
+/// レコード型のスキーマ
+///
+/// ## Member
+/// - `version`: `u32`型
+///   - 定義した時点で1、変更の度に増える
+/// - `fields`: `IndexMap<String, FieldDef>`型
+///   - 属性名→定義(定義した順)
+#[derive(
+  Debug,
+  Clone,
+  Default,
+  PartialEq,
+  Eq,
+  Serialize,
+  Deserialize,
+)]
+pub struct RecordSchema {
+  #[serde(default)]
+  pub version: u32,
+
+  pub fields: IndexMap<String, FieldDef>,
+}
+
+/// 日付
+///
+/// ## Summary
+/// 作中の暦も表せるよう、月は1〜12、日は1〜31であることのみを検証する。
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  PartialOrd,
+  Ord,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+pub struct CustomDate {
+  pub year: i32,
+  pub month: u8,
+  pub day: u8,
+}
+
+impl CustomDate {
+  /// 月・日が範囲内か判定する
+  pub fn is_valid(&self) -> bool {
+    (1..=12).contains(&self.month)
+      && (1..=31).contains(&self.day)
+  }
+}
+
+impl Display for CustomDate {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    write!(
+      f,
+      "{}-{:02}-{:02}",
+      self.year, self.month, self.day
+    )
+  }
+}
+
+/// 属性の値
+#[derive(
+  Debug, Clone, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum CustomValue {
+  Text(String),
+  Number(i64),
+  Bool(bool),
+  Date(CustomDate),
+  Enum(String),
+  Reference(ElementId),
+  List(Vec<CustomValue>),
+}
+
+impl CustomValue {
+  /// 値の型の名称(エラー報告用)
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Self::Text(_) => "text",
+      Self::Number(_) => "number",
+      Self::Bool(_) => "bool",
+      Self::Date(_) => "date",
+      Self::Enum(_) => "enum",
+      Self::Reference(_) => "reference",
+      Self::List(_) => "list",
+    }
+  }
+}
+
+/// 作品のスキーマに従うレコード
+///
+/// ## Member
+/// - `schema`: `String`型
+///   - スキーマの名前
+/// - `fields`: `BTreeMap<String, CustomValue>`型
+///   - 属性名→値
+#[derive(
+  Debug,
+  Clone,
+  Default,
+  PartialEq,
+  Eq,
+  Serialize,
+  Deserialize,
+)]
+pub struct CustomRecord {
+  pub schema: String,
+  pub fields: BTreeMap<String, CustomValue>,
+}
+
+impl CustomRecord {
+  /// 参照する要素を、属性名と組にして列挙する
+  ///
+  /// ## Summary
+  /// 列の要素の属性名は`tags[2]`のように添字を付ける。
+  pub fn references(&self) -> Vec<(String, ElementId)> {
+    fn walk(
+      field: String,
+      value: &CustomValue,
+      refs: &mut Vec<(String, ElementId)>,
+    ) {
+      match value {
+        CustomValue::Reference(id) => {
+          refs.push((field, *id))
+        }
+        CustomValue::List(values) => {
+          for (i, value) in values.iter().enumerate() {
+            walk(format!("{field}[{i}]"), value, refs);
+          }
+        }
+        _ => {}
+      }
+    }
+    let mut refs = Vec::new();
+    for (field, value) in &self.fields {
+      walk(field.clone(), value, &mut refs);
+    }
+    refs
+  }
+}
+
+/// 存在しない要素への参照
+///
+/// ## Summary
+/// 参照先の要素を削除しても、参照元のレコードは書き換えないので残る。
+///
+/// ## Member
+/// - `element`: `ElementId`型
+///   - 参照元の要素
+/// - `field`: `String`型
+///   - 属性名(列の要素は`tags[2]`のように添字を付ける)
+/// - `target`: `ElementId`型
+///   - 存在しない参照先
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleReference {
+  pub element: ElementId,
+  pub field: String,
+  pub target: ElementId,
+}
+
+/// スキーマの検証・変更のエラー
+///
+/// ## Summary
+/// `field`は属性名で、列の要素は`tags[2]`のように添字を付ける。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaError {
+  /// 作品にスキーマが定義されていない
+  SchemaNotFound { work: WorkId, schema: String },
+
+  /// 作品に同名のスキーマが定義されている
+  SchemaExists(String),
+
+  /// スキーマを使うレコードが残っている
+  SchemaInUse { schema: String, records: usize },
+
+  /// 要素の属する作品を辿れない
+  NoWork(ElementId),
+
+  /// スキーマに無い属性
+  UnknownField(String),
+
+  /// 省略できない属性が無い
+  MissingField(String),
+
+  /// スキーマに同名の属性がある
+  FieldExists(String),
+
+  /// 省略できない属性の追加に、既存のレコードへ入れる値が無い
+  DefaultRequired(String),
+
+  /// 値の型が異なる
+  TypeMismatch {
+    field: String,
+    expected: String,
+    found: &'static str,
+  },
+
+  /// 列挙に無い値
+  InvalidEnum {
+    field: String,
+    value: String,
+    allowed: Vec<String>,
+  },
+
+  /// 月・日が範囲外の日付
+  InvalidDate { field: String, date: CustomDate },
+
+  /// 存在しない要素への参照
+  DanglingReference { field: String, id: ElementId },
+}
+
+impl Display for SchemaError {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    match self {
+      Self::SchemaNotFound { work, schema } => {
+        write!(
+          f,
+          "schema `{schema}` is not defined in work {work}"
+        )
+      }
+      Self::SchemaExists(schema) => {
+        write!(f, "schema `{schema}` is already defined")
+      }
+      Self::SchemaInUse { schema, records } => write!(
+        f,
+        "schema `{schema}` is still used by {records} record(s)"
+      ),
+      Self::NoWork(id) => {
+        write!(f, "element does not belong to a work: {id}")
+      }
+      Self::UnknownField(field) => {
+        write!(f, "field `{field}` is not in the schema")
+      }
+      Self::MissingField(field) => {
+        write!(f, "required field `{field}` is missing")
+      }
+      Self::FieldExists(field) => {
+        write!(f, "field `{field}` already exists")
+      }
+      Self::DefaultRequired(field) => write!(
+        f,
+        "required field `{field}` needs a default for existing records"
+      ),
+      Self::TypeMismatch { field, expected, found } => {
+        write!(
+          f,
+          "field `{field}`: expected {expected}, found {found}"
+        )
+      }
+      Self::InvalidEnum { field, value, allowed } => {
+        write!(
+          f,
+          "field `{field}`: `{value}` is not one of {}",
+          allowed.join(", ")
+        )
+      }
+      Self::InvalidDate { field, date } => {
+        write!(f, "field `{field}`: invalid date {date}")
+      }
+      Self::DanglingReference { field, id } => {
+        write!(
+          f,
+          "field `{field}`: element not found: {id}"
+        )
+      }
+    }
+  }
+}
+
+impl std::error::Error for SchemaError {}
+
+impl RecordSchema {
+  /// 値が型に従うか検証する
+  fn check_value(
+    field: &str,
+    ty: &FieldType,
+    value: &CustomValue,
+    exists: &impl Fn(ElementId) -> bool,
+  ) -> Result<(), SchemaError> {
+    match (ty, value) {
+      (FieldType::Text, CustomValue::Text(_))
+      | (FieldType::Number, CustomValue::Number(_))
+      | (FieldType::Bool, CustomValue::Bool(_)) => Ok(()),
+      (FieldType::Date, CustomValue::Date(date)) => {
+        if date.is_valid() {
+          Ok(())
+        } else {
+          Err(SchemaError::InvalidDate {
+            field: field.to_string(),
+            date: *date,
+          })
+        }
+      }
+      (
+        FieldType::Enum(allowed),
+        CustomValue::Enum(value),
+      ) => {
+        if allowed.contains(value) {
+          Ok(())
+        } else {
+          Err(SchemaError::InvalidEnum {
+            field: field.to_string(),
+            value: value.clone(),
+            allowed: allowed.clone(),
+          })
+        }
+      }
+      (
+        FieldType::Reference,
+        CustomValue::Reference(id),
+      ) => {
+        if exists(*id) {
+          Ok(())
+        } else {
+          Err(SchemaError::DanglingReference {
+            field: field.to_string(),
+            id: *id,
+          })
+        }
+      }
+      (
+        FieldType::List(inner),
+        CustomValue::List(values),
+      ) => {
+        for (i, value) in values.iter().enumerate() {
+          let field = format!("{field}[{i}]");
+          Self::check_value(&field, inner, value, exists)?;
+        }
+        Ok(())
+      }
+      _ => Err(SchemaError::TypeMismatch {
+        field: field.to_string(),
+        expected: ty.to_string(),
+        found: value.type_name(),
+      }),
+    }
+  }
+
+  /// 属性の値を検証する
+  ///
+  /// ## Argument
+  /// - `exists`: `Fn(ElementId) -> bool`
+  ///   - 参照先の要素が存在するか判定する関数
+  ///
+  /// ## Error
+  /// - `SchemaError::UnknownField`: スキーマに無い属性がある
+  /// - `SchemaError::MissingField`: 省略できない属性が無い
+  /// - `SchemaError::TypeMismatch`・`InvalidEnum`・`InvalidDate`・`DanglingReference`:
+  ///   値が型に従わない
+  pub fn validate_field(
+    &self,
+    field: &str,
+    value: &CustomValue,
+    exists: &impl Fn(ElementId) -> bool,
+  ) -> Result<(), SchemaError> {
+    let def = self.fields.get(field).ok_or_else(|| {
+      SchemaError::UnknownField(field.to_string())
+    })?;
+    Self::check_value(field, &def.ty, value, exists)
+  }
+
+  /// レコードを検証する
+  ///
+  /// ## Error
+  /// - `validate_field`のエラー
+  /// - `SchemaError::MissingField`: 省略できない属性が無い
+  pub fn validate(
+    &self,
+    fields: &BTreeMap<String, CustomValue>,
+    exists: impl Fn(ElementId) -> bool,
+  ) -> Result<(), SchemaError> {
+    for (field, value) in fields {
+      self.validate_field(field, value, &exists)?;
+    }
+    match self.fields.iter().find(|(name, def)| {
+      def.required && !fields.contains_key(*name)
+    }) {
+      Some((name, _)) => {
+        Err(SchemaError::MissingField(name.clone()))
+      }
+      None => Ok(()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    CustomDate, CustomValue, FieldDef, FieldType,
+    RecordSchema, SchemaError,
+  };
+  use crate::domain::element::ElementId;
+
+  /// 全ての型の属性を持つスキーマ
+  fn schema() -> RecordSchema {
+    let fields = [
+      ("name", FieldDef::required(FieldType::Text)),
+      ("level", FieldDef::optional(FieldType::Number)),
+      (
+        "school",
+        FieldDef::optional(FieldType::Enum(vec![
+          "fire".into(),
+          "water".into(),
+        ])),
+      ),
+      ("learned", FieldDef::optional(FieldType::Date)),
+      (
+        "related",
+        FieldDef::optional(FieldType::List(Box::new(
+          FieldType::Reference,
+        ))),
+      ),
+    ];
+    RecordSchema {
+      version: 1,
+      fields: fields
+        .into_iter()
+        .map(|(name, def)| (name.to_string(), def))
+        .collect(),
+    }
+  }
+
+  #[test]
+  fn validates_fields_against_their_types() {
+    let schema = schema();
+    let known = ElementId::new();
+    let exists = |id| id == known;
+    let check = |field: &str, value: CustomValue| {
+      schema.validate_field(field, &value, &exists)
+    };
+
+    assert!(
+      check("name", CustomValue::Text("a".into())).is_ok()
+    );
+    assert!(
+      check("level", CustomValue::Number(-1)).is_ok()
+    );
+    assert!(
+      check("school", CustomValue::Enum("fire".into()))
+        .is_ok()
+    );
+    assert!(matches!(
+      check("level", CustomValue::Text("1".into())),
+      Err(SchemaError::TypeMismatch { field, found: "text", .. })
+        if field == "level"
+    ));
+    assert!(matches!(
+      check("school", CustomValue::Enum("wind".into())),
+      Err(SchemaError::InvalidEnum { value, .. }) if value == "wind"
+    ));
+    let date = CustomDate { year: 1, month: 13, day: 1 };
+    assert_eq!(
+      check("learned", CustomValue::Date(date)),
+      Err(SchemaError::InvalidDate {
+        field: "learned".into(),
+        date
+      })
+    );
+    let missing = ElementId::new();
+    assert_eq!(
+      check(
+        "related",
+        CustomValue::List(vec![
+          CustomValue::Reference(known),
+          CustomValue::Reference(missing),
+        ])
+      ),
+      Err(SchemaError::DanglingReference {
+        field: "related[1]".into(),
+        id: missing
+      })
+    );
+    assert_eq!(
+      check("mana", CustomValue::Number(1)),
+      Err(SchemaError::UnknownField("mana".into()))
+    );
+  }
+
+  #[test]
+  fn validate_requires_required_fields() {
+    let schema = schema();
+    let record = |fields: &[(&str, CustomValue)]| {
+      let fields = fields
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.clone()))
+        .collect();
+      schema.validate(&fields, |_| false)
+    };
+
+    assert!(
+      record(&[("name", CustomValue::Text("a".into()))])
+        .is_ok()
+    );
+    assert_eq!(
+      record(&[("level", CustomValue::Number(1))]),
+      Err(SchemaError::MissingField("name".into()))
+    );
+    assert_eq!(
+      record(&[
+        ("name", CustomValue::Text("a".into())),
+        ("level", CustomValue::Bool(true)),
+      ]),
+      Err(SchemaError::TypeMismatch {
+        field: "level".into(),
+        expected: "number".into(),
+        found: "bool",
+      })
+    );
+  }
+}
@@ -0,0 +1,362 @@
+//! 親を考慮したデータの作成
+//!
+//! ## Summary
+//! - `Transaction::spawn_user`・`spawn_work`・`spawn_element`: データを作成する
+//!
+//! IDをUUIDv7で生成し、親からキーを組み立てて挿入した上で、
+//! 親の`children`に登録する。親が存在しない場合は何も変更せずに失敗する。
+//!
+//! 空の名前は完全修飾パスで表せないので、キーを組み立てる時点で拒否する。
+
+use super::{
+  element::{
+    ElementData, ElementDataBody, ElementId, ElementKey,
+    ElementParent,
+  },
+  error::HierarchyError,
+  transaction::Transaction,
+  user::{UserData, UserDataBody, UserId, UserKey},
+  work::{WorkData, WorkDataBody, WorkId, WorkKey},
+};
+
+/// 空でない名前を取り出す
+///
+/// ## Error
+/// - `HierarchyError::EmptyName`: 名前が空である
+pub(super) fn key_name(
+  name: impl ToString,
+) -> Result<String, HierarchyError> {
+  let name = name.to_string();
+  if name.is_empty() {
+    return Err(HierarchyError::EmptyName);
+  }
+  Ok(name)
+}
+
+impl Transaction<'_> {
+  /// ユーザを作成する
+  ///
+  /// ## Summary
+  /// `body.children`は空にしてから挿入する。
+  ///
+  /// ## Return
+  /// 作成したユーザのID
+  ///
+  /// ## Error
+  /// - `HierarchyError::EmptyName`: ユーザ名が空である
+  /// - `HierarchyError::User`: ユーザ名が既に使われている
+  pub fn spawn_user(
+    &mut self,
+    name: impl ToString,
+    mut body: UserDataBody,
+  ) -> Result<UserId, HierarchyError> {
+    let name = key_name(name)?;
+    body.children.clear();
+    let id = UserId::new();
+    let user = UserData::new(id, UserKey::new(name), body);
+    self
+      .users
+      .insert(user)
+      .map_err(|e| HierarchyError::User(e.error))?;
+    Ok(id)
+  }
+
+  /// ユーザの作品を作成する
+  ///
+  /// ## Summary
+  /// `body.children`は空にしてから挿入し、
+  /// ユーザの`children`の末尾に登録する。
+  ///
+  /// ## Return
+  /// 作成した作品のID
+  ///
+  /// ## Error
+  /// - `HierarchyError::EmptyName`: 作品名が空である
+  /// - `HierarchyError::UserNotFound`: ユーザが存在しない
+  /// - `HierarchyError::Work`: 同じユーザに同名の作品が存在する
+  pub fn spawn_work(
+    &mut self,
+    user_id: UserId,
+    work_name: impl ToString,
+    mut body: WorkDataBody,
+  ) -> Result<WorkId, HierarchyError> {
+    let work_name = key_name(work_name)?;
+    if self.users.get(user_id).is_none() {
+      return Err(HierarchyError::UserNotFound(user_id));
+    }
+    body.children.clear();
+    let id = WorkId::new();
+    let key = WorkKey::from((user_id, work_name));
+    self.atomic(|tx| {
+      tx.works
+        .insert(WorkData::new(id, key, body))
+        .map_err(|e| HierarchyError::Work(e.error))?;
+      tx.users.modify(user_id, |b| b.children.push(id))?;
+      Ok(id)
+    })
+  }
+
+  /// 作品または要素の直下に要素を作成する
+  ///
+  /// ## Summary
+  /// `body.children`が`Some`の場合は空にしてから挿入し、
+  /// 親の`children`の末尾に登録する。
+  /// 最初の版は、確定時に作品の所有者の版として記録される。
+  ///
+  /// ## Return
+  /// 作成した要素のID
+  ///
+  /// ## Error
+  /// - `HierarchyError::EmptyName`: 要素名が空である
+  /// - `HierarchyError::WorkNotFound`: 親の作品が存在しない
+  /// - `HierarchyError::ElementNotFound`: 親の要素が存在しない
+  /// - `HierarchyError::NotContainer`: 親の要素が子を持てない
+  /// - `HierarchyError::Schema`: `body.kind`のレコードが作品のスキーマに従わない
+  /// - `HierarchyError::Element`: 同じ親に同名の要素が存在する
+  pub fn spawn_element(
+    &mut self,
+    parent: ElementParent,
+    name: impl ToString,
+    mut body: ElementDataBody,
+  ) -> Result<ElementId, HierarchyError> {
+    let name = key_name(name)?;
+    match parent {
+      ElementParent::Root(work_id) => {
+        if self.works.get(work_id).is_none() {
+          return Err(HierarchyError::WorkNotFound(
+            work_id,
+          ));
+        }
+      }
+      ElementParent::Nest(element_id) => {
+        let Some(element) = self.elements.get(element_id)
+        else {
+          return Err(HierarchyError::ElementNotFound(
+            element_id,
+          ));
+        };
+        if element.body.children.is_none() {
+          return Err(HierarchyError::NotContainer(
+            element_id,
+          ));
+        }
+      }
+    }
+    self.check_record(parent, &body.kind)?;
+    if let Some(children) = &mut body.children {
+      children.clear();
+    }
+    let id = ElementId::new();
+    let key = ElementKey { parent, name };
+    self.atomic(|tx| {
+      tx.elements
+        .insert(ElementData::new(id, key, body))
+        .map_err(|e| HierarchyError::Element(e.error))?;
+      match parent {
+        ElementParent::Root(work_id) => tx
+          .works
+          .modify(work_id, |b| b.children.push(id))
+          .map_err(HierarchyError::Work)?,
+        ElementParent::Nest(element_id) => tx
+          .elements
+          .modify(element_id, |b| {
+            b.children.get_or_insert_default().push(id)
+          })
+          .map_err(HierarchyError::Element)?,
+      }
+      Ok(id)
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    domain::{
+      element::{ElementId, ElementParent},
+      error::HierarchyError,
+      user::{UserDataBody, UserId},
+      work::WorkId,
+    },
+    fixture::{
+      Store, container_body, element_body, work_body,
+    },
+    traits::prelude::*,
+  };
+
+  #[test]
+  fn registers_children_in_parents() {
+    let mut store = Store::default();
+    let (user, works, roots, nested) = store
+      .run(|tx| {
+        let mut body = UserDataBody::default();
+        body.children.push(WorkId::new());
+        let user = tx.spawn_user("alice", body)?;
+        let works = [
+          tx.spawn_work(user, "a", work_body("a"))?,
+          tx.spawn_work(user, "b", work_body("b"))?,
+        ];
+        let roots = [
+          tx.spawn_element(
+            ElementParent::Root(works[0]),
+            "a",
+            container_body(),
+          )?,
+          tx.spawn_element(
+            ElementParent::Root(works[0]),
+            "b",
+            element_body("text"),
+          )?,
+        ];
+        let nested = tx.spawn_element(
+          ElementParent::Nest(roots[0]),
+          "a",
+          element_body("text"),
+        )?;
+        Ok((user, works, roots, nested))
+      })
+      .unwrap();
+
+    // 渡した`children`は捨てられ、作成順に登録される
+    let user = store.users.get(user).unwrap();
+    assert_eq!(user.children().collect::<Vec<_>>(), works);
+    let work = store.works.get(works[0]).unwrap();
+    assert_eq!(work.body.children, roots);
+    assert!(
+      store
+        .works
+        .get(works[1])
+        .unwrap()
+        .body
+        .children
+        .is_empty()
+    );
+    let parent = store.elements.get(roots[0]).unwrap();
+    assert_eq!(parent.body.children, Some(vec![nested]));
+    let key = store.elements.key(nested).unwrap();
+    assert_eq!(key.parent, ElementParent::Nest(roots[0]));
+  }
+
+  #[test]
+  fn missing_or_leaf_parents_change_nothing() {
+    let mut store = Store::default();
+    let (work, leaf) = store
+      .run(|tx| {
+        let user =
+          tx.spawn_user("alice", UserDataBody::default())?;
+        let work =
+          tx.spawn_work(user, "novel", work_body("novel"))?;
+        let leaf = tx.spawn_element(
+          ElementParent::Root(work),
+          "leaf",
+          element_body("text"),
+        )?;
+        Ok((work, leaf))
+      })
+      .unwrap();
+
+    let missing = UserId::new();
+    let result = store.run(|tx| {
+      tx.spawn_work(missing, "a", work_body("a"))
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::UserNotFound(id)) if id == missing
+    ));
+
+    let missing = WorkId::new();
+    let result = store.run(|tx| {
+      tx.spawn_element(
+        ElementParent::Root(missing),
+        "a",
+        element_body(""),
+      )
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::WorkNotFound(id)) if id == missing
+    ));
+
+    let missing = ElementId::new();
+    let result = store.run(|tx| {
+      tx.spawn_element(
+        ElementParent::Nest(missing),
+        "a",
+        element_body(""),
+      )
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::ElementNotFound(id)) if id == missing
+    ));
+
+    let result = store.run(|tx| {
+      tx.spawn_element(
+        ElementParent::Nest(leaf),
+        "a",
+        element_body(""),
+      )
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::NotContainer(id)) if id == leaf
+    ));
+
+    assert_eq!(store.elements.len(), 1);
+    assert_eq!(
+      store.works.get(work).unwrap().body.children,
+      [leaf]
+    );
+    assert_eq!(
+      store.elements.get(leaf).unwrap().body.children,
+      None
+    );
+  }
+
+  #[test]
+  fn empty_names_are_rejected() {
+    let mut store = Store::default();
+    let result = store
+      .run(|tx| tx.spawn_user("", UserDataBody::default()));
+    assert!(matches!(
+      result,
+      Err(HierarchyError::EmptyName)
+    ));
+
+    let (user, work) = store
+      .run(|tx| {
+        let user =
+          tx.spawn_user("alice", UserDataBody::default())?;
+        let work =
+          tx.spawn_work(user, "novel", work_body("novel"))?;
+        Ok((user, work))
+      })
+      .unwrap();
+    let result = store
+      .run(|tx| tx.spawn_work(user, "", work_body("")));
+    assert!(matches!(
+      result,
+      Err(HierarchyError::EmptyName)
+    ));
+    let result = store.run(|tx| {
+      tx.spawn_element(
+        ElementParent::Root(work),
+        "",
+        element_body(""),
+      )
+    });
+    assert!(matches!(
+      result,
+      Err(HierarchyError::EmptyName)
+    ));
+    let result = store.run(|tx| tx.rename_work(work, ""));
+    assert!(matches!(
+      result,
+      Err(HierarchyError::EmptyName)
+    ));
+
+    assert_eq!(store.users.len(), 1);
+    assert_eq!(store.works.len(), 1);
+    assert!(store.elements.is_empty());
+  }
+}
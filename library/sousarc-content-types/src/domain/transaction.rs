@@ -0,0 +1,299 @@
+//! ユーザ・作品・要素の3つのストレージに跨るトランザクション
+//!
+//! ## Summary
+//! - `Transaction`: 3つの`StorageTx`の組
+//!
+//! 「親の`children`を更新して子を挿入する」等、複数のストレージに跨る
+//! 操作を、全て確定するか全て取り消すかのどちらかにする。
+//! ジャーナルを添えた場合は、確定時に3つのストレージの変更を
+//! 1つのフレームとして記録する。
+
+use crate::storage::{
+  Savepoint, StandardStorage, StorageTx,
+};
+
+use super::{
+  element::ElementData,
+  error::HierarchyError,
+  journal::{TransactionJournal, TransactionRecordRef},
+  user::UserData,
+  work::WorkData,
+};
+
+/// 3つのストレージに跨るトランザクション
+///
+/// ## Summary
+/// `commit`せずにドロップした場合、全てのストレージの操作が取り消される。
+///
+/// ## Member
+/// - `users`: `StorageTx<UserData>`型
+/// - `works`: `StorageTx<WorkData>`型
+/// - `elements`: `StorageTx<ElementData>`型
+/// - `journal`: `Option<&mut TransactionJournal>`型
+///   - 確定時に変更を記録するジャーナル
+pub struct Transaction<'a> {
+  pub users: StorageTx<'a, UserData>,
+  pub works: StorageTx<'a, WorkData>,
+  pub elements: StorageTx<'a, ElementData>,
+  journal: Option<&'a mut TransactionJournal>,
+}
+
+/// トランザクション内の途中の時点(ストレージ毎)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionSavepoint {
+  users: Savepoint,
+  works: Savepoint,
+  elements: Savepoint,
+}
+
+impl<'a> Transaction<'a> {
+  pub fn new(
+    users: &'a mut StandardStorage<UserData>,
+    works: &'a mut StandardStorage<WorkData>,
+    elements: &'a mut StandardStorage<ElementData>,
+  ) -> Self {
+    Self {
+      users: StorageTx::new(users),
+      works: StorageTx::new(works),
+      elements: StorageTx::new_transactional(elements),
+      journal: None,
+    }
+  }
+
+  /// 確定時に変更をジャーナルへ記録するトランザクションを作成する
+  pub fn journaled(
+    users: &'a mut StandardStorage<UserData>,
+    works: &'a mut StandardStorage<WorkData>,
+    elements: &'a mut StandardStorage<ElementData>,
+    journal: &'a mut TransactionJournal,
+  ) -> Self {
+    Self {
+      journal: Some(journal),
+      ..Self::new(users, works, elements)
+    }
+  }
+
+  /// クロージャをトランザクション内で実行する
+  ///
+  /// ## Summary
+  /// `f`が`Ok`を返した場合は確定し、`Err`を返した場合は全て取り消す。
+  ///
+  /// ## Error
+  /// - `f`が返したエラー
+  pub fn run<T, E>(
+    users: &'a mut StandardStorage<UserData>,
+    works: &'a mut StandardStorage<WorkData>,
+    elements: &'a mut StandardStorage<ElementData>,
+    f: impl FnOnce(&mut Self) -> Result<T, E>,
+  ) -> Result<T, E>
+  where
+    E: From<HierarchyError>,
+  {
+    let mut tx = Self::new(users, works, elements);
+    let ret = f(&mut tx)?;
+    tx.commit()?;
+    Ok(ret)
+  }
+
+  /// 現在の時点を取得する
+  pub fn savepoint(&self) -> TransactionSavepoint {
+    TransactionSavepoint {
+      users: self.users.savepoint(),
+      works: self.works.savepoint(),
+      elements: self.elements.savepoint(),
+    }
+  }
+
+  /// `savepoint`の時点より後の操作を全て取り消す
+  pub fn rollback_to(
+    &mut self,
+    savepoint: TransactionSavepoint,
+  ) {
+    self.users.rollback_to(savepoint.users);
+    self.works.rollback_to(savepoint.works);
+    self.elements.rollback_to(savepoint.elements);
+  }
+
+  /// クロージャを不可分に実行する
+  ///
+  /// ## Summary
+  /// `f`が`Err`を返した場合、`f`の中で行った操作のみを取り消す。
+  /// トランザクション自体は継続する。
+  pub fn atomic<T, E>(
+    &mut self,
+    f: impl FnOnce(&mut Self) -> Result<T, E>,
+  ) -> Result<T, E> {
+    let savepoint = self.savepoint();
+    let ret = f(self);
+    if ret.is_err() {
+      self.rollback_to(savepoint);
+    }
+    ret
+  }
+
+  /// 全てのストレージの操作を確定する
+  ///
+  /// ## Summary
+  /// 書き込んだ要素のレコードをスキーマで検証し、
+  /// 表示名・本文を変更した要素の版を記録した上で、
+  /// ジャーナルがある場合は、変更したデータの確定時の内容を
+  /// 1つのフレームとして記録してから確定する。
+  ///
+  /// ## Error
+  /// - `HierarchyError::Schema`: レコードがスキーマに従わない
+  /// - `HierarchyError::Journal`: ジャーナルへの記録に失敗した
+  /// - `HierarchyError::Element`: 版の記録に失敗した
+  ///
+  /// いずれの場合も全ての操作は取り消される。
+  pub fn commit(mut self) -> Result<(), HierarchyError> {
+    self.check_touched_records()?;
+    self.record_revisions()?;
+    let Self { users, works, elements, journal } = self;
+    if let Some(journal) = journal {
+      let record = TransactionRecordRef {
+        users: users.redo(),
+        works: works.redo(),
+        elements: elements.redo(),
+      };
+      if !record.is_empty() {
+        journal
+          .append(&record)
+          .map_err(HierarchyError::Journal)?;
+      }
+    }
+    users.commit();
+    works.commit();
+    elements.commit();
+    Ok(())
+  }
+
+  /// 全てのストレージの操作を取り消す
+  pub fn rollback(self) {
+    // 各`StorageTx`の`Drop`で取り消される
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    domain::{
+      element::ElementParent, error::HierarchyError,
+      user::UserDataBody,
+    },
+    fixture::{
+      Store, container_body, element_body, work_body,
+    },
+    traits::prelude::*,
+  };
+
+  #[test]
+  fn failed_run_rolls_back_every_storage() {
+    let mut store = Store::default();
+    let (user, work, chapter) = store
+      .run(|tx| {
+        let user =
+          tx.spawn_user("alice", UserDataBody::default())?;
+        let work =
+          tx.spawn_work(user, "novel", work_body("work"))?;
+        let chapter = tx.spawn_element(
+          ElementParent::Root(work),
+          "chapter",
+          container_body(),
+        )?;
+        Ok((user, work, chapter))
+      })
+      .unwrap();
+
+    let failed = store.run(|tx| {
+      tx.spawn_user("bob", UserDataBody::default())?;
+      let other =
+        tx.spawn_work(user, "essay", work_body("other"))?;
+      tx.spawn_element(
+        ElementParent::Nest(chapter),
+        "section",
+        element_body("text"),
+      )?;
+      tx.elements.remove(chapter)?;
+      tx.works.modify(work, |b| {
+        b.display_name = "renamed".to_string()
+      })?;
+      Err::<(), _>(HierarchyError::WorkNotFound(other))
+    });
+    assert!(failed.is_err());
+
+    assert_eq!(store.users.len(), 1);
+    let user = store.users.get(user).unwrap();
+    assert_eq!(user.children().collect::<Vec<_>>(), [work]);
+    assert_eq!(store.works.len(), 1);
+    let work = store.works.get(work).unwrap();
+    assert_eq!(work.body.display_name, "work");
+    assert_eq!(work.body.children, [chapter]);
+    assert_eq!(store.elements.len(), 1);
+    let chapter = store.elements.get(chapter).unwrap();
+    assert_eq!(chapter.body.children, Some(Vec::new()));
+  }
+
+  #[test]
+  fn savepoints_and_nested_atomic_undo_only_their_part() {
+    let mut store = Store::default();
+    let (user, kept, dropped) = store
+      .run(|tx| {
+        let user =
+          tx.spawn_user("alice", UserDataBody::default())?;
+        let savepoint = tx.savepoint();
+        tx.spawn_work(user, "draft", work_body("draft"))?;
+        tx.rollback_to(savepoint);
+
+        let (kept, dropped) =
+          tx.atomic(|tx| {
+            let kept = tx.spawn_work(
+              user,
+              "novel",
+              work_body("novel"),
+            )?;
+            let inner = tx.atomic(|tx| {
+              let dropped = tx.spawn_element(
+                ElementParent::Root(kept),
+                "chapter",
+                element_body("text"),
+              )?;
+              Err::<(), _>(HierarchyError::ElementNotFound(
+                dropped,
+              ))
+            });
+            let Err(HierarchyError::ElementNotFound(
+              dropped,
+            )) = inner
+            else {
+              panic!("inner atomic must fail: {inner:?}");
+            };
+            Ok::<_, HierarchyError>((kept, dropped))
+          })?;
+
+        let outer = tx.atomic(|tx| {
+          tx.atomic(|tx| {
+            tx.spawn_work(user, "essay", work_body("essay"))
+          })?;
+          Err::<(), _>(HierarchyError::UserNotFound(user))
+        });
+        assert!(outer.is_err());
+        Ok((user, kept, dropped))
+      })
+      .unwrap();
+
+    let user = store.users.get(user).unwrap();
+    assert_eq!(user.children().collect::<Vec<_>>(), [kept]);
+    assert_eq!(store.works.len(), 1);
+    assert!(
+      store
+        .works
+        .get(kept)
+        .unwrap()
+        .body
+        .children
+        .is_empty()
+    );
+    assert!(store.elements.get(dropped).is_none());
+    assert!(store.elements.is_empty());
+  }
+}
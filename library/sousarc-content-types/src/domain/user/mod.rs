@@ -1,11 +1,10 @@
 use serde::{Deserialize, Serialize};
-use std::fmt::Display;
-use tokio::sync::RwLock;
+use std::{fmt::Display, sync::Arc};
 use uuid::Uuid;
 
 use crate::traits::prelude::*;
 
-use super::work::WorkId;
+use super::work::{WorkData, WorkId, WorkKey};
 
 #[derive(
   Debug,
@@ -34,6 +33,19 @@ impl SousARCId for UserId {
   type Bound = UserData;
 }
 
+impl UserId {
+  /// UUIDv7で新しいIDを生成する
+  pub fn new() -> Self {
+    Self(Uuid::now_v7())
+  }
+}
+
+impl Default for UserId {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 #[derive(
   Debug,
   Clone,
@@ -60,12 +72,85 @@ impl SousARCKey for UserKey {
   type Bound = UserData;
 }
 
-#[derive(Debug)]
+impl UserKey {
+  pub fn new(name: impl ToString) -> Self {
+    Self(name.to_string())
+  }
+
+  /// ユーザ名を取得する
+  pub fn name(&self) -> &str {
+    &self.0
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct UserData {
   id_key: IdKeySet<Self>,
 
-  pub body: RwLock<UserDataBody>,
+  pub body: UserBody,
 }
+
+/// ユーザの本体
+///
+/// ## Summary
+/// 本体を`Arc`で共有し、`read`は複製せずに参照を返す。
+/// 書き込みは`&mut`を経由する`get_mut`のみで、
+/// ストレージ(`get_mut`・`StorageTx::modify`等)を通して行う。
+/// 書き込み時に読み取った本体が残っていれば複製してから変更するので、
+/// 読み取った本体は変わらない。
+///
+/// ## Member
+/// - `body`: `Arc<UserDataBody>`型
+pub struct UserBody {
+  body: Arc<UserDataBody>,
+}
+
+impl std::fmt::Debug for UserBody {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    self.body.fmt(f)
+  }
+}
+
+impl Serialize for UserBody {
+  fn serialize<S: serde::Serializer>(
+    &self,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    self.body.serialize(serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for UserBody {
+  fn deserialize<D: serde::Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Self, D::Error> {
+    UserDataBody::deserialize(deserializer).map(Self::new)
+  }
+}
+
+impl UserBody {
+  pub fn new(body: UserDataBody) -> Self {
+    Self { body: Arc::new(body) }
+  }
+
+  /// 本体を取得する
+  pub fn read(&self) -> Arc<UserDataBody> {
+    self.body.clone()
+  }
+
+  /// 本体を可変参照で取得する
+  pub fn get_mut(&mut self) -> &mut UserDataBody {
+    Arc::make_mut(&mut self.body)
+  }
+
+  pub fn into_inner(self) -> UserDataBody {
+    Arc::unwrap_or_clone(self.body)
+  }
+}
+
 impl SousARCData for UserData {
   type Id = UserId;
   type Key = UserKey;
@@ -73,6 +158,48 @@ impl SousARCData for UserData {
   fn id_key_set(&self) -> &IdKeySet<Self> {
     &self.id_key
   }
+
+  fn id_key_set_mut(
+    &mut self,
+    _: IdKeyToken,
+  ) -> &mut IdKeySet<Self> {
+    &mut self.id_key
+  }
+}
+
+impl SousARCDataHasBody for UserData {
+  type Body = UserDataBody;
+
+  fn body(&self) -> &UserDataBody {
+    &self.body.body
+  }
+
+  fn body_mut(&mut self) -> &mut UserDataBody {
+    self.body.get_mut()
+  }
+
+  fn from_parts(
+    id_key: IdKeySet<Self>,
+    body: Self::Body,
+  ) -> Self {
+    Self { id_key, body: UserBody::new(body) }
+  }
+
+  fn into_parts(self) -> (IdKeySet<Self>, Self::Body) {
+    (self.id_key, self.body.into_inner())
+  }
+}
+
+impl SousARCDataHasChild<WorkData, UserId, WorkKey>
+  for UserData
+{
+  /// 子要素(`WorkId`)のイテレータを返す
+  ///
+  /// ## Summary
+  /// 本体の`children`を複製して返す。
+  fn children(&self) -> impl Iterator<Item = WorkId> {
+    self.body.read().children.clone().into_iter()
+  }
 }
 
 impl UserData {
@@ -83,12 +210,12 @@ impl UserData {
   ) -> Self {
     Self {
       id_key: IdKeySet::new(id, key),
-      body: RwLock::new(body),
+      body: UserBody::new(body),
     }
   }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct UserDataBody {
   pub children: Vec<WorkId>,
 
@@ -102,3 +229,41 @@ impl UserDataBody {
     self.children.iter().copied()
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::storage::StandardStorage;
+
+  #[test]
+  fn read_body_is_unchanged_by_later_writes() {
+    let id = UserId::new();
+    let mut storage = StandardStorage::new();
+    storage
+      .try_insert(UserData::new(
+        id,
+        UserKey::new("alice"),
+        UserDataBody {
+          display_name: "Alice".to_string(),
+          ..Default::default()
+        },
+      ))
+      .unwrap();
+
+    let read = storage.get(id).unwrap().body.read();
+    storage
+      .modify(id, |b| b.display_name = "Edited".to_string())
+      .unwrap();
+    assert_eq!(read.display_name, "Alice");
+
+    let mut bytes = Vec::new();
+    storage.write_snapshot(&mut bytes).unwrap();
+    let loaded: StandardStorage<UserData> =
+      StandardStorage::read_snapshot(&mut bytes.as_slice())
+        .unwrap();
+    assert_eq!(
+      loaded.get(id).unwrap().body.read().display_name,
+      "Edited"
+    );
+  }
+}
@@ -4,8 +4,9 @@ use uuid::Uuid;
 
 use crate::traits::prelude::*;
 
-use super::element::ElementId;
-use super::user::UserId;
+use super::element::{ElementData, ElementId, ElementKey};
+use super::schema::RecordSchema;
+use super::user::{UserData, UserId};
 
 #[derive(
   Debug,
@@ -34,6 +35,19 @@ impl SousARCId for WorkId {
   type Bound = WorkData;
 }
 
+impl WorkId {
+  /// UUIDv7で新しいIDを生成する
+  pub fn new() -> Self {
+    Self(Uuid::now_v7())
+  }
+}
+
+impl Default for WorkId {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 #[derive(
   Debug,
   Clone,
@@ -56,6 +70,29 @@ impl<T: ToString> From<(UserId, T)> for WorkKey {
   }
 }
 
+impl WorkKey {
+  /// 所有者の`UserId`を取得する
+  pub fn user_id(&self) -> UserId {
+    self.user_id
+  }
+
+  /// 作品名を取得する
+  pub fn work_name(&self) -> &str {
+    &self.work_name
+  }
+
+  /// 作品名だけを差し替えたキーを作成する
+  ///
+  /// ## Summary
+  /// `SousARCStorageMut::rekey`と組み合わせて作品名の変更に使う。
+  pub fn renamed(&self, work_name: impl ToString) -> Self {
+    Self {
+      user_id: self.user_id,
+      work_name: work_name.to_string(),
+    }
+  }
+}
+
 impl Display for WorkKey {
   fn fmt(
     &self,
@@ -74,7 +111,26 @@ impl SousARCKey for WorkKey {
   type Bound = WorkData;
 }
 
-#[derive(Debug)]
+/// 同じユーザの作品は`work_name`の昇順で連続して並ぶ
+impl SousARCKeyPrefix<UserId> for WorkKey {
+  fn prefix_lower(prefix: &UserId) -> Self {
+    Self { user_id: *prefix, work_name: String::new() }
+  }
+
+  fn has_prefix(&self, prefix: &UserId) -> bool {
+    &self.user_id == prefix
+  }
+}
+
+impl SousARCKeyHasParent<UserData, WorkData, UserId>
+  for WorkKey
+{
+  fn parent_id(&self) -> Option<UserId> {
+    Some(self.user_id)
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct WorkData {
   id_key: IdKeySet<Self>,
 
@@ -88,13 +144,65 @@ impl SousARCData for WorkData {
   fn id_key_set(&self) -> &IdKeySet<Self> {
     &self.id_key
   }
+
+  fn id_key_set_mut(
+    &mut self,
+    _: IdKeyToken,
+  ) -> &mut IdKeySet<Self> {
+    &mut self.id_key
+  }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl SousARCDataHasBody for WorkData {
+  type Body = WorkDataBody;
+
+  fn body(&self) -> &WorkDataBody {
+    &self.body
+  }
+
+  fn body_mut(&mut self) -> &mut WorkDataBody {
+    &mut self.body
+  }
+
+  fn from_parts(
+    id_key: IdKeySet<Self>,
+    body: Self::Body,
+  ) -> Self {
+    Self { id_key, body }
+  }
+
+  fn into_parts(self) -> (IdKeySet<Self>, Self::Body) {
+    (self.id_key, self.body)
+  }
+}
+
+impl SousARCDataHasChild<ElementData, WorkId, ElementKey>
+  for WorkData
+{
+  fn children(&self) -> impl Iterator<Item = ElementId> {
+    self.body.children.iter().copied()
+  }
+}
+
+impl WorkData {
+  pub fn new(
+    id: WorkId,
+    key: WorkKey,
+    body: WorkDataBody,
+  ) -> Self {
+    Self { id_key: IdKeySet::new(id, key), body }
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkDataBody {
   pub children: Vec<ElementId>,
 
   pub display_name: String,
 
   pub description: String,
+
+  /// 要素のレコードの型(スキーマ名→スキーマ)
+  #[serde(default)]
+  pub schemas: indexmap::IndexMap<String, RecordSchema>,
 }
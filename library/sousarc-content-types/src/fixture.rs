@@ -0,0 +1,113 @@
+//! テスト用の共通のデータ
+//!
+//! ## Summary
+//! - `work`・`work_body`・`element_body`・`container_body`:
+//!   最小限の中身を持つデータ
+//! - `spell_schema`・`spell_body`: スキーマ`spell`と、それに従う要素
+//! - `Store`: ユーザ・作品・要素の3つのストレージの組
+
+use crate::{
+  domain::{
+    element::{ElementData, ElementDataBody, ElementKind},
+    error::HierarchyError,
+    schema::{
+      CustomRecord, CustomValue, FieldDef, FieldType,
+      RecordSchema,
+    },
+    transaction::Transaction,
+    user::{UserData, UserId},
+    work::{WorkData, WorkDataBody, WorkId, WorkKey},
+  },
+  storage::StandardStorage,
+};
+
+/// 表示名を`name`とした作品
+pub fn work(user: UserId, name: &str) -> WorkData {
+  WorkData::new(
+    WorkId::new(),
+    WorkKey::from((user, name)),
+    work_body(name),
+  )
+}
+
+/// 表示名を`name`とした、子を持たない作品の本体
+pub fn work_body(name: &str) -> WorkDataBody {
+  WorkDataBody {
+    children: Vec::new(),
+    display_name: name.to_string(),
+    description: String::new(),
+    schemas: Default::default(),
+  }
+}
+
+/// 本文を`content`とした、子を持たない要素の本体
+pub fn element_body(content: &str) -> ElementDataBody {
+  ElementDataBody {
+    children: None,
+    display_name: String::new(),
+    content: content.to_string(),
+    kind: ElementKind::default(),
+  }
+}
+
+/// 子を持てる、本文が空の要素の本体
+pub fn container_body() -> ElementDataBody {
+  ElementDataBody {
+    children: Some(Vec::new()),
+    ..element_body("")
+  }
+}
+
+/// 必須の整数`power`を持つスキーマ
+pub fn spell_schema() -> RecordSchema {
+  RecordSchema {
+    version: 0,
+    fields: [(
+      "power".to_string(),
+      FieldDef::required(FieldType::Number),
+    )]
+    .into_iter()
+    .collect(),
+  }
+}
+
+/// `power`を持つ、スキーマ`spell`のレコードの要素の本体
+pub fn spell_body(power: i64) -> ElementDataBody {
+  ElementDataBody {
+    kind: ElementKind::Custom(CustomRecord {
+      schema: "spell".to_string(),
+      fields: [(
+        "power".to_string(),
+        CustomValue::Number(power),
+      )]
+      .into_iter()
+      .collect(),
+    }),
+    ..element_body("")
+  }
+}
+
+/// 3つのストレージの組
+#[derive(Default)]
+pub struct Store {
+  pub users: StandardStorage<UserData>,
+  pub works: StandardStorage<WorkData>,
+  pub elements: StandardStorage<ElementData>,
+}
+
+impl Store {
+  /// クロージャをトランザクション内で実行する
+  pub fn run<T>(
+    &mut self,
+    f: impl FnOnce(
+      &mut Transaction<'_>,
+    ) -> Result<T, HierarchyError>,
+  ) -> Result<T, HierarchyError> {
+    Transaction::run(
+      &mut self.users,
+      &mut self.works,
+      &mut self.elements,
+      f,
+    )
+  }
+}
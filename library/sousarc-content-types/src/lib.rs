@@ -3,10 +3,18 @@ pub mod traits;
 pub mod domain;
 pub mod storage;
 
+pub mod crdt;
+
+#[cfg(test)]
+mod fixture;
+
 pub mod prelude {
   pub use crate::{
     domain::{
       element::{ElementData, ElementId, ElementKey},
+      record::{
+        DataRecord, ElementRecord, UserRecord, WorkRecord,
+      },
       user::{UserData, UserId, UserKey},
       work::{WorkData, WorkId, WorkKey},
     },
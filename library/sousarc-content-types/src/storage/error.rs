@@ -0,0 +1,234 @@
+//! ストレージ操作のエラー型
+//!
+//! ## Summary
+//! - `StorageError`: 操作が失敗した理由(ID・キー・インデックス)
+//! - `InsertError`: 値を受け取る操作が失敗した際の理由と、
+//!   受け取った値そのもの
+//! - `SnapshotError`: スナップショットの保存・読み込みが失敗した理由
+//! - `EventError`: イベントの配信を有効にできなかった理由
+
+use std::fmt::Display;
+
+use crate::traits::prelude::*;
+
+/// ストレージ操作のエラー
+///
+/// ## Summary
+/// `StandardStorage`の操作が失敗した理由を表す。
+/// いずれの場合もストレージの内容は変更されない。
+#[derive(Debug)]
+pub enum StorageError<D: SousARCData> {
+  /// キーが既に別のデータに使われている
+  DuplicateKey(D::Key),
+
+  /// IDが既に別のデータに使われている
+  DuplicateId(D::Id),
+
+  /// IDに対応するデータが存在しない
+  NotFound(D::Id),
+
+  /// 一意インデックスの制約に違反した(インデックスの名称)
+  IndexConflict(String),
+
+  /// ジャーナルへの書き込みに失敗した
+  Journal(std::io::Error),
+
+  /// トランザクションの外から本体を変更しようとした
+  /// (`SousARCData::TRANSACTIONAL`なデータ型)
+  Transactional(D::Id),
+}
+
+impl<D: SousARCData> Display for StorageError<D> {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    match self {
+      Self::DuplicateKey(key) => {
+        write!(f, "duplicate key: {key}")
+      }
+      Self::DuplicateId(id) => {
+        write!(f, "duplicate id: {id}")
+      }
+      Self::NotFound(id) => write!(f, "not found: {id}"),
+      Self::IndexConflict(name) => {
+        write!(f, "unique index conflict: {name}")
+      }
+      Self::Journal(e) => {
+        write!(f, "journal write failed: {e}")
+      }
+      Self::Transactional(id) => {
+        write!(f, "must be modified in a transaction: {id}")
+      }
+    }
+  }
+}
+
+impl<D> std::error::Error for StorageError<D>
+where
+  D: SousARCData + std::fmt::Debug,
+{
+  fn source(
+    &self,
+  ) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Self::Journal(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+/// 値を受け取る操作のエラー
+///
+/// ## Summary
+/// `try_insert`・`replace`・`upsert`が失敗した場合に、
+/// 失敗の理由と共に受け取ったデータを呼び出し元へ返す。
+///
+/// ## Member
+/// - `error`: `StorageError<D>`型
+///   - 失敗の理由
+/// - `data`: `D`型
+///   - 挿入されなかったデータ
+#[derive(Debug)]
+pub struct InsertError<D: SousARCData> {
+  /// 失敗の理由
+  pub error: StorageError<D>,
+
+  /// 挿入されなかったデータ
+  pub data: D,
+}
+
+impl<D: SousARCData> InsertError<D> {
+  pub(super) fn new(
+    error: StorageError<D>,
+    data: D,
+  ) -> Self {
+    Self { error, data }
+  }
+
+  /// 挿入されなかったデータを取り出す
+  pub fn into_data(self) -> D {
+    self.data
+  }
+}
+
+impl<D: SousARCData> Display for InsertError<D> {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    write!(f, "{}", self.error)
+  }
+}
+
+impl<D> std::error::Error for InsertError<D>
+where
+  D: SousARCData + std::fmt::Debug,
+{
+  fn source(
+    &self,
+  ) -> Option<&(dyn std::error::Error + 'static)> {
+    Some(&self.error)
+  }
+}
+
+/// スナップショットの保存・読み込みのエラー
+#[derive(Debug)]
+pub enum SnapshotError {
+  /// ファイルの読み書きに失敗した
+  Io(std::io::Error),
+
+  /// MessagePackへの変換に失敗した
+  Encode(rmp_serde::encode::Error),
+
+  /// MessagePackからの変換に失敗した
+  Decode(rmp_serde::decode::Error),
+
+  /// スナップショットの形式ではない
+  InvalidFormat,
+
+  /// 対応していない形式のバージョン
+  UnsupportedVersion(u32),
+
+  /// 内容が整合しない(IDやキーの重複等)
+  Corrupted(String),
+}
+
+impl Display for SnapshotError {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    match self {
+      Self::Io(e) => write!(f, "snapshot io error: {e}"),
+      Self::Encode(e) => {
+        write!(f, "snapshot encode error: {e}")
+      }
+      Self::Decode(e) => {
+        write!(f, "snapshot decode error: {e}")
+      }
+      Self::InvalidFormat => {
+        write!(f, "not a storage snapshot")
+      }
+      Self::UnsupportedVersion(v) => {
+        write!(f, "unsupported snapshot version: {v}")
+      }
+      Self::Corrupted(reason) => {
+        write!(f, "corrupted snapshot: {reason}")
+      }
+    }
+  }
+}
+
+impl std::error::Error for SnapshotError {
+  fn source(
+    &self,
+  ) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Self::Io(e) => Some(e),
+      Self::Encode(e) => Some(e),
+      Self::Decode(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<std::io::Error> for SnapshotError {
+  fn from(e: std::io::Error) -> Self {
+    Self::Io(e)
+  }
+}
+
+impl From<rmp_serde::encode::Error> for SnapshotError {
+  fn from(e: rmp_serde::encode::Error) -> Self {
+    Self::Encode(e)
+  }
+}
+
+impl From<rmp_serde::decode::Error> for SnapshotError {
+  fn from(e: rmp_serde::decode::Error) -> Self {
+    Self::Decode(e)
+  }
+}
+
+/// イベントの配信の設定のエラー
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventError {
+  /// 受信側毎に溜めておけるイベントの数が0
+  ZeroCapacity,
+}
+
+impl Display for EventError {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    match self {
+      Self::ZeroCapacity => {
+        write!(f, "event capacity must be positive")
+      }
+    }
+  }
+}
+
+impl std::error::Error for EventError {}
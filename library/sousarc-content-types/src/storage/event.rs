@@ -0,0 +1,417 @@
+//! ストレージの変更イベント
+//!
+//! ## Summary
+//! - `StorageEvent`: 挿入・更新・削除のイベント
+//! - `EventSink`: イベントの送信元(リビジョンの採番も行う)
+//! - `EventSubscriber`: イベントの受信側。親等で絞り込める
+//!
+//! イベントの配信は`tokio::sync::broadcast`で行い、
+//! `enable_events`を呼ぶまでは配信しない(オプトイン)。
+//! `StorageTx`の間は配信を保留し、確定した操作のイベントのみを配信する。
+
+use std::sync::{
+  Arc,
+  atomic::{AtomicU64, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use super::EventError;
+use crate::traits::prelude::*;
+
+/// 変更イベントの種類
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  Hash,
+  Serialize,
+  Deserialize,
+)]
+pub enum StorageEventKind {
+  /// データが挿入された
+  Inserted,
+
+  /// データ(本体またはキー)が更新された
+  Updated,
+
+  /// データが削除された
+  Removed,
+}
+
+/// 変更イベント
+///
+/// ## Member
+/// - `kind`: `StorageEventKind`型
+///   - イベントの種類
+/// - `id`: `D::Id`型
+///   - 変更されたデータのID
+/// - `key`: `D::Key`型
+///   - 変更後のキー(削除の場合は削除時のキー)
+/// - `old_key`: `Option<D::Key>`型
+///   - キーが変更された場合は変更前のキー
+/// - `revision`: `u64`型
+///   - ストレージ内で変更毎に単調増加するリビジョン
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct StorageEvent<D: SousARCData> {
+  pub kind: StorageEventKind,
+  pub id: D::Id,
+  pub key: D::Key,
+  #[serde(default)]
+  pub old_key: Option<D::Key>,
+  pub revision: u64,
+}
+impl<D: SousARCData> Clone for StorageEvent<D> {
+  fn clone(&self) -> Self {
+    Self {
+      kind: self.kind,
+      id: self.id,
+      key: self.key.clone(),
+      old_key: self.old_key.clone(),
+      revision: self.revision,
+    }
+  }
+}
+impl<D: SousARCData> StorageEvent<D> {
+  /// リビジョンを採番する前のイベントを作成する
+  pub(super) fn new(
+    kind: StorageEventKind,
+    id: D::Id,
+    key: &D::Key,
+  ) -> Self {
+    Self {
+      kind,
+      id,
+      key: key.clone(),
+      old_key: None,
+      revision: 0,
+    }
+  }
+
+  /// 変更前のキーを添える
+  ///
+  /// ## Summary
+  /// キーが変わっていない場合は何もしない。
+  pub(super) fn moved_from(
+    mut self,
+    old_key: &D::Key,
+  ) -> Self {
+    if old_key != &self.key {
+      self.old_key = Some(old_key.clone());
+    }
+    self
+  }
+}
+
+/// イベントの送信元
+///
+/// ## Summary
+/// リビジョンはイベントの配信が無効でも採番する。
+/// 複製すると同じ配信先・リビジョンを共有する(保留中のイベントは共有しない)。
+///
+/// ## Member
+/// - `sender`: 配信先(配信が無効の場合は`None`)
+/// - `revision`: `Arc<AtomicU64>`型
+///   - 最後に採番したリビジョン
+/// - `pending`: `Option<Vec<StorageEvent<D>>>`型
+///   - 配信を保留しているイベント(保留していない場合は`None`)
+pub struct EventSink<D: SousARCData> {
+  sender: Option<broadcast::Sender<StorageEvent<D>>>,
+  revision: Arc<AtomicU64>,
+  pending: Option<Vec<StorageEvent<D>>>,
+}
+impl<D: SousARCData> Clone for EventSink<D> {
+  fn clone(&self) -> Self {
+    Self {
+      sender: self.sender.clone(),
+      revision: self.revision.clone(),
+      pending: None,
+    }
+  }
+}
+impl<D: SousARCData> Default for EventSink<D> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+impl<D: SousARCData> std::fmt::Debug for EventSink<D> {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    f.debug_struct("EventSink")
+      .field("enabled", &self.sender.is_some())
+      .field("revision", &self.revision())
+      .finish()
+  }
+}
+impl<D: SousARCData> EventSink<D> {
+  pub fn new() -> Self {
+    Self {
+      sender: None,
+      revision: Arc::new(AtomicU64::new(0)),
+      pending: None,
+    }
+  }
+
+  /// イベントの配信を有効にする
+  ///
+  /// ## Argument
+  /// - `capacity`: `usize`
+  ///   - 受信側毎に溜めておけるイベントの数
+  ///
+  /// ## Error
+  /// - `EventError::ZeroCapacity`: `capacity`が0
+  pub fn enable(
+    &mut self,
+    capacity: usize,
+  ) -> Result<(), EventError> {
+    if capacity == 0 {
+      return Err(EventError::ZeroCapacity);
+    }
+    if self.sender.is_none() {
+      self.sender = Some(broadcast::channel(capacity).0);
+    }
+    Ok(())
+  }
+
+  /// 最後に採番したリビジョンを取得する
+  pub fn revision(&self) -> u64 {
+    self.revision.load(Ordering::Acquire)
+  }
+
+  /// イベントを購読する
+  ///
+  /// ## Return
+  /// 配信が無効の場合は`None`
+  pub fn subscribe(&self) -> Option<EventSubscriber<D>> {
+    self.sender.as_ref().map(|s| EventSubscriber {
+      receiver: s.subscribe(),
+      filter: None,
+    })
+  }
+
+  /// リビジョンを採番し、イベントを直ちに配信する
+  pub(super) fn emit(&self, mut event: StorageEvent<D>) {
+    event.revision =
+      self.revision.fetch_add(1, Ordering::AcqRel) + 1;
+    if let Some(sender) = &self.sender
+      && sender.receiver_count() > 0
+    {
+      let _ = sender.send(event);
+    }
+  }
+
+  /// イベントを記録する
+  ///
+  /// ## Summary
+  /// 配信を保留している場合は溜めておき、そうでなければ直ちに配信する。
+  pub(super) fn record(&mut self, event: StorageEvent<D>) {
+    match &mut self.pending {
+      Some(pending) => pending.push(event),
+      None => self.emit(event),
+    }
+  }
+
+  /// 以降のイベントの配信を`release`まで保留する
+  pub(super) fn hold(&mut self) {
+    self.pending.get_or_insert_default();
+  }
+
+  /// 保留しているイベントの数
+  pub(super) fn held(&self) -> usize {
+    self.pending.as_ref().map_or(0, Vec::len)
+  }
+
+  /// 保留しているイベントを`len`件まで破棄する
+  pub(super) fn discard_to(&mut self, len: usize) {
+    if let Some(pending) = &mut self.pending {
+      pending.truncate(len);
+    }
+  }
+
+  /// 保留を解除し、保留していたイベントを順に配信する
+  pub(super) fn release(&mut self) {
+    for event in self.pending.take().into_iter().flatten() {
+      self.emit(event);
+    }
+  }
+}
+
+/// 絞り込み条件
+type EventFilter<D> =
+  Box<dyn Fn(&StorageEvent<D>) -> bool + Send + Sync>;
+
+/// イベントの受信側
+pub struct EventSubscriber<D: SousARCData> {
+  receiver: broadcast::Receiver<StorageEvent<D>>,
+  filter: Option<EventFilter<D>>,
+}
+impl<D: SousARCData> EventSubscriber<D> {
+  /// 条件を満たすイベントのみを受信する
+  ///
+  /// ## Summary
+  /// 既に条件がある場合は、両方を満たすイベントのみを受信する。
+  pub fn filter(
+    mut self,
+    f: impl Fn(&StorageEvent<D>) -> bool + Send + Sync + 'static,
+  ) -> Self {
+    self.filter = Some(match self.filter.take() {
+      Some(prev) => Box::new(move |e| prev(e) && f(e)),
+      None => Box::new(f),
+    });
+    self
+  }
+
+  /// キーが接頭辞`prefix`を持つデータのイベントのみを受信する
+  ///
+  /// ## Summary
+  /// 例えば`ElementParent`を指定すると、その親の直下の要素のみを受信する。
+  /// キーの変更は、変更前・変更後のどちらかが接頭辞を持てば受信するので、
+  /// 接頭辞の外へ移動したデータも検知できる。
+  pub fn with_prefix<P>(self, prefix: P) -> Self
+  where
+    D::Key: SousARCKeyPrefix<P>,
+    P: Send + Sync + 'static,
+  {
+    self.filter(move |e| {
+      e.key.has_prefix(&prefix)
+        || e
+          .old_key
+          .as_ref()
+          .is_some_and(|k| k.has_prefix(&prefix))
+    })
+  }
+
+  fn accepts(&self, event: &StorageEvent<D>) -> bool {
+    self.filter.as_ref().is_none_or(|f| f(event))
+  }
+
+  /// 次のイベントを待機して受信する
+  ///
+  /// ## Error
+  /// - `RecvError::Lagged`: 受信が遅れ、イベントを取りこぼした
+  /// - `RecvError::Closed`: 送信元が破棄された
+  pub async fn recv(
+    &mut self,
+  ) -> Result<StorageEvent<D>, broadcast::error::RecvError>
+  {
+    loop {
+      let event = self.receiver.recv().await?;
+      if self.accepts(&event) {
+        return Ok(event);
+      }
+    }
+  }
+
+  /// 受信済みのイベントがあれば受信する
+  pub fn try_recv(
+    &mut self,
+  ) -> Result<StorageEvent<D>, broadcast::error::TryRecvError>
+  {
+    loop {
+      let event = self.receiver.try_recv()?;
+      if self.accepts(&event) {
+        return Ok(event);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    domain::{
+      user::UserId,
+      work::{WorkData, WorkKey},
+    },
+    fixture::work,
+    storage::{
+      EventError, StandardStorage, StorageEventKind,
+      StorageTx,
+    },
+    traits::prelude::*,
+  };
+
+  #[test]
+  fn zero_capacity_is_rejected() {
+    let mut storage = StandardStorage::<WorkData>::new();
+    assert_eq!(
+      storage.enable_events(0),
+      Err(EventError::ZeroCapacity)
+    );
+    assert!(storage.subscribe().is_none());
+  }
+
+  #[test]
+  fn rolled_back_operations_are_not_delivered() {
+    let user = UserId::new();
+    let mut storage = StandardStorage::new();
+    storage.enable_events(16).unwrap();
+    let mut events = storage.subscribe().unwrap();
+
+    let mut tx = StorageTx::new(&mut storage);
+    tx.insert(work(user, "a")).unwrap();
+    tx.rollback();
+    assert!(events.try_recv().is_err());
+
+    let mut tx = StorageTx::new(&mut storage);
+    let kept = work(user, "b");
+    let kept_id = kept.id();
+    tx.insert(kept).unwrap();
+    let savepoint = tx.savepoint();
+    tx.insert(work(user, "c")).unwrap();
+    tx.rollback_to(savepoint);
+    assert!(events.try_recv().is_err());
+    tx.commit();
+
+    let event = events.try_recv().unwrap();
+    assert_eq!(event.kind, StorageEventKind::Inserted);
+    assert_eq!(event.id, kept_id);
+    assert!(events.try_recv().is_err());
+  }
+
+  #[test]
+  fn untouched_guard_is_silent() {
+    let mut storage = StandardStorage::new();
+    storage.enable_events(16).unwrap();
+    let data = work(UserId::new(), "a");
+    let id = data.id();
+    storage.try_insert(data).unwrap();
+    let mut events = storage.subscribe().unwrap();
+
+    let name = storage.get_mut(id).unwrap().key().clone();
+    assert_eq!(name.work_name(), "a");
+    assert!(events.try_recv().is_err());
+
+    storage.get_mut(id).unwrap().body.description =
+      "changed".to_string();
+    let event = events.try_recv().unwrap();
+    assert_eq!(event.kind, StorageEventKind::Updated);
+  }
+
+  #[test]
+  fn prefix_subscriber_sees_moves_out() {
+    let (from, to) = (UserId::new(), UserId::new());
+    let mut storage = StandardStorage::new();
+    storage.enable_events(16).unwrap();
+    let data = work(from, "a");
+    let id = data.id();
+    storage.try_insert(data).unwrap();
+    let mut events =
+      storage.subscribe().unwrap().with_prefix(from);
+
+    storage.rekey(id, WorkKey::from((to, "a"))).unwrap();
+    let event = events.try_recv().unwrap();
+    assert_eq!(event.id, id);
+    assert_eq!(event.key.user_id(), to);
+    assert_eq!(
+      event.old_key.map(|k| k.user_id()),
+      Some(from)
+    );
+  }
+}
@@ -0,0 +1,36 @@
+//! 世代付きスロットハンドル
+//!
+//! ## Summary
+//! - `SlotHandle`: スロット番号と世代の組
+//!
+//! ハッシュを引かずにO(1)でデータへアクセスする為に使う。
+//! 世代はストレージ内で挿入毎に一意に採番されるので、
+//! 削除や再配置の後に古いハンドルでアクセスしても`None`になる。
+
+/// 世代付きスロットハンドル
+///
+/// ## Member
+/// - `index`: `usize`型
+///   - `StandardStorage::data`上のスロット番号
+/// - `generation`: `u64`型
+///   - 挿入時に採番された世代
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SlotHandle {
+  index: usize,
+  generation: u64,
+}
+impl SlotHandle {
+  pub(super) fn new(index: usize, generation: u64) -> Self {
+    Self { index, generation }
+  }
+
+  /// スロット番号を取得する
+  pub fn index(&self) -> usize {
+    self.index
+  }
+
+  /// 世代を取得する
+  pub fn generation(&self) -> u64 {
+    self.generation
+  }
+}
@@ -0,0 +1,564 @@
+//! データ本体に対するセカンダリインデックス
+//!
+//! ## Summary
+//! - `SecondaryIndex`: 抽出関数でデータから値を取り出し、値→IDを引く索引
+//! - `IndexHandle`: 登録したインデックスを型付きで参照するハンドル
+//! - `StorageRefMut`: 可変参照のガード。ドロップ時にインデックスを更新する
+//!
+//! インデックスは`StandardStorage`の挿入・削除・可変参照と同期される。
+//! 一意インデックスを登録したストレージは可変参照を渡さないので、
+//! 本体は制約に違反する変更を取り消す`StandardStorage::modify`で変更する。
+
+use std::{
+  any::Any,
+  collections::{BTreeMap, BTreeSet, HashMap},
+  fmt::Debug,
+  marker::PhantomData,
+  ops::{Deref, DerefMut},
+};
+
+use super::{EventSink, StorageEvent, StorageEventKind};
+use crate::traits::prelude::*;
+
+/// インデックスの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IndexKind {
+  /// 1つの値に対して高々1件のデータのみを許す
+  Unique,
+
+  /// 1つの値に対して複数のデータを許す
+  NonUnique,
+}
+
+/// 登録済みインデックスへの型付きハンドル
+///
+/// ## Summary
+/// `StandardStorage::add_index`が返す。
+/// 検索時に値の型`V`を静的に保証する為に使う。
+pub struct IndexHandle<V> {
+  slot: usize,
+  _marker: PhantomData<fn() -> V>,
+}
+impl<V> Clone for IndexHandle<V> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+impl<V> Copy for IndexHandle<V> {}
+impl<V> Debug for IndexHandle<V> {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    write!(f, "IndexHandle({})", self.slot)
+  }
+}
+
+/// インデックスの値として使える型
+pub trait IndexValue:
+  Ord + Clone + Send + Sync + 'static
+{
+}
+impl<V: Ord + Clone + Send + Sync + 'static> IndexValue
+  for V
+{
+}
+
+/// 抽出関数
+type Extractor<D, V> =
+  Box<dyn Fn(&D) -> Vec<V> + Send + Sync>;
+
+/// セカンダリインデックス
+///
+/// ## Member
+/// - `name`: `String`型
+///   - インデックスの名称(エラー報告用)
+/// - `kind`: `IndexKind`型
+///   - 一意制約の有無
+/// - `extractor`: 抽出関数
+///   - データから索引する値を取り出す
+/// - `forward`: `BTreeMap<V, BTreeSet<D::Id>>`型
+///   - 値→IDの索引
+/// - `reverse`: `HashMap<D::Id, Vec<V>>`型
+///   - ID→値の逆引き(更新・削除時に使う)
+pub struct SecondaryIndex<D: SousARCData, V: IndexValue> {
+  name: String,
+  kind: IndexKind,
+  extractor: Extractor<D, V>,
+  forward: BTreeMap<V, BTreeSet<D::Id>>,
+  reverse: HashMap<D::Id, Vec<V>>,
+}
+impl<D: SousARCData, V: IndexValue> SecondaryIndex<D, V> {
+  fn new(
+    name: String,
+    kind: IndexKind,
+    extractor: Extractor<D, V>,
+  ) -> Self {
+    Self {
+      name,
+      kind,
+      extractor,
+      forward: BTreeMap::new(),
+      reverse: HashMap::new(),
+    }
+  }
+
+  /// 値に対応するIDを列挙する
+  pub fn ids(
+    &self,
+    value: &V,
+  ) -> impl Iterator<Item = D::Id> {
+    self.forward.get(value).into_iter().flatten().copied()
+  }
+
+  /// 範囲に含まれる値を持つIDを、値の昇順で列挙する
+  pub fn range<R>(
+    &self,
+    range: R,
+  ) -> impl Iterator<Item = D::Id>
+  where
+    R: std::ops::RangeBounds<V>,
+  {
+    self
+      .forward
+      .range(range)
+      .flat_map(|(_, ids)| ids)
+      .copied()
+  }
+
+  /// 索引されている値を昇順で列挙する
+  pub fn values(&self) -> impl Iterator<Item = &V> {
+    self.forward.keys()
+  }
+
+  /// 一意制約に違反する値が含まれるか判定する
+  fn conflicts(&self, values: &[V], id: D::Id) -> bool {
+    self.kind == IndexKind::Unique
+      && values.iter().any(|v| {
+        self
+          .forward
+          .get(v)
+          .is_some_and(|ids| ids.iter().any(|i| *i != id))
+      })
+  }
+}
+
+/// 型消去したインデックスの操作
+pub(super) trait DynIndex<D: SousARCData>:
+  Send + Sync
+{
+  /// インデックスの名称
+  fn name(&self) -> &str;
+
+  /// データを索引した場合に一意制約に違反するか判定する
+  fn conflicts_with(&self, data: &D) -> bool;
+
+  /// データを索引する
+  ///
+  /// ## Summary
+  /// 一意制約は検査しないので、先に`conflicts_with`で検査すること。
+  fn insert(&mut self, data: &D);
+
+  /// IDの索引を削除する
+  fn remove(&mut self, id: D::Id);
+
+  /// 一意インデックスか
+  fn is_unique(&self) -> bool;
+
+  fn as_any(&self) -> &dyn Any;
+}
+impl<D: SousARCData, V: IndexValue> DynIndex<D>
+  for SecondaryIndex<D, V>
+{
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn conflicts_with(&self, data: &D) -> bool {
+    self.conflicts(&(self.extractor)(data), data.id())
+  }
+
+  fn insert(&mut self, data: &D) {
+    let id = data.id();
+    let values = (self.extractor)(data);
+    for v in &values {
+      self.forward.entry(v.clone()).or_default().insert(id);
+    }
+    self.reverse.insert(id, values);
+  }
+
+  fn remove(&mut self, id: D::Id) {
+    for v in self.reverse.remove(&id).into_iter().flatten()
+    {
+      if let Some(ids) = self.forward.get_mut(&v) {
+        ids.remove(&id);
+        if ids.is_empty() {
+          self.forward.remove(&v);
+        }
+      }
+    }
+  }
+
+  fn is_unique(&self) -> bool {
+    self.kind == IndexKind::Unique
+  }
+
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+}
+
+/// ストレージに登録されたインデックスの集合
+pub struct Indexes<D: SousARCData> {
+  slots: Vec<Option<Box<dyn DynIndex<D>>>>,
+}
+impl<D: SousARCData> Debug for Indexes<D> {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    f.debug_list()
+      .entries(
+        self.slots.iter().flatten().map(|i| i.name()),
+      )
+      .finish()
+  }
+}
+impl<D: SousARCData> Indexes<D> {
+  pub(super) fn new() -> Self {
+    Self { slots: Vec::new() }
+  }
+
+  /// 登録されたインデックスが無いか判定する
+  pub(super) fn is_empty(&self) -> bool {
+    self.slots.iter().all(Option::is_none)
+  }
+
+  /// 一意インデックスが登録されているか判定する
+  pub(super) fn has_unique(&self) -> bool {
+    self.iter().any(|i| i.is_unique())
+  }
+
+  fn iter(&self) -> impl Iterator<Item = &dyn DynIndex<D>> {
+    self.slots.iter().flatten().map(|i| i.as_ref())
+  }
+
+  /// 一意制約に違反するインデックスの名称を返す
+  pub(super) fn conflict(
+    &self,
+    data: &D,
+  ) -> Option<String> {
+    self
+      .iter()
+      .find(|i| i.conflicts_with(data))
+      .map(|i| i.name().to_string())
+  }
+
+  /// データを索引する
+  ///
+  /// ## Summary
+  /// 一意制約は検査しないので、先に`conflict`で検査すること。
+  pub(super) fn insert(&mut self, data: &D) {
+    for index in self.slots.iter_mut().flatten() {
+      index.insert(data);
+    }
+  }
+
+  pub(super) fn remove(&mut self, id: D::Id) {
+    for index in self.slots.iter_mut().flatten() {
+      index.remove(id);
+    }
+  }
+
+  /// データの索引を作り直す
+  pub(super) fn update(&mut self, data: &D) {
+    if self.is_empty() {
+      return;
+    }
+    self.remove(data.id());
+    self.insert(data);
+  }
+
+  /// インデックスを登録する
+  ///
+  /// ## Error
+  /// 既存のデータが一意制約に違反する場合、
+  /// 登録せずにインデックスの名称を返す
+  pub(super) fn add<'a, V: IndexValue>(
+    &mut self,
+    name: String,
+    kind: IndexKind,
+    extractor: Extractor<D, V>,
+    existing: impl Iterator<Item = &'a D>,
+  ) -> Result<IndexHandle<V>, String> {
+    let mut index =
+      SecondaryIndex::new(name, kind, extractor);
+    for data in existing {
+      if index.conflicts_with(data) {
+        return Err(index.name);
+      }
+      DynIndex::insert(&mut index, data);
+    }
+    self.slots.push(Some(Box::new(index)));
+    Ok(IndexHandle {
+      slot: self.slots.len() - 1,
+      _marker: PhantomData,
+    })
+  }
+
+  /// インデックスの登録を解除する
+  pub(super) fn drop_index<V: IndexValue>(
+    &mut self,
+    handle: IndexHandle<V>,
+  ) -> bool {
+    self
+      .slots
+      .get_mut(handle.slot)
+      .and_then(Option::take)
+      .is_some()
+  }
+
+  /// 名称と値の型が一致するインデックスのハンドルを引く
+  pub(super) fn find<V: IndexValue>(
+    &self,
+    name: &str,
+  ) -> Option<IndexHandle<V>> {
+    self.slots.iter().enumerate().find_map(
+      |(slot, index)| {
+        let index = index.as_ref()?;
+        (index.name() == name
+          && index.as_any().is::<SecondaryIndex<D, V>>())
+        .then_some(IndexHandle {
+          slot,
+          _marker: PhantomData,
+        })
+      },
+    )
+  }
+
+  pub(super) fn get<V: IndexValue>(
+    &self,
+    handle: IndexHandle<V>,
+  ) -> Option<&SecondaryIndex<D, V>> {
+    self
+      .slots
+      .get(handle.slot)
+      .and_then(Option::as_ref)
+      .and_then(|i| i.as_any().downcast_ref())
+  }
+}
+
+/// 可変参照のガード
+///
+/// ## Summary
+/// `SousARCStorageMut::get_mut`・`get_by_key_mut`が返す。
+/// 可変参照を取り出した場合のみ、ドロップ時に変更後のデータで
+/// インデックスを更新し、`StorageEventKind::Updated`のイベントを配信する。
+///
+/// ドロップ時には変更を取り消せないので、
+/// 一意インデックスを持つストレージはガードを返さない。
+pub struct StorageRefMut<'a, D: SousARCData> {
+  data: &'a mut D,
+  indexes: &'a mut Indexes<D>,
+  events: &'a mut EventSink<D>,
+  touched: bool,
+}
+impl<'a, D: SousARCData> StorageRefMut<'a, D> {
+  pub(super) fn new(
+    data: &'a mut D,
+    indexes: &'a mut Indexes<D>,
+    events: &'a mut EventSink<D>,
+  ) -> Self {
+    Self { data, indexes, events, touched: false }
+  }
+}
+impl<D: SousARCData> Deref for StorageRefMut<'_, D> {
+  type Target = D;
+
+  fn deref(&self) -> &D {
+    self.data
+  }
+}
+impl<D: SousARCData> DerefMut for StorageRefMut<'_, D> {
+  fn deref_mut(&mut self) -> &mut D {
+    self.touched = true;
+    self.data
+  }
+}
+impl<D: SousARCData> Drop for StorageRefMut<'_, D> {
+  fn drop(&mut self) {
+    if !self.touched {
+      return;
+    }
+    self.indexes.update(self.data);
+    self.events.record(StorageEvent::new(
+      StorageEventKind::Updated,
+      self.data.id(),
+      self.data.key(),
+    ));
+  }
+}
+impl<D: SousARCData + Debug> Debug
+  for StorageRefMut<'_, D>
+{
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    self.data.fmt(f)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    domain::{
+      user::UserId,
+      work::{WorkData, WorkKey},
+    },
+    fixture::work,
+    storage::{IndexKind, StandardStorage, StorageError},
+    traits::prelude::*,
+  };
+
+  #[test]
+  fn modify_rejects_unique_conflict() {
+    let user = UserId::new();
+    let mut storage = StandardStorage::new();
+    let by_name = storage
+      .add_index(
+        "name",
+        IndexKind::Unique,
+        |w: &WorkData| [w.body.display_name.clone()],
+      )
+      .unwrap();
+    let a = work(user, "a");
+    let b = work(user, "b");
+    let (a_id, b_id) = (a.id(), b.id());
+    storage.try_insert(a).unwrap();
+    storage.try_insert(b).unwrap();
+
+    let result = storage.modify(b_id, |b| {
+      b.display_name = "a".to_string();
+    });
+    assert!(matches!(
+      result,
+      Err(StorageError::IndexConflict(_))
+    ));
+    assert_eq!(
+      storage.get(b_id).unwrap().body.display_name,
+      "b"
+    );
+    let found: Vec<_> = storage
+      .find(by_name, &"b".to_string())
+      .map(|w| w.id())
+      .collect();
+    assert_eq!(found, [b_id]);
+    assert_eq!(
+      storage
+        .find_unique(by_name, &"a".to_string())
+        .map(|w| w.id()),
+      Some(a_id)
+    );
+  }
+
+  #[test]
+  fn rekey_rejects_unique_conflict() {
+    let user = UserId::new();
+    let mut storage = StandardStorage::new();
+    storage
+      .add_index(
+        "key",
+        IndexKind::Unique,
+        |w: &WorkData| [w.key().work_name().to_uppercase()],
+      )
+      .unwrap();
+    let a = work(user, "a");
+    let b = work(user, "b");
+    let b_id = b.id();
+    storage.try_insert(a).unwrap();
+    storage.try_insert(b).unwrap();
+
+    let renamed = WorkKey::from((user, "A"));
+    assert!(matches!(
+      storage.rekey(b_id, renamed.clone()),
+      Err(StorageError::IndexConflict(_))
+    ));
+    assert_eq!(storage.key(b_id).unwrap().work_name(), "b");
+    assert!(storage.get_by_key(&renamed).is_none());
+  }
+
+  #[test]
+  fn get_mut_is_refused_with_unique_index() {
+    let user = UserId::new();
+    let mut storage = StandardStorage::new();
+    let by_name = storage
+      .add_index(
+        "name",
+        IndexKind::Unique,
+        |w: &WorkData| [w.body.display_name.clone()],
+      )
+      .unwrap();
+    let a = work(user, "a");
+    let b = work(user, "b");
+    let b_id = b.id();
+    storage.try_insert(a).unwrap();
+    let handle = storage.try_insert(b).unwrap();
+
+    // 違反する変更は確定前に検査できる`modify`でのみ受け付ける
+    assert!(storage.get_mut(b_id).is_none());
+    assert!(
+      storage
+        .get_by_key_mut(&WorkKey::from((user, "b")))
+        .is_none()
+    );
+    assert!(storage.get_by_handle_mut(handle).is_none());
+    assert!(matches!(
+      storage.modify(b_id, |b| {
+        b.display_name = "a".to_string();
+      }),
+      Err(StorageError::IndexConflict(_))
+    ));
+    assert_eq!(
+      storage.find(by_name, &"a".to_string()).count(),
+      1
+    );
+
+    assert!(storage.drop_index(by_name));
+    storage.get_mut(b_id).unwrap().body.display_name =
+      "a".to_string();
+    assert_eq!(
+      storage.get(b_id).unwrap().body.display_name,
+      "a"
+    );
+  }
+
+  #[test]
+  fn get_mut_reindexes_non_unique_values() {
+    let user = UserId::new();
+    let mut storage = StandardStorage::new();
+    let by_name = storage
+      .add_index(
+        "name",
+        IndexKind::NonUnique,
+        |w: &WorkData| [w.body.display_name.clone()],
+      )
+      .unwrap();
+    let a = work(user, "a");
+    let b = work(user, "b");
+    let b_id = b.id();
+    storage.try_insert(a).unwrap();
+    storage.try_insert(b).unwrap();
+
+    storage.get_mut(b_id).unwrap().body.display_name =
+      "a".to_string();
+    assert_eq!(
+      storage.find(by_name, &"a".to_string()).count(),
+      2
+    );
+    assert_eq!(
+      storage.find(by_name, &"b".to_string()).count(),
+      0
+    );
+  }
+}
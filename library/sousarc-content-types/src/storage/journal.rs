@@ -0,0 +1,901 @@
+//! 先行書き込みログ(ジャーナル)とクラッシュからの復旧
+//!
+//! ## Summary
+//! - `JournalRecord`: ジャーナルに記録するデータ毎の変更
+//! - `Journal`: 追記専用のジャーナルファイル
+//! - `JournaledStorage`: 操作を適用する前にジャーナルへ記録するストレージ
+//!
+//! ジャーナルは次のフレームを連ねたもの。
+//!
+//! ```text
+//! [長さ: u32 LE][CRC32: u32 LE][本体: MessagePack (連番, 記録)]
+//! ```
+//!
+//! 1つのフレームには1つのトランザクションの変更を、確定時のデータの内容
+//! (`JournalRecord`の列)として記録する。途中で失敗したトランザクションは
+//! 記録されないので、再適用はフレーム単位で不可分になる。
+//!
+//! 起動時は最新のスナップショットを読み込み、その後の操作をジャーナルから再適用する。
+//! 末尾のフレームが途中で途切れている、または末尾のフレームのCRC32が合わない場合は、
+//! 書き込み途中で異常終了したものとして切り詰める。
+//! それ以外の破損(途中のフレームのCRC32の不一致・変換できない本体)はエラーにし、
+//! ファイルには手を付けない。
+//! チェックポイントではスナップショットを保存してからジャーナルを空にする。
+//! スナップショットには反映済みの連番を記録するので、
+//! ジャーナルを空にする前に異常終了しても操作が二重に適用されることはない。
+
+use std::{
+  fs::{File, OpenOptions},
+  io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+  marker::PhantomData,
+  path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use super::{
+  EventError, InsertError, SlotHandle, SnapshotError,
+  StandardStorage, StorageError, StorageTx,
+};
+use crate::traits::prelude::*;
+
+/// フレームの本体の長さの上限(これを超える長さは破損とみなす)
+const MAX_FRAME_LEN: u32 = 1 << 30;
+
+/// ジャーナルに記録するデータ毎の変更
+///
+/// ## Summary
+/// トランザクションの確定時の内容を記録するので、
+/// 同じデータへの途中の変更は残らない。
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound(
+  serialize = "D: Serialize",
+  deserialize = "D: DeserializeOwned"
+))]
+pub enum JournalRecord<D: SousARCData> {
+  /// データを確定時の内容で置いた(挿入・変更・キーの変更)
+  Put(D),
+
+  /// データを削除した
+  Remove(D::Id),
+}
+
+/// 記録用に借用した`JournalRecord`(同じ形式に変換される)
+#[derive(Serialize)]
+#[serde(rename = "JournalRecord", bound = "D: Serialize")]
+pub(crate) enum JournalRecordRef<'a, D: SousARCData> {
+  Put(&'a D),
+  Remove(D::Id),
+}
+
+impl<D: SousARCData> JournalRecord<D> {
+  /// 変更されたデータのID
+  pub fn id(&self) -> D::Id {
+    match self {
+      Self::Put(data) => data.id(),
+      Self::Remove(id) => *id,
+    }
+  }
+
+  /// 1つのトランザクションの変更をストレージへ適用する
+  ///
+  /// ## Summary
+  /// 変更された全てのデータを取り除いてから置き直すので、
+  /// キーの入れ替え等の途中状態に依存せずに確定時の状態になる。
+  ///
+  /// ## Error
+  /// `StandardStorage::try_insert`と同じ
+  pub fn apply_all(
+    records: Vec<Self>,
+    storage: &mut StandardStorage<D>,
+  ) -> Result<(), StorageError<D>> {
+    for record in &records {
+      storage.remove(record.id());
+    }
+    for record in records {
+      if let Self::Put(data) = record {
+        storage.try_insert(data).map_err(|e| e.error)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+impl<'a, D: SousARCData> JournalRecordRef<'a, D> {
+  /// データの現在の内容を記録する
+  ///
+  /// ## Summary
+  /// ストレージに存在しない場合は削除として記録する。
+  pub(crate) fn current(
+    storage: &'a StandardStorage<D>,
+    id: D::Id,
+  ) -> Self {
+    match storage.get(id) {
+      Some(data) => Self::Put(data),
+      None => Self::Remove(id),
+    }
+  }
+}
+
+/// 追記専用のジャーナルファイル
+///
+/// ## Summary
+/// 型引数`R`は1つのフレームに記録する値の型。
+///
+/// ## Member
+/// - `file`: `File`型
+///   - ジャーナルファイル
+/// - `next_seq`: `u64`型
+///   - 次に記録するフレームの連番
+/// - `records`: `u64`型
+///   - ファイル内のフレームの数
+pub struct Journal<R> {
+  file: File,
+  next_seq: u64,
+  records: u64,
+  _marker: PhantomData<fn(R)>,
+}
+
+impl<R> std::fmt::Debug for Journal<R> {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    f.debug_struct("Journal")
+      .field("next_seq", &self.next_seq)
+      .field("records", &self.records)
+      .finish_non_exhaustive()
+  }
+}
+
+impl<R: DeserializeOwned> Journal<R> {
+  /// ジャーナルを開き、`after`より後の連番のフレームを`f`へ渡す
+  ///
+  /// ## Summary
+  /// 書き込み途中で途切れた末尾のフレームは切り詰めてから開く。
+  /// ファイルが存在しない場合は作成する。
+  ///
+  /// ## Argument
+  /// - `f`: `FnMut(u64, R)`
+  ///   - フレームの連番と記録を受け取る
+  ///
+  /// ## Return
+  /// 追記できる状態のジャーナル
+  ///
+  /// ## Error
+  /// - `SnapshotError::Io`: ファイルの読み書きに失敗した
+  /// - `SnapshotError::Corrupted`: 末尾以外のフレームが壊れている、
+  ///   またはフレームの本体を変換できない
+  pub fn open(
+    path: impl AsRef<Path>,
+    after: u64,
+    mut f: impl FnMut(u64, R),
+  ) -> Result<Self, SnapshotError> {
+    let mut file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .open(path)?;
+    let file_len = file.metadata()?.len();
+
+    let mut reader = BufReader::new(&mut file);
+    let mut valid_len = 0;
+    let mut last_seq = after;
+    let mut records = 0;
+    loop {
+      match read_frame::<R>(&mut reader, valid_len)? {
+        Frame::Record { len, seq, record } => {
+          valid_len += len;
+          records += 1;
+          if seq > after {
+            last_seq = last_seq.max(seq);
+            f(seq, record);
+          }
+        }
+        Frame::End => break,
+        Frame::Torn => break,
+        Frame::Mismatch { len }
+          if valid_len + len == file_len =>
+        {
+          break;
+        }
+        Frame::Mismatch { .. } => {
+          return Err(SnapshotError::Corrupted(format!(
+            "journal checksum mismatch at byte {valid_len}"
+          )));
+        }
+      }
+    }
+    drop(reader);
+
+    if file_len != valid_len {
+      tracing::warn!(
+        "discarding a torn journal tail at byte {valid_len}"
+      );
+      file.set_len(valid_len)?;
+      file.sync_all()?;
+    }
+    file.seek(SeekFrom::Start(valid_len))?;
+    Ok(Self {
+      file,
+      next_seq: last_seq + 1,
+      records,
+      _marker: PhantomData,
+    })
+  }
+}
+
+impl<R> Journal<R> {
+  /// 記録を1つのフレームとして追記し、ディスクへ同期する
+  ///
+  /// ## Summary
+  /// `record`は`R`と同じ形式に変換される値(`R`を借用した型等)を渡す。
+  /// 書き込みに失敗した場合は、書きかけのフレームを切り詰める。
+  ///
+  /// ## Error
+  /// - `std::io::Error`: 変換・書き込み・同期に失敗した
+  pub(crate) fn append(
+    &mut self,
+    record: &impl Serialize,
+  ) -> std::io::Result<()> {
+    let payload =
+      rmp_serde::to_vec(&(self.next_seq, record))
+        .map_err(std::io::Error::other)?;
+    let len = u32::try_from(payload.len())
+      .ok()
+      .filter(|len| *len <= MAX_FRAME_LEN)
+      .ok_or_else(|| {
+        std::io::Error::other("journal record is too large")
+      })?;
+    let mut frame = Vec::with_capacity(payload.len() + 8);
+    frame.extend_from_slice(&len.to_le_bytes());
+    frame.extend_from_slice(
+      &crc32fast::hash(&payload).to_le_bytes(),
+    );
+    frame.extend_from_slice(&payload);
+
+    let start = self.file.stream_position()?;
+    let written = self
+      .file
+      .write_all(&frame)
+      .and_then(|_| self.file.sync_data());
+    if let Err(e) = written {
+      // 書きかけのフレームは再適用時に破棄されるが、
+      // 後続のフレームを読めるよう切り詰めておく
+      let _ = self.file.set_len(start);
+      let _ = self.file.seek(SeekFrom::Start(start));
+      return Err(e);
+    }
+    self.next_seq += 1;
+    self.records += 1;
+    Ok(())
+  }
+
+  /// 最後に記録したフレームの連番
+  pub fn last_seq(&self) -> u64 {
+    self.next_seq - 1
+  }
+
+  /// ファイル内のフレームの数
+  pub fn records(&self) -> u64 {
+    self.records
+  }
+
+  /// ジャーナルを空にする
+  ///
+  /// ## Summary
+  /// 連番は引き続き増加させる。
+  pub fn truncate(&mut self) -> std::io::Result<()> {
+    self.file.set_len(0)?;
+    self.file.seek(SeekFrom::Start(0))?;
+    self.file.sync_all()?;
+    self.records = 0;
+    Ok(())
+  }
+}
+
+/// 読み込んだフレーム
+enum Frame<R> {
+  /// 正しいフレーム(`len`はヘッダを含むフレームの長さ)
+  Record { len: u64, seq: u64, record: R },
+
+  /// ファイルの終端
+  End,
+
+  /// ファイルの終端で途切れている
+  Torn,
+
+  /// CRC32が合わない(`len`はヘッダを含むフレームの長さ)
+  Mismatch { len: u64 },
+}
+
+/// 読み込みを試み、ファイルの終端に達した場合は`false`を返す
+fn read_full(
+  reader: &mut impl Read,
+  buf: &mut [u8],
+) -> std::io::Result<bool> {
+  match reader.read_exact(buf) {
+    Ok(()) => Ok(true),
+    Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+      Ok(false)
+    }
+    Err(e) => Err(e),
+  }
+}
+
+/// `offset`バイト目からフレームを1つ読み込む
+///
+/// ## Error
+/// - `SnapshotError::Io`: 読み込みに失敗した
+/// - `SnapshotError::Corrupted`: 長さが上限を超えるヘッダの後にデータが続く、
+///   またはCRC32が合うのに本体を変換できない
+///
+/// 長さが上限を超えるヘッダがファイルの終端にある場合は、
+/// 書き込みの途中で途切れたものとして`Frame::Torn`を返す。
+fn read_frame<R: DeserializeOwned>(
+  reader: &mut impl Read,
+  offset: u64,
+) -> Result<Frame<R>, SnapshotError> {
+  let mut header = [0; 8];
+  let mut first = [0; 1];
+  if !read_full(reader, &mut first)? {
+    return Ok(Frame::End);
+  }
+  header[0] = first[0];
+  if !read_full(reader, &mut header[1..])? {
+    return Ok(Frame::Torn);
+  }
+  let [l0, l1, l2, l3, c0, c1, c2, c3] = header;
+  let len = u32::from_le_bytes([l0, l1, l2, l3]);
+  let crc = u32::from_le_bytes([c0, c1, c2, c3]);
+  if len > MAX_FRAME_LEN {
+    if !read_full(reader, &mut first)? {
+      return Ok(Frame::Torn);
+    }
+    return Err(SnapshotError::Corrupted(format!(
+      "journal frame at byte {offset} is too long: {len}"
+    )));
+  }
+
+  let mut payload = vec![0; len as usize];
+  if !read_full(reader, &mut payload)? {
+    return Ok(Frame::Torn);
+  }
+  let frame_len = u64::from(len) + 8;
+  if crc32fast::hash(&payload) != crc {
+    return Ok(Frame::Mismatch { len: frame_len });
+  }
+  let (seq, record) = rmp_serde::from_slice(&payload)
+    .map_err(|e| {
+      SnapshotError::Corrupted(format!(
+        "undecodable journal frame at byte {offset}: {e}"
+      ))
+    })?;
+  Ok(Frame::Record { len: frame_len, seq, record })
+}
+
+/// 1つのストレージのジャーナル(1つのフレームが1つのトランザクション)
+pub type StorageJournal<D> = Journal<Vec<JournalRecord<D>>>;
+
+/// 操作をジャーナルへ記録してから適用するストレージ
+///
+/// ## Summary
+/// 読み取りは`storage`で`StandardStorage`として行い、
+/// 変更は必ずこの型のメソッドか`transaction`を通して行う。
+///
+/// 変更は`StorageTx`で行うので、`SousARCData::TRANSACTIONAL`なデータ型
+/// (要素)には使えない。ドメインのデータは`JournaledStore`で扱う。
+///
+/// ## Member
+/// - `storage`: `StandardStorage<D>`型
+///   - 操作を適用するストレージ
+/// - `journal`: `StorageJournal<D>`型
+///   - 操作の記録
+/// - `snapshot_path`: `PathBuf`型
+///   - チェックポイントで保存するスナップショットのパス
+/// - `checkpoint_interval`: `Option<u64>`型
+///   - 自動でチェックポイントを行う記録数
+#[derive(Debug)]
+pub struct JournaledStorage<D>
+where
+  D: SousARCDataHasBody + Serialize + DeserializeOwned,
+  D::Body: Clone,
+{
+  storage: StandardStorage<D>,
+  journal: StorageJournal<D>,
+  snapshot_path: PathBuf,
+  checkpoint_interval: Option<u64>,
+}
+
+impl<D> JournaledStorage<D>
+where
+  D: SousARCDataHasBody + Serialize + DeserializeOwned,
+  D::Body: Clone,
+{
+  /// スナップショットとジャーナルからストレージを復元する
+  ///
+  /// ## Summary
+  /// スナップショットが存在しない場合は空のストレージから始める。
+  ///
+  /// ## Error
+  /// - `SnapshotError`: スナップショットまたはジャーナルを読めない
+  /// - `SnapshotError::Corrupted`: 記録を再適用できない
+  pub fn open(
+    snapshot_path: impl Into<PathBuf>,
+    journal_path: impl AsRef<Path>,
+  ) -> Result<Self, SnapshotError> {
+    let snapshot_path = snapshot_path.into();
+    let (mut storage, seq) = load_or_new(&snapshot_path)?;
+    let mut failed = None;
+    let journal =
+      Journal::open(journal_path, seq, |seq, records| {
+        if failed.is_none()
+          && let Err(e) =
+            JournalRecord::apply_all(records, &mut storage)
+        {
+          failed = Some(format!(
+            "cannot replay journal frame {seq}: {e}"
+          ));
+        }
+      })?;
+    if let Some(reason) = failed {
+      return Err(SnapshotError::Corrupted(reason));
+    }
+    Ok(Self {
+      storage,
+      journal,
+      snapshot_path,
+      checkpoint_interval: None,
+    })
+  }
+
+  /// 記録数が`interval`に達する毎に自動でチェックポイントを行う
+  pub fn with_checkpoint_interval(
+    mut self,
+    interval: u64,
+  ) -> Self {
+    self.checkpoint_interval = Some(interval);
+    self
+  }
+
+  /// 操作を適用するストレージを参照する
+  pub fn storage(&self) -> &StandardStorage<D> {
+    &self.storage
+  }
+
+  /// ジャーナルを参照する
+  pub fn journal(&self) -> &StorageJournal<D> {
+    &self.journal
+  }
+
+  /// 変更イベントの配信を有効にする
+  ///
+  /// ## Error
+  /// `StandardStorage::enable_events`と同じ
+  pub fn enable_events(
+    &mut self,
+    capacity: usize,
+  ) -> Result<(), EventError> {
+    self.storage.enable_events(capacity)
+  }
+
+  /// スナップショットを保存し、ジャーナルを空にする
+  ///
+  /// ## Error
+  /// - `SnapshotError`: スナップショットの保存、またはジャーナルの切り詰めに失敗した
+  pub fn checkpoint(
+    &mut self,
+  ) -> Result<(), SnapshotError> {
+    self.storage.save_at(
+      &self.snapshot_path,
+      self.journal.last_seq(),
+    )?;
+    self.journal.truncate()?;
+    Ok(())
+  }
+
+  /// 記録数が上限に達していればチェックポイントを行う
+  fn maybe_checkpoint(&mut self) {
+    let due = self
+      .checkpoint_interval
+      .is_some_and(|n| self.journal.records() >= n);
+    if due && let Err(e) = self.checkpoint() {
+      tracing::error!("checkpoint failed: {e}");
+    }
+  }
+
+  /// クロージャをトランザクション内で実行する
+  ///
+  /// ## Summary
+  /// `f`が`Ok`を返した場合は、変更したデータの確定時の内容を
+  /// 1つのフレームとして記録してから確定する。
+  /// `f`が`Err`を返した場合、または記録に失敗した場合は全て取り消す。
+  ///
+  /// ## Error
+  /// - `StorageError::Journal`: ジャーナルへの記録に失敗した
+  /// - その他は`f`が返したエラー
+  pub fn transaction<T, E>(
+    &mut self,
+    f: impl FnOnce(&mut StorageTx<'_, D>) -> Result<T, E>,
+  ) -> Result<T, E>
+  where
+    E: From<StorageError<D>>,
+  {
+    let mut tx = StorageTx::new(&mut self.storage);
+    let ret = f(&mut tx)?;
+    let records = tx.redo();
+    if !records.is_empty() {
+      self
+        .journal
+        .append(&records)
+        .map_err(StorageError::Journal)?;
+    }
+    tx.commit();
+    self.maybe_checkpoint();
+    Ok(ret)
+  }
+
+  /// データを挿入する
+  ///
+  /// ## Error
+  /// - `StorageError::Journal`: ジャーナルへの記録に失敗した
+  /// - その他は`StandardStorage::try_insert`と同じ
+  pub fn insert(
+    &mut self,
+    data: D,
+  ) -> Result<SlotHandle, InsertError<D>> {
+    if let Some(error) = self.insert_conflict(&data) {
+      return Err(InsertError::new(error, data));
+    }
+    let record = [JournalRecordRef::Put(&data)];
+    if let Err(e) = self.journal.append(&record) {
+      let error = StorageError::Journal(e);
+      return Err(InsertError::new(error, data));
+    }
+    let handle = self.storage.try_insert(data)?;
+    self.maybe_checkpoint();
+    Ok(handle)
+  }
+
+  /// 挿入が失敗する理由を検査する
+  fn insert_conflict(
+    &self,
+    data: &D,
+  ) -> Option<StorageError<D>> {
+    if self.storage.idmap.contains_key(&data.id()) {
+      return Some(StorageError::DuplicateId(data.id()));
+    }
+    if self.storage.keymap.contains_key(data.key()) {
+      return Some(StorageError::DuplicateKey(
+        data.key().clone(),
+      ));
+    }
+    self
+      .storage
+      .indexes
+      .conflict(data)
+      .map(StorageError::IndexConflict)
+  }
+
+  /// データを削除する
+  ///
+  /// ## Error
+  /// - `StorageError::NotFound`: IDに対応するデータが存在しない
+  /// - `StorageError::Journal`: ジャーナルへの記録に失敗した
+  pub fn remove(
+    &mut self,
+    id: D::Id,
+  ) -> Result<D, StorageError<D>> {
+    if !self.storage.idmap.contains_key(&id) {
+      return Err(StorageError::NotFound(id));
+    }
+    let record = [JournalRecordRef::<D>::Remove(id)];
+    self
+      .journal
+      .append(&record)
+      .map_err(StorageError::Journal)?;
+    let data = self
+      .storage
+      .remove(id)
+      .ok_or(StorageError::NotFound(id))?;
+    self.maybe_checkpoint();
+    Ok(data)
+  }
+
+  /// データ本体を変更する
+  ///
+  /// ## Return
+  /// `f`の返り値
+  ///
+  /// ## Error
+  /// - `StorageError::Journal`: ジャーナルへの記録に失敗した
+  /// - その他は`StandardStorage::modify`と同じ
+  pub fn modify<R>(
+    &mut self,
+    id: D::Id,
+    f: impl FnOnce(&mut D::Body) -> R,
+  ) -> Result<R, StorageError<D>> {
+    self.transaction(|tx| tx.modify(id, f))
+  }
+
+  /// データのキーを変更する
+  ///
+  /// ## Error
+  /// - `StorageError::Journal`: ジャーナルへの記録に失敗した
+  /// - その他は`SousARCStorageMut::rekey`と同じ
+  pub fn rekey(
+    &mut self,
+    id: D::Id,
+    new_key: D::Key,
+  ) -> Result<D::Key, StorageError<D>> {
+    self.transaction(|tx| tx.rekey(id, new_key))
+  }
+}
+
+/// スナップショットを読み込む(存在しない場合は空のストレージ)
+///
+/// ## Return
+/// ストレージと、反映済みのジャーナルの連番
+pub(crate) fn load_or_new<D>(
+  path: &Path,
+) -> Result<(StandardStorage<D>, u64), SnapshotError>
+where
+  D: SousARCData + DeserializeOwned,
+{
+  match StandardStorage::load_at(path) {
+    Ok(loaded) => Ok(loaded),
+    Err(SnapshotError::Io(e))
+      if e.kind() == ErrorKind::NotFound =>
+    {
+      Ok((StandardStorage::new(), 0))
+    }
+    Err(e) => Err(e),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    io::Write,
+    path::{Path, PathBuf},
+  };
+
+  use super::JournaledStorage;
+  use crate::{
+    domain::{
+      user::UserId,
+      work::{WorkData, WorkId, WorkKey},
+    },
+    fixture::work,
+    storage::{SnapshotError, StorageError},
+    traits::prelude::*,
+  };
+
+  /// テスト毎の空のディレクトリ
+  fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir()
+      .join(format!("sousarc-journal-{}", WorkId::new()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn open(dir: &Path) -> JournaledStorage<WorkData> {
+    JournaledStorage::open(
+      dir.join("works.snapshot"),
+      dir.join("works.journal"),
+    )
+    .unwrap()
+  }
+
+  /// フレームをそのまま追記する
+  fn append_raw(path: &Path, payload: &[u8], crc: u32) {
+    let mut file = std::fs::OpenOptions::new()
+      .append(true)
+      .open(path)
+      .unwrap();
+    let len = payload.len() as u32;
+    file.write_all(&len.to_le_bytes()).unwrap();
+    file.write_all(&crc.to_le_bytes()).unwrap();
+    file.write_all(payload).unwrap();
+  }
+
+  #[test]
+  fn replays_committed_operations() {
+    let dir = temp_dir();
+    let user = UserId::new();
+    let (a, b) = (work(user, "a"), work(user, "b"));
+    let (a_id, b_id) = (a.id(), b.id());
+    {
+      let mut storage = open(&dir);
+      storage.insert(a).unwrap();
+      storage.insert(b).unwrap();
+      storage
+        .modify(b_id, |b| b.description = "edited".into())
+        .unwrap();
+      storage
+        .rekey(b_id, WorkKey::from((user, "c")))
+        .unwrap();
+      storage.remove(a_id).unwrap();
+    }
+
+    let storage = open(&dir);
+    assert_eq!(storage.journal().last_seq(), 5);
+    assert!(storage.storage().get(a_id).is_none());
+    let b = storage.storage().get(b_id).unwrap();
+    assert_eq!(b.key().work_name(), "c");
+    assert_eq!(b.body.description, "edited");
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn transaction_is_one_frame_and_replays_key_swaps() {
+    let dir = temp_dir();
+    let user = UserId::new();
+    let (a, b) = (work(user, "a"), work(user, "b"));
+    let (a_id, b_id) = (a.id(), b.id());
+    {
+      let mut storage = open(&dir);
+      storage
+        .transaction(|tx| {
+          tx.insert(a).map_err(|e| e.error)?;
+          tx.insert(b).map_err(|e| e.error)
+        })
+        .unwrap();
+      storage
+        .transaction(|tx| {
+          tx.rekey(a_id, WorkKey::from((user, "tmp")))?;
+          tx.rekey(b_id, WorkKey::from((user, "a")))?;
+          tx.rekey(a_id, WorkKey::from((user, "b")))
+        })
+        .unwrap();
+      let failed: Result<(), StorageError<WorkData>> =
+        storage.transaction(|tx| {
+          tx.remove(a_id)?;
+          Err(StorageError::NotFound(a_id))
+        });
+      assert!(failed.is_err());
+      assert_eq!(storage.journal().records(), 2);
+    }
+
+    let storage = open(&dir);
+    assert_eq!(storage.journal().records(), 2);
+    let name = |id| {
+      storage
+        .storage()
+        .key(id)
+        .unwrap()
+        .work_name()
+        .to_string()
+    };
+    assert_eq!(name(a_id), "b");
+    assert_eq!(name(b_id), "a");
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn truncates_torn_tail() {
+    let dir = temp_dir();
+    let journal = dir.join("works.journal");
+    let a = work(UserId::new(), "a");
+    let a_id = a.id();
+    open(&dir).insert(a).unwrap();
+    let valid = std::fs::metadata(&journal).unwrap().len();
+
+    // 本体の途中で途切れたフレーム
+    let mut file = std::fs::OpenOptions::new()
+      .append(true)
+      .open(&journal)
+      .unwrap();
+    file.write_all(&100u32.to_le_bytes()).unwrap();
+    file.write_all(&[0; 10]).unwrap();
+    drop(file);
+    let storage = open(&dir);
+    assert!(storage.storage().get(a_id).is_some());
+    assert_eq!(
+      std::fs::metadata(&journal).unwrap().len(),
+      valid
+    );
+    drop(storage);
+
+    // CRC32が合わない末尾のフレーム
+    append_raw(&journal, b"torn", 0);
+    let storage = open(&dir);
+    assert_eq!(storage.journal().records(), 1);
+    assert_eq!(
+      std::fs::metadata(&journal).unwrap().len(),
+      valid
+    );
+    drop(storage);
+
+    // 長さが上限を超える、ファイルの終端にあるヘッダ
+    let mut file = std::fs::OpenOptions::new()
+      .append(true)
+      .open(&journal)
+      .unwrap();
+    file.write_all(&u32::MAX.to_le_bytes()).unwrap();
+    file.write_all(&0u32.to_le_bytes()).unwrap();
+    drop(file);
+    let storage = open(&dir);
+    assert_eq!(storage.journal().records(), 1);
+    assert_eq!(
+      std::fs::metadata(&journal).unwrap().len(),
+      valid
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn rejects_oversized_frame_before_tail() {
+    let dir = temp_dir();
+    let journal = dir.join("works.journal");
+    open(&dir).insert(work(UserId::new(), "a")).unwrap();
+    append_raw(&journal, &[0], 0);
+    let mut bytes = std::fs::read(&journal).unwrap();
+    let header = bytes.len() - 9;
+    bytes[header..header + 4]
+      .copy_from_slice(&u32::MAX.to_le_bytes());
+    std::fs::write(&journal, &bytes).unwrap();
+
+    let reopened = JournaledStorage::<WorkData>::open(
+      dir.join("works.snapshot"),
+      &journal,
+    );
+    assert!(matches!(
+      reopened,
+      Err(SnapshotError::Corrupted(_))
+    ));
+    assert_eq!(std::fs::read(&journal).unwrap(), bytes);
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn rejects_corruption_before_tail() {
+    let dir = temp_dir();
+    let journal = dir.join("works.journal");
+    {
+      let mut storage = open(&dir);
+      storage.insert(work(UserId::new(), "a")).unwrap();
+      storage.insert(work(UserId::new(), "b")).unwrap();
+    }
+    // 正しいフレームが後に続く、最初のフレームの本体を書き換える
+    let mut bytes = std::fs::read(&journal).unwrap();
+    bytes[8] ^= 0xff;
+    std::fs::write(&journal, &bytes).unwrap();
+
+    let reopened = JournaledStorage::<WorkData>::open(
+      dir.join("works.snapshot"),
+      &journal,
+    );
+    assert!(matches!(
+      reopened,
+      Err(SnapshotError::Corrupted(_))
+    ));
+    assert_eq!(std::fs::read(&journal).unwrap(), bytes);
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn rejects_undecodable_frame() {
+    let dir = temp_dir();
+    let journal = dir.join("works.journal");
+    open(&dir).insert(work(UserId::new(), "a")).unwrap();
+    let payload = b"not a record";
+    append_raw(&journal, payload, crc32fast::hash(payload));
+    let len = std::fs::metadata(&journal).unwrap().len();
+
+    let reopened = JournaledStorage::<WorkData>::open(
+      dir.join("works.snapshot"),
+      &journal,
+    );
+    assert!(matches!(
+      reopened,
+      Err(SnapshotError::Corrupted(_))
+    ));
+    assert_eq!(
+      std::fs::metadata(&journal).unwrap().len(),
+      len
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
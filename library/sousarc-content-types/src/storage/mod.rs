@@ -1,15 +1,51 @@
-use std::collections::{HashMap, VecDeque};
+use std::{
+  collections::{BTreeMap, HashMap, VecDeque},
+  ops::{DerefMut, RangeBounds},
+};
 
 use indexmap::IndexMap;
 
 use crate::traits::prelude::*;
 
+pub mod error;
+pub use error::*;
+pub mod event;
+pub use event::*;
+pub mod handle;
+pub use handle::*;
+pub mod index;
+pub use index::*;
+pub mod journal;
+pub use journal::*;
+pub mod shared;
+pub use shared::*;
+pub mod snapshot;
+pub use snapshot::*;
+pub mod transaction;
+pub use transaction::*;
+
 #[derive(Debug)]
 pub struct StandardStorage<D: SousARCData> {
   pub data: Vec<Option<D>>,
+  /// スロット毎の世代(`data`と同じ長さ)
+  pub generations: Vec<u64>,
+  /// 次に採番する世代
+  pub next_generation: u64,
   pub empty_slot: VecDeque<usize>,
+  /// キー→スロット番号(順序は保持しない)
   pub keymap: IndexMap<D::Key, usize>,
+  /// キーの昇順に並んだインデックス(範囲検索用)
+  pub keyorder: BTreeMap<D::Key, usize>,
   pub idmap: HashMap<D::Id, usize>,
+  /// データ本体に対するセカンダリインデックス
+  pub indexes: Indexes<D>,
+  /// 変更イベントの送信元
+  pub events: EventSink<D>,
+}
+impl<D: SousARCData> Default for StandardStorage<D> {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 impl<D: SousARCData> StandardStorage<D> {
   pub fn get_data(&self) -> &Vec<Option<D>> {
@@ -19,53 +55,529 @@ impl<D: SousARCData> StandardStorage<D> {
   pub fn new() -> Self {
     Self {
       data: Vec::new(),
+      generations: Vec::new(),
+      next_generation: 0,
       empty_slot: VecDeque::new(),
       keymap: IndexMap::new(),
+      keyorder: BTreeMap::new(),
       idmap: HashMap::new(),
+      indexes: Indexes::new(),
+      events: EventSink::new(),
     }
   }
 
   pub fn remove(&mut self, id: D::Id) -> Option<D> {
-    if let Some(idx) = self.idmap.remove(&id) {
-      if let Some(data) = self.data[idx].take() {
-        self.keymap.shift_remove(data.key());
-        self.empty_slot.push_back(idx);
-        return Some(data);
+    let idx = *self.idmap.get(&id)?;
+    let data = self.data.get_mut(idx)?.take()?;
+    self.idmap.remove(&id);
+    // 順序は`keyorder`が保持するので、`keymap`はO(1)で削除する
+    self.keymap.swap_remove(data.key());
+    self.keyorder.remove(data.key());
+    self.indexes.remove(id);
+    self.empty_slot.push_back(idx);
+    self.events.record(StorageEvent::new(
+      StorageEventKind::Removed,
+      id,
+      data.key(),
+    ));
+    Some(data)
+  }
+
+  /// 空きスロットを詰めて再配置する
+  ///
+  /// ## Summary
+  /// 削除で生じた`data`上の空きスロットを取り除き、
+  /// `keymap`・`keyorder`・`idmap`のスロット番号を書き換える。
+  /// 再配置されたデータの古い`SlotHandle`は無効になるので、
+  /// `handle`で取得し直すこと。
+  ///
+  /// ## Return
+  /// 取り除いた空きスロットの数
+  pub fn compact(&mut self) -> usize {
+    let holes = self.data.len() - self.len();
+    if holes == 0 {
+      return 0;
+    }
+    let data = std::mem::take(&mut self.data);
+    let generations = std::mem::take(&mut self.generations);
+    self.data.reserve_exact(data.len() - holes);
+    self.generations.reserve_exact(data.len() - holes);
+    for (slot, generation) in
+      data.into_iter().zip(generations)
+    {
+      let Some(data) = slot else { continue };
+      let idx = self.data.len();
+      if let Some(i) = self.keymap.get_mut(data.key()) {
+        *i = idx;
+      }
+      if let Some(i) = self.keyorder.get_mut(data.key()) {
+        *i = idx;
+      }
+      if let Some(i) = self.idmap.get_mut(&data.id()) {
+        *i = idx;
       }
+      self.data.push(Some(data));
+      self.generations.push(generation);
+    }
+    self.empty_slot.clear();
+    self.empty_slot.shrink_to_fit();
+    holes
+  }
+
+  /// 変更イベントの配信を有効にする
+  ///
+  /// ## Argument
+  /// - `capacity`: `usize`
+  ///   - 購読者毎に溜めておけるイベントの数
+  ///
+  /// ## Error
+  /// - `EventError::ZeroCapacity`: `capacity`が0
+  pub fn enable_events(
+    &mut self,
+    capacity: usize,
+  ) -> Result<(), EventError> {
+    self.events.enable(capacity)
+  }
+
+  /// 変更イベントを購読する
+  ///
+  /// ## Return
+  /// 配信が有効でない場合は`None`
+  pub fn subscribe(&self) -> Option<EventSubscriber<D>> {
+    self.events.subscribe()
+  }
+
+  /// 最後の変更のリビジョンを取得する
+  pub fn revision(&self) -> u64 {
+    self.events.revision()
+  }
+
+  /// IDに対応するデータのハンドルを取得する
+  pub fn handle(&self, id: D::Id) -> Option<SlotHandle> {
+    let idx = *self.idmap.get(&id)?;
+    Some(SlotHandle::new(idx, *self.generations.get(idx)?))
+  }
+
+  /// ハンドルが現在も有効なデータを指しているか判定する
+  pub fn is_valid(&self, handle: SlotHandle) -> bool {
+    self.slot_index(handle).is_some()
+  }
+
+  /// ハンドルを検証し、スロット番号を返す
+  fn slot_index(
+    &self,
+    handle: SlotHandle,
+  ) -> Option<usize> {
+    let idx = handle.index();
+    let alive =
+      self.data.get(idx).is_some_and(Option::is_some);
+    (alive
+      && self.generations.get(idx)
+        == Some(&handle.generation()))
+    .then_some(idx)
+  }
+
+  /// ハンドルを指定してデータを取得する
+  ///
+  /// ## Return
+  /// - `Some(&D)`: ハンドルが指すデータ
+  /// - `None`: データが削除済み、またはスロットが再利用されている
+  pub fn get_by_handle(
+    &self,
+    handle: SlotHandle,
+  ) -> Option<&D> {
+    self.data[self.slot_index(handle)?].as_ref()
+  }
+
+  /// ハンドルを指定してデータを可変参照で取得する
+  ///
+  /// ## Summary
+  /// 返り値は`get_mut`と同じくガードで、
+  /// ドロップ時にインデックスを更新する。
+  ///
+  /// ## Return
+  /// `get_mut`と同じく、一意インデックスが登録されている場合は`None`
+  pub fn get_by_handle_mut(
+    &mut self,
+    handle: SlotHandle,
+  ) -> Option<StorageRefMut<'_, D>> {
+    let idx = self.slot_index(handle)?;
+    self.guard(idx)
+  }
+
+  /// スロットのデータの可変参照のガードを作成する
+  ///
+  /// ## Summary
+  /// ガードのドロップ時には一意制約に違反する変更を取り消せないので、
+  /// 一意インデックスが登録されている場合は作成しない。
+  /// `SousARCData::TRANSACTIONAL`なデータ型の場合も作成しない。
+  fn guard(
+    &mut self,
+    idx: usize,
+  ) -> Option<StorageRefMut<'_, D>> {
+    if D::TRANSACTIONAL || self.indexes.has_unique() {
+      return None;
     }
-    None
+    let data = self.data.get_mut(idx)?.as_mut()?;
+    Some(StorageRefMut::new(
+      data,
+      &mut self.indexes,
+      &mut self.events,
+    ))
   }
 
-  pub fn insert(&mut self, data: D) -> Option<D> {
-    let idx = self
-      .empty_slot
-      .pop_front()
-      .ok_or_else(|| self.data.len());
-    if let Some(_) = self
+  /// ハンドルを指定してデータを削除する
+  pub fn remove_by_handle(
+    &mut self,
+    handle: SlotHandle,
+  ) -> Option<D> {
+    let id = self.get_by_handle(handle)?.id();
+    self.remove(id)
+  }
+
+  /// キーを指定してデータを削除する
+  ///
+  /// ## Return
+  /// - `Some(D)`: 削除したデータ
+  /// - `None`: キーに対応するデータが存在しない
+  pub fn remove_by_key<Q: Eq + std::hash::Hash>(
+    &mut self,
+    key: &Q,
+  ) -> Option<D>
+  where
+    D::Key: std::borrow::Borrow<Q>,
+  {
+    let id = self.id(key)?;
+    self.remove(id)
+  }
+
+  /// データを挿入する
+  ///
+  /// ## Return
+  /// - `Ok(SlotHandle)`: 挿入したデータのハンドル
+  /// - `Err(D)`: 挿入されなかったデータ
+  pub fn insert(
+    &mut self,
+    data: D,
+  ) -> Result<SlotHandle, D> {
+    self.try_insert(data).map_err(InsertError::into_data)
+  }
+
+  /// データを挿入する
+  ///
+  /// ## Summary
+  /// IDとキーの重複を検査してから挿入する。
+  /// 失敗した場合、ストレージの内容は変更されない。
+  ///
+  /// ## Return
+  /// 挿入したデータのハンドル
+  ///
+  /// ## Error
+  /// - `StorageError::DuplicateId`: IDが既に使われている
+  /// - `StorageError::DuplicateKey`: キーが既に使われている
+  pub fn try_insert(
+    &mut self,
+    data: D,
+  ) -> Result<SlotHandle, InsertError<D>> {
+    if self.idmap.contains_key(&data.id()) {
+      let error = StorageError::DuplicateId(data.id());
+      return Err(InsertError::new(error, data));
+    }
+    if self.keymap.contains_key(data.key()) {
+      let error =
+        StorageError::DuplicateKey(data.key().clone());
+      return Err(InsertError::new(error, data));
+    }
+
+    if let Some(name) = self.indexes.conflict(&data) {
+      let error = StorageError::IndexConflict(name);
+      return Err(InsertError::new(error, data));
+    }
+
+    let (id, key) = (data.id(), data.key().clone());
+    let generation = self.next_generation;
+    self.next_generation += 1;
+    let idx = match self.empty_slot.pop_front() {
+      Some(idx) => {
+        self.data[idx] = Some(data);
+        self.generations[idx] = generation;
+        idx
+      }
+      None => {
+        self.data.push(Some(data));
+        self.generations.push(generation);
+        self.data.len() - 1
+      }
+    };
+    self.keymap.insert(key.clone(), idx);
+    self.keyorder.insert(key, idx);
+    self.idmap.insert(id, idx);
+    if let Some(data) = self.data[idx].as_ref() {
+      self.indexes.insert(data);
+      self.events.record(StorageEvent::new(
+        StorageEventKind::Inserted,
+        id,
+        data.key(),
+      ));
+    }
+    Ok(SlotHandle::new(idx, generation))
+  }
+
+  /// 同じIDを持つデータを置き換える
+  ///
+  /// ## Summary
+  /// スロットの位置を保ったまま、データを入れ替える。
+  /// キーが変わる場合は`keymap`も更新する。
+  ///
+  /// ## Return
+  /// 置き換えられた元のデータ
+  ///
+  /// ## Error
+  /// - `StorageError::NotFound`: IDに対応するデータが存在しない
+  /// - `StorageError::DuplicateKey`: 新しいキーが別のデータに使われている
+  pub fn replace(
+    &mut self,
+    data: D,
+  ) -> Result<D, InsertError<D>> {
+    let idx = self.idmap.get(&data.id()).copied();
+    let Some(slot) = idx
+      .and_then(|i| self.data.get_mut(i))
+      .and_then(Option::as_mut)
+    else {
+      let error = StorageError::NotFound(data.id());
+      return Err(InsertError::new(error, data));
+    };
+    match self.keymap.get(data.key()) {
+      Some(&other) if Some(other) != idx => {
+        let error =
+          StorageError::DuplicateKey(data.key().clone());
+        return Err(InsertError::new(error, data));
+      }
+      _ => {}
+    }
+    if let Some(name) = self.indexes.conflict(&data) {
+      let error = StorageError::IndexConflict(name);
+      return Err(InsertError::new(error, data));
+    }
+
+    let new_key = data.key().clone();
+    let old = std::mem::replace(slot, data);
+    self.indexes.update(slot);
+    self.events.record(
+      StorageEvent::new(
+        StorageEventKind::Updated,
+        slot.id(),
+        &new_key,
+      )
+      .moved_from(old.key()),
+    );
+    if old.key() != &new_key {
+      self.reindex_key(old.key(), new_key);
+    }
+    Ok(old)
+  }
+
+  /// `keymap`・`keyorder`上のキーを差し替える
+  ///
+  /// ## Return
+  /// - `true`: 差し替えた
+  /// - `false`: 元のキーが存在しない、または新しいキーが使用済み
+  fn reindex_key(
+    &mut self,
+    old: &D::Key,
+    new_key: D::Key,
+  ) -> bool {
+    let Some(pos) = self.keymap.get_index_of(old) else {
+      return false;
+    };
+    // 順序を保ったままキーだけを差し替える
+    if self
       .keymap
-      .insert(data.key().clone(), idx.unwrap_or_else(|e| e))
+      .replace_index(pos, new_key.clone())
+      .is_err()
     {
-      Some(data)
+      return false;
+    }
+    if let Some(idx) = self.keyorder.remove(old) {
+      self.keyorder.insert(new_key, idx);
+    }
+    true
+  }
+
+  /// データを挿入、または同じIDを持つデータを置き換える
+  ///
+  /// ## Return
+  /// - `None`: 新規に挿入した
+  /// - `Some(D)`: 置き換えられた元のデータ
+  ///
+  /// ## Error
+  /// - `StorageError::DuplicateKey`: キーが別のデータに使われている
+  pub fn upsert(
+    &mut self,
+    data: D,
+  ) -> Result<Option<D>, InsertError<D>> {
+    if self.idmap.contains_key(&data.id()) {
+      self.replace(data).map(Some)
     } else {
-      match self
-        .idmap
-        .insert(data.id(), idx.unwrap_or_else(|e| e))
-      {
-        None => {}
-        Some(_) => unreachable!("IDが重複しています"),
-      }
-      match idx {
-        Ok(idx) => {
-          self.data[idx] = Some(data);
-          None
-        }
-        Err(_) => {
-          self.data.push(Some(data));
-          None
-        }
-      }
+      self.try_insert(data).map(|_| None)
     }
   }
+
+  /// セカンダリインデックスを登録する
+  ///
+  /// ## Summary
+  /// 抽出関数`extractor`でデータから値を取り出し、値→データの索引を作る。
+  /// 索引は挿入・削除・`get_mut`等による変更と自動で同期される。
+  /// 一意インデックスを登録すると、`get_mut`等は可変参照を渡さなくなる。
+  ///
+  /// ## Argument
+  /// - `name`: `impl ToString`
+  ///   - インデックスの名称(エラー報告用)
+  /// - `kind`: `IndexKind`
+  ///   - 一意制約の有無
+  /// - `extractor`: `Fn(&D) -> impl IntoIterator<Item = V>`
+  ///   - 索引する値を取り出す関数(複数の値を返すとタグ等を索引できる)
+  ///
+  /// ## Error
+  /// - `StorageError::IndexConflict`: 既存のデータが一意制約に違反する
+  pub fn add_index<V, F, I>(
+    &mut self,
+    name: impl ToString,
+    kind: IndexKind,
+    extractor: F,
+  ) -> Result<IndexHandle<V>, StorageError<D>>
+  where
+    V: IndexValue,
+    F: Fn(&D) -> I + Send + Sync + 'static,
+    I: IntoIterator<Item = V>,
+  {
+    let extractor = Box::new(move |d: &D| {
+      extractor(d).into_iter().collect()
+    });
+    let existing = self.data.iter().flatten();
+    self
+      .indexes
+      .add(name.to_string(), kind, extractor, existing)
+      .map_err(StorageError::IndexConflict)
+  }
+
+  /// セカンダリインデックスの登録を解除する
+  pub fn drop_index<V: IndexValue>(
+    &mut self,
+    handle: IndexHandle<V>,
+  ) -> bool {
+    self.indexes.drop_index(handle)
+  }
+
+  /// 名称と値の型から、登録済みのインデックスのハンドルを引く
+  ///
+  /// ## Summary
+  /// ハンドルを持たない処理が、登録されていれば使う為に引く。
+  pub fn index_handle<V: IndexValue>(
+    &self,
+    name: &str,
+  ) -> Option<IndexHandle<V>> {
+    self.indexes.find(name)
+  }
+
+  /// セカンダリインデックスを取得する
+  pub fn index<V: IndexValue>(
+    &self,
+    handle: IndexHandle<V>,
+  ) -> Option<&SecondaryIndex<D, V>> {
+    self.indexes.get(handle)
+  }
+
+  /// セカンダリインデックスで値に一致するデータを列挙する
+  pub fn find<V: IndexValue>(
+    &self,
+    handle: IndexHandle<V>,
+    value: &V,
+  ) -> impl Iterator<Item = &D> {
+    self
+      .index(handle)
+      .into_iter()
+      .flat_map(|i| i.ids(value))
+      .filter_map(|id| self.get(id))
+  }
+
+  /// 一意インデックスで値に一致するデータを取得する
+  pub fn find_unique<V: IndexValue>(
+    &self,
+    handle: IndexHandle<V>,
+    value: &V,
+  ) -> Option<&D> {
+    self.find(handle, value).next()
+  }
+}
+impl<D> StandardStorage<D>
+where
+  D: SousARCDataHasBody,
+  D::Body: Clone,
+{
+  /// データ本体を変更する
+  ///
+  /// ## Summary
+  /// 変更前の本体を複製してから`f`を呼び出し、
+  /// 変更後の本体が一意インデックスの制約に違反する場合は元に戻す。
+  /// 一意インデックスを持つストレージの本体は、これで変更する。
+  ///
+  /// ## Return
+  /// `f`の返り値
+  ///
+  /// ## Error
+  /// - `StorageError::NotFound`: IDに対応するデータが存在しない
+  /// - `StorageError::IndexConflict`: 変更後の本体が一意制約に違反する
+  /// - `StorageError::Transactional`: データ型が`SousARCData::TRANSACTIONAL`
+  pub fn modify<R>(
+    &mut self,
+    id: D::Id,
+    f: impl FnOnce(&mut D::Body) -> R,
+  ) -> Result<R, StorageError<D>> {
+    if D::TRANSACTIONAL {
+      return Err(StorageError::Transactional(id));
+    }
+    self.modify_keeping(id, f).map(|(ret, _)| ret)
+  }
+
+  /// データを可変参照で取得する(インデックス・イベントは更新しない)
+  pub(super) fn data_mut(
+    &mut self,
+    id: D::Id,
+  ) -> Option<&mut D> {
+    self
+      .idmap
+      .get(&id)
+      .and_then(|i| self.data.get_mut(*i))
+      .and_then(Option::as_mut)
+  }
+
+  /// データ本体を変更し、`f`の返り値と変更前の本体を返す
+  pub(super) fn modify_keeping<R>(
+    &mut self,
+    id: D::Id,
+    f: impl FnOnce(&mut D::Body) -> R,
+  ) -> Result<(R, D::Body), StorageError<D>> {
+    let data = self
+      .idmap
+      .get(&id)
+      .and_then(|i| self.data.get_mut(*i))
+      .and_then(Option::as_mut)
+      .ok_or(StorageError::NotFound(id))?;
+    let before = data.body_mut().clone();
+    let ret = f(data.body_mut());
+    if let Some(name) = self.indexes.conflict(data) {
+      *data.body_mut() = before;
+      return Err(StorageError::IndexConflict(name));
+    }
+    self.indexes.update(data);
+    self.events.record(StorageEvent::new(
+      StorageEventKind::Updated,
+      id,
+      data.key(),
+    ));
+    Ok((ret, before))
+  }
 }
 impl<D> SousARCStorage<D> for StandardStorage<D>
 where
@@ -75,8 +587,8 @@ where
     self
       .idmap
       .get(&id)
-      .map(|i| self.data[*i].as_ref())
-      .flatten()
+      .and_then(|i| self.data.get(*i))
+      .and_then(Option::as_ref)
   }
 
   fn get_by_key<Q: Eq + std::hash::Hash>(
@@ -89,8 +601,8 @@ where
     self
       .keymap
       .get(key)
-      .map(|i| self.data[*i].as_ref())
-      .flatten()
+      .and_then(|i| self.data.get(*i))
+      .and_then(Option::as_ref)
   }
 
   fn id<Q: Eq + std::hash::Hash>(
@@ -100,24 +612,33 @@ where
   where
     <D as SousARCData>::Key: std::borrow::Borrow<Q>,
   {
-    if let Some(idx) = self.keymap.get(key) {
-      if let Some(data) = self.data.get(*idx) {
-        return data.as_ref().map(|d| d.id());
-      }
-    }
-    None
+    self.get_by_key(key).map(|d| d.id())
   }
 
   fn key(
     &self,
     id: <D as SousARCData>::Id,
   ) -> Option<&<D as SousARCData>::Key> {
-    if let Some(idx) = self.idmap.get(&id) {
-      if let Some(data) = self.data.get(*idx) {
-        return data.as_ref().map(|d| d.key());
-      }
-    }
-    None
+    self.get(id).map(|d| d.key())
+  }
+
+  fn len(&self) -> usize {
+    self.idmap.len()
+  }
+
+  fn iter(&self) -> impl Iterator<Item = &D> {
+    self.range(..)
+  }
+
+  fn range<R>(&self, range: R) -> impl Iterator<Item = &D>
+  where
+    R: RangeBounds<<D as SousARCData>::Key>,
+  {
+    self
+      .keyorder
+      .range(range)
+      .filter_map(|(_, i)| self.data.get(*i))
+      .filter_map(Option::as_ref)
   }
 }
 impl<D> SousARCStorageMut<D> for StandardStorage<D>
@@ -127,21 +648,459 @@ where
   fn get_mut(
     &mut self,
     id: <D as SousARCData>::Id,
-  ) -> Option<&mut D> {
-    self
-      .idmap
-      .get(&id)
-      .map(|i| self.data[*i].as_mut())
-      .flatten()
+  ) -> Option<impl DerefMut<Target = D>> {
+    let idx = *self.idmap.get(&id)?;
+    self.guard(idx)
   }
 
   fn get_by_key_mut<Q: Eq + std::hash::Hash>(
     &mut self,
     key: &Q,
-  ) -> Option<&mut D>
+  ) -> Option<impl DerefMut<Target = D>>
   where
     <D as SousARCData>::Key: std::borrow::Borrow<Q>,
   {
-    self.keymap.get(key).and_then(|i| self.data[*i].as_mut())
+    let idx = *self.keymap.get(key)?;
+    self.guard(idx)
+  }
+
+  fn rekey(
+    &mut self,
+    id: <D as SousARCData>::Id,
+    new_key: <D as SousARCData>::Key,
+  ) -> Result<<D as SousARCData>::Key, StorageError<D>> {
+    let Some(&idx) = self.idmap.get(&id) else {
+      return Err(StorageError::NotFound(id));
+    };
+    let Some(old_key) = self.key(id).cloned() else {
+      return Err(StorageError::NotFound(id));
+    };
+    if old_key == new_key {
+      return Ok(old_key);
+    }
+    if self.keymap.contains_key(&new_key) {
+      return Err(StorageError::DuplicateKey(new_key));
+    }
+    let Some(data) =
+      self.data.get_mut(idx).and_then(Option::as_mut)
+    else {
+      return Err(StorageError::NotFound(id));
+    };
+    let old_key = data
+      .id_key_set_mut(IdKeyToken::new())
+      .replace_key(new_key.clone());
+    // 抽出関数がキーを使う場合もあるので、差し替えた後に一意制約を検査する
+    if let Some(name) = self.indexes.conflict(data) {
+      data
+        .id_key_set_mut(IdKeyToken::new())
+        .replace_key(old_key);
+      return Err(StorageError::IndexConflict(name));
+    }
+    self.indexes.update(data);
+    self.events.record(
+      StorageEvent::new(
+        StorageEventKind::Updated,
+        id,
+        data.key(),
+      )
+      .moved_from(&old_key),
+    );
+    self.reindex_key(&old_key, new_key);
+    Ok(old_key)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{StandardStorage, StorageError};
+  use crate::{
+    domain::{
+      user::UserId,
+      work::{WorkData, WorkId, WorkKey},
+    },
+    fixture::{work, work_body},
+    traits::prelude::*,
+  };
+
+  /// IDを指定した作品
+  fn work_with_id(
+    id: WorkId,
+    user: UserId,
+    name: &str,
+  ) -> WorkData {
+    WorkData::new(
+      id,
+      WorkKey::from((user, name)),
+      work_body(name),
+    )
+  }
+
+  #[test]
+  fn try_insert_rejects_duplicates_without_leaking_slots() {
+    let user = UserId::new();
+    let mut storage = StandardStorage::new();
+    let a = work(user, "a");
+    let a_id = a.id();
+    storage.try_insert(a).unwrap();
+    let b = work(user, "b");
+    let b_id = b.id();
+    storage.try_insert(b).unwrap();
+    storage.remove(b_id).unwrap();
+    assert_eq!(storage.empty_slot.len(), 1);
+
+    let error =
+      storage.try_insert(work(user, "a")).unwrap_err();
+    assert!(matches!(
+      error.error,
+      StorageError::DuplicateKey(_)
+    ));
+    assert_eq!(error.into_data().key().work_name(), "a");
+    let error = storage
+      .try_insert(work_with_id(a_id, user, "c"))
+      .unwrap_err();
+    assert!(matches!(
+      error.error,
+      StorageError::DuplicateId(id) if id == a_id
+    ));
+
+    // 失敗した挿入は空きスロットを消費しない
+    assert_eq!(storage.empty_slot.len(), 1);
+    assert_eq!(storage.len(), 1);
+    assert_eq!(storage.idmap.len(), storage.keymap.len());
+    let c = work(user, "c");
+    let handle = storage.try_insert(c).unwrap();
+    assert_eq!(handle.index(), 1);
+    assert!(storage.empty_slot.is_empty());
+  }
+
+  #[test]
+  fn replace_keeps_the_slot_and_moves_the_key() {
+    let user = UserId::new();
+    let mut storage = StandardStorage::new();
+    let a = work(user, "a");
+    let a_id = a.id();
+    let handle = storage.try_insert(a).unwrap();
+    storage.try_insert(work(user, "b")).unwrap();
+
+    let error =
+      storage.replace(work(user, "c")).unwrap_err();
+    assert!(matches!(
+      error.error,
+      StorageError::NotFound(_)
+    ));
+    let error = storage
+      .replace(work_with_id(a_id, user, "b"))
+      .unwrap_err();
+    assert!(matches!(
+      error.error,
+      StorageError::DuplicateKey(_)
+    ));
+    assert_eq!(storage.key(a_id).unwrap().work_name(), "a");
+
+    let old = storage
+      .replace(work_with_id(a_id, user, "z"))
+      .unwrap();
+    assert_eq!(old.key().work_name(), "a");
+    assert!(storage.is_valid(handle));
+    assert!(
+      storage
+        .get_by_key(&WorkKey::from((user, "a")))
+        .is_none()
+    );
+    assert_eq!(
+      storage
+        .get_by_key(&WorkKey::from((user, "z")))
+        .map(|w| w.id()),
+      Some(a_id)
+    );
+    let names: Vec<_> =
+      storage.keys().map(|k| k.work_name()).collect();
+    assert_eq!(names, ["b", "z"]);
+  }
+
+  #[test]
+  fn upsert_inserts_or_replaces() {
+    let user = UserId::new();
+    let mut storage = StandardStorage::new();
+    let id = WorkId::new();
+    assert!(
+      storage
+        .upsert(work_with_id(id, user, "a"))
+        .unwrap()
+        .is_none()
+    );
+    let old = storage
+      .upsert(work_with_id(id, user, "b"))
+      .unwrap()
+      .unwrap();
+    assert_eq!(old.key().work_name(), "a");
+    assert_eq!(storage.len(), 1);
+
+    let error =
+      storage.upsert(work(user, "b")).unwrap_err();
+    assert!(matches!(
+      error.error,
+      StorageError::DuplicateKey(_)
+    ));
+    assert_eq!(storage.len(), 1);
+  }
+
+  #[test]
+  fn remove_by_key_frees_the_key() {
+    let user = UserId::new();
+    let mut storage = StandardStorage::new();
+    let a = work(user, "a");
+    let a_id = a.id();
+    storage.try_insert(a).unwrap();
+
+    let key = WorkKey::from((user, "a"));
+    assert!(
+      storage
+        .remove_by_key(&WorkKey::from((user, "b")))
+        .is_none()
+    );
+    assert_eq!(
+      storage.remove_by_key(&key).unwrap().id(),
+      a_id
+    );
+    assert!(storage.remove_by_key(&key).is_none());
+    assert!(storage.get(a_id).is_none());
+    assert!(storage.is_empty());
+    storage.try_insert(work(user, "a")).unwrap();
+    assert!(storage.get_by_key(&key).is_some());
+  }
+
+  #[test]
+  fn rekey_moves_the_key_in_place() {
+    let user = UserId::new();
+    let mut storage = StandardStorage::new();
+    let a = work(user, "a");
+    let a_id = a.id();
+    let handle = storage.try_insert(a).unwrap();
+    storage.try_insert(work(user, "b")).unwrap();
+    let keymap_pos = storage
+      .keymap
+      .get_index_of(&WorkKey::from((user, "a")));
+
+    assert!(matches!(
+      storage
+        .rekey(WorkId::new(), WorkKey::from((user, "c"))),
+      Err(StorageError::NotFound(_))
+    ));
+    assert!(matches!(
+      storage.rekey(a_id, WorkKey::from((user, "b"))),
+      Err(StorageError::DuplicateKey(_))
+    ));
+    assert_eq!(storage.key(a_id).unwrap().work_name(), "a");
+
+    let old = storage
+      .rekey(a_id, WorkKey::from((user, "c")))
+      .unwrap();
+    assert_eq!(old, WorkKey::from((user, "a")));
+    assert!(storage.is_valid(handle));
+    assert!(!storage.keymap.contains_key(&old));
+    assert!(!storage.keyorder.contains_key(&old));
+    let new_key = WorkKey::from((user, "c"));
+    // `keymap`上の位置と、`keyorder`上の順序が更新される
+    assert_eq!(
+      storage.keymap.get_index_of(&new_key),
+      keymap_pos
+    );
+    assert_eq!(
+      storage.keyorder.get(&new_key),
+      Some(&handle.index())
+    );
+    let names: Vec<_> =
+      storage.keys().map(|k| k.work_name()).collect();
+    assert_eq!(names, ["b", "c"]);
+    assert_eq!(
+      storage.get_by_key(&new_key).map(|w| w.id()),
+      Some(a_id)
+    );
+
+    // 同じキーへの変更は何もしない
+    assert_eq!(
+      storage.rekey(a_id, new_key.clone()).unwrap(),
+      new_key
+    );
+  }
+
+  #[test]
+  fn iter_range_and_prefix_follow_key_order() {
+    use std::ops::Bound;
+
+    let (u1, empty, u2) =
+      (UserId::new(), UserId::new(), UserId::new());
+    assert!(u1 < empty && empty < u2);
+    let mut storage = StandardStorage::new();
+    for (user, name) in
+      [(u2, "b"), (u1, "c"), (u2, "a"), (u1, "a")]
+    {
+      storage.try_insert(work(user, name)).unwrap();
+    }
+    let names = |works: Vec<&WorkData>| {
+      works
+        .into_iter()
+        .map(|w| w.key().to_string())
+        .collect::<Vec<_>>()
+    };
+    let key =
+      |user, name| WorkKey::from((user, name)).to_string();
+
+    assert_eq!(storage.len(), 4);
+    assert_eq!(
+      names(storage.iter().collect()),
+      [
+        key(u1, "a"),
+        key(u1, "c"),
+        key(u2, "a"),
+        key(u2, "b")
+      ]
+    );
+    assert_eq!(
+      storage.ids().collect::<Vec<_>>(),
+      storage.iter().map(|w| w.id()).collect::<Vec<_>>()
+    );
+
+    // 下限は含み、上限は指定に従う
+    let lower = WorkKey::from((u1, "c"));
+    let upper = WorkKey::from((u2, "b"));
+    assert_eq!(
+      names(
+        storage
+          .range(lower.clone()..upper.clone())
+          .collect()
+      ),
+      [key(u1, "c"), key(u2, "a")]
+    );
+    assert_eq!(
+      names(storage.range(lower.clone()..=upper).collect()),
+      [key(u1, "c"), key(u2, "a"), key(u2, "b")]
+    );
+    assert_eq!(
+      names(
+        storage
+          .range((Bound::Excluded(lower), Bound::Unbounded))
+          .collect()
+      ),
+      [key(u2, "a"), key(u2, "b")]
+    );
+    let missing = WorkKey::from((u1, "b"));
+    assert!(
+      storage
+        .range(missing.clone()..missing)
+        .next()
+        .is_none()
+    );
+
+    assert_eq!(
+      names(storage.prefix(&u2).collect()),
+      [key(u2, "a"), key(u2, "b")]
+    );
+    // 前後にデータがあっても、接頭辞に一致するデータが無ければ空
+    assert!(storage.prefix(&empty).next().is_none());
+    assert!(
+      storage.prefix(&UserId::new()).next().is_none()
+    );
+
+    storage.remove_by_key(&WorkKey::from((u1, "a")));
+    assert_eq!(
+      names(storage.prefix(&u1).collect()),
+      [key(u1, "c")]
+    );
+  }
+
+  #[test]
+  fn stale_handles_return_none() {
+    let user = UserId::new();
+    let mut storage = StandardStorage::new();
+    let a = work(user, "a");
+    let a_id = a.id();
+    let a_handle = storage.try_insert(a).unwrap();
+    let b = work(user, "b");
+    let b_id = b.id();
+    let b_handle = storage.try_insert(b).unwrap();
+
+    // 削除して同じスロットに別のデータを挿入しても、古いハンドルは無効
+    storage.remove(a_id).unwrap();
+    assert!(!storage.is_valid(a_handle));
+    assert!(storage.get_by_handle(a_handle).is_none());
+    let c = work(user, "c");
+    let c_handle = storage.try_insert(c).unwrap();
+    assert_eq!(c_handle.index(), a_handle.index());
+    assert_ne!(
+      c_handle.generation(),
+      a_handle.generation()
+    );
+    assert!(storage.get_by_handle(a_handle).is_none());
+    assert!(storage.get_by_handle_mut(a_handle).is_none());
+    assert!(storage.remove_by_handle(a_handle).is_none());
+    assert_eq!(
+      storage
+        .get_by_handle(c_handle)
+        .map(|w| w.key().work_name()),
+      Some("c")
+    );
+
+    // 再配置されたデータのハンドルは無効になり、取得し直せる
+    storage.remove_by_handle(c_handle).unwrap();
+    assert_eq!(storage.compact(), 1);
+    assert!(!storage.is_valid(b_handle));
+    assert!(storage.get_by_handle(b_handle).is_none());
+    let b_handle = storage.handle(b_id).unwrap();
+    assert_eq!(b_handle.index(), 0);
+    assert_eq!(
+      storage.get_by_handle(b_handle).map(|w| w.id()),
+      Some(b_id)
+    );
+  }
+
+  #[test]
+  fn compact_rewrites_slot_maps() {
+    let user = UserId::new();
+    let mut storage = StandardStorage::new();
+    let works: Vec<_> =
+      (0..6).map(|i| work(user, &i.to_string())).collect();
+    let ids: Vec<_> =
+      works.iter().map(|w| w.id()).collect();
+    for w in works {
+      storage.try_insert(w).unwrap();
+    }
+    assert_eq!(storage.compact(), 0);
+    for i in [0, 2, 3] {
+      storage.remove(ids[i]).unwrap();
+    }
+    let kept = [ids[1], ids[4], ids[5]];
+    let handles: Vec<_> = kept
+      .iter()
+      .map(|id| storage.handle(*id).unwrap())
+      .collect();
+
+    assert_eq!(storage.compact(), 3);
+    assert_eq!(storage.data.len(), 3);
+    assert_eq!(storage.generations.len(), 3);
+    assert!(storage.empty_slot.is_empty());
+    for (idx, id) in kept.iter().enumerate() {
+      let data = storage.get(*id).unwrap();
+      assert_eq!(storage.idmap[id], idx);
+      assert_eq!(storage.keymap[data.key()], idx);
+      assert_eq!(storage.keyorder[data.key()], idx);
+      let handle = storage.handle(*id).unwrap();
+      assert_eq!(handle.index(), idx);
+      // 世代は再配置の前後で変わらない
+      assert_eq!(
+        handle.generation(),
+        handles[idx].generation()
+      );
+    }
+    let names: Vec<_> =
+      storage.keys().map(|k| k.work_name()).collect();
+    assert_eq!(names, ["1", "4", "5"]);
+
+    // 再配置後の挿入は末尾に追加される
+    let handle =
+      storage.try_insert(work(user, "6")).unwrap();
+    assert_eq!(handle.index(), 3);
+    assert_eq!(storage.len(), 4);
   }
 }
@@ -0,0 +1,420 @@
+//! 非同期タスク間で共有するストレージ
+//!
+//! ## Summary
+//! - `SharedStorage`: データ毎に`tokio::sync::RwLock`を持つストレージ
+//! - `SharedReadGuard`・`SharedWriteGuard`: データのロックガード
+//!
+//! ID・キーの索引(ディレクトリ)は同期ロックで保護するが、
+//! 索引の参照・更新の間だけ保持し、`.await`を跨いで保持しない。
+//!
+//! セカンダリインデックスは持たないので、インデックスを登録した
+//! `StandardStorage`は変換できない(`TryFrom`がそのまま返す)。
+
+use std::{
+  collections::{BTreeMap, HashMap},
+  ops::{Deref, DerefMut},
+  sync::{Arc, PoisonError, RwLock as SyncRwLock},
+};
+
+use tokio::sync::{
+  OwnedRwLockMappedWriteGuard, OwnedRwLockReadGuard, RwLock,
+};
+
+use super::{
+  EventError, EventSink, EventSubscriber, InsertError,
+  StandardStorage, StorageError, StorageEvent,
+  StorageEventKind,
+};
+use crate::traits::prelude::*;
+
+/// データ毎のロック(削除済みの場合は`None`)
+type Entry<D> = Arc<RwLock<Option<D>>>;
+
+/// 読み取りロックのガード
+pub type SharedReadGuard<D> =
+  OwnedRwLockReadGuard<Option<D>, D>;
+
+/// 書き込みロックのガード
+///
+/// ## Summary
+/// 可変参照を取り出した場合のみ、
+/// ドロップ時に`StorageEventKind::Updated`のイベントを配信する。
+pub struct SharedWriteGuard<D: SousARCData> {
+  guard: OwnedRwLockMappedWriteGuard<Option<D>, D>,
+  events: EventSink<D>,
+  touched: bool,
+}
+impl<D: SousARCData> Deref for SharedWriteGuard<D> {
+  type Target = D;
+
+  fn deref(&self) -> &D {
+    &self.guard
+  }
+}
+impl<D: SousARCData> DerefMut for SharedWriteGuard<D> {
+  fn deref_mut(&mut self) -> &mut D {
+    self.touched = true;
+    &mut self.guard
+  }
+}
+impl<D: SousARCData> Drop for SharedWriteGuard<D> {
+  fn drop(&mut self) {
+    if !self.touched {
+      return;
+    }
+    let data = &*self.guard;
+    self.events.emit(StorageEvent::new(
+      StorageEventKind::Updated,
+      data.id(),
+      data.key(),
+    ));
+  }
+}
+
+/// ID・キーの索引
+///
+/// ## Member
+/// - `entries`: `HashMap<D::Id, (D::Key, Entry<D>)>`型
+///   - ID→キーとデータのロック
+/// - `keys`: `BTreeMap<D::Key, D::Id>`型
+///   - キー→ID(キーの昇順)
+struct Directory<D: SousARCData> {
+  entries: HashMap<D::Id, (D::Key, Entry<D>)>,
+  keys: BTreeMap<D::Key, D::Id>,
+}
+
+/// 非同期タスク間で共有するストレージ
+///
+/// ## Summary
+/// `Arc<SharedStorage<D>>`としてaxumのハンドラ等で共有する。
+/// 読み書きは`SousARCAsyncStorage`を通して行う。
+pub struct SharedStorage<D: SousARCData> {
+  directory: SyncRwLock<Directory<D>>,
+  events: EventSink<D>,
+}
+
+impl<D: SousARCData> Default for SharedStorage<D> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<D: SousARCData> std::fmt::Debug for SharedStorage<D> {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    f.debug_struct("SharedStorage")
+      .field("len", &self.len())
+      .finish_non_exhaustive()
+  }
+}
+
+/// `StandardStorage`から変換する
+///
+/// ## Summary
+/// `SharedStorage`はセカンダリインデックスを持たないので、
+/// インデックスを登録したストレージは変換せずにそのまま返す。
+/// `drop_index`で全て解除してから変換すること。
+impl<D: SousARCData> TryFrom<StandardStorage<D>>
+  for SharedStorage<D>
+{
+  type Error = StandardStorage<D>;
+
+  fn try_from(
+    storage: StandardStorage<D>,
+  ) -> Result<Self, Self::Error> {
+    if !storage.indexes.is_empty() {
+      return Err(storage);
+    }
+    Ok(Self::from_storage(storage))
+  }
+}
+
+impl<D: SousARCData> SharedStorage<D> {
+  pub fn new() -> Self {
+    Self {
+      directory: SyncRwLock::new(Directory {
+        entries: HashMap::new(),
+        keys: BTreeMap::new(),
+      }),
+      events: EventSink::new(),
+    }
+  }
+
+  /// インデックスを持たない`StandardStorage`のデータを移す
+  pub(super) fn from_storage(
+    storage: StandardStorage<D>,
+  ) -> Self {
+    let mut directory = Directory {
+      entries: HashMap::with_capacity(storage.len()),
+      keys: BTreeMap::new(),
+    };
+    for data in storage.data.into_iter().flatten() {
+      let (id, key) = (data.id(), data.key().clone());
+      directory.keys.insert(key.clone(), id);
+      directory.entries.insert(
+        id,
+        (key, Arc::new(RwLock::new(Some(data)))),
+      );
+    }
+    Self {
+      directory: SyncRwLock::new(directory),
+      events: storage.events,
+    }
+  }
+
+  /// 変更イベントの配信を有効にしたストレージを作成する
+  ///
+  /// ## Argument
+  /// - `capacity`: `usize`
+  ///   - 購読者毎に溜めておけるイベントの数
+  ///
+  /// ## Error
+  /// - `EventError::ZeroCapacity`: `capacity`が0
+  pub fn with_events(
+    capacity: usize,
+  ) -> Result<Self, EventError> {
+    let mut storage = Self::new();
+    storage.events.enable(capacity)?;
+    Ok(storage)
+  }
+
+  /// 変更イベントを購読する
+  ///
+  /// ## Return
+  /// 配信が有効でない場合は`None`
+  pub fn subscribe(&self) -> Option<EventSubscriber<D>> {
+    self.events.subscribe()
+  }
+
+  /// 最後の変更のリビジョンを取得する
+  pub fn revision(&self) -> u64 {
+    self.events.revision()
+  }
+
+  /// 索引を読み取る
+  ///
+  /// ## Summary
+  /// 索引の更新中にパニックしても索引自体は常に整合しているので、
+  /// ポイズニングは無視する。
+  fn directory(
+    &self,
+  ) -> std::sync::RwLockReadGuard<'_, Directory<D>> {
+    self
+      .directory
+      .read()
+      .unwrap_or_else(PoisonError::into_inner)
+  }
+
+  /// 索引を更新する
+  fn directory_mut(
+    &self,
+  ) -> std::sync::RwLockWriteGuard<'_, Directory<D>> {
+    self
+      .directory
+      .write()
+      .unwrap_or_else(PoisonError::into_inner)
+  }
+
+  /// 全てのデータの読み取りロックを、キーの昇順に取得する
+  ///
+  /// ## Summary
+  /// 索引を読み取りロックしたまま、全てのデータのロックを待たずに取得する。
+  /// 取得できないデータがあれば全て解放し、そのデータの書き込みが
+  /// 終わるのを待ってからやり直す。
+  /// 全てのロックを同時に取得するので、返したガードの内容は
+  /// ある時点での全てのデータと一致する。
+  /// ロックを保持したまま待つことはないので、
+  /// 複数のガードを保持する書き込みとデッドロックしない。
+  pub(super) async fn read_all(
+    &self,
+  ) -> Vec<SharedReadGuard<D>> {
+    loop {
+      let busy = {
+        let directory = self.directory();
+        let mut guards =
+          Vec::with_capacity(directory.entries.len());
+        let mut busy = None;
+        for id in directory.keys.values() {
+          let Some((_, entry)) = directory.entries.get(id)
+          else {
+            continue;
+          };
+          let Ok(guard) = entry.clone().try_read_owned()
+          else {
+            busy = Some(entry.clone());
+            break;
+          };
+          // 削除の途中で取り出されたデータは含めない
+          if let Ok(guard) = OwnedRwLockReadGuard::try_map(
+            guard,
+            Option::as_ref,
+          ) {
+            guards.push(guard);
+          }
+        }
+        match busy {
+          Some(entry) => entry,
+          None => return guards,
+        }
+      };
+      drop(busy.read_owned().await);
+    }
+  }
+
+  /// IDに対応するデータのロックを複製して取得する
+  fn entry(&self, id: D::Id) -> Option<Entry<D>> {
+    self
+      .directory()
+      .entries
+      .get(&id)
+      .map(|(_, e)| e.clone())
+  }
+}
+
+impl<D: SousARCData> SousARCAsyncStorage<D>
+  for SharedStorage<D>
+{
+  fn len(&self) -> usize {
+    self.directory().entries.len()
+  }
+
+  fn contains(&self, id: D::Id) -> bool {
+    self.directory().entries.contains_key(&id)
+  }
+
+  fn id(&self, key: &D::Key) -> Option<D::Id> {
+    self.directory().keys.get(key).copied()
+  }
+
+  fn key(&self, id: D::Id) -> Option<D::Key> {
+    self
+      .directory()
+      .entries
+      .get(&id)
+      .map(|(k, _)| k.clone())
+  }
+
+  fn ids(&self) -> Vec<D::Id> {
+    self.directory().keys.values().copied().collect()
+  }
+
+  /// ガードの具体的な型(`Shared*Guard`)を公開する
+  #[allow(refining_impl_trait)]
+  async fn read(
+    &self,
+    id: D::Id,
+  ) -> Option<SharedReadGuard<D>> {
+    let guard = self.entry(id)?.read_owned().await;
+    OwnedRwLockReadGuard::try_map(guard, Option::as_ref)
+      .ok()
+  }
+
+  /// ガードの具体的な型(`Shared*Guard`)を公開する
+  ///
+  /// `SousARCData::TRANSACTIONAL`なデータ型では常に`None`
+  #[allow(refining_impl_trait)]
+  async fn write(
+    &self,
+    id: D::Id,
+  ) -> Option<SharedWriteGuard<D>> {
+    if D::TRANSACTIONAL {
+      return None;
+    }
+    let guard = self.entry(id)?.write_owned().await;
+    let guard =
+      tokio::sync::OwnedRwLockWriteGuard::try_map(
+        guard,
+        Option::as_mut,
+      )
+      .ok()?;
+    Some(SharedWriteGuard {
+      guard,
+      events: self.events.clone(),
+      touched: false,
+    })
+  }
+
+  async fn insert(
+    &self,
+    data: D,
+  ) -> Result<(), InsertError<D>> {
+    let mut directory = self.directory_mut();
+    if directory.entries.contains_key(&data.id()) {
+      let error = StorageError::DuplicateId(data.id());
+      return Err(InsertError::new(error, data));
+    }
+    if directory.keys.contains_key(data.key()) {
+      let error =
+        StorageError::DuplicateKey(data.key().clone());
+      return Err(InsertError::new(error, data));
+    }
+    let (id, key) = (data.id(), data.key().clone());
+    self.events.emit(StorageEvent::new(
+      StorageEventKind::Inserted,
+      id,
+      &key,
+    ));
+    directory.keys.insert(key.clone(), id);
+    directory
+      .entries
+      .insert(id, (key, Arc::new(RwLock::new(Some(data)))));
+    Ok(())
+  }
+
+  async fn remove(&self, id: D::Id) -> Option<D> {
+    let entry = {
+      let mut directory = self.directory_mut();
+      let (key, entry) = directory.entries.remove(&id)?;
+      directory.keys.remove(&key);
+      self.events.emit(StorageEvent::new(
+        StorageEventKind::Removed,
+        id,
+        &key,
+      ));
+      entry
+    };
+    // 既存のガードが解放されるのを待ってから取り出す
+    entry.write_owned().await.take()
+  }
+
+  async fn rekey(
+    &self,
+    id: D::Id,
+    new_key: D::Key,
+  ) -> Result<D::Key, StorageError<D>> {
+    let entry =
+      self.entry(id).ok_or(StorageError::NotFound(id))?;
+    let mut guard = entry.write_owned().await;
+    let data =
+      guard.as_mut().ok_or(StorageError::NotFound(id))?;
+
+    let mut directory = self.directory_mut();
+    if data.key() == &new_key {
+      return Ok(new_key);
+    }
+    if directory.keys.contains_key(&new_key) {
+      return Err(StorageError::DuplicateKey(new_key));
+    }
+    let Some((key, _)) = directory.entries.get_mut(&id)
+    else {
+      return Err(StorageError::NotFound(id));
+    };
+    *key = new_key.clone();
+    directory.keys.insert(new_key.clone(), id);
+    let old_key = data
+      .id_key_set_mut(IdKeyToken::new())
+      .replace_key(new_key);
+    directory.keys.remove(&old_key);
+    self.events.emit(
+      StorageEvent::new(
+        StorageEventKind::Updated,
+        id,
+        data.key(),
+      )
+      .moved_from(&old_key),
+    );
+    Ok(old_key)
+  }
+}
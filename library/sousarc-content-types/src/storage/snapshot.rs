@@ -0,0 +1,474 @@
+//! ストレージのスナップショット
+//!
+//! ## Summary
+//! - `StandardStorage::save`・`load`: ストレージ全体をMessagePackで保存・復元する
+//! - `SharedStorage::save`・`load`: 同上(共有ストレージ用)
+//!
+//! ファイルは「ヘッダ(形式名・バージョン)」「本体」の2つの値を
+//! 続けて書き込んだもの。ヘッダを先に検査するので、
+//! 本体の形式が変わってもバージョンの不一致として検出できる。
+//!
+//! 保存は同じディレクトリの一時ファイルへ書き込んでから置き換えるので、
+//! 書き込み中に異常終了しても元のファイルは壊れない。
+//!
+//! セカンダリインデックス(抽出関数を含む)とイベントの購読者は保存されない。
+//! 読み込み後に`add_index`・`enable_events`で登録し直すこと。
+
+use std::{
+  collections::{BTreeMap, HashMap, VecDeque},
+  fs::File,
+  io::{BufReader, BufWriter, Read, Write},
+  path::{Path, PathBuf},
+};
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use super::{
+  EventSink, Indexes, SharedStorage, SnapshotError,
+  StandardStorage,
+};
+use crate::traits::prelude::*;
+
+/// スナップショットの形式名
+pub const SNAPSHOT_FORMAT: &str = "sousarc-storage";
+
+/// スナップショットの形式のバージョン
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// スナップショットのヘッダ
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+  format: String,
+  version: u32,
+}
+
+/// 書き込み用のスナップショット本体(データを借用する)
+///
+/// ## Member
+/// - `next_generation`: `u64`型
+///   - 次に採番する世代
+/// - `generations`: `&[u64]`型
+///   - スロット毎の世代
+/// - `data`: `Vec<Option<&D>>`型
+///   - スロット(空きスロットは`None`)
+/// - `journal_seq`: `u64`型
+///   - スナップショットに反映済みのジャーナルの最後の連番
+#[derive(Serialize)]
+#[serde(bound = "D: Serialize")]
+struct BodyRef<'a, D> {
+  next_generation: u64,
+  generations: &'a [u64],
+  data: Vec<Option<&'a D>>,
+  journal_seq: u64,
+}
+
+/// 読み込み用のスナップショット本体
+#[derive(Deserialize)]
+#[serde(bound = "D: DeserializeOwned")]
+struct Body<D> {
+  next_generation: u64,
+  generations: Vec<u64>,
+  data: Vec<Option<D>>,
+  #[serde(default)]
+  journal_seq: u64,
+}
+
+/// ヘッダと本体を書き込む
+fn write_body<D: Serialize>(
+  writer: &mut impl Write,
+  body: &BodyRef<'_, D>,
+) -> Result<(), SnapshotError> {
+  let header = Header {
+    format: SNAPSHOT_FORMAT.to_string(),
+    version: SNAPSHOT_VERSION,
+  };
+  rmp_serde::encode::write_named(writer, &header)?;
+  rmp_serde::encode::write_named(writer, body)?;
+  Ok(())
+}
+
+/// ヘッダを検査してから本体を読み込む
+fn read_body<D: DeserializeOwned>(
+  reader: &mut impl Read,
+) -> Result<Body<D>, SnapshotError> {
+  let header: Header =
+    rmp_serde::decode::from_read(&mut *reader)
+      .map_err(|_| SnapshotError::InvalidFormat)?;
+  if header.format != SNAPSHOT_FORMAT {
+    return Err(SnapshotError::InvalidFormat);
+  }
+  if header.version != SNAPSHOT_VERSION {
+    return Err(SnapshotError::UnsupportedVersion(
+      header.version,
+    ));
+  }
+  Ok(rmp_serde::decode::from_read(reader)?)
+}
+
+/// `path`へアトミックに書き込む
+///
+/// ## Summary
+/// `<path>.tmp`へ書き込み、ディスクへ同期してから`path`へ名前を変更し、
+/// 名前の変更が失われないよう親ディレクトリも同期する。
+/// 失敗した場合は一時ファイルを削除する。
+fn write_atomic(
+  path: &Path,
+  f: impl FnOnce(
+    &mut BufWriter<File>,
+  ) -> Result<(), SnapshotError>,
+) -> Result<(), SnapshotError> {
+  let mut tmp = PathBuf::from(path).into_os_string();
+  tmp.push(".tmp");
+  let tmp = PathBuf::from(tmp);
+
+  let result = (|| {
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    f(&mut writer)?;
+    writer
+      .into_inner()
+      .map_err(|e| e.into_error())?
+      .sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    sync_parent(path)?;
+    Ok(())
+  })();
+  if result.is_err() {
+    let _ = std::fs::remove_file(&tmp);
+  }
+  result
+}
+
+/// `path`の親ディレクトリをディスクへ同期する
+///
+/// ## Summary
+/// ディレクトリを開いて同期できないプラットフォーム(Windows)では何もしない。
+fn sync_parent(path: &Path) -> std::io::Result<()> {
+  if cfg!(windows) {
+    return Ok(());
+  }
+  let parent = match path.parent() {
+    Some(p) if !p.as_os_str().is_empty() => p,
+    _ => Path::new("."),
+  };
+  File::open(parent)?.sync_all()
+}
+
+impl<D> StandardStorage<D>
+where
+  D: SousARCData + Serialize,
+{
+  /// スナップショットを書き込む
+  ///
+  /// ## Error
+  /// - `SnapshotError::Io`: 書き込みに失敗した
+  /// - `SnapshotError::Encode`: データの変換に失敗した
+  pub fn write_snapshot(
+    &self,
+    writer: &mut impl Write,
+  ) -> Result<(), SnapshotError> {
+    self.write_snapshot_at(writer, 0)
+  }
+
+  /// ジャーナルの連番を添えてスナップショットを書き込む
+  fn write_snapshot_at(
+    &self,
+    writer: &mut impl Write,
+    journal_seq: u64,
+  ) -> Result<(), SnapshotError> {
+    let body = BodyRef {
+      next_generation: self.next_generation,
+      generations: &self.generations,
+      data: self.data.iter().map(Option::as_ref).collect(),
+      journal_seq,
+    };
+    write_body(writer, &body)
+  }
+
+  /// スナップショットをファイルへ保存する
+  ///
+  /// ## Summary
+  /// 一時ファイルへ書き込んでから置き換えるので、
+  /// 失敗しても既存のファイルは変更されない。
+  ///
+  /// ## Error
+  /// `write_snapshot`と同じ
+  pub fn save(
+    &self,
+    path: impl AsRef<Path>,
+  ) -> Result<(), SnapshotError> {
+    self.save_at(path.as_ref(), 0)
+  }
+
+  /// ジャーナルの連番を添えてスナップショットをファイルへ保存する
+  pub(crate) fn save_at(
+    &self,
+    path: &Path,
+    journal_seq: u64,
+  ) -> Result<(), SnapshotError> {
+    write_atomic(path, |w| {
+      self.write_snapshot_at(w, journal_seq)
+    })
+  }
+}
+
+impl<D> StandardStorage<D>
+where
+  D: SousARCData + DeserializeOwned,
+{
+  /// スナップショットを読み込む
+  ///
+  /// ## Summary
+  /// スロットの配置と世代はそのまま復元し、
+  /// `keymap`・`keyorder`・`idmap`・`empty_slot`は読み込んだデータから作り直す。
+  ///
+  /// ## Error
+  /// - `SnapshotError::InvalidFormat`: スナップショットの形式ではない
+  /// - `SnapshotError::UnsupportedVersion`: 形式のバージョンが異なる
+  /// - `SnapshotError::Decode`: データの変換に失敗した
+  /// - `SnapshotError::Corrupted`: IDやキーが重複している
+  pub fn read_snapshot(
+    reader: &mut impl Read,
+  ) -> Result<Self, SnapshotError> {
+    Self::read_snapshot_at(reader)
+      .map(|(storage, _)| storage)
+  }
+
+  /// スナップショットを読み込み、反映済みのジャーナルの連番と共に返す
+  fn read_snapshot_at(
+    reader: &mut impl Read,
+  ) -> Result<(Self, u64), SnapshotError> {
+    let body: Body<D> = read_body(reader)?;
+    if body.generations.len() != body.data.len() {
+      return Err(SnapshotError::Corrupted(
+        "slot and generation counts differ".to_string(),
+      ));
+    }
+
+    let mut keymap =
+      IndexMap::with_capacity(body.data.len());
+    let mut keyorder = BTreeMap::new();
+    let mut idmap = HashMap::with_capacity(body.data.len());
+    let mut empty_slot = VecDeque::new();
+    for (idx, slot) in body.data.iter().enumerate() {
+      let Some(data) = slot else {
+        empty_slot.push_back(idx);
+        continue;
+      };
+      if idmap.insert(data.id(), idx).is_some() {
+        return Err(SnapshotError::Corrupted(format!(
+          "duplicate id: {}",
+          data.id()
+        )));
+      }
+      if keymap.insert(data.key().clone(), idx).is_some() {
+        return Err(SnapshotError::Corrupted(format!(
+          "duplicate key: {}",
+          data.key()
+        )));
+      }
+      keyorder.insert(data.key().clone(), idx);
+    }
+
+    let next_generation = body
+      .generations
+      .iter()
+      .map(|g| g + 1)
+      .max()
+      .unwrap_or(0)
+      .max(body.next_generation);
+    let storage = Self {
+      data: body.data,
+      generations: body.generations,
+      next_generation,
+      empty_slot,
+      keymap,
+      keyorder,
+      idmap,
+      indexes: Indexes::new(),
+      events: EventSink::new(),
+    };
+    Ok((storage, body.journal_seq))
+  }
+
+  /// スナップショットをファイルから読み込む
+  ///
+  /// ## Error
+  /// - `SnapshotError::Io`: ファイルを開けない
+  /// - その他は`read_snapshot`と同じ
+  pub fn load(
+    path: impl AsRef<Path>,
+  ) -> Result<Self, SnapshotError> {
+    Self::load_at(path.as_ref()).map(|(storage, _)| storage)
+  }
+
+  /// スナップショットをファイルから読み込み、
+  /// 反映済みのジャーナルの連番と共に返す
+  pub(crate) fn load_at(
+    path: &Path,
+  ) -> Result<(Self, u64), SnapshotError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    Self::read_snapshot_at(&mut reader)
+  }
+}
+
+impl<D> SharedStorage<D>
+where
+  D: SousARCData + Serialize,
+{
+  /// スナップショットをファイルへ保存する
+  ///
+  /// ## Summary
+  /// 全てのデータの読み取りロックを同時に取得してから変換するので、
+  /// 保存される内容はある時点での全てのデータと一致する。
+  /// 変換の間は書き込みが待たされ、ファイルの書き込みは
+  /// ロックを解放してから`tokio::task::spawn_blocking`で行う。
+  ///
+  /// 世代は保存しないので、スロットの順に採番し直す。
+  ///
+  /// ## Error
+  /// `StandardStorage::save`と同じ
+  pub async fn save(
+    &self,
+    path: impl AsRef<Path>,
+  ) -> Result<(), SnapshotError> {
+    let guards = self.read_all().await;
+    let generations: Vec<u64> =
+      (0..guards.len() as u64).collect();
+    let body = BodyRef {
+      next_generation: generations.len() as u64,
+      generations: &generations,
+      data: guards.iter().map(|g| Some(&**g)).collect(),
+      journal_seq: 0,
+    };
+    let mut bytes = Vec::new();
+    write_body(&mut bytes, &body)?;
+    drop(body);
+    drop(guards);
+
+    let path = path.as_ref().to_path_buf();
+    tokio::task::spawn_blocking(move || {
+      write_atomic(&path, |w| Ok(w.write_all(&bytes)?))
+    })
+    .await
+    .map_err(|e| {
+      SnapshotError::Io(std::io::Error::other(e))
+    })?
+  }
+}
+
+impl<D> SharedStorage<D>
+where
+  D: SousARCData + DeserializeOwned,
+{
+  /// スナップショットをファイルから読み込む
+  ///
+  /// ## Error
+  /// `StandardStorage::load`と同じ
+  pub fn load(
+    path: impl AsRef<Path>,
+  ) -> Result<Self, SnapshotError> {
+    StandardStorage::load(path).map(Self::from_storage)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use crate::{
+    domain::{user::UserId, work::WorkData},
+    fixture::work,
+    storage::{IndexKind, SharedStorage, StandardStorage},
+    traits::prelude::*,
+  };
+
+  #[test]
+  fn try_from_refuses_indexed_storage() {
+    let mut storage = StandardStorage::new();
+    let handle = storage
+      .add_index(
+        "name",
+        IndexKind::Unique,
+        |w: &WorkData| [w.body.display_name.clone()],
+      )
+      .unwrap();
+    storage.try_insert(work(UserId::new(), "a")).unwrap();
+
+    let mut storage =
+      SharedStorage::try_from(storage).unwrap_err();
+    assert!(storage.drop_index(handle));
+    let shared = SharedStorage::try_from(storage).unwrap();
+    assert_eq!(shared.len(), 1);
+  }
+
+  #[test]
+  fn shared_save_round_trips() {
+    let user = UserId::new();
+    let (a, b) = (work(user, "a"), work(user, "b"));
+    let ids = [a.id(), b.id()];
+    let path = std::env::temp_dir()
+      .join(format!("sousarc-shared-{}.snap", ids[0]));
+
+    let runtime =
+      tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+      let shared = SharedStorage::new();
+      shared.insert(a).await.unwrap();
+      shared.insert(b).await.unwrap();
+      shared.save(&path).await.unwrap();
+    });
+
+    let loaded: StandardStorage<WorkData> =
+      StandardStorage::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.len(), 2);
+    for id in ids {
+      assert!(loaded.get(id).is_some());
+    }
+  }
+
+  #[test]
+  fn shared_save_waits_for_writers_without_deadlock() {
+    let user = UserId::new();
+    let (a, b) = (work(user, "a"), work(user, "b"));
+    let ids = [a.id(), b.id()];
+    let path = std::env::temp_dir()
+      .join(format!("sousarc-shared-{}.snap", ids[0]));
+
+    let runtime =
+      tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+      let shared = Arc::new(SharedStorage::new());
+      shared.insert(a).await.unwrap();
+      shared.insert(b).await.unwrap();
+
+      // キーの逆順にロックする書き込みと並行して保存する
+      let mut second = shared.write(ids[1]).await.unwrap();
+      let saving = tokio::spawn({
+        let (shared, path) = (shared.clone(), path.clone());
+        async move { shared.save(&path).await }
+      });
+      tokio::task::yield_now().await;
+      let mut first = shared.write(ids[0]).await.unwrap();
+      first.body.description = "edited".to_string();
+      second.body.description = "edited".to_string();
+      drop((first, second));
+      saving.await.unwrap().unwrap();
+    });
+
+    let loaded: StandardStorage<WorkData> =
+      StandardStorage::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    for id in ids {
+      assert_eq!(
+        loaded.get(id).unwrap().body.description,
+        "edited"
+      );
+    }
+  }
+}
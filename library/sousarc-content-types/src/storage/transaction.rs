@@ -0,0 +1,337 @@
+//! ストレージ単位のトランザクション
+//!
+//! ## Summary
+//! - `StorageTx`: 1つの`StandardStorage`に対する操作と、その取り消し記録
+//!
+//! 操作は直ちにストレージへ適用し、取り消しに必要な情報を記録する。
+//! ストレージは`&mut`で借用されるので、確定(`commit`)までの途中状態は
+//! トランザクションの外から観測されない。
+//! 確定せずにドロップした場合は、記録を逆順に適用して元に戻す。
+//! 変更イベントは確定するまで保留し、取り消した操作のイベントは配信しない。
+
+use indexmap::IndexSet;
+
+use super::{
+  InsertError, JournalRecordRef, SlotHandle,
+  StandardStorage, StorageError,
+};
+use crate::traits::prelude::*;
+
+/// 取り消し記録
+enum Undo<D: SousARCDataHasBody> {
+  /// 挿入したデータを削除する
+  Insert(D::Id),
+
+  /// 削除したデータを戻す
+  Remove(D),
+
+  /// 本体を変更前に戻す
+  Body(D::Id, D::Body),
+
+  /// キーを変更前に戻す
+  Rekey(D::Id, D::Key),
+
+  /// 本体・キー以外の部分を変更前に戻す
+  Data(D::Id, Box<dyn FnOnce(&mut D)>),
+}
+
+/// トランザクション内の途中の時点
+///
+/// ## Summary
+/// `StorageTx::savepoint`で取得し、`rollback_to`でその時点まで取り消す。
+///
+/// ## Member
+/// - `undo`: `usize`型
+///   - 取り消し記録の数
+/// - `events`: `usize`型
+///   - 保留しているイベントの数
+/// - `touched`: `usize`型
+///   - 変更したデータの数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Savepoint {
+  undo: usize,
+  events: usize,
+  touched: usize,
+}
+
+/// ストレージ単位のトランザクション
+///
+/// ## Member
+/// - `storage`: `&mut StandardStorage<D>`型
+///   - 操作対象のストレージ
+/// - `undo`: `Vec<Undo<D>>`型
+///   - 取り消し記録(適用順)
+/// - `touched`: `IndexSet<D::Id>`型
+///   - 変更したデータのID(最初に変更した順)
+pub struct StorageTx<'a, D>
+where
+  D: SousARCDataHasBody,
+  D::Body: Clone,
+{
+  storage: &'a mut StandardStorage<D>,
+  undo: Vec<Undo<D>>,
+  touched: IndexSet<D::Id>,
+}
+
+impl<'a, D> StorageTx<'a, D>
+where
+  D: SousARCDataHasBody,
+  D::Body: Clone,
+{
+  /// ストレージに対するトランザクションを開始する
+  ///
+  /// ## Summary
+  /// `SousARCData::TRANSACTIONAL`なデータ型では、
+  /// 確定処理を行うドメインの`Transaction`の外から作れないよう、
+  /// コンパイル時に拒否する。
+  pub fn new(storage: &'a mut StandardStorage<D>) -> Self {
+    const {
+      assert!(
+        !D::TRANSACTIONAL,
+        "transactional data must use domain::Transaction"
+      )
+    };
+    Self::new_transactional(storage)
+  }
+
+  /// `SousARCData::TRANSACTIONAL`なデータ型も受け付けて開始する
+  pub(crate) fn new_transactional(
+    storage: &'a mut StandardStorage<D>,
+  ) -> Self {
+    storage.events.hold();
+    Self {
+      storage,
+      undo: Vec::new(),
+      touched: IndexSet::new(),
+    }
+  }
+
+  /// 操作対象のストレージを参照する
+  pub fn storage(&self) -> &StandardStorage<D> {
+    self.storage
+  }
+
+  /// データを取得する
+  pub fn get(&self, id: D::Id) -> Option<&D> {
+    self.storage.get(id)
+  }
+
+  /// データを挿入する
+  ///
+  /// ## Error
+  /// `StandardStorage::try_insert`と同じ
+  pub fn insert(
+    &mut self,
+    data: D,
+  ) -> Result<SlotHandle, InsertError<D>> {
+    let id = data.id();
+    let handle = self.storage.try_insert(data)?;
+    self.undo.push(Undo::Insert(id));
+    self.touched.insert(id);
+    Ok(handle)
+  }
+
+  /// データを削除する
+  ///
+  /// ## Error
+  /// - `StorageError::NotFound`: IDに対応するデータが存在しない
+  pub fn remove(
+    &mut self,
+    id: D::Id,
+  ) -> Result<(), StorageError<D>> {
+    let data = self
+      .storage
+      .remove(id)
+      .ok_or(StorageError::NotFound(id))?;
+    self.undo.push(Undo::Remove(data));
+    self.touched.insert(id);
+    Ok(())
+  }
+
+  /// データ本体を変更する
+  ///
+  /// ## Summary
+  /// `StandardStorage::modify`で変更し、変更前の本体を記録する。
+  ///
+  /// ## Return
+  /// `f`の返り値
+  ///
+  /// ## Error
+  /// `StandardStorage::modify`と同じ
+  pub fn modify<R>(
+    &mut self,
+    id: D::Id,
+    f: impl FnOnce(&mut D::Body) -> R,
+  ) -> Result<R, StorageError<D>> {
+    let (ret, before) =
+      self.storage.modify_keeping(id, f)?;
+    self.undo.push(Undo::Body(id, before));
+    self.touched.insert(id);
+    Ok(ret)
+  }
+
+  /// データの本体・キー以外の部分を変更する
+  ///
+  /// ## Summary
+  /// 本体を複製せずに、取り消し用の`undo`を記録する。
+  /// `f`は本体・キーを変更してはならない(インデックス・イベントは更新しない)。
+  ///
+  /// ## Return
+  /// `f`の返り値
+  ///
+  /// ## Error
+  /// - `StorageError::NotFound`: IDに対応するデータが存在しない
+  pub(crate) fn modify_data<R>(
+    &mut self,
+    id: D::Id,
+    f: impl FnOnce(&mut D) -> R,
+    undo: impl FnOnce(&mut D) + 'static,
+  ) -> Result<R, StorageError<D>> {
+    let data = self
+      .storage
+      .data_mut(id)
+      .ok_or(StorageError::NotFound(id))?;
+    let ret = f(data);
+    self.undo.push(Undo::Data(id, Box::new(undo)));
+    self.touched.insert(id);
+    Ok(ret)
+  }
+
+  /// データのキーを変更する
+  ///
+  /// ## Error
+  /// `SousARCStorageMut::rekey`と同じ
+  pub fn rekey(
+    &mut self,
+    id: D::Id,
+    new_key: D::Key,
+  ) -> Result<D::Key, StorageError<D>> {
+    let old_key = self.storage.rekey(id, new_key)?;
+    self.undo.push(Undo::Rekey(id, old_key.clone()));
+    self.touched.insert(id);
+    Ok(old_key)
+  }
+
+  /// 変更したデータのIDを、最初に変更した順に列挙する
+  pub(crate) fn touched(
+    &self,
+  ) -> impl Iterator<Item = D::Id> + '_ {
+    self.touched.iter().copied()
+  }
+
+  /// トランザクションの開始時点の本体を取得する
+  ///
+  /// ## Return
+  /// トランザクション内で挿入したデータ、削除して戻したデータ、
+  /// 本体を変更していないデータは`None`
+  pub(crate) fn original_body(
+    &self,
+    id: D::Id,
+  ) -> Option<&D::Body> {
+    self.undo.iter().find_map(|undo| match undo {
+      Undo::Insert(i) if *i == id => Some(None),
+      Undo::Remove(data) if data.id() == id => Some(None),
+      Undo::Body(i, body) if *i == id => Some(Some(body)),
+      _ => None,
+    })?
+  }
+
+  /// データがトランザクション内で削除されたか判定する
+  ///
+  /// ## Summary
+  /// 削除した後に同じIDで挿入し直した場合も`true`
+  pub(crate) fn is_removed(&self, id: D::Id) -> bool {
+    self.undo.iter().any(|undo| {
+      matches!(undo, Undo::Remove(data) if data.id() == id)
+    })
+  }
+
+  /// 現在の時点を取得する
+  pub fn savepoint(&self) -> Savepoint {
+    Savepoint {
+      undo: self.undo.len(),
+      events: self.storage.events.held(),
+      touched: self.touched.len(),
+    }
+  }
+
+  /// 変更したデータの現在の内容を、ジャーナルの記録として列挙する
+  pub(crate) fn redo(
+    &self,
+  ) -> Vec<JournalRecordRef<'_, D>> {
+    self
+      .touched
+      .iter()
+      .map(|id| {
+        JournalRecordRef::current(self.storage, *id)
+      })
+      .collect()
+  }
+
+  /// `savepoint`の時点より後の操作を取り消す
+  pub fn rollback_to(&mut self, savepoint: Savepoint) {
+    self.revert_to(savepoint);
+  }
+
+  /// 操作を確定し、保留していたイベントを配信する
+  pub fn commit(mut self) {
+    self.undo.clear();
+    self.touched.clear();
+    self.storage.events.release();
+  }
+
+  /// 操作を全て取り消す
+  pub fn rollback(self) {
+    // `Drop`で取り消される
+  }
+
+  /// 記録を逆順に適用し、`savepoint`の時点まで元に戻す
+  ///
+  /// ## Summary
+  /// 取り消した操作と、取り消しの操作によるイベントは破棄する。
+  fn revert_to(&mut self, savepoint: Savepoint) {
+    while self.undo.len() > savepoint.undo {
+      let Some(undo) = self.undo.pop() else { break };
+      let reverted = match undo {
+        Undo::Insert(id) => {
+          self.storage.remove(id).is_some()
+        }
+        Undo::Remove(data) => {
+          self.storage.try_insert(data).is_ok()
+        }
+        Undo::Body(id, body) => self
+          .storage
+          .modify_keeping(id, |b| *b = body)
+          .is_ok(),
+        Undo::Rekey(id, key) => {
+          self.storage.rekey(id, key).is_ok()
+        }
+        Undo::Data(id, undo) => {
+          self.storage.data_mut(id).map(undo).is_some()
+        }
+      };
+      if !reverted {
+        tracing::error!(
+          "failed to revert a storage operation"
+        );
+      }
+    }
+    self.storage.events.discard_to(savepoint.events);
+    self.touched.truncate(savepoint.touched);
+  }
+}
+
+impl<D> Drop for StorageTx<'_, D>
+where
+  D: SousARCDataHasBody,
+  D::Body: Clone,
+{
+  fn drop(&mut self) {
+    self.revert_to(Savepoint {
+      undo: 0,
+      events: 0,
+      touched: 0,
+    });
+    self.storage.events.release();
+  }
+}
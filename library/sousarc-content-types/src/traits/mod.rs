@@ -7,21 +7,28 @@
 //!
 //! これらは相互に依存しつつ、柔軟なデータ構造を表現するための基盤となるよ！
 
-use std::hash::Hash;
+use std::{
+  hash::Hash,
+  ops::{Deref, DerefMut, RangeBounds},
+};
+
+use crate::storage::{InsertError, StorageError};
 
 pub mod primitive;
-//pub mod topology;
+pub mod topology;
 
 pub mod prelude {
   pub use super::{
-    SousARCStorage, SousARCStorageMut,
+    SousARCAsyncStorage, SousARCStorage, SousARCStorageMut,
     primitive::{
-      IdKeySet, SousARCData, SousARCId, SousARCKey,
+      IdKeySet, IdKeyToken, SousARCData,
+      SousARCDataHasBody, SousARCId, SousARCKey,
+      SousARCKeyPrefix,
     },
-    /*topology::{
+    topology::{
       SousARCDataHasChild, SousARCDataHasParent,
       SousARCIdHasChild, SousARCKeyHasParent,
-    },*/
+    },
   };
 }
 
@@ -38,16 +45,209 @@ pub trait SousARCStorage<I: primitive::SousARCData> {
     I::Key: std::borrow::Borrow<Q>;
 
   fn key(&self, id: I::Id) -> Option<&I::Key>;
+
+  /// 格納されているデータの件数を返す
+  fn len(&self) -> usize;
+
+  /// データが1件も格納されていないか判定する
+  fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// 全てのデータをキーの昇順で列挙する
+  fn iter(&self) -> impl Iterator<Item = &I>;
+
+  /// 全てのIDをキーの昇順で列挙する
+  fn ids(&self) -> impl Iterator<Item = I::Id> {
+    self.iter().map(|d| d.id())
+  }
+
+  /// 全てのキーを昇順で列挙する
+  fn keys(&self) -> impl Iterator<Item = &I::Key> {
+    self.iter().map(|d| d.key())
+  }
+
+  /// キーが範囲`range`に含まれるデータを昇順で列挙する
+  fn range<R>(&self, range: R) -> impl Iterator<Item = &I>
+  where
+    R: RangeBounds<I::Key>;
+
+  /// キーが接頭辞`prefix`を持つデータを昇順で列挙する
+  ///
+  /// ## Summary
+  /// 例えば`ElementParent`を接頭辞とすると、
+  /// その親の直下にある要素を全て取得できる。
+  fn prefix<'a, P>(
+    &'a self,
+    prefix: &'a P,
+  ) -> impl Iterator<Item = &'a I>
+  where
+    I::Key: primitive::SousARCKeyPrefix<P>,
+    I: 'a,
+  {
+    use primitive::SousARCKeyPrefix;
+    self
+      .range(I::Key::prefix_lower(prefix)..)
+      .take_while(move |d| d.key().has_prefix(prefix))
+  }
 }
 
 pub trait SousARCStorageMut<I: primitive::SousARCData>:
   SousARCStorage<I>
 {
-  fn get_mut(&mut self, id: I::Id) -> Option<&mut I>;
+  /// データを可変参照で取得する
+  ///
+  /// ## Summary
+  /// 返り値はガードで、ドロップ時にストレージが
+  /// インデックス等の派生データを更新する。
+  ///
+  /// ## Return
+  /// - `Some`: データの可変参照のガード
+  /// - `None`: データが存在しない、またはストレージが直接の変更を受け付けない
+  ///   (`StandardStorage`では一意インデックスが登録されている場合、
+  ///   データ型が`SousARCData::TRANSACTIONAL`の場合)
+  fn get_mut(
+    &mut self,
+    id: I::Id,
+  ) -> Option<impl DerefMut<Target = I>>;
+
+  /// キーを指定してデータを可変参照で取得する
+  ///
+  /// ## Return
+  /// `get_mut`と同じ
   fn get_by_key_mut<Q: Eq + Hash>(
     &mut self,
     key: &Q,
-  ) -> Option<&mut I>
+  ) -> Option<impl DerefMut<Target = I>>
   where
     I::Key: std::borrow::Borrow<Q>;
+
+  /// データのキーを変更する
+  ///
+  /// ## Summary
+  /// データのスロットとIDはそのままに、キーのみを差し替える。
+  /// 失敗した場合、ストレージの内容は変更されない。
+  ///
+  /// ## Return
+  /// 変更前のキー
+  ///
+  /// ## Error
+  /// - `StorageError::NotFound`: IDに対応するデータが存在しない
+  /// - `StorageError::DuplicateKey`: 新しいキーが別のデータに使われている
+  /// - `StorageError::IndexConflict`: 新しいキーのデータが一意制約に違反する
+  fn rekey(
+    &mut self,
+    id: I::Id,
+    new_key: I::Key,
+  ) -> Result<I::Key, StorageError<I>>;
+}
+
+/// 非同期タスク間で共有するストレージのトレイト
+///
+/// ## Summary
+/// `&self`のみで読み書きできるストレージ。
+/// データ毎のロックを非同期に待機し、ロックの取得を待つ間に
+/// ストレージ全体をロックし続けることはない。
+/// 読み取り・書き込みのガードを保持している間は、
+/// 同じデータへの`write`・`remove`・`rekey`が待機する。
+pub trait SousARCAsyncStorage<I: primitive::SousARCData>:
+  Send + Sync
+{
+  /// 格納されているデータの件数を返す
+  fn len(&self) -> usize;
+
+  /// データが1件も格納されていないか判定する
+  fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// IDに対応するデータが存在するか判定する
+  fn contains(&self, id: I::Id) -> bool;
+
+  /// キーに対応するIDを取得する
+  fn id(&self, key: &I::Key) -> Option<I::Id>;
+
+  /// IDに対応するキーを取得する
+  fn key(&self, id: I::Id) -> Option<I::Key>;
+
+  /// 全てのIDをキーの昇順で取得する(呼び出し時点のスナップショット)
+  fn ids(&self) -> Vec<I::Id>;
+
+  /// データの読み取りロックを取得する
+  fn read(
+    &self,
+    id: I::Id,
+  ) -> impl Future<
+    Output = Option<impl Deref<Target = I> + Send + Sync>,
+  > + Send;
+
+  /// キーを指定してデータの読み取りロックを取得する
+  fn read_by_key(
+    &self,
+    key: &I::Key,
+  ) -> impl Future<
+    Output = Option<impl Deref<Target = I> + Send + Sync>,
+  > + Send {
+    let id = self.id(key);
+    async move { self.read(id?).await }
+  }
+
+  /// データの書き込みロックを取得する
+  ///
+  /// ## Summary
+  /// キーは`rekey`で変更すること。
+  fn write(
+    &self,
+    id: I::Id,
+  ) -> impl Future<
+    Output = Option<
+      impl DerefMut<Target = I> + Send + Sync,
+    >,
+  > + Send;
+
+  /// キーを指定してデータの書き込みロックを取得する
+  fn write_by_key(
+    &self,
+    key: &I::Key,
+  ) -> impl Future<
+    Output = Option<
+      impl DerefMut<Target = I> + Send + Sync,
+    >,
+  > + Send {
+    let id = self.id(key);
+    async move { self.write(id?).await }
+  }
+
+  /// データを挿入する
+  ///
+  /// ## Error
+  /// - `StorageError::DuplicateId`: IDが既に使われている
+  /// - `StorageError::DuplicateKey`: キーが既に使われている
+  fn insert(
+    &self,
+    data: I,
+  ) -> impl Future<Output = Result<(), InsertError<I>>> + Send;
+
+  /// データを削除する
+  ///
+  /// ## Summary
+  /// 削除は直ちに反映され、既存のガードが解放された後にデータを返す。
+  fn remove(
+    &self,
+    id: I::Id,
+  ) -> impl Future<Output = Option<I>> + Send;
+
+  /// データのキーを変更する
+  ///
+  /// ## Return
+  /// 変更前のキー
+  ///
+  /// ## Error
+  /// - `StorageError::NotFound`: IDに対応するデータが存在しない
+  /// - `StorageError::DuplicateKey`: 新しいキーが別のデータに使われている
+  fn rekey(
+    &self,
+    id: I::Id,
+    new_key: I::Key,
+  ) -> impl Future<Output = Result<I::Key, StorageError<I>>> + Send;
 }
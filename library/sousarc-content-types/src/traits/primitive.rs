@@ -4,6 +4,7 @@
 //! - データID（`SousARCId`）
 //! - データキー（`SousARCKey`）
 //! - データ本体（`SousARCData`）
+//!
 //! を定義するモジュール。
 //!
 //! これらは`topology.rs`や`wrapper.rs`からも参照される基盤だよ！
@@ -43,6 +44,32 @@ impl<D: SousARCData> IdKeySet<D> {
   pub fn key(&self) -> &D::Key {
     &self.key
   }
+
+  /// キーを差し替え、元のキーを返す
+  ///
+  /// ## Summary
+  /// ストレージの`keymap`と同期を取る必要がある為、
+  /// ストレージの`rekey`からのみ呼び出す。
+  pub(crate) fn replace_key(
+    &mut self,
+    key: D::Key,
+  ) -> D::Key {
+    std::mem::replace(&mut self.key, key)
+  }
+}
+
+/// `SousARCData::id_key_set_mut`の呼び出しに必要な鍵
+///
+/// ## Summary
+/// クレートの外からは作成できないので、`IdKeySet`を書き換えられるのは
+/// ストレージの`rekey`のみになる。
+/// データ型を実装する側は、受け取った鍵を使わずに`IdKeySet`を返せばよい。
+#[derive(Debug)]
+pub struct IdKeyToken(());
+impl IdKeyToken {
+  pub(crate) fn new() -> Self {
+    Self(())
+  }
 }
 
 /// データIDトレイト
@@ -99,6 +126,20 @@ pub trait SousARCKey:
   }
 }
 
+/// 前方一致検索が可能なキーのトレイト
+///
+/// ## Summary
+/// キーの順序(`Ord`)において、同じ接頭辞`P`を持つキーが
+/// 連続して並ぶ場合に実装する。
+/// `SousARCStorage::prefix`で、接頭辞を共有するデータを列挙できる。
+pub trait SousARCKeyPrefix<P>: SousARCKey {
+  /// 接頭辞`prefix`を持つキーの中で最小のキーを返す
+  fn prefix_lower(prefix: &P) -> Self;
+
+  /// キーが接頭辞`prefix`を持つか判定する
+  fn has_prefix(&self, prefix: &P) -> bool;
+}
+
 /// データ本体トレイト
 ///
 /// ## Summary
@@ -111,9 +152,30 @@ pub trait SousARCData:
   /// データキー型
   type Key: SousARCKey;
 
+  /// 本体の変更をトランザクションの確定処理に限るか
+  ///
+  /// ## Summary
+  /// 確定時に変更履歴の記録や検証を行うデータ型で`true`にする。
+  /// `true`の場合、ストレージは`get_mut`・`modify`・`write`等の
+  /// 直接の変更を受け付けず、`StorageTx`も`JournaledStorage`では作れない。
+  const TRANSACTIONAL: bool = false;
+
   /// IDとキーのセットを取得する
   fn id_key_set(&self) -> &IdKeySet<Self>;
 
+  /// IDとキーのセットを可変参照で取得する
+  ///
+  /// ## Summary
+  /// ストレージの`rekey`がキーを差し替える為に使う。
+  /// ストレージに格納されたデータの`IdKeySet`を直接置き換えると
+  /// ストレージのインデックスと食い違うので、`IdKeyToken`を要求し、
+  /// クレートの外から呼び出せないようにしている。
+  /// キーの変更は`SousARCStorageMut::rekey`を使うこと。
+  fn id_key_set_mut(
+    &mut self,
+    token: IdKeyToken,
+  ) -> &mut IdKeySet<Self>;
+
   /// IDを取得する
   fn id(&self) -> Self::Id {
     self.id_key_set().id()
@@ -124,3 +186,28 @@ pub trait SousARCData:
     self.id_key_set().key()
   }
 }
+
+/// 本体を持つデータ型のトレイト
+///
+/// ## Summary
+/// ID・キー以外の本体(`Body`)を可変参照で取り出す為のトレイト。
+/// トランザクションの巻き戻し等、本体を丸ごと退避・復元する処理に使う。
+pub trait SousARCDataHasBody: SousARCData {
+  /// データ本体の型
+  type Body;
+
+  /// データ本体を取得する
+  fn body(&self) -> &Self::Body;
+
+  /// データ本体を可変参照で取得する
+  fn body_mut(&mut self) -> &mut Self::Body;
+
+  /// IDとキーのセットと本体からデータを組み立てる
+  fn from_parts(
+    id_key: IdKeySet<Self>,
+    body: Self::Body,
+  ) -> Self;
+
+  /// データをIDとキーのセットと本体に分解する
+  fn into_parts(self) -> (IdKeySet<Self>, Self::Body);
+}
@@ -4,6 +4,13 @@
 //! - データ型間の親子・階層関係を型レベルで表現するためのトレイト群
 //! - `primitive.rs`のID/Key/Dataトレイトと相互依存
 //!
+//! ## Bound
+//! トレイト境界が循環しない様に、以下の順で積み上げている。
+//! 1. `SousARCKeyHasParent`: プリミティブトレイトのみに依存
+//! 2. `SousARCDataHasChild`: `SousARCKeyHasParent`に依存
+//! 3. `SousARCIdHasChild`・`SousARCDataHasParent`:
+//!    `SousARCDataHasChild`から自動実装される
+//!
 //! これでツリー・グラフ構造も型安全に表現できるよ！
 
 use super::{SousARCStorage, primitive::*};
@@ -14,9 +21,9 @@ use super::{SousARCStorage, primitive::*};
 /// 子要素（ID）を返すデータ型のためのトレイト
 pub trait SousARCDataHasChild<C, Pi, Ck>
 where
-  Self: SousARCData,
-  C: SousARCDataHasParent<Self, Pi, Ck>,
-  Pi: SousARCIdHasChild<Self, C, Ck>,
+  Self: SousARCData<Id = Pi>,
+  C: SousARCData<Key = Ck>,
+  Pi: SousARCId<Bound = Self>,
   Ck: SousARCKeyHasParent<Self, C, Pi>,
 {
   /// 子要素IDのイテレータを返す
@@ -38,7 +45,7 @@ where
 pub trait SousARCIdHasChild<P, C, Ck>
 where
   P: SousARCDataHasChild<C, Self, Ck>,
-  C: SousARCDataHasParent<P, Self, Ck>,
+  C: SousARCData<Key = Ck>,
   Self: SousARCId<Bound = P>,
   Ck: SousARCKeyHasParent<P, C, Self>,
 {
@@ -46,20 +53,18 @@ where
   where
     Ck: From<(Self, T)>,
   {
-    Ck::from((self.clone(), value))
+    Ck::from((*self, value))
   }
 }
-/*
 /// 自動実装
 impl<P, C, Pi, Ck> SousARCIdHasChild<P, C, Ck> for Pi
 where
   P: SousARCDataHasChild<C, Self, Ck>,
-  C: SousARCDataHasParent<P, Self, Ck>,
+  C: SousARCData<Key = Ck>,
   Pi: SousARCId<Bound = P>,
   Ck: SousARCKeyHasParent<P, C, Self>,
 {
 }
-*/
 
 /// 親要素を持つデータ型のマーカートレイト
 ///
@@ -68,8 +73,8 @@ where
 pub trait SousARCDataHasParent<P, Pi, Ck>
 where
   P: SousARCDataHasChild<Self, Pi, Ck>,
-  Self: SousARCData,
-  Pi: SousARCIdHasChild<P, Self, Ck>,
+  Self: SousARCData<Key = Ck>,
+  Pi: SousARCId<Bound = P>,
   Ck: SousARCKeyHasParent<P, Self, Pi>,
 {
 }
@@ -77,8 +82,8 @@ where
 impl<P, C, Pi, Ck> SousARCDataHasParent<P, Pi, Ck> for C
 where
   P: SousARCDataHasChild<C, Pi, Ck>,
-  C: SousARCData,
-  Pi: SousARCIdHasChild<P, C, Ck>,
+  C: SousARCData<Key = Ck>,
+  Pi: SousARCId<Bound = P>,
   Ck: SousARCKeyHasParent<P, C, Pi>,
 {
 }
@@ -89,9 +94,9 @@ where
 /// 子→親のキー関係を型レベルで表現
 pub trait SousARCKeyHasParent<P, C, Pi>
 where
-  P: SousARCDataHasChild<C, Pi, Self>,
-  C: SousARCDataHasParent<P, Pi, Self>,
-  Pi: SousARCIdHasChild<P, C, Self>,
+  P: SousARCData<Id = Pi>,
+  C: SousARCData<Key = Self>,
+  Pi: SousARCId<Bound = P>,
   Self: SousARCKey<Bound = C>,
 {
   /// 親IDのイテレータを返す
@@ -105,3 +110,125 @@ where
     self.parent_id().and_then(|id| storage.key(id))
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    domain::{
+      element::{ElementData, ElementKey, ElementParent},
+      user::UserDataBody,
+      work::{WorkData, WorkKey},
+    },
+    fixture::{
+      Store, container_body, element_body, work_body,
+    },
+  };
+
+  /// 子のキーから親を引き、親の子の一覧に子が含まれるか判定する
+  fn linked_both_ways<P, C, Pi, Ck>(
+    child: &C,
+    parents: &impl SousARCStorage<P>,
+  ) -> bool
+  where
+    P: SousARCDataHasChild<C, Pi, Ck>,
+    C: SousARCData<Key = Ck>,
+    Pi: SousARCId<Bound = P>,
+    Ck: SousARCKeyHasParent<P, C, Pi>,
+  {
+    child
+      .key()
+      .parent_id()
+      .and_then(|id| parents.get(id))
+      .is_some_and(|p| {
+        p.children().any(|c| c == child.id())
+      })
+  }
+
+  #[test]
+  fn walks_parents_and_children() {
+    let mut store = Store::default();
+    let (user, work, root, nested) = store
+      .run(|tx| {
+        let user =
+          tx.spawn_user("alice", UserDataBody::default())?;
+        let work =
+          tx.spawn_work(user, "novel", work_body("novel"))?;
+        let root = tx.spawn_element(
+          ElementParent::Root(work),
+          "chapter",
+          container_body(),
+        )?;
+        let nested = tx.spawn_element(
+          ElementParent::Nest(root),
+          "scene",
+          element_body(""),
+        )?;
+        Ok((user, work, root, nested))
+      })
+      .unwrap();
+    let Store { users, works, elements } = &store;
+
+    // ユーザ→作品
+    let user_data = users.get(user).unwrap();
+    assert_eq!(
+      user_data.children().collect::<Vec<_>>(),
+      [work]
+    );
+    let work_key = WorkKey::from((user, "novel"));
+    assert_eq!(
+      user_data.children_key(works).collect::<Vec<_>>(),
+      [&work_key]
+    );
+    assert_eq!(user.child_key("novel"), work_key);
+    let work_data = works.get(work).unwrap();
+    assert_eq!(
+      work_data.key().parent_key(users),
+      Some(users.key(user).unwrap())
+    );
+    assert!(linked_both_ways(work_data, users));
+
+    // 作品→要素
+    assert_eq!(
+      work_data.children().collect::<Vec<_>>(),
+      [root]
+    );
+    let root_data = elements.get(root).unwrap();
+    assert_eq!(
+      SousARCIdHasChild::<WorkData, _, _>::child_key(
+        &work, "chapter"
+      ),
+      *root_data.key()
+    );
+    assert!(linked_both_ways::<WorkData, _, _, _>(
+      root_data, works
+    ));
+    assert!(!linked_both_ways::<ElementData, _, _, _>(
+      root_data, elements
+    ));
+
+    // 要素→要素(`ElementParent::Nest`)
+    assert_eq!(
+      SousARCDataHasChild::<ElementData, _, _>::children(
+        root_data
+      )
+      .collect::<Vec<_>>(),
+      [nested]
+    );
+    let nested_data = elements.get(nested).unwrap();
+    assert_eq!(
+      *nested_data.key(),
+      ElementKey::from((root, "scene"))
+    );
+    assert_eq!(
+      SousARCKeyHasParent::<WorkData, _, _>::parent_id(
+        nested_data.key()
+      ),
+      None
+    );
+    assert!(linked_both_ways::<ElementData, _, _, _>(
+      nested_data,
+      elements
+    ));
+  }
+}
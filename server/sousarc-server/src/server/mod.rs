@@ -3,18 +3,23 @@ use std::{net::SocketAddr, sync::LazyLock};
 use axum::{Router, response::Html, routing::get};
 use serde::{Deserialize, Serialize};
 
+mod order;
+mod store;
 mod ws;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerConfig {
   pub host: String,
   pub socket: Vec<SocketAddr>,
+  #[serde(default)]
+  pub store: store::StoreConfig,
 }
 impl Default for ServerConfig {
   fn default() -> Self {
     Self {
       host: "https://app.example.com".to_string(),
       socket: vec![SocketAddr::from(([0, 0, 0, 0], 8080))],
+      store: store::StoreConfig::default(),
     }
   }
 }
@@ -46,7 +51,18 @@ async fn wait_for_ctrlc_and_sigterm() {
     _ = tokio::signal::ctrl_c() => {
       tracing::info!("Ctrl-C received");
     }
-    _ = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) => {
+    _ = async {
+      use tokio::signal::unix::{SignalKind, signal};
+      match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+          terminate.recv().await;
+        }
+        Err(e) => {
+          tracing::error!("Failed to listen for SIGTERM: {e}");
+          std::future::pending::<()>().await;
+        }
+      }
+    } => {
       tracing::info!("SIGTERM received");
     }
   }
@@ -68,8 +84,14 @@ pub async fn server_starting() -> Result<(), crate::StdError>
   // Ctrl-CとSIGTERMを待機する
   tokio::spawn(wait_for_ctrlc_and_sigterm());
 
+  // データストアを開き、整合性を検査する
+  let store = store::open(&server_conf.store)?;
+
   let app = Router::new()
-    .route("/", get(|| async { Html("Hello, World!") }));
+    .route("/", get(|| async { Html("Hello, World!") }))
+    .merge(store::routes())
+    .merge(order::routes())
+    .with_state(store);
 
   tracing::info!(
     "Server starting on {}",
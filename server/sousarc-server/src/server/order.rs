@@ -0,0 +1,82 @@
+use axum::{
+  Json, Router,
+  extract::{Path, State},
+  http::StatusCode,
+  routing::put,
+};
+use serde::Deserialize;
+use sousarc_content_types::domain::{
+  element::{ElementId, ElementParent},
+  error::HierarchyError,
+  order::SiblingPosition,
+};
+
+use super::store::SharedStore;
+
+/// 移動先のリクエスト
+/// Move request
+#[derive(Debug, Deserialize)]
+struct MoveRequest {
+  parent: ElementParent,
+  position: SiblingPosition,
+}
+
+/// エラーをステータスコードとメッセージに変換する
+/// Convert an error into a status code and a message
+fn error_response(
+  e: HierarchyError,
+) -> (StatusCode, String) {
+  let status = match e {
+    HierarchyError::ElementNotFound(_)
+    | HierarchyError::WorkNotFound(_) => {
+      StatusCode::NOT_FOUND
+    }
+    HierarchyError::Journal(_) => {
+      StatusCode::INTERNAL_SERVER_ERROR
+    }
+    _ => StatusCode::CONFLICT,
+  };
+  (status, e.to_string())
+}
+
+/// 要素を兄弟の中で並び替え、移した後の添字を返す
+/// Reorder an element among its siblings and return its new index
+async fn place(
+  State(store): State<SharedStore>,
+  Path(id): Path<ElementId>,
+  Json(position): Json<SiblingPosition>,
+) -> Result<Json<usize>, (StatusCode, String)> {
+  let mut store = store.lock().await;
+  store
+    .transaction(|tx| tx.place_element(id, position))
+    .map(Json)
+    .map_err(error_response)
+}
+
+/// 要素を別の親の指定した位置へ移し、移した後の添字を返す
+/// Move an element under another parent and return its new index
+async fn move_to(
+  State(store): State<SharedStore>,
+  Path(id): Path<ElementId>,
+  Json(request): Json<MoveRequest>,
+) -> Result<Json<usize>, (StatusCode, String)> {
+  let mut store = store.lock().await;
+  store
+    .transaction(|tx| {
+      tx.move_element_at(
+        id,
+        request.parent,
+        request.position,
+      )
+    })
+    .map(Json)
+    .map_err(error_response)
+}
+
+/// 並び順のルーティング
+/// Ordering routes
+pub fn routes() -> Router<SharedStore> {
+  Router::new()
+    .route("/elements/{id}/position", put(place))
+    .route("/elements/{id}/parent", put(move_to))
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use axum::{
+  Json, Router, extract::State, http::StatusCode,
+  routing::get,
+};
+use serde::{Deserialize, Serialize};
+use sousarc_content_types::domain::{
+  error::HierarchyError,
+  fsck::{self, FsckReport, OrphanPolicy},
+  journal::JournaledStore,
+};
+use tokio::sync::Mutex;
+
+use crate::StdError;
+
+/// データストアの設定
+/// Data store configuration
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoreConfig {
+  pub dir: String,
+  pub checkpoint_interval: u64,
+}
+impl Default for StoreConfig {
+  fn default() -> Self {
+    Self {
+      dir: "data".to_string(),
+      checkpoint_interval: 1000,
+    }
+  }
+}
+
+/// ハンドラ間で共有するデータストア
+/// Data store shared between handlers
+pub type SharedStore = Arc<Mutex<JournaledStore>>;
+
+/// データストアを開き、整合性を検査・修復する
+/// 親が存在しないデータは削除せず、ログに報告する。
+///
+/// Open the data store, then check and repair its integrity
+pub fn open(
+  config: &StoreConfig,
+) -> Result<SharedStore, StdError> {
+  let mut store = JournaledStore::open(&config.dir)?
+    .with_checkpoint_interval(config.checkpoint_interval);
+  let report = repair(&mut store)?;
+  if report.is_clean() {
+    tracing::info!("Data store is consistent");
+  }
+  Ok(Arc::new(Mutex::new(store)))
+}
+
+/// 親子関係を修復し、検出した不整合をログに出力する
+/// Repair the hierarchy and log the detected issues
+fn repair(
+  store: &mut JournaledStore,
+) -> Result<FsckReport, HierarchyError> {
+  let report = store
+    .transaction(|tx| tx.repair(OrphanPolicy::Keep))?;
+  for issue in &report.issues {
+    tracing::warn!("fsck: {issue}");
+  }
+  Ok(report)
+}
+
+/// 検査結果のレスポンス
+/// Integrity check response
+#[derive(Debug, Serialize)]
+struct FsckResponse {
+  clean: bool,
+  issues: Vec<String>,
+}
+impl From<FsckReport> for FsckResponse {
+  fn from(report: FsckReport) -> Self {
+    Self {
+      clean: report.is_clean(),
+      issues: report
+        .issues
+        .iter()
+        .map(|issue| issue.to_string())
+        .collect(),
+    }
+  }
+}
+
+/// 整合性を検査する(変更しない)
+/// Check the integrity without changing anything
+async fn check(
+  State(store): State<SharedStore>,
+) -> Json<FsckResponse> {
+  let store = store.lock().await;
+  let report = fsck::check(
+    store.users(),
+    store.works(),
+    store.elements(),
+  );
+  Json(report.into())
+}
+
+/// 整合性を検査し、親子関係を修復する
+/// Check the integrity and repair the hierarchy
+async fn check_and_repair(
+  State(store): State<SharedStore>,
+) -> Result<Json<FsckResponse>, (StatusCode, String)> {
+  let mut store = store.lock().await;
+  repair(&mut store)
+    .map(|report| Json(report.into()))
+    .map_err(|e| {
+      (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })
+}
+
+/// データストアのルーティング
+/// Data store routes
+pub fn routes() -> Router<SharedStore> {
+  Router::new()
+    .route("/fsck", get(check).post(check_and_repair))
+}
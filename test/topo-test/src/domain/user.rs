@@ -71,6 +71,12 @@ impl SousARCData for UserData {
   fn id_key_set(&self) -> &IdKeySet<Self> {
     &self.id_key
   }
+  fn id_key_set_mut(
+    &mut self,
+    _: IdKeyToken,
+  ) -> &mut IdKeySet<Self> {
+    &mut self.id_key
+  }
 }
 impl UserData {
   pub fn new(user_name: impl ToString) -> Self {
@@ -93,6 +93,12 @@ impl SousARCData for WorkData {
   fn id_key_set(&self) -> &IdKeySet<Self> {
     &self.id_key
   }
+  fn id_key_set_mut(
+    &mut self,
+    _: IdKeyToken,
+  ) -> &mut IdKeySet<Self> {
+    &mut self.id_key
+  }
 }
 impl WorkData {
   pub fn new(
@@ -12,33 +12,33 @@ fn main() {
   let user = domain::user::UserData::new("test_user");
   let user_id = user.id();
   let mut user_storage = StandardStorage::new();
-  user_storage.insert(user);
+  user_storage.insert(user).unwrap();
   let work = user_storage
     .get_mut(user_id)
     .unwrap()
     .spawn("test_work");
   let work_id = work.id();
   let mut work_storage = StandardStorage::new();
-  work_storage.insert(work);
+  work_storage.insert(work).unwrap();
   let element = work_storage
     .get_mut(work_id)
     .unwrap()
     .spawn("test_element");
   let element_id = element.id();
   let mut element_storage = StandardStorage::new();
-  element_storage.insert(element);
+  element_storage.insert(element).unwrap();
   let element_2 = element_storage
     .get_mut(element_id)
     .unwrap()
     .spawn("test_element_2");
   let element_id_2 = element_2.id();
-  element_storage.insert(element_2);
+  element_storage.insert(element_2).unwrap();
   let element_3 = element_storage
     .get_mut(element_id_2)
     .unwrap()
     .spawn("test_element_3");
   let element_id_3 = element_3.id();
-  element_storage.insert(element_3);
+  element_storage.insert(element_3).unwrap();
 
   println!("{:?}", user_storage.get(user_id).unwrap());
   println!("{:?}", work_storage.get(work_id).unwrap());
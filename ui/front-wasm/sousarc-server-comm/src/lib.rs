@@ -1,9 +1,12 @@
 use parking_lot::Mutex;
-use sousarc_content_types::prelude::*;
+use sousarc_content_types::{
+  crdt::{ReplicaId, TextCrdt, TextOp},
+  prelude::*,
+};
 use wasm_bindgen::{JsValue, prelude::*};
 
 static USERDATA: Mutex<
-  Option<Vec<SousARCDataWrap<UserData, UserData>>>,
+  Option<Vec<UserRecord>>,
 > = Mutex::new(None);
 
 #[wasm_bindgen]
@@ -11,8 +14,8 @@ pub fn userdata_from_bytes(
   input: &[u8],
   idx: Option<usize>,
 ) -> Result<(), JsValue> {
-  let data: SousARCDataWrap<UserData, UserData> =
-    rmp_serde::from_slice(input).map_err(|e| {
+  let data =
+    UserRecord::from_msgpack(input).map_err(|e| {
       JsValue::from_str(&format!(
         "Failed to deserialize: {}",
         e
@@ -44,7 +47,7 @@ pub fn userdata_into_bytes(
     .get(idx)
     .ok_or(JsValue::from_str("Index out of bounds"))
     .and_then(|data| {
-      rmp_serde::to_vec(data).map_err(|e| {
+      data.to_msgpack().map_err(|e| {
         JsValue::from_str(&format!(
           "Failed to serialize: {}",
           e
@@ -58,7 +61,7 @@ pub fn userdata_from_json(
   input: &str,
   idx: Option<usize>,
 ) -> Result<(), JsValue> {
-  let data: SousARCDataWrap<UserData, UserData> =
+  let data: UserRecord =
     serde_json::from_str(input).map_err(|e| {
       JsValue::from_str(&format!(
         "Failed to deserialize: {}",
@@ -137,3 +140,103 @@ pub fn user_keys() -> Vec<String> {
     })
     .unwrap_or_default()
 }
+
+static CARDTEXT: Mutex<Option<Vec<TextCrdt>>> =
+  Mutex::new(None);
+
+fn with_cardtext<T>(
+  idx: usize,
+  f: impl FnOnce(&mut TextCrdt) -> Result<T, JsValue>,
+) -> Result<T, JsValue> {
+  let mut v = CARDTEXT.lock();
+  let text = v
+    .as_mut()
+    .ok_or(JsValue::from_str("No data available"))?
+    .get_mut(idx)
+    .ok_or(JsValue::from_str("Index out of bounds"))?;
+  f(text)
+}
+
+fn encode_op(
+  op: Option<TextOp>,
+) -> Result<Vec<u8>, JsValue> {
+  op.map(|op| {
+    op.to_msgpack().map_err(|e| {
+      JsValue::from_str(&format!(
+        "Failed to serialize: {}",
+        e
+      ))
+    })
+  })
+  .unwrap_or(Ok(Vec::new()))
+}
+
+#[wasm_bindgen]
+pub fn cardtext_from_snapshot(
+  replica: u64,
+  input: &[u8],
+) -> Result<usize, JsValue> {
+  let snapshot =
+    rmp_serde::from_slice(input).map_err(|e| {
+      JsValue::from_str(&format!(
+        "Failed to deserialize: {}",
+        e
+      ))
+    })?;
+  let mut v = CARDTEXT.lock();
+  let v = v.get_or_insert(Vec::new());
+  let text = TextCrdt::from_snapshot(
+    ReplicaId(replica),
+    snapshot,
+  )
+  .map_err(|e| JsValue::from_str(&e.to_string()))?;
+  v.push(text);
+  Ok(v.len() - 1)
+}
+
+#[wasm_bindgen]
+pub fn cardtext_insert(
+  idx: usize,
+  index: usize,
+  text: &str,
+) -> Result<Vec<u8>, JsValue> {
+  with_cardtext(idx, |t| {
+    let op = t
+      .insert(index, text)
+      .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    encode_op(op)
+  })
+}
+
+#[wasm_bindgen]
+pub fn cardtext_delete(
+  idx: usize,
+  index: usize,
+  len: usize,
+) -> Result<Vec<u8>, JsValue> {
+  with_cardtext(idx, |t| encode_op(t.delete(index, len)))
+}
+
+#[wasm_bindgen]
+pub fn cardtext_apply(
+  idx: usize,
+  input: &[u8],
+) -> Result<(), JsValue> {
+  let op = TextOp::from_msgpack(input).map_err(|e| {
+    JsValue::from_str(&format!(
+      "Failed to deserialize: {}",
+      e
+    ))
+  })?;
+  with_cardtext(idx, |t| {
+    t.apply(op)
+      .map_err(|e| JsValue::from_str(&e.to_string()))
+  })
+}
+
+#[wasm_bindgen]
+pub fn cardtext_text(
+  idx: usize,
+) -> Result<String, JsValue> {
+  with_cardtext(idx, |t| Ok(t.text()))
+}